@@ -1,10 +1,11 @@
 //! CLI command implementations
 
-use crate::{ConfigAction, OutputFormat, QueueAction};
+use crate::{ConfigAction, OutputFormat, QueueAction, VaultAction};
 use anyhow::{anyhow, Result};
 use console::style;
 use dlman_core::DlmanCore;
-use dlman_types::{Download, DownloadStatus, Queue, QueueOptions};
+use dlman_types::{AddDownloadOptions, Checksum, Download, DownloadStatus, Queue, QueueOptions, RequestHeader, VaultKey, VaultStatus};
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use uuid::Uuid;
 
@@ -12,11 +13,33 @@ use uuid::Uuid;
 // Download Commands
 // ============================================================================
 
+/// Parse the `--checksum`, `--mirror` and `--header` arguments of `add`
+pub fn add_download_options(
+    checksum: Option<String>,
+    mirrors: Vec<String>,
+    headers: Vec<String>,
+) -> Result<AddDownloadOptions> {
+    let checksum = checksum
+        .map(|c| c.parse::<Checksum>().map_err(|e| anyhow!(e)))
+        .transpose()?;
+
+    let headers = headers
+        .iter()
+        .map(|h| match h.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => Ok(RequestHeader::new(name.trim(), value.trim())),
+            _ => Err(anyhow!("Invalid header {:?}, expected \"Name: value\"", h)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(AddDownloadOptions { checksum, mirrors, headers, ..Default::default() })
+}
+
 pub async fn add_download(
     core: &DlmanCore,
     url: &str,
     output: Option<PathBuf>,
     queue: Option<String>,
+    options: AddDownloadOptions,
     format: OutputFormat,
 ) -> Result<()> {
     let destination = output.unwrap_or_else(|| {
@@ -28,21 +51,33 @@ pub async fn add_download(
         None => Uuid::nil(), // Default queue
     };
 
-    let download = core.add_download(url, destination, queue_id, None, None, true).await?;
+    if dlman_core::metalink::is_metalink_path(url) {
+        let document = tokio::fs::read_to_string(url).await?;
+        let downloads = core.add_metalink(&document, destination, queue_id, None, true).await?;
+        return print_added(&downloads, format);
+    }
+
+    let download = core.add_download(url, destination, queue_id, None, options, true).await?;
 
     match format {
         OutputFormat::Json => {
             println!("{}", serde_json::to_string_pretty(&download)?);
         }
+        OutputFormat::Human | OutputFormat::Table => print_added_download(&download),
+    }
+
+    Ok(())
+}
+
+/// Report the downloads imported from a Metalink file
+fn print_added(downloads: &[Download], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(downloads)?);
+        }
         OutputFormat::Human | OutputFormat::Table => {
-            println!(
-                "{} Added download: {}",
-                style("✓").green().bold(),
-                style(&download.filename).cyan()
-            );
-            println!("  ID: {}", download.id);
-            if let Some(size) = download.size {
-                println!("  Size: {}", human_bytes::human_bytes(size as f64));
+            for download in downloads {
+                print_added_download(download);
             }
         }
     }
@@ -50,6 +85,27 @@ pub async fn add_download(
     Ok(())
 }
 
+fn print_added_download(download: &Download) {
+    println!(
+        "{} Added download: {}",
+        style("✓").green().bold(),
+        style(&download.filename).cyan()
+    );
+    println!("  ID: {}", download.id);
+    if let Some(size) = download.size {
+        println!("  Size: {}", human_bytes::human_bytes(size as f64));
+    }
+    if let Some(ref checksum) = download.checksum {
+        println!("  Checksum: {}", checksum);
+    }
+    if let Some(ref pieces) = download.piece_hashes {
+        println!("  Piece hashes: {} × {} ({})", pieces.hashes.len(), human_bytes::human_bytes(pieces.length as f64), pieces.algorithm.name());
+    }
+    for mirror in &download.mirrors {
+        println!("  Mirror: {}", mirror.url);
+    }
+}
+
 pub async fn list_downloads(
     core: &DlmanCore,
     status_filter: Option<String>,
@@ -143,6 +199,7 @@ fn print_download_summary(download: &Download, detailed: bool) {
         DownloadStatus::Paused => style("⏸").yellow(),
         DownloadStatus::Failed => style("✗").red(),
         DownloadStatus::Cancelled => style("○").dim(),
+        DownloadStatus::AwaitingAuth => style("🔒").yellow(),
         _ => style("·").dim(),
     };
 
@@ -196,13 +253,66 @@ pub async fn pause_download(core: &DlmanCore, id: &str, _format: OutputFormat) -
     Ok(())
 }
 
-pub async fn resume_download(core: &DlmanCore, id: &str, _format: OutputFormat) -> Result<()> {
+pub async fn resume_download(core: &DlmanCore, id: &str, format: OutputFormat) -> Result<()> {
     let uuid = Uuid::parse_str(id)?;
+    // A download the server refused needs a login first; ask for one if
+    // someone is there to answer
+    let download = core.get_download(uuid).await?;
+    if download.status == DownloadStatus::AwaitingAuth && std::io::stdin().is_terminal() {
+        return login_download(core, id, None, false, format).await;
+    }
     core.resume_download(uuid).await?;
     println!("{} Download resumed", style("✓").green().bold());
     Ok(())
 }
 
+pub async fn login_download(
+    core: &DlmanCore,
+    id: &str,
+    username: Option<String>,
+    remember: bool,
+    _format: OutputFormat,
+) -> Result<()> {
+    use dialoguer::{Confirm, Input, Password};
+
+    let uuid = Uuid::parse_str(id)?;
+    let download = core.get_download(uuid).await?;
+    let interactive = std::io::stdin().is_terminal();
+
+    let (username, password, remember) = if interactive {
+        println!(
+            "{} {} needs a login",
+            style("🔒").yellow(),
+            style(&download.filename).bold()
+        );
+        let username = match username {
+            Some(username) => username,
+            None => Input::new().with_prompt("Username").allow_empty(true).interact_text()?,
+        };
+        let password = Password::new().with_prompt("Password").interact()?;
+        let remember = remember
+            || Confirm::new()
+                .with_prompt("Save this login for the site?")
+                .default(true)
+                .interact()?;
+        (username, password, remember)
+    } else {
+        let username = username.ok_or_else(|| anyhow!("--username is required without a terminal"))?;
+        (username, read_stdin_line()?, remember)
+    };
+
+    core.provide_credentials(uuid, username, password, remember).await?;
+    println!("{} Download resumed", style("✓").green().bold());
+    Ok(())
+}
+
+pub async fn restart_download(core: &DlmanCore, id: &str, _format: OutputFormat) -> Result<()> {
+    let uuid = Uuid::parse_str(id)?;
+    core.restart_download(uuid).await?;
+    println!("{} Download restarted", style("✓").green().bold());
+    Ok(())
+}
+
 pub async fn cancel_download(core: &DlmanCore, id: &str, _format: OutputFormat) -> Result<()> {
     let uuid = Uuid::parse_str(id)?;
     core.cancel_download(uuid).await?;
@@ -436,6 +546,97 @@ pub async fn export_data(
     Ok(())
 }
 
+// ============================================================================
+// Saved Login Encryption
+// ============================================================================
+
+/// Unlock saved logins for a command that may use them: with `keyfile` if
+/// given, otherwise by asking for the master passphrase on a terminal.
+/// Without either, downloads go without their saved logins.
+pub async fn unlock_credentials(core: &DlmanCore, keyfile: Option<PathBuf>) -> Result<()> {
+    use dialoguer::Password;
+
+    if core.credential_vault_status() != VaultStatus::Locked {
+        return Ok(());
+    }
+    if let Some(path) = keyfile {
+        core.unlock_credentials(VaultKey::Keyfile(path)).await?;
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "{} Saved logins are locked; pass --keyfile to use them",
+            style("!").yellow().bold()
+        );
+        return Ok(());
+    }
+    for _ in 0..3 {
+        let passphrase = Password::new().with_prompt("Master passphrase").interact()?;
+        match core.unlock_credentials(VaultKey::Passphrase(passphrase)).await {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("{} {}", style("✗").red().bold(), e),
+        }
+    }
+    Err(anyhow!("Saved logins are still locked"))
+}
+
+/// A line of stdin, without the line break
+fn read_stdin_line() -> Result<String> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+pub async fn vault_action(
+    core: &DlmanCore,
+    action: VaultAction,
+    keyfile: Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<()> {
+    match action {
+        VaultAction::Status => {
+            let status = core.credential_vault_status();
+            match format {
+                OutputFormat::Json => println!("{}", serde_json::json!({ "status": status })),
+                _ => println!(
+                    "Saved passwords: {}",
+                    match status {
+                        VaultStatus::Disabled => "not encrypted",
+                        VaultStatus::Locked => "encrypted (locked)",
+                        VaultStatus::Unlocked => "encrypted (unlocked)",
+                    }
+                ),
+            }
+        }
+
+        VaultAction::SetKey { use_keyfile } => {
+            use dialoguer::Password;
+
+            let interactive = std::io::stdin().is_terminal();
+            // Changing the key takes the current one
+            if core.credential_vault_status() == VaultStatus::Locked && keyfile.is_none() && !interactive {
+                core.unlock_credentials(VaultKey::Passphrase(read_stdin_line()?)).await?;
+            } else {
+                unlock_credentials(core, keyfile).await?;
+            }
+            let key = match use_keyfile {
+                Some(path) => VaultKey::Keyfile(path),
+                None if interactive => VaultKey::Passphrase(
+                    Password::new()
+                        .with_prompt("New master passphrase")
+                        .with_confirmation("Repeat it", "The passphrases don't match")
+                        .interact()?,
+                ),
+                None => VaultKey::Passphrase(read_stdin_line()?),
+            };
+            core.set_master_key(key).await?;
+            println!("{} Saved passwords encrypted with the new key", style("✓").green().bold());
+        }
+    }
+
+    Ok(())
+}
+
 // ============================================================================
 // Config Commands
 // ============================================================================
@@ -28,6 +28,11 @@ struct Cli {
     #[arg(short, long)]
     verbose: bool,
 
+    /// Keyfile that unlocks saved logins, instead of asking for the master
+    /// passphrase
+    #[arg(long, env = "DLMAN_KEYFILE", global = true)]
+    keyfile: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -43,7 +48,7 @@ enum OutputFormat {
 enum Commands {
     /// Add a new download
     Add {
-        /// URL to download
+        /// URL to download, or a local Metalink file (.meta4 / .metalink)
         url: String,
 
         /// Output file path
@@ -61,6 +66,21 @@ enum Commands {
         /// Start download immediately
         #[arg(short = 'n', long)]
         now: bool,
+
+        /// Expected checksum of the file, e.g. sha256:<hex> (md5, sha1,
+        /// sha256 and sha512; a bare hex digest is matched by length)
+        #[arg(long)]
+        checksum: Option<String>,
+
+        /// Another URL serving the same file; segments are spread across
+        /// all mirrors (repeatable)
+        #[arg(short = 'm', long = "mirror")]
+        mirrors: Vec<String>,
+
+        /// Extra request header as "Name: value", sent to the download's
+        /// host on top of the header rules (repeatable)
+        #[arg(short = 'H', long = "header")]
+        headers: Vec<String>,
     },
 
     /// List downloads
@@ -96,6 +116,28 @@ enum Commands {
         id: String,
     },
 
+    /// Log in for a download the server refused, then resume it. Prompts
+    /// for what isn't given; without a terminal the password is read from
+    /// the first line of stdin
+    Login {
+        /// Download ID
+        id: String,
+
+        /// Username to log in with
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Save the login for the download's site
+        #[arg(long)]
+        remember: bool,
+    },
+
+    /// Restart a download from the beginning, discarding partial data
+    Restart {
+        /// Download ID
+        id: String,
+    },
+
     /// Cancel a download
     Cancel {
         /// Download ID
@@ -143,6 +185,12 @@ enum Commands {
         action: Option<ConfigAction>,
     },
 
+    /// Encryption of saved login passwords
+    Vault {
+        #[command(subcommand)]
+        action: VaultAction,
+    },
+
     /// Generate shell completions
     Completions {
         /// Shell to generate completions for
@@ -216,6 +264,23 @@ enum ConfigAction {
     Reset,
 }
 
+#[derive(Subcommand)]
+enum VaultAction {
+    /// Show whether saved passwords are encrypted
+    Status,
+
+    /// Encrypt saved passwords under a new master passphrase, or a keyfile;
+    /// changes the key if they already are. Without a terminal, the current
+    /// passphrase (when locked) and the new one are read from stdin, a line
+    /// each
+    SetKey {
+        /// Derive the key from this file instead of a passphrase (a new
+        /// random one is written if it doesn't exist)
+        #[arg(long)]
+        use_keyfile: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -230,6 +295,19 @@ async fn main() -> Result<()> {
     // Initialize core
     let core = dlman_core::DlmanCore::new(data_dir).await?;
 
+    // Commands that may start downloads or probe need the saved logins
+    if matches!(
+        cli.command,
+        Commands::Add { .. }
+            | Commands::Resume { .. }
+            | Commands::Login { .. }
+            | Commands::Restart { .. }
+            | Commands::Queue { .. }
+            | Commands::Probe { .. }
+    ) {
+        commands::unlock_credentials(&core, cli.keyfile.clone()).await?;
+    }
+
     // Execute command
     match cli.command {
         Commands::Add {
@@ -238,7 +316,13 @@ async fn main() -> Result<()> {
             queue,
             segments: _,
             now: _,
-        } => commands::add_download(&core, &url, output, queue, cli.output).await?,
+            checksum,
+            mirrors,
+            headers,
+        } => {
+            let options = commands::add_download_options(checksum, mirrors, headers)?;
+            commands::add_download(&core, &url, output, queue, options, cli.output).await?
+        }
 
         Commands::List { status, queue, all } => {
             commands::list_downloads(&core, status, queue, all, cli.output).await?
@@ -250,6 +334,12 @@ async fn main() -> Result<()> {
 
         Commands::Resume { id } => commands::resume_download(&core, &id, cli.output).await?,
 
+        Commands::Login { id, username, remember } => {
+            commands::login_download(&core, &id, username, remember, cli.output).await?
+        }
+
+        Commands::Restart { id } => commands::restart_download(&core, &id, cli.output).await?,
+
         Commands::Cancel { id } => commands::cancel_download(&core, &id, cli.output).await?,
 
         Commands::Delete { id, with_file } => {
@@ -266,6 +356,8 @@ async fn main() -> Result<()> {
 
         Commands::Config { action } => commands::config_action(&core, action, cli.output).await?,
 
+        Commands::Vault { action } => commands::vault_action(&core, action, cli.keyfile, cli.output).await?,
+
         Commands::Completions { shell } => {
             use clap::CommandFactory;
             clap_complete::generate(shell, &mut Cli::command(), "dlman", &mut std::io::stdout());
@@ -88,11 +88,30 @@ impl DownloadProgress {
                                 style("○").dim()
                             ));
                         }
+                        DownloadStatus::AwaitingAuth => {
+                            pb.abandon_with_message(format!(
+                                "{} Login required: run `dlman login {}`",
+                                style("🔒").yellow(),
+                                id
+                            ));
+                        }
                         _ => {}
                     }
                 }
             }
 
+            CoreEvent::ContentChanged { id, .. } => {
+                let bars = self.bars.read().await;
+                if let Some(pb) = bars.get(id) {
+                    pb.println(format!(
+                        "{} The file changed on the server. Run `dlman restart {}` to start over, or `dlman cancel {}`",
+                        style("!").yellow().bold(),
+                        id,
+                        id
+                    ));
+                }
+            }
+
             _ => {}
         }
     }
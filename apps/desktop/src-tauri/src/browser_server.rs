@@ -14,7 +14,7 @@ use axum::{
         ws::{Message, WebSocket},
         State, WebSocketUpgrade,
     },
-    http::{header, Method},
+    http::{header, HeaderMap, Method},
     response::IntoResponse,
     routing::{get, post},
     Router,
@@ -40,6 +40,9 @@ pub struct ShowDialogRequest {
     pub referrer: Option<String>,
     pub filename: Option<String>,
     pub cookies: Option<String>,
+    /// Expected checksum of the file (e.g. "sha256:<hex>"), prefilled in the dialog
+    #[serde(default)]
+    pub checksum: Option<String>,
 }
 
 /// Request to show the batch download dialog (multiple URLs)
@@ -65,6 +68,16 @@ pub struct StatusResponse {
     pub queues: usize,
 }
 
+/// Login supplied for a download waiting for credentials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvideCredentialsRequest {
+    pub username: String,
+    pub password: String,
+    /// Save the login for the download's site; refused over HTTP
+    #[serde(default)]
+    pub remember: bool,
+}
+
 /// Simple response for control operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ControlResponse {
@@ -155,6 +168,7 @@ impl BrowserServer {
             .route("/api/downloads/:id/pause", post(handle_pause_download))
             .route("/api/downloads/:id/resume", post(handle_resume_download))
             .route("/api/downloads/:id/cancel", post(handle_cancel_download))
+            .route("/api/downloads/:id/credentials", post(handle_provide_credentials))
             // Media download — handles video streams from extension
             .route("/api/media/download", post(handle_media_download))
             // WebSocket for real-time events (optional)
@@ -220,12 +234,13 @@ async fn handle_show_dialog(
         None
     };
 
-    // Emit event to frontend with the full request (url, referrer, filename, cookies)
+    // Emit event to frontend with the full request (url, referrer, filename, cookies, checksum)
     let payload = serde_json::json!({
         "url": req.url,
         "referrer": req.referrer,
         "filename": req.filename,
         "cookies": req.cookies,
+        "checksum": req.checksum,
         // Include media metadata when URL is a streaming manifest
         "media_protocol": media_protocol,
         "media_master_url": if media_protocol.is_some() { Some(&req.url) } else { None },
@@ -411,6 +426,61 @@ async fn handle_cancel_download(
     }
 }
 
+/// Whether a request comes from a browser extension rather than a web page.
+/// Browsers set `Origin` themselves, so a page can't claim an extension's.
+fn is_extension_origin(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .is_some_and(|origin| {
+            ["chrome-extension://", "moz-extension://", "safari-web-extension://"]
+                .iter()
+                .any(|scheme| origin.starts_with(scheme))
+        })
+}
+
+/// POST /api/downloads/:id/credentials — log in for a download the server
+/// refused and resume it. Only the extension may call this, and only for this
+/// session: saving a login is left to the app.
+async fn handle_provide_credentials(
+    State(state): axum::extract::State<SharedState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    headers: HeaderMap,
+    axum::Json(req): axum::Json<ProvideCredentialsRequest>,
+) -> impl axum::response::IntoResponse {
+    if !is_extension_origin(&headers) {
+        return axum::Json(ControlResponse {
+            success: false,
+            error: Some("Credentials are only accepted from the browser extension".to_string()),
+        });
+    }
+    if req.remember {
+        return axum::Json(ControlResponse {
+            success: false,
+            error: Some("Logins can only be saved in the app".to_string()),
+        });
+    }
+
+    let Ok(uuid) = uuid::Uuid::parse_str(&id) else {
+        return axum::Json(ControlResponse {
+            success: false,
+            error: Some("Invalid download ID".to_string()),
+        });
+    };
+
+    let state = state.write().await;
+    match state.core.provide_credentials(uuid, req.username, req.password, false).await {
+        Ok(_) => axum::Json(ControlResponse {
+            success: true,
+            error: None,
+        }),
+        Err(e) => axum::Json(ControlResponse {
+            success: false,
+            error: Some(e.to_string()),
+        }),
+    }
+}
+
 // ============================================================================
 // Media Download — handles video stream downloads from extension
 // ============================================================================
@@ -488,6 +558,9 @@ async fn handle_media_download(
         "media_master_url": req.media.master_url,
         "media_page_title": req.media.page_title,
         "variant_index": req.variant_index,
+        "recording": req.recording,
+        "audio_track": req.audio_track,
+        "subtitle_track": req.subtitle_track,
     });
 
     if let Err(e) = app_handle.emit("show-new-download-dialog", payload) {
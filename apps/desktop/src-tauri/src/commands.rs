@@ -1,7 +1,10 @@
 //! Tauri commands for the desktop application
 
 use crate::state::AppState;
-use dlman_types::{Download, LinkInfo, Queue, QueueOptions, Settings, SiteCredential};
+use dlman_types::{
+    AddDownloadOptions, Download, HeaderRule, LinkInfo, Queue, QueueOptions, RequestHeader, Settings, SiteCredential,
+    VaultKey, VaultStatus,
+};
 use std::path::PathBuf;
 use tauri::{Manager, State};
 use uuid::Uuid;
@@ -42,10 +45,22 @@ pub async fn add_download(
     probed_info: Option<ProbedInfo>,
     start_later: Option<bool>,
     cookies: Option<String>,
+    checksum: Option<String>,
+    mirrors: Option<Vec<String>>,
+    headers: Option<Vec<RequestHeader>>,
 ) -> Result<Download, String> {
     tracing::info!("[add_download] URL={} start_later={:?}", &url, start_later);
+    // Expected hash, e.g. "sha256:<hex>"; an empty field means none
+    let checksum = checksum
+        .filter(|c| !c.trim().is_empty())
+        .map(|c| c.parse::<dlman_types::Checksum>())
+        .transpose()?;
     // Auto-detect HLS/DASH streaming URLs and route to the streaming pipeline.
     if let Some(_protocol) = is_streaming_url(&url) {
+        // Streams are remuxed after download, so no published hash can match
+        if checksum.is_some() {
+            return Err("A checksum can't be verified for an HLS/DASH stream".to_string());
+        }
         let filename = probed_info.as_ref().and_then(|p| p.filename.clone());
         let auto_start = !start_later.unwrap_or(false);
         return state
@@ -57,6 +72,7 @@ pub async fn add_download(
                     None,
                     cookies.clone(),
                     None,
+                    dlman_types::TrackSelection::default(),
                     auto_start,
                 )
                 .await
@@ -68,16 +84,22 @@ pub async fn add_download(
     let category_uuid = category_id.map(|s| Uuid::parse_str(&s).map_err(|e| e.to_string())).transpose()?;
     let dest_path = PathBuf::from(destination);
     let auto_start = !start_later.unwrap_or(false);
+    let options = AddDownloadOptions {
+        cookies,
+        checksum,
+        mirrors: mirrors.unwrap_or_default(),
+        headers: headers.unwrap_or_default(),
+    };
 
     state
         .with_core_async(|core| async move { 
-            let mut download = core.add_download(&url, dest_path, queue_uuid, category_uuid, cookies.clone(), auto_start).await?;
+            let mut download = core.add_download(&url, dest_path, queue_uuid, category_uuid, options, auto_start).await?;
             
             // Apply probed info if provided (filename, size from dialog probe)
             if let Some(info) = probed_info {
                 let mut updated = false;
                 if let Some(filename) = info.filename {
-                    download.filename = filename;
+                    download.filename = dlman_core::filename::sanitize(&filename);
                     updated = true;
                 }
                 if let Some(size) = info.size {
@@ -108,6 +130,8 @@ pub async fn add_download(
 pub struct BatchDownloadRequest {
     pub url: String,
     pub probed_info: Option<ProbedInfo>,
+    /// Expected hash, e.g. "sha256:<hex>"
+    pub checksum: Option<String>,
 }
 
 /// Add multiple downloads at once (batch import)
@@ -130,7 +154,20 @@ pub async fn add_downloads_batch(
             let mut results = Vec::with_capacity(downloads.len());
             
             for req in downloads {
-                let add_result = core.add_download(&req.url, dest_path.clone(), queue_uuid, category_uuid, None, should_start).await;
+                // A local Metalink file expands into one download per file it lists
+                if dlman_core::metalink::is_metalink_path(&req.url) {
+                    let document = tokio::fs::read_to_string(&req.url).await?;
+                    results.extend(core.add_metalink(&document, dest_path.clone(), queue_uuid, category_uuid, should_start).await?);
+                    continue;
+                }
+                
+                let checksum = req.checksum
+                    .filter(|c| !c.trim().is_empty())
+                    .map(|c| c.parse::<dlman_types::Checksum>())
+                    .transpose()
+                    .map_err(dlman_core::DlmanError::InvalidOperation)?;
+                let options = AddDownloadOptions { checksum, ..Default::default() };
+                let add_result = core.add_download(&req.url, dest_path.clone(), queue_uuid, category_uuid, options, should_start).await;
                 
                 match add_result {
                     Ok(mut download) => {
@@ -138,7 +175,7 @@ pub async fn add_downloads_batch(
                         if let Some(info) = req.probed_info {
                             let mut updated = false;
                             if let Some(filename) = info.filename {
-                                download.filename = filename;
+                                download.filename = dlman_core::filename::sanitize(&filename);
                                 updated = true;
                             }
                             if let Some(size) = info.size {
@@ -195,6 +232,14 @@ pub async fn retry_download(state: State<'_, AppState>, id: String) -> Result<()
         .await
 }
 
+#[tauri::command]
+pub async fn restart_download(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
+    state
+        .with_core_async(|core| async move { core.restart_download(uuid).await })
+        .await
+}
+
 #[tauri::command]
 pub async fn cancel_download(state: State<'_, AppState>, id: String) -> Result<(), String> {
     let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
@@ -219,27 +264,53 @@ pub async fn start_media_download(
     cookies: Option<String>,
     referrer: Option<String>,
     start_later: Option<bool>,
+    recording: Option<dlman_types::RecordingOptions>,
+    audio_track: Option<usize>,
+    subtitle_track: Option<usize>,
 ) -> Result<dlman_types::Download, String> {
     tracing::info!(
-        "[start_media_download] protocol={} variant={:?} start_later={:?}",
-        protocol, variant_index, start_later,
+        "[start_media_download] protocol={} variant={:?} audio={:?} start_later={:?} recording={:?}",
+        protocol, variant_index, audio_track, start_later, recording,
     );
     if protocol != "hls" && protocol != "dash" {
         return Err(format!("Unsupported media protocol: {}", protocol));
     }
     let auto_start = !start_later.unwrap_or(false);
+    let tracks = dlman_types::TrackSelection {
+        audio: audio_track,
+        subtitles: subtitle_track,
+    };
     state
         .with_core_async(|core| async move {
-            core.download_hls_stream(
-                &master_url,
-                variant_index,
-                filename,
-                page_title,
-                cookies,
-                referrer,
-                auto_start,
-            )
-            .await
+            match recording {
+                // Recording limits only apply to live streams, which always start now
+                Some(options) if auto_start => {
+                    core.record_live_stream(
+                        &master_url,
+                        variant_index,
+                        filename,
+                        page_title,
+                        cookies,
+                        referrer,
+                        tracks,
+                        options,
+                    )
+                    .await
+                }
+                _ => {
+                    core.download_hls_stream(
+                        &master_url,
+                        variant_index,
+                        filename,
+                        page_title,
+                        cookies,
+                        referrer,
+                        tracks,
+                        auto_start,
+                    )
+                    .await
+                }
+            }
         })
         .await
 }
@@ -463,6 +534,97 @@ pub async fn delete_credential(
         .await
 }
 
+/// Log in for a download waiting for credentials and resume it
+#[tauri::command]
+pub async fn provide_credentials(
+    state: State<'_, AppState>,
+    id: String,
+    username: String,
+    password: String,
+    remember: bool,
+) -> Result<(), String> {
+    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
+    state
+        .with_core_async(|core| async move { core.provide_credentials(uuid, username, password, remember).await })
+        .await
+}
+
+#[tauri::command]
+pub async fn test_credential_login(
+    state: State<'_, AppState>,
+    credential: SiteCredential,
+) -> Result<(), String> {
+    state
+        .with_core_async(|core| async move { core.test_credential_login(credential).await })
+        .await
+}
+
+#[tauri::command]
+pub async fn get_credential_vault_status(state: State<'_, AppState>) -> Result<VaultStatus, String> {
+    state.with_core(|core| core.credential_vault_status()).await
+}
+
+#[tauri::command]
+pub async fn unlock_credentials(state: State<'_, AppState>, key: VaultKey) -> Result<(), String> {
+    state
+        .with_core_async(|core| async move { core.unlock_credentials(key).await })
+        .await
+}
+
+#[tauri::command]
+pub async fn lock_credentials(state: State<'_, AppState>) -> Result<(), String> {
+    state.with_core(|core| core.lock_credentials()).await
+}
+
+#[tauri::command]
+pub async fn set_master_key(state: State<'_, AppState>, key: VaultKey) -> Result<(), String> {
+    state
+        .with_core_async(|core| async move { core.set_master_key(key).await })
+        .await
+}
+
+// ============================================================================
+// Header Rule Commands
+// ============================================================================
+
+#[tauri::command]
+pub async fn get_header_rules(state: State<'_, AppState>) -> Result<Vec<HeaderRule>, String> {
+    state
+        .with_core_async(|core| async move { core.get_all_header_rules().await })
+        .await
+}
+
+#[tauri::command]
+pub async fn add_header_rule(
+    state: State<'_, AppState>,
+    rule: HeaderRule,
+) -> Result<HeaderRule, String> {
+    state
+        .with_core_async(|core| async move { core.upsert_header_rule(rule).await })
+        .await
+}
+
+#[tauri::command]
+pub async fn update_header_rule(
+    state: State<'_, AppState>,
+    rule: HeaderRule,
+) -> Result<HeaderRule, String> {
+    state
+        .with_core_async(|core| async move { core.upsert_header_rule(rule).await })
+        .await
+}
+
+#[tauri::command]
+pub async fn delete_header_rule(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<(), String> {
+    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
+    state
+        .with_core_async(|core| async move { core.delete_header_rule(uuid).await })
+        .await
+}
+
 // ============================================================================
 // Data Commands
 // ============================================================================
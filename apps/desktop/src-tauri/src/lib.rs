@@ -111,6 +111,7 @@ pub fn run() {
             commands::pause_download,
             commands::resume_download,
             commands::retry_download,
+            commands::restart_download,
             commands::cancel_download,
             commands::delete_download,
             commands::update_download,
@@ -133,6 +134,16 @@ pub fn run() {
             commands::add_credential,
             commands::update_credential,
             commands::delete_credential,
+            commands::test_credential_login,
+            commands::get_credential_vault_status,
+            commands::unlock_credentials,
+            commands::lock_credentials,
+            commands::set_master_key,
+            commands::provide_credentials,
+            commands::get_header_rules,
+            commands::add_header_rule,
+            commands::update_header_rule,
+            commands::delete_header_rule,
             // Data commands
             commands::export_data,
             commands::import_data,
@@ -72,6 +72,9 @@ impl AppState {
                                 CoreEvent::QueueStarted { .. } => "queue-started",
                                 CoreEvent::QueueCompleted { .. } => "queue-completed",
                                 CoreEvent::CredentialRequired { .. } => "credential-required",
+                                CoreEvent::RecordingProgress { .. } => "recording-progress",
+                                CoreEvent::ChecksumVerified { .. } => "checksum-verified",
+                                CoreEvent::ContentChanged { .. } => "content-changed",
                                 CoreEvent::Error { .. } => "core-error",
                             };
                             
@@ -154,6 +157,38 @@ impl AppState {
                                         }
                                     })
                                 }
+                                CoreEvent::RecordingProgress { id, elapsed_secs, recorded, segments } => {
+                                    serde_json::json!({
+                                        "type": "RecordingProgress",
+                                        "payload": {
+                                            "id": id.to_string(),
+                                            "elapsedSecs": elapsed_secs,
+                                            "recorded": recorded,
+                                            "segments": segments
+                                        }
+                                    })
+                                }
+                                CoreEvent::ChecksumVerified { id, algorithm, expected, actual, matched } => {
+                                    serde_json::json!({
+                                        "type": "ChecksumVerified",
+                                        "payload": {
+                                            "id": id.to_string(),
+                                            "algorithm": algorithm,
+                                            "expected": expected,
+                                            "actual": actual,
+                                            "matched": matched
+                                        }
+                                    })
+                                }
+                                CoreEvent::ContentChanged { id, url } => {
+                                    serde_json::json!({
+                                        "type": "ContentChanged",
+                                        "payload": {
+                                            "id": id.to_string(),
+                                            "url": url
+                                        }
+                                    })
+                                }
                                 CoreEvent::CredentialRequired { download_id, domain, url, status_code } => {
                                     serde_json::json!({
                                         "type": "CredentialRequired",
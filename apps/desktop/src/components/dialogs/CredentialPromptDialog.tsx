@@ -25,7 +25,7 @@ import { invoke } from '@tauri-apps/api/core';
  */
 export function CredentialPromptDialog() {
   const { t } = useTranslation();
-  const { pendingRequest, setPendingRequest, credentials, addCredential, updateCredential } = useCredentialsStore();
+  const { pendingRequest, setPendingRequest, credentials, loadFromBackend: loadCredentials } = useCredentialsStore();
   const [username, setUsername] = useState('');
   const [password, setPassword] = useState('');
   const [remember, setRemember] = useState(true);
@@ -68,40 +68,24 @@ export function CredentialPromptDialog() {
     setSubmitting(true);
 
     try {
-      // Save or update credential if "Remember" is on
+      // The core saves the login when "Remember" is on (updating the one that
+      // failed) and resumes the download with it
+      await invoke('provide_credentials', {
+        id: pendingRequest.downloadId,
+        username,
+        password,
+        remember,
+      });
       if (remember) {
-        if (existingCredId) {
-          // Update existing credential with new values
-          const existing = credentials.find((c) => c.id === existingCredId);
-          if (existing) {
-            await updateCredential({ ...existing, username, password });
-          }
-        } else {
-          // Save new credential
-          const now = new Date().toISOString();
-          await addCredential({
-            id: crypto.randomUUID(),
-            domain: pendingRequest.domain,
-            protocol: 'https',
-            username,
-            password,
-            enabled: true,
-            created_at: now,
-            last_used_at: null,
-            notes: null,
-          });
-        }
+        await loadCredentials();
       }
 
-      // Retry the download — the engine will pick up the saved credentials
-      await invoke('retry_download', { id: pendingRequest.downloadId });
-
       handleClose();
     } catch (err) {
       console.error('Failed to submit credentials:', err);
       setSubmitting(false);
     }
-  }, [pendingRequest, username, password, remember, existingCredId, credentials, addCredential, updateCredential, handleClose]);
+  }, [pendingRequest, username, password, remember, loadCredentials, handleClose]);
 
   if (!isOpen) return null;
 
@@ -39,6 +39,7 @@ import {
   Save,
   X,
   FolderInput,
+  KeyRound,
 } from 'lucide-react';
 import { useTranslation } from 'react-i18next';
 import { formatBytes } from '@/lib/utils';
@@ -216,6 +217,8 @@ export function DownloadInfoDialog({ open, onOpenChange, download }: DownloadInf
         return { icon: Pause, color: 'text-yellow-500', label: t('status.paused') };
       case 'failed':
         return { icon: XCircle, color: 'text-red-500', label: t('status.failed') };
+      case 'awaitingauth':
+        return { icon: KeyRound, color: 'text-yellow-500', label: t('status.awaitingauth') };
       case 'queued':
       case 'pending':
         return { icon: Clock, color: 'text-muted-foreground', label: t('status.queued') };
@@ -45,9 +45,10 @@ import { useUIStore } from '@/stores/ui';
 import { useQueuesArray, useQueueStore } from '@/stores/queues';
 import { useDownloadStore } from '@/stores/downloads';
 import { useCategoryStore } from '@/stores/categories';
-import { getPendingClipboardUrls, getPendingDropUrls, getPendingCookies, getPendingMediaMeta } from '@/lib/events';
+import { getPendingClipboardUrls, getPendingDropUrls, getPendingCookies, getPendingChecksum, getPendingMediaMeta } from '@/lib/events';
+import { parseHeaders } from '@/lib/utils';
 import { getDefaultBasePath, getCategoryDownloadPath, detectCategoryFromFilename } from '@/lib/download-path';
-import type { LinkInfo, Download as DownloadType } from '@/types';
+import type { LinkInfo, Download as DownloadType, RecordingOptions } from '@/types';
 
 // Check if we're in Tauri context
 const isTauri = () => typeof window !== 'undefined' && (window as any).__TAURI_INTERNALS__ !== undefined;
@@ -97,13 +98,22 @@ export function NewDownloadDialog() {
   const [probeTrigger, setProbeTrigger] = useState(0);
   // Browser cookies passed from extension for session-based auth
   const [browserCookies, setBrowserCookies] = useState<string | undefined>(undefined);
+  // Expected checksum (e.g. "sha256:<hex>"), verified once the download completes
+  const [checksum, setChecksum] = useState('');
+  // Other URLs serving the same file, one per line
+  const [mirrors, setMirrors] = useState('');
+  // Extra request headers, one "Name: value" per line
+  const [headers, setHeaders] = useState('');
   // Media metadata for HLS/DASH streaming downloads (from browser extension)
   const [mediaMeta, setMediaMeta] = useState<{
     protocol: string;
     master_url: string;
     page_title?: string;
     variant_index?: number;
+    audio_track?: number;
+    subtitle_track?: number;
     referrer?: string;
+    recording?: RecordingOptions | null;
   } | undefined>(undefined);
 
   // Tracks which intake we've already processed. Keyed on the intake nonce so
@@ -133,6 +143,10 @@ export function NewDownloadDialog() {
     // Check for pending cookies from browser extension
     const cookies = getPendingCookies();
     setBrowserCookies(cookies);
+    const pendingChecksum = getPendingChecksum();
+    setChecksum(pendingChecksum ?? '');
+    setMirrors('');
+    setHeaders('');
     
     // Check for media metadata (HLS/DASH streaming)
     const meta = getPendingMediaMeta();
@@ -147,7 +161,8 @@ export function NewDownloadDialog() {
     setFilename('');
     setCustomFilename('');
     setFilenameEdited(false);
-    setShowAdvancedPath(false);
+    // Show the advanced fields when a checksum was passed in, so it can be reviewed
+    setShowAdvancedPath(!!pendingChecksum);
     setFileSize(null);
     setProbeError(null);
     setCategoryId(null);
@@ -292,7 +307,7 @@ export function NewDownloadDialog() {
   const handlePasteFromClipboard = useCallback(async () => {
     try {
       const text = await navigator.clipboard.readText();
-      if (text && (text.startsWith('http://') || text.startsWith('https://'))) {
+      if (text && /^(?:https?|ftpe?s?|sftp):\/\//i.test(text)) {
         setUrl(text);
       }
     } catch (err) {
@@ -398,11 +413,14 @@ export function NewDownloadDialog() {
             master_url: mediaMeta.master_url,
             protocol: mediaMeta.protocol,
             variant_index: mediaMeta.variant_index ?? null,
+            audio_track: mediaMeta.audio_track ?? null,
+            subtitle_track: mediaMeta.subtitle_track ?? null,
             filename: filenameToUse || null,
             page_title: mediaMeta.page_title || null,
             cookies: browserCookies || null,
             referrer: mediaMeta.referrer || (url !== mediaMeta.master_url ? url : null),
             start_later: startLater,
+            recording: mediaMeta.recording ?? null,
           });
         } else {
           // Regular download — standard flow
@@ -420,6 +438,9 @@ export function NewDownloadDialog() {
             probed_info: probedInfo,
             start_later: startLater,
             cookies: browserCookies || undefined,
+            checksum: checksum.trim() || undefined,
+            mirrors: mirrors.split('\n').map(m => m.trim()).filter(Boolean),
+            headers: parseHeaders(headers),
           });
         }
         
@@ -437,7 +458,7 @@ export function NewDownloadDialog() {
         toast.error(t('toasts.addDownloadFailed'), { description: errorMsg });
       }
     }
-  }, [url, destination, queueId, categoryId, filename, customFilename, filenameEdited, fileSize, browserCookies, mediaMeta, addDownload, removeDownload, setShowNewDownloadDialog, rememberPathForCategory, updateCategory, selectedCategoryId, setSelectedCategory, setFilter, setSelectedQueue, selectedQueueId, t]);
+  }, [url, destination, queueId, categoryId, filename, customFilename, filenameEdited, fileSize, browserCookies, checksum, mirrors, headers, mediaMeta, addDownload, removeDownload, setShowNewDownloadDialog, rememberPathForCategory, updateCategory, selectedCategoryId, setSelectedCategory, setFilter, setSelectedQueue, selectedQueueId, t]);
 
   const formatFileSize = (bytes: number) => {
     if (bytes >= 1024 * 1024 * 1024) {
@@ -660,6 +681,41 @@ export function NewDownloadDialog() {
                           </Button>
                         </div>
                       )}
+                      <Label htmlFor="checksum" className="text-xs text-muted-foreground">
+                        {t('newDownload.checksum')}
+                      </Label>
+                      <Input
+                        id="checksum"
+                        value={checksum}
+                        onChange={(e) => setChecksum(e.target.value)}
+                        placeholder={t('newDownload.checksumPlaceholder')}
+                        className="h-8 text-sm font-mono"
+                        spellCheck={false}
+                      />
+                      <Label htmlFor="mirrors" className="text-xs text-muted-foreground">
+                        {t('newDownload.mirrors')}
+                      </Label>
+                      <textarea
+                        id="mirrors"
+                        value={mirrors}
+                        onChange={(e) => setMirrors(e.target.value)}
+                        placeholder={t('newDownload.mirrorsPlaceholder')}
+                        rows={2}
+                        spellCheck={false}
+                        className="w-full resize-none rounded-md border border-input bg-background px-3 py-2 text-sm ring-offset-background placeholder:text-muted-foreground focus-visible:outline-none focus-visible:ring-2 focus-visible:ring-ring focus-visible:ring-offset-2"
+                      />
+                      <Label htmlFor="headers" className="text-xs text-muted-foreground">
+                        {t('newDownload.headers')}
+                      </Label>
+                      <textarea
+                        id="headers"
+                        value={headers}
+                        onChange={(e) => setHeaders(e.target.value)}
+                        placeholder={t('newDownload.headersPlaceholder')}
+                        rows={2}
+                        spellCheck={false}
+                        className="w-full resize-none rounded-md border border-input bg-background px-3 py-2 text-sm font-mono ring-offset-background placeholder:text-muted-foreground focus-visible:outline-none focus-visible:ring-2 focus-visible:ring-ring focus-visible:ring-offset-2"
+                      />
                     </motion.div>
                   )}
                 </AnimatePresence>
@@ -1,5 +1,6 @@
-import { useState, useCallback, useEffect } from 'react';
+import { useState, useCallback, useEffect, useMemo } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { toast } from 'sonner';
 import { open } from '@tauri-apps/plugin-dialog';
 import { open as openUrl } from '@tauri-apps/plugin-shell';
 import {
@@ -32,6 +33,9 @@ import {
   Languages,
   Type,
   HardDrive,
+  Braces,
+  Lock,
+  LockOpen,
 } from 'lucide-react';
 
 import {
@@ -60,9 +64,11 @@ import { useUIStore } from '@/stores/ui';
 import { useSettingsStore } from '@/stores/settings';
 import { useCategoryStore, Category } from '@/stores/categories';
 import { useCredentialsStore } from '@/stores/credentials';
+import { useHeaderRulesStore } from '@/stores/headerRules';
+import { parseHeaders, formatHeaders, parseFormFields, formatFormFields, formatBytes } from '@/lib/utils';
 import { getIconComponent } from '@/lib/categoryIcons';
 import { CategoryDialog } from './CategoryDialog';
-import type { Settings as SettingsType, Theme, ProxySettings, SiteCredential } from '@/types';
+import type { Settings as SettingsType, Theme, ProxySettings, SiteCredential, HeaderRule, AuthScheme, FormLogin, LoginCheck, VaultKey } from '@/types';
 import { useTranslation } from 'react-i18next';
 import { LOCALES } from '@/i18n/config';
 import { FONTS } from '@/i18n/fonts';
@@ -70,6 +76,9 @@ import { FONTS } from '@/i18n/fonts';
 // Sentinel Select value meaning "no explicit font override — follow the language".
 const FONT_AUTO = 'auto';
 
+// Daily quotas of saved logins are entered in GiB
+const GIB = 1024 ** 3;
+
 type SettingsTab = 'downloads' | 'categories' | 'notifications' | 'appearance' | 'extensions' | 'proxy' | 'saved-logins' | 'advanced';
 
 const tabs: { id: SettingsTab; icon: React.ReactNode }[] = [
@@ -88,7 +97,8 @@ export function SettingsDialog() {
   const { settings, updateSettings, setTheme } = useSettingsStore();
   const { t } = useTranslation();
   const { categories, updateCategory, removeCategory } = useCategoryStore();
-  const { credentials, loadFromBackend: loadCredentials, addCredential, updateCredential, deleteCredential } = useCredentialsStore();
+  const { credentials, loadFromBackend: loadCredentials, addCredential, updateCredential, deleteCredential, testLogin, vaultStatus, loadVaultStatus, unlock: unlockVault, lock: lockVault, setMasterKey } = useCredentialsStore();
+  const { rules: headerRules, loadFromBackend: loadHeaderRules, addRule: addHeaderRule, updateRule: updateHeaderRule, deleteRule: deleteHeaderRule } = useHeaderRulesStore();
 
   const [activeTab, setActiveTab] = useState<SettingsTab>('downloads');
   const [localSettings, setLocalSettings] = useState<SettingsType>(settings);
@@ -106,13 +116,41 @@ export function SettingsDialog() {
   const [credentialForm, setCredentialForm] = useState({
     domain: '',
     protocol: 'https',
+    authScheme: 'basic' as AuthScheme,
     username: '',
     password: '',
+    sshKeyPath: '',
+    sshKeyPassphrase: '',
+    loginUrl: '',
+    loginMethod: 'POST',
+    loginFields: '',
+    loginCheck: 'any_cookie' as LoginCheck['type'],
+    loginCheckValue: '',
+    dailyQuotaGb: '',
     notes: '',
     enabled: true,
   });
+  const [testingLogin, setTestingLogin] = useState(false);
   const [showPasswords, setShowPasswords] = useState<Set<string>>(new Set());
 
+  // Saved password encryption state
+  const [vaultForm, setVaultForm] = useState({
+    keyType: 'passphrase' as VaultKey['type'],
+    value: '',
+    confirm: '',
+  });
+  const [vaultBusy, setVaultBusy] = useState(false);
+
+  // Header rule form state
+  const [showHeaderRuleForm, setShowHeaderRuleForm] = useState(false);
+  const [editingHeaderRule, setEditingHeaderRule] = useState<HeaderRule | null>(null);
+  const [headerRuleForm, setHeaderRuleForm] = useState({
+    domain: '',
+    headers: '',
+    notes: '',
+    enabled: true,
+  });
+
   // Sync local settings when dialog opens or settings change
   useEffect(() => {
     if (showSettingsDialog) {
@@ -124,16 +162,21 @@ export function SettingsDialog() {
       });
       setCategoryPaths(paths);
       setHasChanges(false);
-      // Load credentials when dialog opens
+      // Load credentials and header rules when dialog opens
+      loadVaultStatus();
       loadCredentials();
+      loadHeaderRules();
     }
-  }, [showSettingsDialog, settings, categories, loadCredentials]);
+  }, [showSettingsDialog, settings, categories, loadVaultStatus, loadCredentials, loadHeaderRules]);
 
   // Credential form helpers
   const resetCredentialForm = useCallback(() => {
     setShowCredentialForm(false);
     setEditingCredential(null);
-    setCredentialForm({ domain: '', protocol: 'https', username: '', password: '', notes: '', enabled: true });
+    setCredentialForm({
+      domain: '', protocol: 'https', authScheme: 'basic', username: '', password: '', sshKeyPath: '', sshKeyPassphrase: '',
+      loginUrl: '', loginMethod: 'POST', loginFields: '', loginCheck: 'any_cookie', loginCheckValue: '', dailyQuotaGb: '', notes: '', enabled: true,
+    });
   }, []);
 
   const handleEditCredential = useCallback((cred: SiteCredential) => {
@@ -141,16 +184,65 @@ export function SettingsDialog() {
     setCredentialForm({
       domain: cred.domain,
       protocol: cred.protocol,
+      authScheme: cred.auth_scheme || 'basic',
       username: cred.username,
       password: cred.password,
+      sshKeyPath: cred.ssh_key?.path || '',
+      sshKeyPassphrase: cred.ssh_key?.passphrase || '',
+      loginUrl: cred.form_login?.url || '',
+      loginMethod: cred.form_login?.method || 'POST',
+      loginFields: formatFormFields(cred.form_login?.fields || []),
+      loginCheck: cred.form_login?.success.type || 'any_cookie',
+      loginCheckValue: (() => {
+        const check = cred.form_login?.success;
+        if (check?.type === 'cookie') return check.name;
+        if (check?.type === 'body_contains' || check?.type === 'body_lacks') return check.text;
+        return '';
+      })(),
+      dailyQuotaGb: cred.daily_quota ? String(+(cred.daily_quota / GIB).toFixed(2)) : '',
       notes: cred.notes || '',
       enabled: cred.enabled,
     });
     setShowCredentialForm(true);
   }, []);
 
+  // Auth schemes only apply to HTTP(S) logins
+  const credentialIsHttp = credentialForm.protocol === 'https' || credentialForm.protocol === 'http';
+  const credentialIsBearer = credentialIsHttp && credentialForm.authScheme === 'bearer';
+  const credentialIsForm = credentialIsHttp && credentialForm.authScheme === 'form';
+
+  const credentialFormLogin = useMemo<FormLogin | null>(() => {
+    if (!credentialIsForm) return null;
+    const value = credentialForm.loginCheckValue.trim();
+    const success: LoginCheck =
+      credentialForm.loginCheck === 'cookie' ? { type: 'cookie', name: value }
+      : credentialForm.loginCheck === 'body_contains' ? { type: 'body_contains', text: value }
+      : credentialForm.loginCheck === 'body_lacks' ? { type: 'body_lacks', text: value }
+      : { type: 'any_cookie' };
+    return {
+      url: credentialForm.loginUrl.trim(),
+      method: credentialForm.loginMethod,
+      fields: parseFormFields(credentialForm.loginFields),
+      success,
+    };
+  }, [credentialIsForm, credentialForm.loginUrl, credentialForm.loginMethod, credentialForm.loginFields, credentialForm.loginCheck, credentialForm.loginCheckValue]);
+
+  // SFTP logins may use a private key instead of a password; a bearer token
+  // needs no username
+  const credentialFormComplete = Boolean(
+    credentialForm.domain &&
+    (credentialForm.username || credentialIsBearer) &&
+    (credentialForm.password || (credentialForm.protocol === 'sftp' && credentialForm.sshKeyPath)) &&
+    (!credentialIsForm || credentialForm.loginUrl.trim())
+  );
+
   const handleSaveCredential = useCallback(async () => {
-    if (!credentialForm.domain || !credentialForm.username || !credentialForm.password) return;
+    if (!credentialFormComplete) return;
+    const quotaGb = parseFloat(credentialForm.dailyQuotaGb);
+    const dailyQuota = quotaGb > 0 ? Math.round(quotaGb * GIB) : null;
+    const sshKey = credentialForm.protocol === 'sftp' && credentialForm.sshKeyPath
+      ? { path: credentialForm.sshKeyPath, passphrase: credentialForm.sshKeyPassphrase || null }
+      : null;
     try {
       const now = new Date().toISOString();
       if (editingCredential) {
@@ -160,6 +252,10 @@ export function SettingsDialog() {
           protocol: credentialForm.protocol,
           username: credentialForm.username,
           password: credentialForm.password,
+          auth_scheme: credentialIsHttp ? credentialForm.authScheme : 'basic',
+          form_login: credentialFormLogin,
+          ssh_key: sshKey,
+          daily_quota: dailyQuota,
           notes: credentialForm.notes || null,
           enabled: credentialForm.enabled,
         });
@@ -170,17 +266,132 @@ export function SettingsDialog() {
           protocol: credentialForm.protocol,
           username: credentialForm.username,
           password: credentialForm.password,
+          auth_scheme: credentialIsHttp ? credentialForm.authScheme : 'basic',
+          form_login: credentialFormLogin,
           enabled: credentialForm.enabled,
           created_at: now,
           last_used_at: null,
+          daily_quota: dailyQuota,
           notes: credentialForm.notes || null,
+          ssh_key: sshKey,
         });
       }
       resetCredentialForm();
     } catch (err) {
       console.error('Failed to save credential:', err);
     }
-  }, [credentialForm, editingCredential, addCredential, updateCredential, resetCredentialForm]);
+  }, [credentialForm, credentialFormComplete, credentialIsHttp, credentialFormLogin, editingCredential, addCredential, updateCredential, resetCredentialForm]);
+
+  const handleTestLogin = useCallback(async () => {
+    if (!credentialFormComplete || !credentialFormLogin) return;
+    setTestingLogin(true);
+    try {
+      await testLogin({
+        id: editingCredential?.id ?? crypto.randomUUID(),
+        domain: credentialForm.domain,
+        protocol: credentialForm.protocol,
+        username: credentialForm.username,
+        password: credentialForm.password,
+        auth_scheme: 'form',
+        form_login: credentialFormLogin,
+        enabled: true,
+        created_at: editingCredential?.created_at ?? new Date().toISOString(),
+        last_used_at: null,
+        notes: null,
+      });
+      toast.success(t('settings.loginTestPassed'));
+    } catch (err) {
+      toast.error(t('settings.loginTestFailed', { error: String(err) }));
+    } finally {
+      setTestingLogin(false);
+    }
+  }, [credentialForm, credentialFormComplete, credentialFormLogin, editingCredential, testLogin, t]);
+
+  // Saved password encryption helpers
+  const vaultKeyFromForm = useCallback((): VaultKey => ({
+    type: vaultForm.keyType,
+    value: vaultForm.value,
+  }), [vaultForm]);
+
+  const handleUnlockVault = useCallback(async () => {
+    if (!vaultForm.value) return;
+    setVaultBusy(true);
+    try {
+      await unlockVault(vaultKeyFromForm());
+      setVaultForm((f) => ({ ...f, value: '', confirm: '' }));
+    } catch (err) {
+      toast.error(t('settings.vaultUnlockFailed', { error: String(err) }));
+    } finally {
+      setVaultBusy(false);
+    }
+  }, [vaultForm, vaultKeyFromForm, unlockVault, t]);
+
+  const handleSetMasterKey = useCallback(async () => {
+    if (!vaultForm.value) return;
+    if (vaultForm.keyType === 'passphrase' && vaultForm.value !== vaultForm.confirm) {
+      toast.error(t('settings.vaultPassphraseMismatch'));
+      return;
+    }
+    setVaultBusy(true);
+    try {
+      await setMasterKey(vaultKeyFromForm());
+      setVaultForm((f) => ({ ...f, value: '', confirm: '' }));
+      toast.success(t('settings.vaultKeySet'));
+    } catch (err) {
+      toast.error(t('settings.vaultKeyFailed', { error: String(err) }));
+    } finally {
+      setVaultBusy(false);
+    }
+  }, [vaultForm, vaultKeyFromForm, setMasterKey, t]);
+
+  // Header rule form helpers
+  const resetHeaderRuleForm = useCallback(() => {
+    setShowHeaderRuleForm(false);
+    setEditingHeaderRule(null);
+    setHeaderRuleForm({ domain: '', headers: '', notes: '', enabled: true });
+  }, []);
+
+  const handleEditHeaderRule = useCallback((rule: HeaderRule) => {
+    setEditingHeaderRule(rule);
+    setHeaderRuleForm({
+      domain: rule.domain,
+      headers: formatHeaders(rule.headers),
+      notes: rule.notes || '',
+      enabled: rule.enabled,
+    });
+    setShowHeaderRuleForm(true);
+  }, []);
+
+  const headerRuleFormComplete = Boolean(
+    headerRuleForm.domain.trim() && parseHeaders(headerRuleForm.headers).length > 0
+  );
+
+  const handleSaveHeaderRule = useCallback(async () => {
+    if (!headerRuleFormComplete) return;
+    try {
+      if (editingHeaderRule) {
+        await updateHeaderRule({
+          ...editingHeaderRule,
+          domain: headerRuleForm.domain.trim(),
+          headers: parseHeaders(headerRuleForm.headers),
+          notes: headerRuleForm.notes || null,
+          enabled: headerRuleForm.enabled,
+        });
+      } else {
+        await addHeaderRule({
+          id: crypto.randomUUID(),
+          domain: headerRuleForm.domain.trim(),
+          headers: parseHeaders(headerRuleForm.headers),
+          enabled: headerRuleForm.enabled,
+          created_at: new Date().toISOString(),
+          notes: headerRuleForm.notes || null,
+        });
+      }
+      resetHeaderRuleForm();
+    } catch (err) {
+      console.error('Failed to save header rule:', err);
+    }
+  }, [headerRuleForm, headerRuleFormComplete, editingHeaderRule, addHeaderRule, updateHeaderRule, resetHeaderRuleForm]);
 
   const togglePasswordVisibility = useCallback((id: string) => {
     setShowPasswords((prev) => {
@@ -359,6 +570,9 @@ export function SettingsDialog() {
                     }
                     className="w-24"
                   />
+                  <p className="text-xs text-muted-foreground">
+                    {t('settings.defaultSegmentsHint')}
+                  </p>
                 </div>
                 <div className="space-y-2">
                   <Label htmlFor="speedLimit">
@@ -386,6 +600,29 @@ export function SettingsDialog() {
                     })}
                   </p>
                 </div>
+                <div className="space-y-2">
+                  <Label htmlFor="maxConnectionsPerHost">
+                    {t('settings.maxConnectionsPerHost')}
+                  </Label>
+                  <Input
+                    id="maxConnectionsPerHost"
+                    type="number"
+                    min={0}
+                    max={64}
+                    value={localSettings.host_limits?.max_connections_per_host ?? 8}
+                    onChange={(e) =>
+                      handleChange('host_limits', {
+                        rules: [],
+                        ...localSettings.host_limits,
+                        max_connections_per_host: Math.max(0, parseInt(e.target.value) || 0),
+                      })
+                    }
+                    className="w-24"
+                  />
+                  <p className="text-xs text-muted-foreground">
+                    {t('settings.maxConnectionsPerHostHint')}
+                  </p>
+                </div>
               </div>
             </div>
 
@@ -457,6 +694,7 @@ export function SettingsDialog() {
                     { value: 'appdata', label: t('settings.tempStorage.appdata.label'), description: t('settings.tempStorage.appdata.desc') },
                     { value: 'destination', label: t('settings.tempStorage.destination.label'), description: t('settings.tempStorage.destination.desc') },
                     { value: 'custom', label: t('settings.tempStorage.custom.label'), description: t('settings.tempStorage.custom.desc') },
+                    { value: 'direct', label: t('settings.tempStorage.direct.label'), description: t('settings.tempStorage.direct.desc') },
                   ].map((option) => {
                     const currentMode = localSettings.temp_storage?.mode || 'auto';
                     const selected = currentMode === option.value;
@@ -682,6 +920,23 @@ export function SettingsDialog() {
                     }
                   />
                 </div>
+                <div className="flex items-center justify-between">
+                  <div className="space-y-0.5">
+                    <Label htmlFor="ffmpegRemuxFallback" className="cursor-pointer">
+                      {t('settings.ffmpegRemuxFallback.label')}
+                    </Label>
+                    <p className="text-xs text-muted-foreground">
+                      {t('settings.ffmpegRemuxFallback.hint')}
+                    </p>
+                  </div>
+                  <Switch
+                    id="ffmpegRemuxFallback"
+                    checked={localSettings.ffmpeg_remux_fallback ?? false}
+                    onCheckedChange={(checked: boolean) =>
+                      handleChange('ffmpeg_remux_fallback', checked)
+                    }
+                  />
+                </div>
               </div>
             </div>
           </div>
@@ -1377,13 +1632,38 @@ export function SettingsDialog() {
                           <SelectItem value="https">HTTPS</SelectItem>
                           <SelectItem value="http">HTTP</SelectItem>
                           <SelectItem value="ftp">FTP</SelectItem>
+                          <SelectItem value="sftp">SFTP</SelectItem>
                         </SelectContent>
                       </Select>
                     </div>
                   </div>
+                  {credentialForm.protocol === 'ftp' && (
+                    <p className="text-xs text-muted-foreground">{t('settings.ftpProtocolHint')}</p>
+                  )}
+                  {credentialIsHttp && (
+                    <div className="space-y-1.5">
+                      <Label htmlFor="credAuthScheme" className="text-xs">{t('settings.authScheme')}</Label>
+                      <Select
+                        value={credentialForm.authScheme}
+                        onValueChange={(value) => setCredentialForm({ ...credentialForm, authScheme: value as AuthScheme })}
+                      >
+                        <SelectTrigger id="credAuthScheme" className="h-8 text-sm">
+                          <SelectValue />
+                        </SelectTrigger>
+                        <SelectContent>
+                          <SelectItem value="basic">{t('settings.authSchemes.basic')}</SelectItem>
+                          <SelectItem value="digest">{t('settings.authSchemes.digest')}</SelectItem>
+                          <SelectItem value="bearer">{t('settings.authSchemes.bearer')}</SelectItem>
+                          <SelectItem value="form">{t('settings.authSchemes.form')}</SelectItem>
+                        </SelectContent>
+                      </Select>
+                    </div>
+                  )}
                   <div className="grid grid-cols-2 gap-3">
                     <div className="space-y-1.5">
-                      <Label htmlFor="credUsername" className="text-xs">{t('settings.username')}</Label>
+                      <Label htmlFor="credUsername" className="text-xs">
+                        {credentialIsBearer ? t('settings.usernameOptional') : t('settings.username')}
+                      </Label>
                       <Input
                         id="credUsername"
                         placeholder={t('settings.usernamePlaceholder')}
@@ -1393,12 +1673,14 @@ export function SettingsDialog() {
                       />
                     </div>
                     <div className="space-y-1.5">
-                      <Label htmlFor="credPassword" className="text-xs">{t('settings.password')}</Label>
+                      <Label htmlFor="credPassword" className="text-xs">
+                        {credentialIsBearer ? t('settings.token') : t('settings.password')}
+                      </Label>
                       <div className="relative">
                         <Input
                           id="credPassword"
                           type={showPasswords.has('form') ? 'text' : 'password'}
-                          placeholder={t('settings.passwordPlaceholder')}
+                          placeholder={credentialIsBearer ? t('settings.tokenPlaceholder') : t('settings.passwordPlaceholder')}
                           value={credentialForm.password}
                           onChange={(e) => setCredentialForm({ ...credentialForm, password: e.target.value })}
                           className="h-8 text-sm pr-8"
@@ -1413,6 +1695,121 @@ export function SettingsDialog() {
                       </div>
                     </div>
                   </div>
+                  {credentialIsForm && (
+                    <div className="space-y-3">
+                      <div className="grid grid-cols-[1fr_6rem] gap-3">
+                        <div className="space-y-1.5">
+                          <Label htmlFor="credLoginUrl" className="text-xs">{t('settings.loginUrl')}</Label>
+                          <Input
+                            id="credLoginUrl"
+                            placeholder={t('settings.loginUrlPlaceholder')}
+                            value={credentialForm.loginUrl}
+                            onChange={(e) => setCredentialForm({ ...credentialForm, loginUrl: e.target.value })}
+                            className="h-8 text-sm"
+                          />
+                        </div>
+                        <div className="space-y-1.5">
+                          <Label htmlFor="credLoginMethod" className="text-xs">{t('settings.loginMethod')}</Label>
+                          <Select
+                            value={credentialForm.loginMethod}
+                            onValueChange={(value) => setCredentialForm({ ...credentialForm, loginMethod: value })}
+                          >
+                            <SelectTrigger id="credLoginMethod" className="h-8 text-sm">
+                              <SelectValue />
+                            </SelectTrigger>
+                            <SelectContent>
+                              <SelectItem value="POST">POST</SelectItem>
+                              <SelectItem value="GET">GET</SelectItem>
+                            </SelectContent>
+                          </Select>
+                        </div>
+                      </div>
+                      <div className="space-y-1.5">
+                        <Label htmlFor="credLoginFields" className="text-xs">{t('settings.loginFields')}</Label>
+                        <textarea
+                          id="credLoginFields"
+                          placeholder={t('settings.loginFieldsPlaceholder')}
+                          value={credentialForm.loginFields}
+                          onChange={(e) => setCredentialForm({ ...credentialForm, loginFields: e.target.value })}
+                          rows={3}
+                          spellCheck={false}
+                          className="w-full resize-none rounded-md border border-input bg-background px-3 py-2 text-sm font-mono ring-offset-background placeholder:text-muted-foreground focus-visible:outline-none focus-visible:ring-2 focus-visible:ring-ring focus-visible:ring-offset-2"
+                        />
+                      </div>
+                      <div className="grid grid-cols-2 gap-3">
+                        <div className="space-y-1.5">
+                          <Label htmlFor="credLoginCheck" className="text-xs">{t('settings.loginCheck')}</Label>
+                          <Select
+                            value={credentialForm.loginCheck}
+                            onValueChange={(value) => setCredentialForm({ ...credentialForm, loginCheck: value as LoginCheck['type'] })}
+                          >
+                            <SelectTrigger id="credLoginCheck" className="h-8 text-sm">
+                              <SelectValue />
+                            </SelectTrigger>
+                            <SelectContent>
+                              <SelectItem value="any_cookie">{t('settings.loginChecks.any_cookie')}</SelectItem>
+                              <SelectItem value="cookie">{t('settings.loginChecks.cookie')}</SelectItem>
+                              <SelectItem value="body_contains">{t('settings.loginChecks.body_contains')}</SelectItem>
+                              <SelectItem value="body_lacks">{t('settings.loginChecks.body_lacks')}</SelectItem>
+                            </SelectContent>
+                          </Select>
+                        </div>
+                        <div className="space-y-1.5">
+                          <Label htmlFor="credLoginCheckValue" className="text-xs">&nbsp;</Label>
+                          <Input
+                            id="credLoginCheckValue"
+                            placeholder={t('settings.loginCheckValuePlaceholder')}
+                            value={credentialForm.loginCheckValue}
+                            onChange={(e) => setCredentialForm({ ...credentialForm, loginCheckValue: e.target.value })}
+                            disabled={credentialForm.loginCheck === 'any_cookie'}
+                            className="h-8 text-sm"
+                          />
+                        </div>
+                      </div>
+                      <p className="text-xs text-muted-foreground">{t('settings.loginFormHint')}</p>
+                    </div>
+                  )}
+                  {credentialForm.protocol === 'sftp' && (
+                    <div className="grid grid-cols-2 gap-3">
+                      <div className="space-y-1.5">
+                        <Label htmlFor="credSshKeyPath" className="text-xs">{t('settings.sshKeyPath')}</Label>
+                        <Input
+                          id="credSshKeyPath"
+                          placeholder={t('settings.sshKeyPathPlaceholder')}
+                          value={credentialForm.sshKeyPath}
+                          onChange={(e) => setCredentialForm({ ...credentialForm, sshKeyPath: e.target.value })}
+                          className="h-8 text-sm"
+                        />
+                      </div>
+                      <div className="space-y-1.5">
+                        <Label htmlFor="credSshKeyPassphrase" className="text-xs">{t('settings.sshKeyPassphrase')}</Label>
+                        <Input
+                          id="credSshKeyPassphrase"
+                          type={showPasswords.has('form') ? 'text' : 'password'}
+                          placeholder={t('settings.sshKeyPassphrasePlaceholder')}
+                          value={credentialForm.sshKeyPassphrase}
+                          onChange={(e) => setCredentialForm({ ...credentialForm, sshKeyPassphrase: e.target.value })}
+                          disabled={!credentialForm.sshKeyPath}
+                          className="h-8 text-sm"
+                        />
+                      </div>
+                      <p className="col-span-2 text-xs text-muted-foreground">{t('settings.sshPasswordOrKey')}</p>
+                    </div>
+                  )}
+                  <div className="space-y-1.5">
+                    <Label htmlFor="credDailyQuota" className="text-xs">{t('settings.dailyQuota')}</Label>
+                    <Input
+                      id="credDailyQuota"
+                      type="number"
+                      min={0}
+                      step="any"
+                      placeholder={t('settings.dailyQuotaPlaceholder')}
+                      value={credentialForm.dailyQuotaGb}
+                      onChange={(e) => setCredentialForm({ ...credentialForm, dailyQuotaGb: e.target.value })}
+                      className="h-8 text-sm"
+                    />
+                    <p className="text-xs text-muted-foreground">{t('settings.dailyQuotaHint')}</p>
+                  </div>
                   <div className="space-y-1.5">
                     <Label htmlFor="credNotes" className="text-xs">{t('settings.notesOptional')}</Label>
                     <Input
@@ -1436,10 +1833,21 @@ export function SettingsDialog() {
                       <Button variant="outline" size="sm" onClick={resetCredentialForm}>
                         {t('common.cancel')}
                       </Button>
+                      {credentialIsForm && (
+                        <Button
+                          variant="outline"
+                          size="sm"
+                          onClick={handleTestLogin}
+                          disabled={!credentialFormComplete || testingLogin}
+                        >
+                          {testingLogin && <Loader2 className="h-3.5 w-3.5 mr-1.5 animate-spin" />}
+                          {t('settings.testLogin')}
+                        </Button>
+                      )}
                       <Button
                         size="sm"
                         onClick={handleSaveCredential}
-                        disabled={!credentialForm.domain || !credentialForm.username || !credentialForm.password}
+                        disabled={!credentialFormComplete}
                       >
                         {editingCredential ? t('common.update') : t('common.save')}
                       </Button>
@@ -1450,7 +1858,13 @@ export function SettingsDialog() {
 
               {/* Credentials List */}
               <div className="space-y-2">
-                {credentials.length === 0 ? (
+                {vaultStatus === 'locked' ? (
+                  <div className="text-center py-8 text-muted-foreground">
+                    <Lock className="h-8 w-8 mx-auto mb-2 opacity-40" />
+                    <p className="text-sm">{t('settings.vaultLockedTitle')}</p>
+                    <p className="text-xs mt-1">{t('settings.vaultLockedHint')}</p>
+                  </div>
+                ) : credentials.length === 0 ? (
                   <div className="text-center py-8 text-muted-foreground">
                     <KeyRound className="h-8 w-8 mx-auto mb-2 opacity-40" />
                     <p className="text-sm">{t('settings.noLoginsTitle')}</p>
@@ -1470,6 +1884,11 @@ export function SettingsDialog() {
                           <span className="text-[10px] text-muted-foreground uppercase px-1.5 py-0.5 rounded bg-muted">
                             {cred.protocol}
                           </span>
+                          {cred.auth_scheme && cred.auth_scheme !== 'basic' && (
+                            <span className="text-[10px] text-muted-foreground px-1.5 py-0.5 rounded bg-muted">
+                              {t(`settings.authSchemes.${cred.auth_scheme}`)}
+                            </span>
+                          )}
                           {!cred.enabled && (
                             <span className="text-[10px] text-muted-foreground px-1.5 py-0.5 rounded bg-muted">
                               {t('settings.disabled')}
@@ -1500,6 +1919,21 @@ export function SettingsDialog() {
                             {t('settings.lastUsed', { date: new Date(cred.last_used_at).toLocaleDateString() })}
                           </p>
                         )}
+                        {(cred.daily_quota || !!cred.used_today) && (
+                          <p className="text-[10px] text-muted-foreground mt-0.5">
+                            {cred.daily_quota
+                              ? t('settings.usedTodayOfQuota', { used: formatBytes(cred.used_today || 0), quota: formatBytes(cred.daily_quota) })
+                              : t('settings.usedToday', { used: formatBytes(cred.used_today || 0) })}
+                          </p>
+                        )}
+                        {!!cred.failure_count && cred.last_failed_at && (
+                          <p className="text-[10px] text-muted-foreground mt-0.5">
+                            {t('settings.refusedCount', {
+                              count: cred.failure_count,
+                              date: new Date(cred.last_failed_at).toLocaleDateString(),
+                            })}
+                          </p>
+                        )}
                       </div>
                       <div className="flex items-center gap-1 ml-2 flex-shrink-0">
                         <Button
@@ -1524,6 +1958,219 @@ export function SettingsDialog() {
                 )}
               </div>
             </div>
+
+            <Separator />
+
+            {/* Saved Password Encryption */}
+            <div className="space-y-4">
+              <div className="flex items-center justify-between">
+                <h3 className="text-sm font-medium flex items-center gap-2">
+                  {vaultStatus === 'unlocked' ? <LockOpen className="h-4 w-4" /> : <Lock className="h-4 w-4" />}
+                  {t('settings.vaultTitle')}
+                </h3>
+                {vaultStatus === 'unlocked' && (
+                  <Button variant="outline" size="sm" onClick={() => lockVault()}>
+                    <Lock className="h-4 w-4 mr-1" />
+                    {t('settings.vaultLock')}
+                  </Button>
+                )}
+              </div>
+              <p className="text-xs text-muted-foreground">
+                {t(`settings.vaultStatus.${vaultStatus}`)}
+              </p>
+
+              <div className="grid grid-cols-[140px_1fr] gap-2">
+                <Select
+                  value={vaultForm.keyType}
+                  onValueChange={(value) => setVaultForm({ keyType: value as VaultKey['type'], value: '', confirm: '' })}
+                >
+                  <SelectTrigger className="h-8 text-xs">
+                    <SelectValue />
+                  </SelectTrigger>
+                  <SelectContent>
+                    <SelectItem value="passphrase">{t('settings.vaultPassphrase')}</SelectItem>
+                    <SelectItem value="keyfile">{t('settings.vaultKeyfile')}</SelectItem>
+                  </SelectContent>
+                </Select>
+                <Input
+                  type={vaultForm.keyType === 'passphrase' ? 'password' : 'text'}
+                  value={vaultForm.value}
+                  onChange={(e) => setVaultForm({ ...vaultForm, value: e.target.value })}
+                  placeholder={vaultForm.keyType === 'passphrase'
+                    ? (vaultStatus === 'locked' ? t('settings.vaultPassphrase') : t('settings.vaultNewPassphrase'))
+                    : t('settings.vaultKeyfilePlaceholder')}
+                  className="h-8 text-xs"
+                />
+              </div>
+              {vaultStatus !== 'locked' && vaultForm.keyType === 'passphrase' && (
+                <Input
+                  type="password"
+                  value={vaultForm.confirm}
+                  onChange={(e) => setVaultForm({ ...vaultForm, confirm: e.target.value })}
+                  placeholder={t('settings.vaultConfirmPassphrase')}
+                  className="h-8 text-xs"
+                />
+              )}
+              {vaultStatus !== 'locked' && vaultForm.keyType === 'keyfile' && (
+                <p className="text-[11px] text-muted-foreground">{t('settings.vaultKeyfileHint')}</p>
+              )}
+              <div className="flex justify-end">
+                {vaultStatus === 'locked' ? (
+                  <Button size="sm" disabled={vaultBusy || !vaultForm.value} onClick={handleUnlockVault}>
+                    {vaultBusy ? <Loader2 className="h-4 w-4 mr-1 animate-spin" /> : <LockOpen className="h-4 w-4 mr-1" />}
+                    {t('settings.vaultUnlock')}
+                  </Button>
+                ) : (
+                  <Button size="sm" disabled={vaultBusy || !vaultForm.value} onClick={handleSetMasterKey}>
+                    {vaultBusy && <Loader2 className="h-4 w-4 mr-1 animate-spin" />}
+                    {vaultStatus === 'unlocked' ? t('settings.vaultChangeKey') : t('settings.vaultEnable')}
+                  </Button>
+                )}
+              </div>
+            </div>
+
+            <Separator />
+
+            <div className="space-y-4">
+              <div className="flex items-center justify-between">
+                <h3 className="text-sm font-medium flex items-center gap-2">
+                  <Braces className="h-4 w-4" />
+                  {t('settings.headerRulesTitle')}
+                </h3>
+                <Button
+                  variant="outline"
+                  size="sm"
+                  onClick={() => {
+                    resetHeaderRuleForm();
+                    setShowHeaderRuleForm(true);
+                  }}
+                >
+                  <Plus className="h-4 w-4 mr-1" />
+                  {t('settings.addHeaderRule')}
+                </Button>
+              </div>
+              <p className="text-xs text-muted-foreground">
+                {t('settings.headerRulesDesc')}
+              </p>
+
+              {/* Header Rule Form (Add/Edit) */}
+              {showHeaderRuleForm && (
+                <div className="border rounded-lg p-4 space-y-3 bg-muted/30">
+                  <h4 className="text-sm font-medium">
+                    {editingHeaderRule ? t('settings.editHeaderRule') : t('settings.addNewHeaderRule')}
+                  </h4>
+                  <div className="space-y-1.5">
+                    <Label htmlFor="headerRuleDomain" className="text-xs">{t('settings.domain')}</Label>
+                    <Input
+                      id="headerRuleDomain"
+                      placeholder={t('settings.domainPlaceholder')}
+                      value={headerRuleForm.domain}
+                      onChange={(e) => setHeaderRuleForm({ ...headerRuleForm, domain: e.target.value })}
+                      className="h-8 text-sm"
+                    />
+                  </div>
+                  <div className="space-y-1.5">
+                    <Label htmlFor="headerRuleHeaders" className="text-xs">{t('settings.requestHeaders')}</Label>
+                    <textarea
+                      id="headerRuleHeaders"
+                      placeholder={t('settings.requestHeadersPlaceholder')}
+                      value={headerRuleForm.headers}
+                      onChange={(e) => setHeaderRuleForm({ ...headerRuleForm, headers: e.target.value })}
+                      rows={3}
+                      spellCheck={false}
+                      className="w-full resize-none rounded-md border border-input bg-background px-3 py-2 text-sm font-mono ring-offset-background placeholder:text-muted-foreground focus-visible:outline-none focus-visible:ring-2 focus-visible:ring-ring focus-visible:ring-offset-2"
+                    />
+                  </div>
+                  <div className="space-y-1.5">
+                    <Label htmlFor="headerRuleNotes" className="text-xs">{t('settings.notesOptional')}</Label>
+                    <Input
+                      id="headerRuleNotes"
+                      placeholder={t('settings.notesPlaceholder')}
+                      value={headerRuleForm.notes}
+                      onChange={(e) => setHeaderRuleForm({ ...headerRuleForm, notes: e.target.value })}
+                      className="h-8 text-sm"
+                    />
+                  </div>
+                  <div className="flex items-center justify-between">
+                    <div className="flex items-center gap-2">
+                      <Switch
+                        id="headerRuleEnabled"
+                        checked={headerRuleForm.enabled}
+                        onCheckedChange={(checked: boolean) => setHeaderRuleForm({ ...headerRuleForm, enabled: checked })}
+                      />
+                      <Label htmlFor="headerRuleEnabled" className="text-xs cursor-pointer">{t('settings.enabled')}</Label>
+                    </div>
+                    <div className="flex gap-2">
+                      <Button variant="outline" size="sm" onClick={resetHeaderRuleForm}>
+                        {t('common.cancel')}
+                      </Button>
+                      <Button
+                        size="sm"
+                        onClick={handleSaveHeaderRule}
+                        disabled={!headerRuleFormComplete}
+                      >
+                        {editingHeaderRule ? t('common.update') : t('common.save')}
+                      </Button>
+                    </div>
+                  </div>
+                </div>
+              )}
+
+              {/* Header Rules List */}
+              <div className="space-y-2">
+                {headerRules.length === 0 ? (
+                  <div className="text-center py-8 text-muted-foreground">
+                    <Braces className="h-8 w-8 mx-auto mb-2 opacity-40" />
+                    <p className="text-sm">{t('settings.noHeaderRulesTitle')}</p>
+                    <p className="text-xs mt-1">{t('settings.noHeaderRulesHint')}</p>
+                  </div>
+                ) : (
+                  headerRules.map((rule) => (
+                    <div
+                      key={rule.id}
+                      className={`flex items-center justify-between p-3 rounded-lg border ${
+                        rule.enabled ? 'bg-background' : 'bg-muted/50 opacity-60'
+                      }`}
+                    >
+                      <div className="flex-1 min-w-0">
+                        <div className="flex items-center gap-2">
+                          <span className="text-sm font-medium truncate">{rule.domain}</span>
+                          {!rule.enabled && (
+                            <span className="text-[10px] text-muted-foreground px-1.5 py-0.5 rounded bg-muted">
+                              {t('settings.disabled')}
+                            </span>
+                          )}
+                        </div>
+                        <p className="text-xs text-muted-foreground font-mono truncate mt-0.5">
+                          {rule.headers.map((h) => h.name).join(', ')}
+                        </p>
+                        {rule.notes && (
+                          <p className="text-[11px] text-muted-foreground mt-0.5">{rule.notes}</p>
+                        )}
+                      </div>
+                      <div className="flex items-center gap-1 ml-2 flex-shrink-0">
+                        <Button
+                          variant="ghost"
+                          size="icon"
+                          className="h-7 w-7"
+                          onClick={() => handleEditHeaderRule(rule)}
+                        >
+                          <Pencil className="h-3.5 w-3.5" />
+                        </Button>
+                        <Button
+                          variant="ghost"
+                          size="icon"
+                          className="h-7 w-7 text-destructive hover:text-destructive"
+                          onClick={() => deleteHeaderRule(rule.id)}
+                        >
+                          <Trash2 className="h-3.5 w-3.5" />
+                        </Button>
+                      </div>
+                    </div>
+                  ))
+                )}
+              </div>
+            </div>
           </div>
         );
 
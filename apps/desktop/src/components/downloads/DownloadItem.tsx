@@ -24,6 +24,7 @@ import {
   Gauge,
   Zap,
   CheckSquare,
+  KeyRound,
 } from "lucide-react";
 import { Progress } from "@/components/ui/progress";
 import { Button } from "@/components/ui/button";
@@ -54,6 +55,7 @@ import { DeleteConfirmDialog } from "@/components/dialogs/DeleteConfirmDialog";
 import { useDownloadStore } from "@/stores/downloads";
 import { useQueueStore, useQueuesArray } from "@/stores/queues";
 import { useCategoryStore } from "@/stores/categories";
+import { useCredentialsStore } from "@/stores/credentials";
 import { getCategoryIcon } from "@/lib/categoryIcons";
 import { formatBytes, formatSpeed, formatDuration } from "@/lib/utils";
 import { cn } from "@/lib/utils";
@@ -251,6 +253,23 @@ export function DownloadItem({ download, isFocused = false }: DownloadItemProps)
     }
   }, [download.url, download.destination, download.queue_id, download.id, updateStatus, t]);
 
+  // Reopen the login prompt for a download the server refused
+  const handleLogIn = useCallback((e?: React.MouseEvent) => {
+    e?.stopPropagation();
+    let domain = "";
+    try {
+      domain = new URL(download.url).hostname;
+    } catch {
+      // Leave the domain empty; the prompt still resumes the download
+    }
+    useCredentialsStore.getState().setPendingRequest({
+      downloadId: download.id,
+      domain,
+      url: download.url,
+      statusCode: 401,
+    });
+  }, [download.id, download.url]);
+
   const handleMoveToQueue = useCallback((newQueueId: string) => {
     moveToQueue([download.id], newQueueId);
     toast.success(t('toasts.movedToQueue'));
@@ -322,6 +341,18 @@ export function DownloadItem({ download, isFocused = false }: DownloadItemProps)
             <RefreshCw className="h-4 w-4" />
           </Button>
         );
+      case "awaitingauth":
+        return (
+          <Button
+            variant="ghost"
+            size="icon"
+            className="h-8 w-8 shrink-0 text-yellow-500 hover:text-yellow-600 hover:bg-yellow-100 dark:hover:bg-yellow-900/20"
+            onClick={handleLogIn}
+            title={t('downloadItem.logIn')}
+          >
+            <KeyRound className="h-4 w-4" />
+          </Button>
+        );
       case "cancelled":
         return (
           <Button
@@ -410,6 +441,13 @@ export function DownloadItem({ download, isFocused = false }: DownloadItemProps)
           </MenuItem>
         )}
 
+        {download.status === "awaitingauth" && (
+          <MenuItem onClick={handleLogIn}>
+            <KeyRound className="h-4 w-4 mr-2" />
+            {t('downloadItem.logIn')}
+          </MenuItem>
+        )}
+
         {download.status === "cancelled" && (
           <MenuItem onClick={handleRedownload}>
             <RefreshCw className="h-4 w-4 mr-2" />
@@ -905,6 +943,8 @@ function StatusIcon({ status }: { status: DownloadStatus }) {
       return <XCircle className="h-8 w-8 text-destructive" />;
     case "paused":
       return <Pause className="h-8 w-8 text-yellow-500" />;
+    case "awaitingauth":
+      return <KeyRound className="h-8 w-8 text-yellow-500" />;
     case "downloading":
       // Simple spinner - lightweight CSS animation
       return (
@@ -929,6 +969,7 @@ function StatusBadge({ status }: { status: DownloadStatus }) {
     queued: "bg-muted text-muted-foreground",
     cancelled: "bg-muted text-muted-foreground",
     deleted: "bg-destructive/10 text-destructive",
+    awaitingauth: "bg-yellow-500/10 text-yellow-500",
   };
 
   const labels: Record<DownloadStatus, string> = {
@@ -940,6 +981,7 @@ function StatusBadge({ status }: { status: DownloadStatus }) {
     queued: t('status.queued'),
     cancelled: t('status.cancelled'),
     deleted: t('status.deleted'),
+    awaitingauth: t('status.awaitingauth'),
   };
 
   return (
@@ -9,7 +9,7 @@ import { useDownloadStore } from "@/stores/downloads";
 import { useQueueStore } from "@/stores/queues";
 import { useUIStore } from "@/stores/ui";
 import { useCredentialsStore } from "@/stores/credentials";
-import { CoreEvent } from "@/types";
+import { CoreEvent, RecordingOptions } from "@/types";
 import {
   notifyDownloadComplete,
   notifyDownloadFailed,
@@ -48,13 +48,18 @@ let pendingDropUrls: string[] = [];
 let pendingClipboardUrls: string[] = [];
 // Store for cookies passed from browser extension
 let pendingCookies: string | undefined = undefined;
+// Store for an expected checksum passed from browser extension
+let pendingChecksum: string | undefined = undefined;
 // Store for media metadata from browser extension (HLS/DASH streaming)
 let pendingMediaMeta: {
   protocol: string;
   master_url: string;
   page_title?: string;
   variant_index?: number;
+  audio_track?: number;
+  subtitle_track?: number;
   referrer?: string;
+  recording?: RecordingOptions | null;
 } | undefined = undefined;
 
 export function getPendingDropUrls(): string[] {
@@ -87,6 +92,16 @@ export function setPendingCookies(cookies: string | undefined) {
   pendingCookies = cookies;
 }
 
+export function getPendingChecksum(): string | undefined {
+  const checksum = pendingChecksum;
+  pendingChecksum = undefined;
+  return checksum;
+}
+
+export function setPendingChecksum(checksum: string | undefined) {
+  pendingChecksum = checksum;
+}
+
 export function getPendingMediaMeta() {
   const meta = pendingMediaMeta;
   pendingMediaMeta = undefined;
@@ -288,6 +303,56 @@ export function setupEventListeners(): () => void {
     }
   }));
 
+  // Listen for checksum results; mismatches also fail the download, which
+  // already raises a toast carrying the expected and actual digests
+  registerListener(listen<CoreEvent>("checksum-verified", (event) => {
+    if (isCleanedUp) return;
+    const data = event.payload;
+    if (data.type === "ChecksumVerified" && data.payload.matched) {
+      const download = useDownloadStore.getState().downloads.get(data.payload.id);
+      const filename = download?.filename || i18n.t('toasts.unknownFile');
+      toast.success(i18n.t('toasts.checksumVerified', { filename }), {
+        description: `${data.payload.algorithm}: ${data.payload.actual}`,
+      });
+    }
+  }));
+
+  // Listen for files that changed on the server while a download was paused;
+  // the partial data is stale, so offer a restart or cancel
+  registerListener(listen<CoreEvent>("content-changed", (event) => {
+    if (isCleanedUp) return;
+    const data = event.payload;
+    if (data.type === "ContentChanged") {
+      const id = data.payload.id;
+      const download = useDownloadStore.getState().downloads.get(id);
+      const filename = download?.filename || i18n.t('toasts.unknownFile');
+      toast.warning(i18n.t('toasts.contentChanged', { filename }), {
+        description: i18n.t('toasts.contentChangedDesc'),
+        duration: Infinity,
+        action: {
+          label: i18n.t('common.restart'),
+          onClick: () => {
+            invoke("restart_download", { id })
+              .then(() => toast.success(i18n.t('toasts.downloadRestarted')))
+              .catch((err) => {
+                console.error("Failed to restart download:", err);
+                toast.error(i18n.t('toasts.restartFailed'));
+              });
+          },
+        },
+        cancel: {
+          label: i18n.t('common.cancel'),
+          onClick: () => {
+            invoke("cancel_download", { id }).catch((err) => {
+              console.error("Failed to cancel download:", err);
+              toast.error(i18n.t('toasts.cancelFailed'));
+            });
+          },
+        },
+      });
+    }
+  }));
+
   // Listen for credential required events (401/403 from server)
   registerListener(listen<CoreEvent>("credential-required", (event) => {
     if (isCleanedUp) return;
@@ -307,9 +372,9 @@ export function setupEventListeners(): () => void {
   // listener here would never fire and only invite confusion.
 
   // Listen for show-new-download-dialog event (from deep links / extension)
-  // Payload can be a plain URL string (legacy) or structured object with url, referrer, filename, cookies
+  // Payload can be a plain URL string (legacy) or structured object with url, referrer, filename, cookies, checksum
   // For HLS/DASH media, also includes media_protocol, media_master_url, etc.
-  registerListener(listen<string | { url: string; referrer?: string; filename?: string; cookies?: string; media_protocol?: string; media_master_url?: string; media_page_title?: string; variant_index?: number } | null>(
+  registerListener(listen<string | { url: string; referrer?: string; filename?: string; cookies?: string; checksum?: string; media_protocol?: string; media_master_url?: string; media_page_title?: string; variant_index?: number; audio_track?: number; subtitle_track?: number; recording?: RecordingOptions | null } | null>(
     "show-new-download-dialog",
     (event) => {
       if (isCleanedUp) return;
@@ -321,11 +386,13 @@ export function setupEventListeners(): () => void {
           // Legacy: plain URL string
           setPendingClipboardUrls([payload]);
           setPendingCookies(undefined);
+          setPendingChecksum(undefined);
           setPendingMediaMeta(undefined);
         } else if (typeof payload === 'object' && payload.url) {
           // Structured payload from browser extension
           setPendingClipboardUrls([payload.url]);
           setPendingCookies(payload.cookies);
+          setPendingChecksum(payload.checksum);
           // Store media metadata if this is an HLS/DASH stream
           if (payload.media_protocol && payload.media_protocol !== 'direct') {
             setPendingMediaMeta({
@@ -333,7 +400,10 @@ export function setupEventListeners(): () => void {
               master_url: payload.media_master_url || payload.url,
               page_title: payload.media_page_title,
               variant_index: payload.variant_index,
+              audio_track: payload.audio_track,
+              subtitle_track: payload.subtitle_track,
               referrer: payload.referrer,
+              recording: payload.recording,
             });
           } else {
             setPendingMediaMeta(undefined);
@@ -13,8 +13,8 @@ import { parseUrls } from '@/lib/utils';
 // (XML namespaces, schema declarations, etc.).
 const JUNK_MARKERS = ['w3.org/', 'xmlns', 'schema.org'];
 
-function isHttpUrl(value: string): boolean {
-  return value.startsWith('http://') || value.startsWith('https://');
+function isDownloadableUrl(value: string): boolean {
+  return /^(?:https?|ftpe?s?|sftp):\/\//i.test(value);
 }
 
 /** De-dupe while preserving order and drop non-downloadable/junk URLs. */
@@ -23,7 +23,7 @@ export function cleanUrls(urls: string[]): string[] {
   const out: string[] = [];
   for (const raw of urls) {
     const url = raw.trim();
-    if (!url || !isHttpUrl(url)) continue;
+    if (!url || !isDownloadableUrl(url)) continue;
     if (JUNK_MARKERS.some((marker) => url.includes(marker))) continue;
     if (seen.has(url)) continue;
     seen.add(url);
@@ -77,11 +77,11 @@ export function extractUrlsFromDataTransfer(dt: DataTransfer | null): string[] {
       // the first absolute anchor in the fragment.
       const base =
         doc.querySelector('base[href]')?.getAttribute('href') ||
-        uriListLines.find(isHttpUrl) ||
-        anchors.find(isHttpUrl);
+        uriListLines.find(isDownloadableUrl) ||
+        anchors.find(isDownloadableUrl);
 
       for (const href of anchors) {
-        if (isHttpUrl(href)) {
+        if (isDownloadableUrl(href)) {
           urls.push(href);
         } else if (base) {
           try {
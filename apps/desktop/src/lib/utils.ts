@@ -1,5 +1,6 @@
 import { type ClassValue, clsx } from "clsx";
 import { twMerge } from "tailwind-merge";
+import type { FormField, RequestHeader } from "@/types";
 
 /**
  * Merge Tailwind classes with clsx
@@ -76,11 +77,55 @@ export function generateId(): string {
  * Parse URLs from text
  */
 export function parseUrls(text: string): string[] {
-  const urlRegex = /https?:\/\/[^\s<>"{}|\\^`[\]]+/gi;
+  const urlRegex = /(?:https?|ftpe?s?|sftp):\/\/[^\s<>"{}|\\^`[\]]+/gi;
   const matches = text.match(urlRegex);
   return matches ? [...new Set(matches)] : [];
 }
 
+/**
+ * Parse request headers from text, one "Name: value" per line.
+ * Lines without a name are ignored.
+ */
+export function parseHeaders(text: string): RequestHeader[] {
+  return text
+    .split("\n")
+    .map((line) => {
+      const colon = line.indexOf(":");
+      if (colon <= 0) return null;
+      return { name: line.slice(0, colon).trim(), value: line.slice(colon + 1).trim() };
+    })
+    .filter((header): header is RequestHeader => !!header && header.name.length > 0);
+}
+
+/**
+ * Format request headers as text, one "Name: value" per line
+ */
+export function formatHeaders(headers: RequestHeader[]): string {
+  return headers.map((h) => `${h.name}: ${h.value}`).join("\n");
+}
+
+/**
+ * Parse login form fields from text, one "name=value" per line.
+ * Lines without a name are ignored.
+ */
+export function parseFormFields(text: string): FormField[] {
+  return text
+    .split("\n")
+    .map((line) => {
+      const eq = line.indexOf("=");
+      if (eq <= 0) return null;
+      return { name: line.slice(0, eq).trim(), value: line.slice(eq + 1).trim() };
+    })
+    .filter((field): field is FormField => !!field && field.name.length > 0);
+}
+
+/**
+ * Format login form fields as text, one "name=value" per line
+ */
+export function formatFormFields(fields: FormField[]): string {
+  return fields.map((f) => `${f.name}=${f.value}`).join("\n");
+}
+
 /**
  * Debounce function
  */
@@ -1,5 +1,5 @@
 import { create } from "zustand";
-import type { SiteCredential, CredentialRequest } from "@/types";
+import type { SiteCredential, CredentialRequest, VaultKey, VaultStatus } from "@/types";
 
 interface CredentialsState {
   // All saved credentials
@@ -8,21 +8,32 @@ interface CredentialsState {
   loaded: boolean;
   // Pending credential request (from 401/403 response)
   pendingRequest: CredentialRequest | null;
+  // Whether saved passwords are encrypted and unlocked
+  vaultStatus: VaultStatus;
 
   // Actions
   loadFromBackend: () => Promise<void>;
   addCredential: (credential: SiteCredential) => Promise<SiteCredential>;
   updateCredential: (credential: SiteCredential) => Promise<SiteCredential>;
   deleteCredential: (id: string) => Promise<void>;
+  // Submit a credential's login form without saving it
+  testLogin: (credential: SiteCredential) => Promise<void>;
+  // Encryption of saved passwords
+  loadVaultStatus: () => Promise<void>;
+  unlock: (key: VaultKey) => Promise<void>;
+  lock: () => Promise<void>;
+  // Encrypt saved passwords under a new key (also used to change it)
+  setMasterKey: (key: VaultKey) => Promise<void>;
   // Handle credential request from download engine
   setPendingRequest: (request: CredentialRequest | null) => void;
 }
 
 export const useCredentialsStore = create<CredentialsState>()(
-  (set) => ({
+  (set, get) => ({
     credentials: [],
     loaded: false,
     pendingRequest: null,
+    vaultStatus: "disabled",
 
     loadFromBackend: async () => {
       const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
@@ -85,6 +96,63 @@ export const useCredentialsStore = create<CredentialsState>()(
       }));
     },
 
+    testLogin: async (credential) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      await invoke("test_credential_login", { credential });
+    },
+
+    loadVaultStatus: async () => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) return;
+
+      try {
+        const { invoke } = await import("@tauri-apps/api/core");
+        const vaultStatus = await invoke<VaultStatus>("get_credential_vault_status");
+        set({ vaultStatus });
+      } catch (err) {
+        console.error("[Credentials] Failed to load vault status:", err);
+      }
+    },
+
+    unlock: async (key) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      await invoke("unlock_credentials", { key });
+      set({ vaultStatus: "unlocked" });
+      await get().loadFromBackend();
+    },
+
+    lock: async () => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      await invoke("lock_credentials");
+      set({ vaultStatus: "locked", credentials: [] });
+    },
+
+    setMasterKey: async (key) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      await invoke("set_master_key", { key });
+      set({ vaultStatus: "unlocked" });
+    },
+
     setPendingRequest: (request) => {
       set({ pendingRequest: request });
     },
@@ -96,5 +164,6 @@ export const useCredentialsStore = create<CredentialsState>()(
  */
 export async function loadCredentialsFromBackend(): Promise<void> {
   console.log("[Credentials] loadCredentialsFromBackend called");
+  await useCredentialsStore.getState().loadVaultStatus();
   await useCredentialsStore.getState().loadFromBackend();
 }
@@ -0,0 +1,80 @@
+import { create } from "zustand";
+import type { HeaderRule } from "@/types";
+
+interface HeaderRulesState {
+  // All saved header rules
+  rules: HeaderRule[];
+  // Whether rules have been loaded from backend
+  loaded: boolean;
+
+  // Actions
+  loadFromBackend: () => Promise<void>;
+  addRule: (rule: HeaderRule) => Promise<HeaderRule>;
+  updateRule: (rule: HeaderRule) => Promise<HeaderRule>;
+  deleteRule: (id: string) => Promise<void>;
+}
+
+export const useHeaderRulesStore = create<HeaderRulesState>()(
+  (set) => ({
+    rules: [],
+    loaded: false,
+
+    loadFromBackend: async () => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        set({ loaded: true });
+        return;
+      }
+
+      try {
+        const { invoke } = await import("@tauri-apps/api/core");
+        const rules = await invoke<HeaderRule[]>("get_header_rules");
+        set({ rules, loaded: true });
+      } catch (err) {
+        console.error("[HeaderRules] Failed to load header rules from backend:", err);
+        set({ loaded: true });
+      }
+    },
+
+    addRule: async (rule) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      const saved = await invoke<HeaderRule>("add_header_rule", { rule });
+      set((state) => ({
+        rules: [...state.rules, saved],
+      }));
+      return saved;
+    },
+
+    updateRule: async (rule) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      const saved = await invoke<HeaderRule>("update_header_rule", { rule });
+      set((state) => ({
+        rules: state.rules.map((r) => (r.id === saved.id ? saved : r)),
+      }));
+      return saved;
+    },
+
+    deleteRule: async (id) => {
+      const isTauri = typeof window !== "undefined" && (window as any).__TAURI_INTERNALS__ !== undefined;
+      if (!isTauri) {
+        throw new Error("Not in Tauri context");
+      }
+
+      const { invoke } = await import("@tauri-apps/api/core");
+      await invoke("delete_header_rule", { id });
+      set((state) => ({
+        rules: state.rules.filter((r) => r.id !== id),
+      }));
+    },
+  })
+);
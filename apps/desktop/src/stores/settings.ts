@@ -50,6 +50,10 @@ const defaultSettings: Settings = {
   font: null,
   // Temp/scratch storage for in-progress segment files
   temp_storage: { mode: "auto", custom_path: null },
+  // Media remuxing
+  ffmpeg_remux_fallback: false,
+  // Per-host connection caps
+  host_limits: { max_connections_per_host: 8, rules: [] },
 };
 
 export const useSettingsStore = create<SettingsState>()(
@@ -18,6 +18,44 @@ export interface Download {
   created_at: string;
   completed_at: string | null;
   retry_count?: number;
+  checksum?: Checksum | null;
+  piece_hashes?: PieceHashes | null;
+  etag?: string | null;
+  last_modified?: string | null;
+  supports_range?: boolean;
+  mirrors?: Mirror[];
+  /** Segments write into `<filename>.part` in the destination, not temp files */
+  direct_write?: boolean;
+  /** Extra request headers, sent to the download's own host */
+  headers?: RequestHeader[];
+  /** Variant and renditions of an HLS/DASH download, reused on resume */
+  stream_selection?: StreamSelection | null;
+}
+
+export interface StreamSelection {
+  variant?: number | null;
+  tracks: { audio?: number | null; subtitles?: number | null };
+}
+
+export interface Mirror {
+  url: string;
+  etag?: string | null;
+  last_modified?: string | null;
+}
+
+export type ChecksumAlgorithm = "md5" | "sha1" | "sha256" | "sha512";
+
+/** Expected hash of a download, verified once it completes */
+export interface Checksum {
+  algorithm: ChecksumAlgorithm;
+  value: string;
+}
+
+/** Hashes of fixed-size pieces (e.g. from a Metalink), checked per segment */
+export interface PieceHashes {
+  algorithm: ChecksumAlgorithm;
+  length: number;
+  hashes: string[];
 }
 
 export type DownloadStatus =
@@ -28,7 +66,9 @@ export type DownloadStatus =
   | "failed"
   | "queued"
   | "cancelled"
-  | "deleted";
+  | "deleted"
+  /** The server refused the login; waits for credentials */
+  | "awaitingauth";
 
 export interface Segment {
   index: number;
@@ -113,6 +153,10 @@ export interface Settings {
   proxy?: ProxySettings;
   // Temp/scratch storage for in-progress segment files
   temp_storage?: TempStorageSettings;
+  /** Retry with an external ffmpeg when the built-in remuxer fails */
+  ffmpeg_remux_fallback?: boolean;
+  // Per-host connection caps shared by all downloads
+  host_limits?: HostLimitSettings;
 }
 
 export interface ProxySettings {
@@ -131,12 +175,31 @@ export interface ProxySettings {
  * - `appdata`: always use the fast app-data dir on the system disk.
  * - `destination`: keep scratch beside the final file (best for external drives).
  * - `custom`: a user-chosen folder (e.g. a fast SSD).
+ * - `direct`: no scratch; new downloads write into a preallocated `.part`
+ *   file in the destination that is renamed when done.
  */
 export interface TempStorageSettings {
-  mode: 'auto' | 'appdata' | 'destination' | 'custom';
+  mode: 'auto' | 'appdata' | 'destination' | 'custom' | 'direct';
   custom_path?: string | null;
 }
 
+/** Connection caps shared by all downloads to the same host. 0 = no cap. */
+export interface HostLimitSettings {
+  max_connections_per_host: number;
+  rules: HostLimitRule[];
+}
+
+/**
+ * Overrides the default cap for hosts matching `pattern` ("example.com" also
+ * matches its subdomains). All matching hosts share one pool of connections.
+ */
+export interface HostLimitRule {
+  pattern: string;
+  max_connections: number;
+  /** Bandwidth shared by the matching hosts, in bytes/sec */
+  speed_limit?: number | null;
+}
+
 export type Theme = "light" | "dark" | "system";
 
 // Site Credential types
@@ -144,15 +207,83 @@ export type Theme = "light" | "dark" | "system";
 export interface SiteCredential {
   id: string;
   domain: string;
+  /** "http", "https", "ftp", "sftp" or "any"; "ftp" also covers ftps:// and
+   * ftpes:// (FileZilla's non-standard scheme for FTP upgraded with AUTH TLS) */
   protocol: string;
   username: string;
+  /** Password, or the token for Bearer auth */
   password: string;
+  auth_scheme?: AuthScheme;
+  /** The login form to submit for the "form" scheme */
+  form_login?: FormLogin | null;
   enabled: boolean;
   created_at: string;
   last_used_at: string | null;
+  /** Times a server refused this login or said its quota was used up */
+  failure_count?: number;
+  last_failed_at?: string | null;
+  /** Bytes per day (UTC) this account may download */
+  daily_quota?: number | null;
+  /** Bytes downloaded with this login today (UTC); kept by the engine */
+  used_today?: number;
+  notes: string | null;
+  ssh_key?: SshKey | null;
+}
+
+/** Whether saved passwords are encrypted, and if so whether they can be read */
+export type VaultStatus = "disabled" | "locked" | "unlocked";
+
+/** The master passphrase or keyfile that encrypts saved passwords */
+export type VaultKey =
+  | { type: "passphrase"; value: string }
+  | { type: "keyfile"; value: string };
+
+/** How HTTP requests authenticate with a saved login */
+export type AuthScheme = "basic" | "digest" | "bearer" | "form";
+
+/** A login request whose session cookies authenticate downloads */
+export interface FormLogin {
+  url: string;
+  /** "POST" or "GET" */
+  method: string;
+  /** `{username}` and `{password}` in values are replaced by the login's */
+  fields: FormField[];
+  success: LoginCheck;
+}
+
+export interface FormField {
+  name: string;
+  value: string;
+}
+
+/** How to tell that a form login worked */
+export type LoginCheck =
+  | { type: "any_cookie" }
+  | { type: "cookie"; name: string }
+  | { type: "body_contains"; text: string }
+  | { type: "body_lacks"; text: string };
+
+/** An extra HTTP request header */
+export interface RequestHeader {
+  name: string;
+  value: string;
+}
+
+/** Headers added to every request to a domain (e.g. "*.example.com") */
+export interface HeaderRule {
+  id: string;
+  domain: string;
+  headers: RequestHeader[];
+  enabled: boolean;
+  created_at: string;
   notes: string | null;
 }
 
+export interface SshKey {
+  path: string;
+  passphrase?: string | null;
+}
+
 export interface CredentialRequest {
   downloadId: string;
   domain: string;
@@ -229,10 +360,42 @@ export type CoreEvent =
   | {
       type: "CredentialRequired";
       payload: CredentialRequest;
+    }
+  | {
+      type: "RecordingProgress";
+      payload: {
+        id: string;
+        elapsedSecs: number;
+        recorded: number;
+        segments: number;
+      };
+    }
+  | {
+      type: "ChecksumVerified";
+      payload: {
+        id: string;
+        algorithm: ChecksumAlgorithm;
+        expected: string;
+        actual: string;
+        matched: boolean;
+      };
+    }
+  | {
+      type: "ContentChanged";
+      payload: {
+        id: string;
+        url: string;
+      };
     };
 
 // API types
 
+/** Optional caps for recording a live HLS/DASH stream */
+export interface RecordingOptions {
+  max_duration_secs?: number | null;
+  max_bytes?: number | null;
+}
+
 export interface LinkInfo {
   url: string;
   final_url: string | null;
@@ -351,6 +351,29 @@ export class DlmanClient {
     }
   }
 
+  /**
+   * Log in for a download the server refused, and resume it.
+   * The login is only used for this download; saving it is done in the app.
+   */
+  async provideCredentials(
+    id: string,
+    username: string,
+    password: string,
+  ): Promise<{ success: boolean; error?: string }> {
+    try {
+      return await this.httpRequest<{ success: boolean; error?: string }>(
+        'POST',
+        `/api/downloads/${id}/credentials`,
+        { username, password },
+      );
+    } catch (error) {
+      return {
+        success: false,
+        error: error instanceof Error ? error.message : 'Unknown error',
+      };
+    }
+  }
+
   /**
    * Send a media download request to the desktop app.
    * For direct files, opens the download dialog.
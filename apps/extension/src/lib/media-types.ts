@@ -32,6 +32,26 @@ export interface MediaVariant {
   audio_only?: boolean;
   /** Estimated file size in bytes */
   estimated_size?: number;
+  /** Alternate audio renditions (HLS EXT-X-MEDIA) */
+  audio_tracks?: MediaRendition[];
+  /** Subtitle renditions */
+  subtitle_tracks?: MediaRendition[];
+}
+
+/** An alternate audio or subtitle rendition */
+export interface MediaRendition {
+  /** Rendition manifest URL (absent when muxed into the variant) */
+  url?: string;
+  /** Human-readable name */
+  name: string;
+  /** Language tag (e.g. "en") */
+  language?: string;
+  /** Whether this is the manifest's default rendition */
+  default?: boolean;
+  /** Bitrate in bits per second (DASH) */
+  bandwidth?: number;
+  /** Codec string */
+  codecs?: string;
 }
 
 // ============================================================================
@@ -81,6 +101,10 @@ export interface MediaDownloadRequest {
   output_filename?: string;
   /** Target queue ID */
   queue_id?: string;
+  /** Index into the chosen variant's audio_tracks (undefined = default) */
+  audio_track?: number;
+  /** Index into the chosen variant's subtitle_tracks (undefined = all of them) */
+  subtitle_track?: number;
 }
 
 /** Response after initiating a media download */
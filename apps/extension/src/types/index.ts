@@ -36,7 +36,8 @@ export type DownloadStatus =
   | 'failed'
   | 'queued'
   | 'cancelled'
-  | 'deleted';
+  | 'deleted'
+  | 'awaitingauth';
 
 export interface Segment {
   index: number;
@@ -145,6 +146,8 @@ export interface ShowDialogRequest {
   referrer?: string;
   filename?: string;
   cookies?: string;
+  /** Expected checksum, e.g. "sha256:<hex>"; prefilled in the dialog */
+  checksum?: string;
 }
 
 /** Request to show the batch download dialog in the desktop app */
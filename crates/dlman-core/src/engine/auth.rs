@@ -0,0 +1,739 @@
+//! HTTP authentication with saved credentials
+//!
+//! Basic and Bearer credentials go on every request. Digest (RFC 7616)
+//! needs a challenge first: the request goes out without credentials, the
+//! server answers 401 with a nonce, and the request is repeated with a
+//! response computed from it. The nonce is kept and shared by every clone of
+//! the [`HttpAuth`], so the other segments of a download authenticate on
+//! their first request instead of each taking a 401.
+//!
+//! Form logins add the cookies of their [`FormSession`] instead, on every host
+//! of the credential's domain, and log in again once when a request comes
+//! back 401 or 403.
+//!
+//! When several saved accounts match a site they are tried in turn: a request
+//! the server refuses (401/403) or turns away for a used-up quota (402/509)
+//! is repeated with the next account, and the rest of the download stays on
+//! that one. Refusals, use and downloaded bytes are recorded per credential.
+
+use super::form_login::FormSession;
+use super::persistence::DownloadDatabase;
+use base64::Engine;
+use dlman_types::{AuthScheme, SiteCredential};
+use md5::Md5;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderValue, AUTHORIZATION, COOKIE, WWW_AUTHENTICATE};
+use reqwest::{Client, Method, Request, RequestBuilder, Response, StatusCode};
+use sha2::{Digest as _, Sha256, Sha512_256};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Credentials for the HTTP requests of one download, sent only to the host
+/// they were saved for
+#[derive(Debug, Clone)]
+pub struct HttpAuth {
+    scheme: AuthScheme,
+    username: String,
+    password: String,
+    host: Option<String>,
+    /// Last Digest challenge, shared between clones
+    digest: Arc<Mutex<Option<DigestSession>>>,
+    /// Session of a form login
+    form: Option<Arc<FormSession>>,
+    /// The saved credential these are, if any
+    credential_id: Option<Uuid>,
+    /// Accounts to switch to when the server refuses these
+    rotation: Option<Arc<Rotation>>,
+}
+
+/// The saved accounts for a site, tried in turn. Clones of an [`HttpAuth`]
+/// share it, so once one request moves on to the next account the others
+/// follow.
+#[derive(Debug)]
+struct Rotation {
+    accounts: Vec<HttpAuth>,
+    /// Index of the account in use
+    current: AtomicUsize,
+    db: DownloadDatabase,
+    /// Credentials whose `last_used_at` this download already updated
+    touched: Mutex<HashSet<Uuid>>,
+}
+
+/// A Digest challenge in use and the number of requests made with its nonce
+#[derive(Debug)]
+struct DigestSession {
+    challenge: DigestChallenge,
+    nonce_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DigestAlgorithm {
+    Md5,
+    Sha256,
+    Sha512_256,
+}
+
+impl DigestAlgorithm {
+    fn hash(self, data: &str) -> String {
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        match self {
+            DigestAlgorithm::Md5 => hex(&Md5::digest(data.as_bytes())),
+            DigestAlgorithm::Sha256 => hex(&Sha256::digest(data.as_bytes())),
+            DigestAlgorithm::Sha512_256 => hex(&Sha512_256::digest(data.as_bytes())),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha512_256 => "SHA-512-256",
+        }
+    }
+}
+
+/// The parts of a `WWW-Authenticate: Digest ...` challenge we use
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    /// `-sess` variant of the algorithm
+    session: bool,
+    /// Chosen quality of protection: "auth", "auth-int" or none (RFC 2069)
+    qop: Option<&'static str>,
+    stale: bool,
+}
+
+impl DigestChallenge {
+    /// Parse one header value. Returns `None` for other schemes and for
+    /// algorithms we don't implement.
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(char::is_whitespace)?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        let params = parse_params(params);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+        };
+
+        let algorithm = param("algorithm").unwrap_or_else(|| "MD5".to_string()).to_uppercase();
+        let (algorithm, session) = match algorithm.strip_suffix("-SESS") {
+            Some(base) => (base.to_string(), true),
+            None => (algorithm, false),
+        };
+        let algorithm = match algorithm.as_str() {
+            "MD5" => DigestAlgorithm::Md5,
+            "SHA-256" => DigestAlgorithm::Sha256,
+            "SHA-512-256" => DigestAlgorithm::Sha512_256,
+            _ => return None,
+        };
+        let qop = match param("qop") {
+            None => None,
+            Some(offered) => {
+                let offered: Vec<String> = offered.split(',').map(|q| q.trim().to_lowercase()).collect();
+                if offered.iter().any(|q| q == "auth") {
+                    Some("auth")
+                } else if offered.iter().any(|q| q == "auth-int") {
+                    Some("auth-int")
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(Self {
+            realm: param("realm").unwrap_or_default(),
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm,
+            session,
+            qop,
+            stale: param("stale").is_some_and(|s| s.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// The `Authorization` value for a request, given its `nonce_count`
+    /// (from 1) and a client nonce
+    fn authorization(
+        &self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        uri: &str,
+        nonce_count: u32,
+        cnonce: &str,
+    ) -> String {
+        let h = |data: &str| self.algorithm.hash(data);
+        let nc = format!("{:08x}", nonce_count);
+
+        let mut ha1 = h(&format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        // Only GETs and HEADs are authenticated, so the entity body is empty
+        let ha2 = match self.qop {
+            Some("auth-int") => h(&format!("{}:{}:{}", method, uri, h(""))),
+            _ => h(&format!("{}:{}", method, uri)),
+        };
+        let response = match self.qop {
+            Some(qop) => h(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2)),
+            None => h(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let mut value = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}{}, response=\"{}\"",
+            quote(username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            self.algorithm.name(),
+            if self.session { "-sess" } else { "" },
+            response,
+        );
+        if let Some(qop) = self.qop {
+            value.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+        }
+        if let Some(ref opaque) = self.opaque {
+            value.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        value
+    }
+}
+
+/// Escape a value for a quoted-string
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Split `key=value, key="quoted, value"` auth parameters
+fn parse_params(input: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ',')).collect();
+        if key.trim().is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                value = std::iter::from_fn(|| chars.next_if(|c| *c != ',')).collect();
+            }
+        }
+        params.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    params
+}
+
+fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+impl HttpAuth {
+    /// Authenticate requests to the host of `url` as `username`. For Bearer
+    /// auth `password` is the token.
+    pub fn new(scheme: AuthScheme, username: String, password: String, url: &str) -> Self {
+        Self {
+            scheme,
+            username,
+            password,
+            host: host_of(url),
+            digest: Arc::default(),
+            form: None,
+            credential_id: None,
+            rotation: None,
+        }
+    }
+
+    /// Send the cookies of a form login's `session` along
+    pub fn with_form_session(mut self, session: Arc<FormSession>) -> Self {
+        self.form = Some(session);
+        self
+    }
+
+    /// Authenticate requests to the host of `url` with a saved credential
+    pub fn from_credential(credential: &SiteCredential, url: &str) -> Self {
+        let mut auth = Self::new(credential.auth_scheme, credential.username.clone(), credential.password.clone(), url);
+        auth.credential_id = Some(credential.id);
+        auth
+    }
+
+    /// Log in with the first of `accounts` (made with [`Self::from_credential`])
+    /// and move on to the next whenever the server refuses one or says its
+    /// quota is used up. `None` if there are no accounts.
+    pub fn rotating(accounts: Vec<HttpAuth>, db: DownloadDatabase) -> Option<Self> {
+        let mut first = accounts.first()?.clone();
+        first.rotation = Some(Arc::new(Rotation {
+            accounts,
+            current: AtomicUsize::new(0),
+            db,
+            touched: Mutex::default(),
+        }));
+        Some(first)
+    }
+
+    /// The saved credential in use, if these came from one
+    pub fn credential_id(&self) -> Option<Uuid> {
+        match self.rotation {
+            Some(ref rotation) => rotation.account().credential_id,
+            None => self.credential_id,
+        }
+    }
+
+    /// Count `bytes` downloaded against the saved credential in use, towards
+    /// its daily quota
+    pub async fn record_usage(&self, bytes: u64) {
+        let (Some(rotation), Some(id)) = (self.rotation.as_ref(), self.credential_id()) else {
+            return;
+        };
+        if bytes == 0 {
+            return;
+        }
+        if let Err(e) = rotation.db.add_credential_usage(id, bytes).await {
+            warn!("Failed to record credential usage: {}", e);
+        }
+    }
+
+    pub fn scheme(&self) -> AuthScheme {
+        self.scheme
+    }
+
+    /// Whether these are the credentials `username` / `password`
+    pub fn logs_in_as(&self, username: &str, password: &str) -> bool {
+        self.username == username && self.password == password
+    }
+
+    fn applies_to(&self, url: &url::Url) -> bool {
+        self.host.is_some() && url.host_str().map(str::to_lowercase) == self.host
+    }
+
+    /// Whether requests to `url` log in with these credentials, by header or
+    /// by session
+    fn covers(&self, url: &url::Url) -> bool {
+        self.applies_to(url) || self.form.as_deref().is_some_and(|s| s.applies_to(url))
+    }
+
+    /// Add the `Authorization` header to `request`, if these credentials are
+    /// for its host. Digest requests only get one once a challenge is known.
+    fn authorize(&self, mut request: Request) -> Request {
+        if !self.applies_to(request.url()) {
+            return request;
+        }
+        let value = match self.scheme {
+            AuthScheme::Basic => {
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", self.username, self.password));
+                format!("Basic {}", encoded)
+            }
+            AuthScheme::Bearer => format!("Bearer {}", self.password.trim()),
+            // Sessions go in the Cookie header, see `send_with_session`
+            AuthScheme::Form => return request,
+            AuthScheme::Digest => {
+                let mut digest = self.digest.lock();
+                let Some(session) = digest.as_mut() else {
+                    return request;
+                };
+                session.nonce_count += 1;
+                let url = request.url();
+                let uri = &url[url::Position::BeforePath..url::Position::AfterQuery];
+                let cnonce = uuid::Uuid::new_v4().simple().to_string();
+                session.challenge.authorization(
+                    &self.username,
+                    &self.password,
+                    request.method(),
+                    uri,
+                    session.nonce_count,
+                    &cnonce,
+                )
+            }
+        };
+        if let Ok(mut value) = HeaderValue::from_str(&value) {
+            value.set_sensitive(true);
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        request
+    }
+
+    /// Take the Digest challenge of a 401 response. True if the request is
+    /// worth repeating: the challenge is new, or the old nonce went stale.
+    fn challenged(&self, response: &Response) -> bool {
+        if self.scheme != AuthScheme::Digest || !self.applies_to(response.url()) {
+            return false;
+        }
+        // Of several challenges, answer the one with the strongest hash
+        let Some(challenge) = response
+            .headers()
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(DigestChallenge::parse)
+            .max_by_key(|c| c.algorithm)
+        else {
+            return false;
+        };
+
+        let mut digest = self.digest.lock();
+        let retry = match digest.as_ref() {
+            Some(session) => challenge.stale || session.challenge.nonce != challenge.nonce,
+            None => true,
+        };
+        debug!("Digest challenge from {:?} (stale: {}, retry: {})", self.host, challenge.stale, retry);
+        *digest = Some(DigestSession { challenge, nonce_count: 0 });
+        retry
+    }
+
+    /// Send `request` with these credentials, answering a Digest challenge
+    /// once. A request that already carries an `Authorization` header (from
+    /// a header rule) is sent as it is.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        match self.rotation {
+            Some(ref rotation) => rotation.execute(&client, request).await,
+            None => self.execute(&client, request).await,
+        }
+    }
+
+    async fn execute(&self, client: &Client, request: Request) -> reqwest::Result<Response> {
+        if let Some(session) = self.form.as_deref().filter(|s| s.applies_to(request.url())) {
+            return send_with_session(session, client, request).await;
+        }
+        if request.headers().contains_key(AUTHORIZATION) {
+            return client.execute(request).await;
+        }
+        let retry = request.try_clone();
+        let response = client.execute(self.authorize(request)).await?;
+        if response.status() == StatusCode::UNAUTHORIZED && self.challenged(&response) {
+            if let Some(retry) = retry {
+                return client.execute(self.authorize(retry)).await;
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Whether a server refused the login (401/403) or said the account's quota
+/// is used up (402 Payment Required, 509 Bandwidth Limit Exceeded)
+fn refuses_account(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 401 | 402 | 403 | 509)
+}
+
+impl Rotation {
+    fn account(&self) -> &HttpAuth {
+        &self.accounts[self.current.load(Ordering::Acquire) % self.accounts.len()]
+    }
+
+    /// Send `request` with the account in use, trying each of the others
+    /// once if the server refuses it
+    async fn execute(&self, client: &Client, mut request: Request) -> reqwest::Result<Response> {
+        // A header rule's own Authorization goes out as it is
+        if request.headers().contains_key(AUTHORIZATION) {
+            return self.account().execute(client, request).await;
+        }
+        let mut index = self.current.load(Ordering::Acquire) % self.accounts.len();
+        let mut tried = 0;
+        loop {
+            tried += 1;
+            let account = &self.accounts[index];
+            let retry = request.try_clone();
+            let response = account.execute(client, request).await?;
+            // A refusal from another host (say, a CDN redirected to) isn't
+            // about this account
+            if !refuses_account(response.status()) || !account.covers(response.url()) {
+                if response.status().is_success() {
+                    self.touch(account).await;
+                }
+                return Ok(response);
+            }
+
+            let next = (index + 1) % self.accounts.len();
+            // Of several requests refused at once, the first moves everyone on
+            if self.current.compare_exchange(index, next, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                info!(
+                    "Account {} refused by {:?} (HTTP {}), trying the next one",
+                    account.username,
+                    account.host,
+                    response.status().as_u16()
+                );
+                if let Some(id) = account.credential_id {
+                    if let Err(e) = self.db.record_credential_failure(id).await {
+                        warn!("Failed to record credential failure: {}", e);
+                    }
+                }
+            }
+            match retry {
+                Some(retry) if tried < self.accounts.len() => {
+                    request = retry;
+                    index = next;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    /// Update `last_used_at` of an account the first time it works
+    async fn touch(&self, account: &HttpAuth) {
+        let Some(id) = account.credential_id else {
+            return;
+        };
+        if !self.touched.lock().insert(id) {
+            return;
+        }
+        if let Err(e) = self.db.touch_credential(id).await {
+            warn!("Failed to update credential last use: {}", e);
+        }
+    }
+}
+
+/// Add the session `cookies` to those `request` already carries
+fn with_cookies(mut request: Request, cookies: &str) -> Request {
+    let cookies = match request.headers().get(COOKIE).and_then(|c| c.to_str().ok()) {
+        Some(own) if !own.trim().is_empty() => format!("{}; {}", own.trim().trim_end_matches(';'), cookies),
+        _ => cookies.to_string(),
+    };
+    if let Ok(mut value) = HeaderValue::from_str(&cookies) {
+        value.set_sensitive(true);
+        request.headers_mut().insert(COOKIE, value);
+    }
+    request
+}
+
+/// Send `request` with the cookies of a form login, logging in again once if
+/// the server refuses them. If the login fails, the request goes out without
+/// the session and the server's answer tells the download what went wrong.
+async fn send_with_session(session: &FormSession, client: &Client, request: Request) -> reqwest::Result<Response> {
+    let cookies = match session.cookies().await {
+        Ok(cookies) => cookies,
+        Err(e) => {
+            warn!("{}", e);
+            return client.execute(request).await;
+        }
+    };
+    let retry = request.try_clone();
+    let response = client.execute(with_cookies(request, &cookies)).await?;
+    if !matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+        return Ok(response);
+    }
+    let Some(retry) = retry else {
+        return Ok(response);
+    };
+    debug!("Session refused with {}, logging in again", response.status());
+    match session.renew(&cookies).await {
+        Ok(fresh) => client.execute(with_cookies(retry, &fresh)).await,
+        Err(e) => {
+            warn!("{}", e);
+            Ok(response)
+        }
+    }
+}
+
+/// Send `request`, with `auth` if there is one
+pub async fn send_with_auth(auth: Option<&HttpAuth>, request: RequestBuilder) -> reqwest::Result<Response> {
+    match auth {
+        Some(auth) => auth.send(request).await,
+        None => request.send().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const RFC_CHALLENGE: &str = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+        algorithm=SHA-256, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+
+    fn response_param(authorization: &str) -> String {
+        parse_params(authorization.strip_prefix("Digest ").unwrap())
+            .into_iter()
+            .find(|(k, _)| k == "response")
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn digest_matches_rfc_7616_example() {
+        let challenge = DigestChallenge::parse(RFC_CHALLENGE).unwrap();
+        assert_eq!(challenge.qop, Some("auth"));
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let sha256 = challenge.authorization("Mufasa", "Circle of Life", &Method::GET, "/dir/index.html", 1, cnonce);
+        assert_eq!(response_param(&sha256), "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+        assert!(sha256.contains("nc=00000001"));
+
+        let md5 = DigestChallenge { algorithm: DigestAlgorithm::Md5, ..challenge };
+        let md5 = md5.authorization("Mufasa", "Circle of Life", &Method::GET, "/dir/index.html", 1, cnonce);
+        assert_eq!(response_param(&md5), "8ca523f5e9506fed4657c9700eebdbec");
+    }
+
+    #[test]
+    fn unsupported_challenges_are_ignored() {
+        assert!(DigestChallenge::parse("Basic realm=\"x\"").is_none());
+        assert!(DigestChallenge::parse("Digest realm=\"x\", nonce=\"n\", algorithm=SHA-1").is_none());
+        let stale = DigestChallenge::parse("digest realm=\"a, b\", nonce=\"n\", stale=TRUE").unwrap();
+        assert_eq!(stale.realm, "a, b");
+        assert_eq!(stale.algorithm, DigestAlgorithm::Md5);
+        assert!(stale.stale && stale.qop.is_none());
+    }
+
+    /// Serve a Digest-protected file with one fixed nonce, counting 401s
+    async fn serve_digest(auth: HttpAuthCheck) -> (String, Arc<AtomicU32>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        let challenges = Arc::new(AtomicU32::new(0));
+        let counter = challenges.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let response = if auth(&request) {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string()
+                } else {
+                    counter.fetch_add(1, Ordering::AcqRel);
+                    "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"files\"\r\n\
+                     WWW-Authenticate: Digest realm=\"files\", qop=\"auth\", nonce=\"abc123\", opaque=\"xyz\"\r\n\
+                     Content-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, challenges)
+    }
+
+    type HttpAuthCheck = fn(&str) -> bool;
+
+    /// Whether a raw request answers the challenge of [`serve_digest`] for
+    /// alice/secret
+    fn valid_digest(request: &str) -> bool {
+        let Some(line) = request.lines().find(|l| l.to_lowercase().starts_with("authorization: digest ")) else {
+            return false;
+        };
+        let params = parse_params(&line["authorization: digest ".len()..]);
+        let param = |name: &str| params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str()).unwrap_or("");
+        let h = |data: String| DigestAlgorithm::Md5.hash(&data);
+        let ha1 = h("alice:files:secret".to_string());
+        let ha2 = h(format!("GET:{}", param("uri")));
+        let expected = h(format!("{}:abc123:{}:{}:auth:{}", ha1, param("nc"), param("cnonce"), ha2));
+        param("response") == expected && param("opaque") == "xyz"
+    }
+
+    #[tokio::test]
+    async fn digest_nonce_is_reused_across_requests() {
+        let (url, challenges) = serve_digest(valid_digest).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let auth = HttpAuth::new(AuthScheme::Digest, "alice".into(), "secret".into(), &url);
+
+        let first = auth.send(client.get(&url)).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        // A clone (as another segment holds) answers without a new challenge
+        let second = auth.clone().send(client.get(&url)).await.unwrap();
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(challenges.load(Ordering::Acquire), 1);
+        assert_eq!(auth.digest.lock().as_ref().unwrap().nonce_count, 2);
+
+        // Wrong credentials get one try, not a loop
+        let wrong = HttpAuth::new(AuthScheme::Digest, "alice".into(), "nope".into(), &url);
+        assert_eq!(wrong.send(client.get(&url)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenges.load(Ordering::Acquire), 3);
+    }
+
+    #[tokio::test]
+    async fn credentials_stay_on_their_host() {
+        let (url, challenges) = serve_digest(|request| request.to_lowercase().contains("authorization: bearer t0ken")).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+
+        let bearer = HttpAuth::new(AuthScheme::Bearer, String::new(), "t0ken".into(), &url);
+        assert_eq!(bearer.send(client.get(&url)).await.unwrap().status(), StatusCode::OK);
+
+        let elsewhere = HttpAuth::new(AuthScheme::Bearer, String::new(), "t0ken".into(), "https://example.com/");
+        assert_eq!(elsewhere.send(client.get(&url)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenges.load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn refused_accounts_rotate_to_the_next() {
+        let (url, refusals) = serve_digest(|request| request.to_lowercase().contains("authorization: bearer second")).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let db = DownloadDatabase::new(dir.path().join("dlman.db")).await.unwrap();
+        let mut credentials = Vec::new();
+        for token in ["first", "second"] {
+            let mut credential = SiteCredential::new("127.0.0.1".into(), String::new(), token.into());
+            credential.auth_scheme = AuthScheme::Bearer;
+            db.upsert_credential(&credential).await.unwrap();
+            credentials.push(credential);
+        }
+        let accounts = credentials.iter().map(|c| HttpAuth::from_credential(c, &url)).collect();
+        let auth = HttpAuth::rotating(accounts, db.clone()).unwrap();
+
+        assert_eq!(auth.send(client.get(&url)).await.unwrap().status(), StatusCode::OK);
+        // Another segment starts on the account that worked
+        assert_eq!(auth.clone().send(client.get(&url)).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(refusals.load(Ordering::Acquire), 1);
+        assert_eq!(auth.credential_id(), Some(credentials[1].id));
+        auth.record_usage(1000).await;
+
+        // Next time the refused account goes last
+        let found = db.find_credentials_for_url(&url).await.unwrap();
+        assert_eq!(found[0].id, credentials[1].id);
+        assert_eq!(found[0].used_today, 1000);
+        assert!(found[0].last_used_at.is_some());
+        assert_eq!(found[1].failure_count, 1);
+
+        // ...unless the one that worked has used up its quota
+        let capped = SiteCredential { daily_quota: Some(1000), ..found[0].clone() };
+        db.upsert_credential(&capped).await.unwrap();
+        let found = db.find_credentials_for_url(&url).await.unwrap();
+        assert_eq!(found[0].id, credentials[0].id);
+        assert!(found[1].quota_exhausted());
+        // Saving an edit from a copy loaded before the refusal keeps the count
+        db.upsert_credential(&credentials[0]).await.unwrap();
+        let found = db.find_credentials_for_url(&url).await.unwrap();
+        assert_eq!(found[0].failure_count, 1);
+        assert!(found[0].last_failed_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn accounts_are_ordered_by_share_of_quota_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DownloadDatabase::new(dir.path().join("dlman.db")).await.unwrap();
+        let mut ids = Vec::new();
+        // (quota, used today): the big account has more bytes left but has
+        // used more of its quota
+        for (quota, used) in [(None, 0), (Some(10_000), 5_000), (Some(1_000), 100), (None, 50)] {
+            let mut credential = SiteCredential::new("example.com".into(), "user".into(), "pass".into());
+            credential.daily_quota = quota;
+            db.upsert_credential(&credential).await.unwrap();
+            db.add_credential_usage(credential.id, used).await.unwrap();
+            ids.push(credential.id);
+        }
+
+        let found = db.find_credentials_for_url("https://example.com/file").await.unwrap();
+        let order: Vec<Uuid> = found.iter().map(|c| c.id).collect();
+        assert_eq!(order, vec![ids[2], ids[1], ids[0], ids[3]]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Checksum verification for completed downloads
+//!
+//! The merge step feeds every byte it writes through a [`ChecksumHasher`], so
+//! a download is verified without reading the final file a second time.
+//! [`hash_file`] covers files that were never merged (e.g. re-checking a
+//! completed download). Piece hashes (e.g. from a Metalink) are checked per
+//! segment with [`first_bad_piece`], so only a bad range is fetched again.
+
+use crate::error::DlmanError;
+use dlman_types::{Checksum, ChecksumAlgorithm, PieceHashes};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Incremental hasher for one of the supported algorithms
+pub enum ChecksumHasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl ChecksumHasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Self::Md5(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Self::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    /// Lowercase hex digest of everything hashed so far
+    pub fn finalize_hex(self) -> String {
+        let digest = match self {
+            Self::Md5(h) => h.finalize().to_vec(),
+            Self::Sha1(h) => h.finalize().to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// Hash a file on disk
+pub async fn hash_file(path: &Path, algorithm: ChecksumAlgorithm) -> Result<String, DlmanError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = ChecksumHasher::new(algorithm);
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// A piece whose content doesn't match its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceMismatch {
+    pub index: usize,
+    /// Offset of the piece in the whole file
+    pub start: u64,
+    pub actual: String,
+}
+
+/// Check the pieces that lie entirely within the byte range `start..=end` of
+/// a `size`-byte file, reading them from `path`, which holds that range
+/// (possibly only partially). Pieces crossing the range's edges, or not yet
+/// written, are skipped. Returns the first piece that doesn't match.
+pub async fn first_bad_piece(
+    path: &Path,
+    start: u64,
+    end: u64,
+    size: u64,
+    pieces: &PieceHashes,
+) -> Result<Option<PieceMismatch>, DlmanError> {
+    if pieces.length == 0 {
+        return Ok(None);
+    }
+    let file = tokio::fs::File::open(path).await?;
+    let written = file.metadata().await?.len();
+    if written == 0 {
+        return Ok(None);
+    }
+    scan_pieces(file, start, end.min(start + written - 1), start, size, pieces).await
+}
+
+/// [`first_bad_piece`] for a file that holds the whole download at its own
+/// offsets, such as the `.part` file of a direct-write download. Its length
+/// says nothing about what was written, so `end` must be the last byte that
+/// was.
+pub async fn first_bad_piece_in_place(
+    path: &Path,
+    start: u64,
+    end: u64,
+    size: u64,
+    pieces: &PieceHashes,
+) -> Result<Option<PieceMismatch>, DlmanError> {
+    if pieces.length == 0 || end < start {
+        return Ok(None);
+    }
+    let file = tokio::fs::File::open(path).await?;
+    scan_pieces(file, start, end, 0, size, pieces).await
+}
+
+/// Check the pieces within `start..=end`, where byte `b` of the download is
+/// at offset `b - base` in `file`
+async fn scan_pieces(
+    mut file: tokio::fs::File,
+    start: u64,
+    end: u64,
+    base: u64,
+    size: u64,
+    pieces: &PieceHashes,
+) -> Result<Option<PieceMismatch>, DlmanError> {
+    let end = end.min(size.saturating_sub(1));
+    let mut buffer = vec![0u8; pieces.length as usize];
+    for (index, expected) in pieces.hashes.iter().enumerate().skip(start.div_ceil(pieces.length) as usize) {
+        let piece_start = index as u64 * pieces.length;
+        let piece_end = (piece_start + pieces.length).min(size) - 1;
+        if piece_start >= size || piece_end > end {
+            break;
+        }
+        let piece = &mut buffer[..(piece_end - piece_start + 1) as usize];
+        file.seek(std::io::SeekFrom::Start(piece_start - base)).await?;
+        file.read_exact(piece).await?;
+
+        let mut hasher = ChecksumHasher::new(pieces.algorithm);
+        hasher.update(piece);
+        let actual = hasher.finalize_hex();
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Ok(Some(PieceMismatch { index, start: piece_start, actual }));
+        }
+    }
+    Ok(None)
+}
+
+/// Compare a computed digest with the expected one
+pub fn verify_checksum(expected: &Checksum, actual: &str) -> Result<(), DlmanError> {
+    if expected.value.eq_ignore_ascii_case(actual) {
+        Ok(())
+    } else {
+        Err(DlmanError::ChecksumMismatch {
+            algorithm: expected.algorithm.name().to_string(),
+            expected: expected.value.clone(),
+            actual: actual.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+        let mut hasher = ChecksumHasher::new(algorithm);
+        // Split updates must match a single pass
+        let (a, b) = data.split_at(data.len() / 2);
+        hasher.update(a);
+        hasher.update(b);
+        hasher.finalize_hex()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(digest(ChecksumAlgorithm::Md5, b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest(ChecksumAlgorithm::Sha1, b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            digest(ChecksumAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(digest(ChecksumAlgorithm::Sha512, b"abc").starts_with("ddaf35a193617aba"));
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let c: Checksum = "SHA-256:BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+            .parse()
+            .unwrap();
+        assert_eq!(c.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(c.to_string(), "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // Bare digests are recognized by length
+        let c: Checksum = "900150983cd24fb0d6963f7d28e17f72".parse().unwrap();
+        assert_eq!(c.algorithm, ChecksumAlgorithm::Md5);
+
+        assert!("sha1:900150983cd24fb0d6963f7d28e17f72".parse::<Checksum>().is_err());
+        assert!("crc32:deadbeef".parse::<Checksum>().is_err());
+        assert!("not-hex".parse::<Checksum>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hash_file_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        tokio::fs::write(&path, b"abc").await.unwrap();
+
+        let actual = hash_file(&path, ChecksumAlgorithm::Sha1).await.unwrap();
+        let expected: Checksum = "sha1:A9993E364706816ABA3E25717850C26C9CD0D89D".parse().unwrap();
+        assert!(verify_checksum(&expected, &actual).is_ok());
+
+        let wrong: Checksum = "sha1:0000000000000000000000000000000000000000".parse().unwrap();
+        assert!(matches!(verify_checksum(&wrong, &actual), Err(DlmanError::ChecksumMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_first_bad_piece() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("segment.part");
+        // The second half of a 10-byte file in 3-byte pieces: pieces 2 and 3
+        // lie inside bytes 5..=9, piece 1 (3..=5) crosses the start
+        tokio::fs::write(&path, b"fghij").await.unwrap();
+        let hash = |data: &[u8]| digest(ChecksumAlgorithm::Sha1, data);
+        let mut pieces = PieceHashes {
+            algorithm: ChecksumAlgorithm::Sha1,
+            length: 3,
+            hashes: vec![hash(b"abc"), "0".repeat(40), hash(b"ghi"), hash(b"j")],
+        };
+        assert_eq!(first_bad_piece(&path, 5, 9, 10, &pieces).await.unwrap(), None);
+
+        pieces.hashes[3] = hash(b"x");
+        let bad = first_bad_piece(&path, 5, 9, 10, &pieces).await.unwrap().unwrap();
+        assert_eq!((bad.index, bad.start, bad.actual), (3, 9, hash(b"j")));
+
+        // Pieces that aren't fully written yet are left alone
+        tokio::fs::write(&path, b"fghi").await.unwrap();
+        assert_eq!(first_bad_piece(&path, 5, 9, 10, &pieces).await.unwrap(), None);
+
+        // The same range inside a file holding the whole download
+        let whole = dir.path().join("file.part");
+        tokio::fs::write(&whole, b"abcdefghij").await.unwrap();
+        let bad = first_bad_piece_in_place(&whole, 5, 9, 10, &pieces).await.unwrap().unwrap();
+        assert_eq!((bad.index, bad.start), (3, 9));
+        assert_eq!(first_bad_piece_in_place(&whole, 5, 8, 10, &pieces).await.unwrap(), None);
+    }
+}
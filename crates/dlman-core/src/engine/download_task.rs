@@ -2,20 +2,86 @@
 //!
 //! This is the main orchestrator for a single download.
 //! It spawns segment workers, monitors their progress, and merges temp files on completion.
+//! A direct-write download skips the temp files: its segments write straight
+//! into a preallocated `.part` file next to the final one, which is renamed
+//! into place at the end.
 
-use crate::engine::{DownloadDatabase, RateLimiter, SegmentWorker};
+use crate::engine::ftp::{is_ftp_url, FtpClient};
+use crate::engine::mirrors::{Source, SourcePool};
+use crate::engine::sftp::{is_sftp_url, SftpConnection};
+use crate::engine::segment_worker::etags_match;
+use crate::engine::tuning::{ConnectionTuner, TuneAction, INITIAL_CONNECTIONS, SAMPLE_INTERVAL};
+use crate::engine::{
+    first_bad_piece, first_bad_piece_in_place, hash_file, verify_checksum, ChecksumHasher, DownloadDatabase, HostLimiter, HostSlot, HttpAuth,
+    PieceMismatch, RateLimiter, RequestHeaders, SegmentHandle, SegmentResult, SegmentWorker,
+};
 use crate::error::DlmanError;
-use dlman_types::{CoreEvent, Download, DownloadStatus, Segment, TempStorageSettings};
+use dlman_types::{AuthScheme, CoreEvent, Download, DownloadStatus, Mirror, Segment, SshKey, TempStorageSettings};
 use reqwest::Client;
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::broadcast;
 use tokio::task::JoinSet;
 use tracing::{error, info, warn};
 
+/// Smallest range a segment keeps, or hands over, when it is split for work
+/// stealing. Also bounds how far ahead of a worker the split point lands, so
+/// a chunk already being written can't spill into the stolen range.
+const MIN_SPLIT_SIZE: u64 = 1024 * 1024;
+
+/// Times one segment may have a bad piece fetched again before the download
+/// fails with a checksum mismatch
+const MAX_PIECE_REFETCHES: u32 = 3;
+
+/// Wait for a connection slot on the host of `url`. Gives up with
+/// [`DlmanError::Paused`] or [`DlmanError::Cancelled`] if the download is
+/// stopped while it waits.
+async fn wait_for_host_slot(
+    limiter: Option<&HostLimiter>,
+    url: &str,
+    paused: &AtomicBool,
+    cancelled: &AtomicBool,
+) -> Result<Option<HostSlot>, DlmanError> {
+    let Some(limiter) = limiter else {
+        return Ok(None);
+    };
+    // Keep the same future so the place in the queue isn't lost
+    let slot = limiter.acquire(url);
+    tokio::pin!(slot);
+    loop {
+        tokio::select! {
+            slot = &mut slot => return Ok(Some(slot)),
+            _ = tokio::time::sleep(std::time::Duration::from_millis(250)) => {
+                if cancelled.load(Ordering::Acquire) {
+                    return Err(DlmanError::Cancelled);
+                }
+                if paused.load(Ordering::Acquire) {
+                    return Err(DlmanError::Paused);
+                }
+            }
+        }
+    }
+}
+
+/// Choose which running segment to split when a connection goes idle: the
+/// one with the most bytes left, provided both halves would be at least
+/// `min_size`.
+fn pick_split_victim(
+    remaining: impl IntoIterator<Item = (u32, u64)>,
+    min_size: u64,
+) -> Option<u32> {
+    remaining
+        .into_iter()
+        .filter(|&(_, left)| left >= min_size.saturating_mul(2))
+        .max_by_key(|&(index, left)| (left, std::cmp::Reverse(index)))
+        .map(|(index, _)| index)
+}
+
 /// Resolve the scratch directory for a download's partial segment files,
 /// honoring the user's temp-storage policy.
 ///
@@ -55,6 +121,9 @@ pub(crate) fn resolve_segment_cache_dir(
             Some(p) if !p.as_os_str().is_empty() => p.clone(),
             _ => appdata_scratch(),
         },
+        // "direct" writes into the destination file itself and only needs a
+        // scratch directory for downloads started before it was picked; those
+        // are treated like "auto".
         // "auto" (default): prefer the fast system-disk scratch, but fall back to
         // the destination when the system disk can't safely hold the download.
         // This fixes the slow-HDD regression (#10) for typical downloads without
@@ -108,6 +177,17 @@ fn appdata_has_room_for(app_data_dir: &Path, size: Option<u64>) -> bool {
     }
 }
 
+/// The in-progress file of a direct-write download, next to its final file
+pub(crate) fn direct_part_path(download: &Download) -> PathBuf {
+    download.destination.join(format!("{}.part", download.filename))
+}
+
+/// Whether two URLs point at the same host
+fn same_host(a: &str, b: &str) -> bool {
+    let host = |url: &str| url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string));
+    host(a).is_some() && host(a) == host(b)
+}
+
 /// A download task that manages multiple segment workers
 pub struct DownloadTask {
     pub download: Download,
@@ -119,7 +199,8 @@ pub struct DownloadTask {
     paused: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
     total_downloaded: Arc<AtomicU64>,
-    /// Number of segments to use for multi-segment downloads
+    /// Most connections a multi-segment download opens. It starts with
+    /// fewer and adds more while they help (see [`ConnectionTuner`]).
     segment_count: u32,
     /// Maximum number of retries for failed segments
     max_retries: u32,
@@ -127,6 +208,16 @@ pub struct DownloadTask {
     retry_delay_secs: u32,
     /// Optional credentials for authenticated downloads
     credentials: Option<(String, String)>,
+    /// Private key for SFTP logins
+    ssh_key: Option<SshKey>,
+    /// SSH connection whose channels the segments of an SFTP download share
+    sftp: Option<Arc<SftpConnection>>,
+    /// Per-host connection caps shared with the other downloads
+    host_limiter: Option<Arc<HostLimiter>>,
+    /// Write a new download straight into its destination
+    direct_write: bool,
+    /// Extra headers for HTTP requests
+    headers: RequestHeaders,
 }
 
 impl DownloadTask {
@@ -179,6 +270,10 @@ impl DownloadTask {
             download.downloaded
         };
         let total_downloaded = Arc::new(AtomicU64::new(initial_downloaded));
+        // HTTP Basic unless `with_headers` brings a login of another scheme
+        let auth = credentials
+            .clone()
+            .map(|(username, password)| HttpAuth::new(AuthScheme::Basic, username, password, &download.url));
         
         Self {
             download,
@@ -194,15 +289,59 @@ impl DownloadTask {
             max_retries,
             retry_delay_secs,
             credentials,
+            ssh_key: None,
+            sftp: None,
+            host_limiter: None,
+            direct_write: false,
+            headers: RequestHeaders::default().with_auth(auth),
         }
     }
     
+    /// Take connection slots from `limiter` before opening a connection, so
+    /// that all downloads together respect the per-host caps
+    pub fn with_host_limiter(mut self, limiter: Arc<HostLimiter>) -> Self {
+        self.host_limiter = Some(limiter);
+        self
+    }
+    
+    /// Write into a preallocated file in the destination instead of per-segment
+    /// temp files. Only applies when the download starts from scratch; one
+    /// already under way keeps the layout it started with.
+    pub fn with_direct_write(mut self, direct_write: bool) -> Self {
+        self.direct_write = direct_write;
+        self
+    }
+    
+    /// Headers from header rules and the download's overrides, and the saved
+    /// HTTP login, used for every HTTP request: probe, mirror checks and
+    /// segments
+    pub fn with_headers(mut self, headers: RequestHeaders) -> Self {
+        self.headers = headers;
+        self
+    }
+    
+    /// Private key to log in to SFTP sources with
+    pub fn with_ssh_key(mut self, ssh_key: Option<SshKey>) -> Self {
+        self.ssh_key = ssh_key;
+        self
+    }
+    
     /// Get the effective URL for downloading segments
     /// Uses final_url (after redirects) if available, otherwise the original url
     fn effective_url(&self) -> &str {
         self.download.final_url.as_deref().unwrap_or(&self.download.url)
     }
     
+    /// Validators only help servers that honour ranges; for the rest a resume
+    /// starts over anyway, so a 200 reply must not be taken as a change.
+    fn resume_etag(&self) -> Option<String> {
+        self.download.etag.clone().filter(|_| self.download.supports_range)
+    }
+    
+    fn resume_last_modified(&self) -> Option<String> {
+        self.download.last_modified.clone().filter(|_| self.download.supports_range)
+    }
+    
     /// Get the paused flag for external control
     pub fn paused(&self) -> Arc<AtomicBool> {
         self.paused.clone()
@@ -213,6 +352,11 @@ impl DownloadTask {
         self.cancelled.clone()
     }
     
+    /// Get the downloaded-bytes counter for external accounting
+    pub fn downloaded(&self) -> Arc<AtomicU64> {
+        self.total_downloaded.clone()
+    }
+    
     /// Run the download task
     pub async fn run(mut self) -> Result<(), DlmanError> {
         info!("Starting download task for {}: {} with segment_count={}", 
@@ -258,7 +402,10 @@ impl DownloadTask {
         // If no segments, probe URL and initialize them
         if self.download.segments.is_empty() {
             info!("No segments found, initializing...");
-            let supports_range = self.probe_url().await?;
+            let supports_range = match self.probe_url().await {
+                Err(e @ DlmanError::AuthenticationRequired { .. }) => return self.await_credentials(e).await,
+                result => result?,
+            };
             
             // Check for pause/cancel after probe (which might have taken time)
             if self.cancelled.load(Ordering::Acquire) {
@@ -275,10 +422,11 @@ impl DownloadTask {
             }
             
             if supports_range && self.download.size.unwrap_or(0) > 1024 * 1024 && self.segment_count > 1 {
-                // Multi-segment download
-                let num_segments = self.segment_count as usize;
+                // Multi-segment download. Start small; more segments are
+                // split off while they make the download faster.
+                let num_segments = self.initial_connections().await as usize;
                 self.download.segments = self.calculate_segments(num_segments);
-                info!("Initialized {} segments", num_segments);
+                info!("Initialized {} segments (up to {})", num_segments, self.segment_count);
             } else {
                 // Single segment download (no range support, small file, or segment_count=1)
                 let size = self.download.size.unwrap_or(u64::MAX);
@@ -292,6 +440,11 @@ impl DownloadTask {
                 info!("Initialized single segment (no range support or small file)");
             }
             
+            self.download.direct_write = self.direct_write;
+            if self.download.direct_write {
+                self.preallocate().await?;
+            }
+            
             // Save segments to DB
             self.db.upsert_download(&self.download).await?;
             
@@ -299,6 +452,17 @@ impl DownloadTask {
             let _ = self.event_tx.send(CoreEvent::DownloadUpdated {
                 download: self.download.clone(),
             });
+        } else if self.download.direct_write && !direct_part_path(&self.download).exists() {
+            // Nothing of what the segments recorded is on disk any more
+            warn!("Partial file of {} is gone, starting over", self.download.filename);
+            for segment in &mut self.download.segments {
+                segment.downloaded = 0;
+                segment.complete = false;
+            }
+            self.download.downloaded = 0;
+            self.total_downloaded.store(0, Ordering::Release);
+            self.preallocate().await?;
+            self.db.upsert_download(&self.download).await?;
         }
         
         // Check if all segments are already complete (resuming a finished-but-not-merged download)
@@ -308,7 +472,9 @@ impl DownloadTask {
             info!("All segments already complete, skipping to merge");
         } else {
             // Spawn segment workers for incomplete segments
-            let result = if self.download.segments.len() == 1 {
+            let result = if let Err(e) = self.connect_sftp().await {
+                Err(e)
+            } else if self.download.segments.len() == 1 {
                 self.download_single_segment().await
             } else {
                 self.download_multi_segment().await
@@ -326,15 +492,15 @@ impl DownloadTask {
                     self.db.update_download_status(self.download.id, DownloadStatus::Cancelled, None).await?;
                     self.emit_status_change(DownloadStatus::Cancelled, None).await;
                     return Ok(());
+                } else if matches!(e, DlmanError::AuthenticationRequired { .. }) {
+                    return self.await_credentials(e).await;
                 } else {
-                    // Check if this is an authentication error — emit credential required event
-                    if let DlmanError::AuthenticationRequired { ref domain, ref url, status } = e {
-                        info!("Download requires authentication for domain: {}", domain);
-                        let _ = self.event_tx.send(CoreEvent::CredentialRequired {
-                            download_id: self.download.id,
-                            domain: domain.clone(),
+                    // Let the user decide between restarting and aborting
+                    if let DlmanError::ContentChanged { ref url } = e {
+                        warn!("File changed on the server since {} started", self.download.filename);
+                        let _ = self.event_tx.send(CoreEvent::ContentChanged {
+                            id: self.download.id,
                             url: url.clone(),
-                            status_code: status,
                         });
                     }
                     
@@ -351,7 +517,40 @@ impl DownloadTask {
         
         // All segments complete - merge into final file
         info!("All segments complete, merging...");
-        let segment_sizes = self.merge_segments().await?;
+        // Split-off segments get new indices, so index order is not byte order
+        self.download.segments.sort_by_key(|s| s.start);
+        let (segment_sizes, digest) = self.merge_segments().await?;
+
+        // Check the expected checksum against the digest taken while merging
+        if let (Some(expected), Some(actual)) = (self.download.checksum.clone(), digest) {
+            let result = verify_checksum(&expected, &actual);
+            let _ = self.event_tx.send(CoreEvent::ChecksumVerified {
+                id: self.download.id,
+                algorithm: expected.algorithm,
+                expected: expected.value.clone(),
+                actual,
+                matched: result.is_ok(),
+            });
+            if let Err(e) = result {
+                error!("Checksum verification failed for {}: {}", self.download.filename, e);
+                // Don't leave a corrupt file where a finished one is expected
+                let final_path = self.download.destination.join(&self.download.filename);
+                if let Err(remove_err) = tokio::fs::remove_file(&final_path).await {
+                    warn!("Failed to remove corrupt file {:?}: {}", final_path, remove_err);
+                }
+                // The data is bad: a retry has to start over rather than
+                // resume from the (already merged) segments
+                self.download.segments.clear();
+                self.download.downloaded = 0;
+                self.download.status = DownloadStatus::Failed;
+                let error_msg = e.to_string();
+                self.download.error = Some(error_msg.clone());
+                self.db.upsert_download(&self.download).await?;
+                self.emit_status_change(DownloadStatus::Failed, Some(error_msg)).await;
+                return Err(e);
+            }
+            info!("Checksum verified ({}): {}", expected.algorithm.name(), self.download.filename);
+        }
         
         // Update segments with actual file sizes and calculate total downloaded
         let mut total_downloaded: u64 = 0;
@@ -394,37 +593,58 @@ impl DownloadTask {
     
     /// Download with a single segment
     async fn download_single_segment(&mut self) -> Result<(), DlmanError> {
-        let segment = self.download.segments[0].clone();
-        
         // Check if already complete
-        if segment.complete {
+        if self.download.segments[0].complete {
             return Ok(());
         }
         
         // Use final_url if available (after redirects), otherwise use original url
         let url = self.effective_url().to_string();
         
-        let worker = SegmentWorker::new_with_credentials(
-            self.download.id,
-            segment,
-            url,
-            self.temp_dir.clone(),
-            self.client.clone(),
-            self.rate_limiter.clone(),
-            self.db.clone(),
-            self.event_tx.clone(),
-            self.paused.clone(),
-            self.cancelled.clone(),
-            self.total_downloaded.clone(),
-            self.credentials.clone(),
-            self.download.cookies.clone(),
-        );
-        
         // Start progress reporter
         let progress_handle = self.spawn_progress_reporter();
         
-        // Run segment worker
-        let result = worker.run().await;
+        // Run segment worker, again from a bad piece if one is found
+        let mut refetches = 0;
+        let result = loop {
+            let worker = SegmentWorker::new_with_credentials(
+                self.download.id,
+                self.download.segments[0].clone(),
+                url.clone(),
+                self.temp_dir.clone(),
+                self.client.clone(),
+                self.rate_limiter.clone(),
+                self.db.clone(),
+                self.event_tx.clone(),
+                self.paused.clone(),
+                self.cancelled.clone(),
+                self.total_downloaded.clone(),
+                self.credentials.clone(),
+                self.download.cookies.clone(),
+            )
+            .with_validators(self.resume_etag(), self.resume_last_modified())
+            .with_sftp(self.ssh_key.clone(), self.sftp.clone())
+            .with_headers(self.headers.for_url(&url))
+            .with_auth(self.headers.auth().cloned())
+            .with_part_file(self.download.direct_write.then(|| direct_part_path(&self.download)));
+            
+            let result = match wait_for_host_slot(self.host_limiter.as_deref(), &url, &self.paused, &self.cancelled).await {
+                Ok(slot) => worker.with_host_slot(slot).run().await,
+                Err(e) => Err(e),
+            };
+            if result.is_ok() {
+                match self.drop_bad_piece(0).await {
+                    Ok(Some(_)) if refetches < MAX_PIECE_REFETCHES => {
+                        refetches += 1;
+                        continue;
+                    }
+                    Ok(Some(bad)) => break Err(self.piece_mismatch_error(bad)),
+                    Err(e) => break Err(e),
+                    Ok(None) => {}
+                }
+            }
+            break result;
+        };
         
         // Stop progress reporter
         self.cancelled.store(true, Ordering::Release);
@@ -453,8 +673,20 @@ impl DownloadTask {
     }
     
     /// Download with multiple parallel segments
-    async fn download_multi_segment(&self) -> Result<(), DlmanError> {
+    ///
+    /// Whenever a segment finishes while others are still running, the idle
+    /// connection steals the second half of the largest remaining segment
+    /// (see [`pick_split_victim`]), so one slow range can't hold up the whole
+    /// download at the end. Segments are spread across the download's mirrors
+    /// and move to another one when theirs fails.
+    ///
+    /// The number of connections follows a [`ConnectionTuner`]: segments
+    /// beyond its target wait their turn, and a new connection takes a
+    /// queued segment or splits a running one.
+    async fn download_multi_segment(&mut self) -> Result<(), DlmanError> {
         let mut retry_counts: std::collections::HashMap<u32, u32> = std::collections::HashMap::new();
+        let mut piece_refetches: HashMap<u32, u32> = HashMap::new();
+        let mut sources = self.check_mirrors().await?;
         
         // Start progress reporter
         let progress_handle = self.spawn_progress_reporter();
@@ -473,6 +705,10 @@ impl DownloadTask {
             return Ok(());
         }
         
+        let mut tuner = ConnectionTuner::new(self.initial_connections().await, self.segment_count, Instant::now());
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        
         // Main retry loop
         loop {
             if segments_to_download.is_empty() {
@@ -480,35 +716,10 @@ impl DownloadTask {
             }
             
             let mut join_set = JoinSet::new();
-            
-            // Use final_url if available (after redirects), otherwise use original url
-            // This avoids re-resolving redirects for every segment request
-            let url = self.effective_url().to_string();
-            
-            // Spawn a worker for each segment that needs downloading
-            for segment in &segments_to_download {
-                let worker = SegmentWorker::new_with_credentials(
-                    self.download.id,
-                    segment.clone(),
-                    url.clone(),
-                    self.temp_dir.clone(),
-                    self.client.clone(),
-                    self.rate_limiter.clone(),
-                    self.db.clone(),
-                    self.event_tx.clone(),
-                    self.paused.clone(),
-                    self.cancelled.clone(),
-                    self.total_downloaded.clone(),
-                    self.credentials.clone(),
-                    self.download.cookies.clone(),
-                );
-                
-                let segment_index = segment.index;
-                join_set.spawn(async move { 
-                    let result = worker.run().await;
-                    (segment_index, result)
-                });
-            }
+            // Live bounds of every running worker, for work stealing
+            let mut live_segments: HashMap<u32, Arc<SegmentHandle>> = HashMap::new();
+            // Segments waiting for a connection
+            let mut queued: VecDeque<Segment> = std::mem::take(&mut segments_to_download).into();
             
             // Track failed segments for retry
             let mut failed_segments: Vec<Segment> = Vec::new();
@@ -516,10 +727,60 @@ impl DownloadTask {
             let mut was_cancelled = false;
             
             // Wait for all segments to complete
-            while let Some(result) = join_set.join_next().await {
+            loop {
+                if !was_paused && !was_cancelled {
+                    self.fill_connections(&mut join_set, &mut live_segments, &mut sources, &mut queued, &tuner).await?;
+                }
+                
+                let joined = tokio::select! {
+                    result = join_set.join_next() => Some(result),
+                    _ = ticker.tick() => None,
+                };
+                let result = match joined {
+                    Some(Some(result)) => result,
+                    Some(None) => break,
+                    None => {
+                        let connections = live_segments.len() as u32;
+                        let downloaded = self.total_downloaded.load(Ordering::Acquire);
+                        if !was_paused && !was_cancelled
+                            && tuner.sample(connections, downloaded, Instant::now()) == TuneAction::Grow
+                        {
+                            self.fill_connections(&mut join_set, &mut live_segments, &mut sources, &mut queued, &tuner).await?;
+                            let connections = live_segments.len() as u32;
+                            if connections < tuner.target() {
+                                tuner.cannot_grow(connections);
+                            } else {
+                                info!("More connections still help {}; now using {}", self.download.filename, connections);
+                            }
+                        }
+                        continue;
+                    }
+                };
+                
                 match result {
                     Ok((segment_idx, Ok(segment_result))) => {
+                        self.retire_worker(&mut live_segments, segment_idx);
+                        if let Some(bad) = self.drop_bad_piece(segment_idx).await? {
+                            let refetches = piece_refetches.entry(segment_idx).or_insert(0);
+                            *refetches += 1;
+                            if *refetches > MAX_PIECE_REFETCHES {
+                                error!("Segment {} kept failing piece verification", segment_idx);
+                                self.cancelled.store(true, Ordering::Release);
+                                let _ = progress_handle.await;
+                                return Err(self.piece_mismatch_error(bad));
+                            }
+                            // The source may be serving bad data; give another one a go
+                            sources.fail_over(segment_idx, false);
+                            if let Some(seg) = self.download.segments.iter().find(|s| s.index == segment_idx) {
+                                failed_segments.push(seg.clone());
+                            }
+                            continue;
+                        }
                         info!("Segment {} completed", segment_idx);
+                        sources.succeeded(segment_idx);
+                        if let Some(seg) = self.download.segments.iter_mut().find(|s| s.index == segment_idx) {
+                            seg.complete = true;
+                        }
                         // Note: For multi-segment, size should already be known
                         // but if somehow discovered, we could update here
                         if segment_result.discovered_size.is_some() {
@@ -528,24 +789,66 @@ impl DownloadTask {
                     }
                     Ok((segment_idx, Err(DlmanError::Paused))) => {
                         info!("Segment {} paused", segment_idx);
+                        self.retire_worker(&mut live_segments, segment_idx);
                         self.paused.store(true, Ordering::Release);
                         was_paused = true;
                     }
                     Ok((segment_idx, Err(DlmanError::Cancelled))) => {
                         info!("Segment {} cancelled", segment_idx);
+                        self.retire_worker(&mut live_segments, segment_idx);
                         self.cancelled.store(true, Ordering::Release);
                         was_cancelled = true;
                     }
+                    Ok((segment_idx, Err(DlmanError::ContentChanged { url })))
+                        if sources.is_mirror(segment_idx) =>
+                    {
+                        // Only this mirror changed; the file itself is still good
+                        warn!("Mirror {} changed, dropping it", url);
+                        self.retire_worker(&mut live_segments, segment_idx);
+                        sources.fail_over(segment_idx, true);
+                        if let Some(seg) = self.download.segments.iter().find(|s| s.index == segment_idx) {
+                            failed_segments.push(seg.clone());
+                        }
+                    }
+                    Ok((segment_idx, Err(e @ DlmanError::ContentChanged { .. }))) => {
+                        // Retrying can't help: every other segment is stale too
+                        error!("Segment {} found the file changed on the server", segment_idx);
+                        self.cancelled.store(true, Ordering::Release);
+                        let _ = progress_handle.await;
+                        return Err(e);
+                    }
                     Ok((segment_idx, Err(e))) => {
+                        self.retire_worker(&mut live_segments, segment_idx);
+                        let throttled = e.is_throttling();
+                        if throttled {
+                            let remaining = live_segments.len() as u32;
+                            warn!("Server pushed back on segment {} ({}); backing off", segment_idx, e);
+                            tuner.throttled(remaining);
+                            self.remember_connections(&tuner).await;
+                        }
+                        if sources.fail_over(segment_idx, false) {
+                            // Another source takes over without using up a retry
+                            let url = &sources.source_for(segment_idx).url;
+                            warn!("Segment {} failed: {}. Switching to {}", segment_idx, e, url);
+                            if let Some(seg) = self.download.segments.iter().find(|s| s.index == segment_idx) {
+                                failed_segments.push(seg.clone());
+                            }
+                            continue;
+                        }
                         let retry_count = retry_counts.entry(segment_idx).or_insert(0);
                         *retry_count += 1;
                         
                         if *retry_count <= self.max_retries {
                             warn!("Segment {} failed (attempt {}/{}): {}. Will retry.", 
                                   segment_idx, retry_count, self.max_retries, e);
-                            // Find the segment to retry
+                            // Find the segment to retry. A throttled one takes
+                            // the next connection that frees up.
                             if let Some(seg) = self.download.segments.iter().find(|s| s.index == segment_idx) {
-                                failed_segments.push(seg.clone());
+                                if throttled && !live_segments.is_empty() {
+                                    queued.push_back(seg.clone());
+                                } else {
+                                    failed_segments.push(seg.clone());
+                                }
                             }
                         } else {
                             error!("Segment {} failed after {} attempts: {}", segment_idx, self.max_retries, e);
@@ -593,12 +896,11 @@ impl DownloadTask {
                 }
                 
                 segments_to_download = failed_segments;
-            } else {
-                // All segments completed successfully
-                segments_to_download.clear();
             }
         }
         
+        self.remember_connections(&tuner).await;
+        
         // Stop progress reporter
         self.cancelled.store(true, Ordering::Release);
         let _ = progress_handle.await;
@@ -606,6 +908,199 @@ impl DownloadTask {
         Ok(())
     }
     
+    /// Forget a worker that returned. A direct-write segment's progress is
+    /// taken from the worker, as no temp file length tells where a retry
+    /// has to pick up.
+    fn retire_worker(&mut self, live_segments: &mut HashMap<u32, Arc<SegmentHandle>>, segment_index: u32) {
+        let Some(handle) = live_segments.remove(&segment_index) else {
+            return;
+        };
+        if self.download.direct_write {
+            if let Some(segment) = self.download.segments.iter_mut().find(|s| s.index == segment_index) {
+                segment.downloaded = handle.downloaded();
+            }
+        }
+    }
+    
+    /// Create the `.part` file of a direct-write download, at full size when
+    /// the size is known so the disk space is claimed before any data arrives
+    async fn preallocate(&self) -> Result<(), DlmanError> {
+        if let Err(e) = tokio::fs::create_dir_all(&self.download.destination).await {
+            error!("Failed to create destination directory {:?}: {}", self.download.destination, e);
+            return Err(DlmanError::Io(e));
+        }
+        let path = direct_part_path(&self.download);
+        let size = self.download.size.filter(|&size| size > 0);
+        info!("Preallocating {:?} ({:?} bytes)", path, size);
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let file = std::fs::OpenOptions::new().create(true).write(true).truncate(true).open(&path)?;
+            if let Some(size) = size {
+                // Filesystems that can't reserve blocks still get a sparse
+                // file of the right length
+                if fs2::FileExt::allocate(&file, size).is_err() {
+                    file.set_len(size)?;
+                }
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| DlmanError::Unknown(format!("Preallocation task failed: {}", e)))??;
+        Ok(())
+    }
+    
+    /// Start queued segments, then split running ones, until the download
+    /// runs as many connections as `tuner` wants
+    async fn fill_connections(
+        &mut self,
+        join_set: &mut JoinSet<(u32, Result<SegmentResult, DlmanError>)>,
+        live_segments: &mut HashMap<u32, Arc<SegmentHandle>>,
+        sources: &mut SourcePool,
+        queued: &mut VecDeque<Segment>,
+        tuner: &ConnectionTuner,
+    ) -> Result<(), DlmanError> {
+        while (live_segments.len() as u32) < tuner.target() {
+            if let Some(segment) = queued.pop_front() {
+                self.spawn_segment_worker(join_set, live_segments, sources, segment);
+            } else if !self.steal_work(join_set, live_segments, sources).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Host whose connection history applies to this download
+    fn tuning_host(&self) -> Option<String> {
+        url::Url::parse(self.effective_url()).ok()?.host_str().map(str::to_lowercase)
+    }
+    
+    /// Connections to start with: as many as last paid off on this host, or
+    /// [`INITIAL_CONNECTIONS`]
+    async fn initial_connections(&self) -> u32 {
+        let remembered = match self.tuning_host() {
+            Some(host) => self.db.load_host_connections(&host).await.unwrap_or_else(|e| {
+                warn!("Failed to load connection history for {}: {}", host, e);
+                None
+            }),
+            None => None,
+        };
+        remembered.unwrap_or(INITIAL_CONNECTIONS).clamp(1, self.segment_count.max(1))
+    }
+    
+    /// Remember the connection count that paid off, to start with next time
+    async fn remember_connections(&self, tuner: &ConnectionTuner) {
+        let (Some(host), Some(best)) = (self.tuning_host(), tuner.best()) else {
+            return;
+        };
+        if let Err(e) = self.db.save_host_connections(&host, best).await {
+            warn!("Failed to save connection history for {}: {}", host, e);
+        }
+    }
+    
+    /// Spawn a worker for `segment` and register its live bounds
+    fn spawn_segment_worker(
+        &self,
+        join_set: &mut JoinSet<(u32, Result<SegmentResult, DlmanError>)>,
+        live_segments: &mut HashMap<u32, Arc<SegmentHandle>>,
+        sources: &mut SourcePool,
+        segment: Segment,
+    ) {
+        let segment_index = segment.index;
+        let source = sources.source_for(segment_index).clone();
+        // Credentials and cookies belong to the primary host; don't leak
+        // them to mirrors elsewhere
+        let same_host = same_host(&source.url, &self.download.url);
+        let primary = source.url == self.effective_url();
+        let url = source.url.clone();
+        if sources.is_mirror(segment_index) {
+            info!("Segment {} uses mirror {}", segment_index, source.url);
+        }
+        
+        let worker = SegmentWorker::new_with_credentials(
+            self.download.id,
+            segment,
+            source.url,
+            self.temp_dir.clone(),
+            self.client.clone(),
+            self.rate_limiter.clone(),
+            self.db.clone(),
+            self.event_tx.clone(),
+            self.paused.clone(),
+            self.cancelled.clone(),
+            self.total_downloaded.clone(),
+            self.credentials.clone().filter(|_| same_host),
+            self.download.cookies.clone().filter(|_| same_host),
+        )
+        .with_validators(source.etag, source.last_modified)
+        .with_sftp(
+            self.ssh_key.clone().filter(|_| same_host),
+            self.sftp.clone().filter(|_| primary),
+        )
+        .with_headers(self.headers.for_url(&url))
+        .with_auth(self.headers.auth().cloned())
+        .with_part_file(self.download.direct_write.then(|| direct_part_path(&self.download)));
+        live_segments.insert(segment_index, worker.handle());
+        
+        let host_limiter = self.host_limiter.clone();
+        let (paused, cancelled) = (self.paused.clone(), self.cancelled.clone());
+        join_set.spawn(async move { 
+            let result = match wait_for_host_slot(host_limiter.as_deref(), &url, &paused, &cancelled).await {
+                Ok(slot) => worker.with_host_slot(slot).run().await,
+                Err(e) => Err(e),
+            };
+            (segment_index, result)
+        });
+    }
+    
+    /// Split the largest running segment and hand its tail to a new worker.
+    ///
+    /// The victim keeps downloading up to the split point; the new segment gets
+    /// the next free index (and its own temp file), and the new boundaries are
+    /// persisted right away so a resume after a crash sees the same layout.
+    /// Returns whether a worker was started.
+    async fn steal_work(
+        &mut self,
+        join_set: &mut JoinSet<(u32, Result<SegmentResult, DlmanError>)>,
+        live_segments: &mut HashMap<u32, Arc<SegmentHandle>>,
+        sources: &mut SourcePool,
+    ) -> Result<bool, DlmanError> {
+        // A segment still waiting for a host slot will take the one just freed
+        if live_segments.values().any(|handle| !handle.started()) {
+            return Ok(false);
+        }
+        let Some(victim_index) = pick_split_victim(
+            live_segments.iter().map(|(index, handle)| (*index, handle.remaining())),
+            MIN_SPLIT_SIZE,
+        ) else {
+            return Ok(false);
+        };
+        
+        // The victim may have progressed since we looked; try_split re-checks
+        let Some((start, end)) = live_segments[&victim_index].try_split(MIN_SPLIT_SIZE, self.piece_alignment()) else {
+            return Ok(false);
+        };
+        
+        let new_index = self.download.segments.iter().map(|s| s.index).max().unwrap_or(0) + 1;
+        let stolen = Segment::new(new_index, start, end);
+        
+        if let Some(victim) = self.download.segments.iter_mut().find(|s| s.index == victim_index) {
+            victim.end = start - 1;
+        }
+        self.download.segments.push(stolen.clone());
+        self.db.split_segment(self.download.id, victim_index, start - 1, &stolen).await?;
+        
+        info!(
+            "Split segment {} at byte {}: segment {} takes {}-{}",
+            victim_index, start, new_index, start, end
+        );
+        
+        let _ = self.event_tx.send(CoreEvent::DownloadUpdated {
+            download: self.download.clone(),
+        });
+        
+        self.spawn_segment_worker(join_set, live_segments, sources, stolen);
+        Ok(true)
+    }
+    
     /// Spawn a background task to report progress periodically
     fn spawn_progress_reporter(&self) -> tokio::task::JoinHandle<()> {
         let download_id = self.download.id;
@@ -699,19 +1194,23 @@ impl DownloadTask {
     /// Probe URL to determine if range requests are supported
     /// Uses HEAD first, then falls back to partial GET for size/resumability detection
     async fn probe_url(&mut self) -> Result<bool, DlmanError> {
-        let mut request = self.client.head(&self.download.url);
-        
-        // Apply credentials if available
-        if let Some((ref username, ref password)) = self.credentials {
-            request = request.basic_auth(username, Some(password));
+        let _slot = wait_for_host_slot(self.host_limiter.as_deref(), &self.download.url, &self.paused, &self.cancelled).await?;
+        if is_ftp_url(&self.download.url) {
+            return self.probe_ftp().await;
+        }
+        if is_sftp_url(&self.download.url) {
+            return self.probe_sftp().await;
         }
         
+        let mut request = self.client.head(&self.download.url);
+        
         // Apply browser cookies if available (session-based auth)
         if let Some(ref cookies) = self.download.cookies {
             request = request.header(reqwest::header::COOKIE, cookies);
         }
         
-        let response = request.send().await?;
+        // Headers and the saved login (Basic, Digest or Bearer)
+        let response = self.headers.send(request, &self.download.url).await?;
         
         // Check for authentication required
         let status = response.status();
@@ -735,6 +1234,10 @@ impl DownloadTask {
             .map(|s| s == "bytes")
             .unwrap_or(false);
         
+        // Remember which version of the file we start with, so a resume can
+        // tell whether it changed in the meantime
+        self.record_validators(response.headers());
+        
         // Update size if we don't have it
         if self.download.size.is_none() {
             self.download.size = response
@@ -764,13 +1267,16 @@ impl DownloadTask {
             if let Some(ref cookies) = self.download.cookies {
                 range_req = range_req.header(reqwest::header::COOKIE, cookies);
             }
-            
-            match range_req.send().await
+            match self.headers.send(range_req, probe_url).await
             {
                 Ok(range_response) => {
                     let status = range_response.status();
                     info!("Partial GET status: {}", status);
                     
+                    if self.download.etag.is_none() && self.download.last_modified.is_none() {
+                        self.record_validators(range_response.headers());
+                    }
+                    
                     // Check for 206 Partial Content - means range is supported
                     if status == reqwest::StatusCode::PARTIAL_CONTENT {
                         supports_range = true;
@@ -809,9 +1315,236 @@ impl DownloadTask {
             }
         }
         
+        self.download.supports_range = supports_range;
         Ok(supports_range)
     }
     
+    /// Probe an FTP source: `SIZE` for the size, `MDTM` as the validator a
+    /// resume is checked against, and `REST` support for segmenting
+    async fn probe_ftp(&mut self) -> Result<bool, DlmanError> {
+        let mut client = FtpClient::connect(&self.download.url, self.credentials.as_ref()).await?;
+        let info = client.probe().await?;
+        info!("FTP probe of {}: {:?}", self.download.url, info);
+        
+        if self.download.size.is_none() {
+            self.download.size = info.size;
+        }
+        self.download.etag = None;
+        self.download.last_modified = info.modified;
+        self.download.supports_range = info.supports_rest;
+        Ok(info.supports_rest)
+    }
+    
+    /// Probe an SFTP source. The connection is kept for the segment workers,
+    /// which each read over a channel of their own.
+    async fn probe_sftp(&mut self) -> Result<bool, DlmanError> {
+        let connection = self
+            .connect_sftp()
+            .await?
+            .ok_or_else(|| DlmanError::InvalidUrl(self.download.url.clone()))?;
+        let info = connection.probe().await?;
+        
+        if self.download.size.is_none() {
+            self.download.size = info.size;
+        }
+        self.download.etag = None;
+        self.download.last_modified = info.modified;
+        self.download.supports_range = true;
+        Ok(true)
+    }
+    
+    /// Log in to the SFTP server of the download, unless that was done
+    /// already. Returns `None` when the source is not SFTP.
+    async fn connect_sftp(&mut self) -> Result<Option<Arc<SftpConnection>>, DlmanError> {
+        if self.sftp.is_none() && is_sftp_url(self.effective_url()) {
+            let connection =
+                SftpConnection::connect(self.effective_url(), self.credentials.as_ref(), self.ssh_key.as_ref()).await?;
+            self.sftp = Some(Arc::new(connection));
+        }
+        Ok(self.sftp.clone())
+    }
+    
+    /// Build the sources for a multi-segment download: the primary URL plus
+    /// every mirror that passes [`Self::check_mirror`]. Validators seen on a
+    /// mirror's first use are saved, so later resumes can tell if it changed.
+    async fn check_mirrors(&mut self) -> Result<SourcePool, DlmanError> {
+        let mut sources = SourcePool::new(Source::new(
+            self.effective_url().to_string(),
+            self.resume_etag(),
+            self.resume_last_modified(),
+        ));
+        if self.download.mirrors.is_empty() {
+            return Ok(sources);
+        }
+        
+        let checks = self.download.mirrors.iter().map(|mirror| self.check_mirror(mirror));
+        let results = futures::future::join_all(checks).await;
+        
+        let mut updated = false;
+        for (mirror, result) in self.download.mirrors.iter_mut().zip(results) {
+            match result {
+                Ok(checked) => {
+                    updated |= checked != *mirror;
+                    sources.push(Source::new(
+                        checked.url.clone(),
+                        checked.etag.clone(),
+                        checked.last_modified.clone(),
+                    ));
+                    *mirror = checked;
+                }
+                Err(reason) => warn!("Not using mirror {}: {}", mirror.url, reason),
+            }
+        }
+        info!("Downloading {} from {} source(s)", self.download.filename, sources.len());
+        
+        if updated {
+            self.db.upsert_download(&self.download).await?;
+        }
+        Ok(sources)
+    }
+    
+    /// Check that a mirror serves ranges of the same file as the primary URL.
+    ///
+    /// Returns the mirror with the validators it reported, or why it can't be
+    /// used.
+    async fn check_mirror(&self, mirror: &Mirror) -> Result<Mirror, String> {
+        let size = self.download.size.ok_or("size of the file is unknown")?;
+        let _slot = wait_for_host_slot(self.host_limiter.as_deref(), &mirror.url, &self.paused, &self.cancelled)
+            .await
+            .map_err(|e| e.to_string())?;
+        if is_ftp_url(&mirror.url) {
+            return self.check_ftp_mirror(mirror, size).await;
+        }
+        if is_sftp_url(&mirror.url) {
+            return self.check_sftp_mirror(mirror, size).await;
+        }
+        
+        let mut request = self
+            .client
+            .get(&mirror.url)
+            .header(reqwest::header::RANGE, "bytes=0-0");
+        // The saved login only goes to its own host
+        if same_host(&mirror.url, &self.download.url) {
+            if let Some(ref cookies) = self.download.cookies {
+                request = request.header(reqwest::header::COOKIE, cookies);
+            }
+        }
+        let response = self.headers.send(request, &mirror.url).await.map_err(|e| e.to_string())?;
+        
+        let status = response.status();
+        if status != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(format!("no range support (HTTP {})", status.as_u16()));
+        }
+        let total = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok());
+        if total != Some(size) {
+            return Err(match total {
+                Some(total) => format!("size {} differs from {}", total, size),
+                None => "size unknown".to_string(),
+            });
+        }
+        
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let changed = match (&mirror.etag, &etag, &mirror.last_modified, &last_modified) {
+            (Some(known), Some(current), _, _) => !etags_match(known, current),
+            (_, _, Some(known), Some(current)) => known != current,
+            _ => false,
+        };
+        if changed {
+            return Err("file changed since the mirror was first used".to_string());
+        }
+        
+        Ok(Mirror {
+            url: mirror.url.clone(),
+            etag: etag.or_else(|| mirror.etag.clone()),
+            last_modified: last_modified.or_else(|| mirror.last_modified.clone()),
+        })
+    }
+    
+    /// [`Self::check_mirror`] for an FTP mirror, with `MDTM` as its validator
+    async fn check_ftp_mirror(&self, mirror: &Mirror, size: u64) -> Result<Mirror, String> {
+        let credentials = self.credentials.as_ref().filter(|_| same_host(&mirror.url, &self.download.url));
+        let mut client = FtpClient::connect(&mirror.url, credentials).await.map_err(|e| e.to_string())?;
+        let info = client.probe().await.map_err(|e| e.to_string())?;
+        
+        if !info.supports_rest {
+            return Err("no REST support".to_string());
+        }
+        if info.size != Some(size) {
+            return Err(match info.size {
+                Some(total) => format!("size {} differs from {}", total, size),
+                None => "size unknown".to_string(),
+            });
+        }
+        if let (Some(known), Some(current)) = (&mirror.last_modified, &info.modified) {
+            if known != current {
+                return Err("file changed since the mirror was first used".to_string());
+            }
+        }
+        
+        Ok(Mirror {
+            url: mirror.url.clone(),
+            etag: None,
+            last_modified: info.modified.or_else(|| mirror.last_modified.clone()),
+        })
+    }
+    
+    /// [`Self::check_mirror`] for an SFTP mirror, with the modification time
+    /// as its validator
+    async fn check_sftp_mirror(&self, mirror: &Mirror, size: u64) -> Result<Mirror, String> {
+        let same_host = same_host(&mirror.url, &self.download.url);
+        let connection = SftpConnection::connect(
+            &mirror.url,
+            self.credentials.as_ref().filter(|_| same_host),
+            self.ssh_key.as_ref().filter(|_| same_host),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        let info = connection.probe().await.map_err(|e| e.to_string())?;
+        
+        if info.size != Some(size) {
+            return Err(match info.size {
+                Some(total) => format!("size {} differs from {}", total, size),
+                None => "size unknown".to_string(),
+            });
+        }
+        if let (Some(known), Some(current)) = (&mirror.last_modified, &info.modified) {
+            if known != current {
+                return Err("file changed since the mirror was first used".to_string());
+            }
+        }
+        
+        Ok(Mirror {
+            url: mirror.url.clone(),
+            etag: None,
+            last_modified: info.modified.or_else(|| mirror.last_modified.clone()),
+        })
+    }
+    
+    /// Store the `ETag` and `Last-Modified` of a probe response
+    fn record_validators(&mut self, headers: &reqwest::header::HeaderMap) {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        self.download.etag = header(reqwest::header::ETAG);
+        self.download.last_modified = header(reqwest::header::LAST_MODIFIED);
+    }
+    
     /// Calculate segments for multi-segment download
     fn calculate_segments(&self, num_segments: usize) -> Vec<Segment> {
         let total_size = self.download.size.unwrap_or(0);
@@ -826,15 +1559,20 @@ impl DownloadTask {
             }];
         }
         
-        let segment_size = total_size / num_segments as u64;
+        // Segment boundaries fall on piece boundaries, so every piece can be
+        // verified as soon as its segment completes
+        let segment_size = (total_size / num_segments as u64).div_ceil(self.piece_alignment()) * self.piece_alignment();
         let mut segments = Vec::new();
         
         for i in 0..num_segments {
             let start = i as u64 * segment_size;
+            if start >= total_size {
+                break;
+            }
             let end = if i == num_segments - 1 {
                 total_size.saturating_sub(1)
             } else {
-                (i as u64 + 1) * segment_size - 1
+                ((i as u64 + 1) * segment_size - 1).min(total_size - 1)
             };
             
             segments.push(Segment {
@@ -848,11 +1586,79 @@ impl DownloadTask {
         
         segments
     }
+
+    /// Granularity of segment boundaries: the piece length when the download
+    /// has piece hashes
+    fn piece_alignment(&self) -> u64 {
+        self.download.piece_hashes.as_ref().map_or(1, |p| p.length.max(1))
+    }
+
+    /// Verify the finished pieces of a segment against the download's piece
+    /// hashes. A bad piece and everything after it are cut from the part file,
+    /// so the next worker for the segment fetches them again. In a direct-write
+    /// file they stay put and are written over.
+    async fn drop_bad_piece(&mut self, segment_index: u32) -> Result<Option<PieceMismatch>, DlmanError> {
+        let (Some(pieces), Some(size)) = (self.download.piece_hashes.as_ref(), self.download.size) else {
+            return Ok(None);
+        };
+        let Some(segment) = self.download.segments.iter().find(|s| s.index == segment_index) else {
+            return Ok(None);
+        };
+        let (start, end) = (segment.start, segment.end);
+        let path = self.temp_dir.join(format!("{}_segment_{}.part", self.download.id, segment_index));
+        let bad = if self.download.direct_write {
+            first_bad_piece_in_place(&direct_part_path(&self.download), start, end, size, pieces).await?
+        } else {
+            first_bad_piece(&path, start, end, size, pieces).await?
+        };
+        let Some(bad) = bad else {
+            return Ok(None);
+        };
+        
+        // Without range support the segment can only be fetched from the start
+        let keep = if self.download.supports_range { bad.start - start } else { 0 };
+        let written = if self.download.direct_write {
+            // The worker finished, so the whole range was written
+            end - start + 1
+        } else {
+            let file = OpenOptions::new().write(true).open(&path).await?;
+            let written = file.metadata().await?.len();
+            file.set_len(keep).await?;
+            written
+        };
+        let _ = self.total_downloaded.fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+            Some(total.saturating_sub(written - keep))
+        });
+        if let Some(segment) = self.download.segments.iter_mut().find(|s| s.index == segment_index) {
+            segment.downloaded = keep;
+            segment.complete = false;
+        }
+        self.db.update_segment_progress(self.download.id, segment_index, keep, false).await?;
+        
+        warn!(
+            "Piece {} of {} failed verification, fetching segment {} again from byte {}",
+            bad.index, self.download.filename, segment_index, start + keep
+        );
+        Ok(Some(bad))
+    }
+
+    fn piece_mismatch_error(&self, bad: PieceMismatch) -> DlmanError {
+        let pieces = self.download.piece_hashes.as_ref();
+        DlmanError::ChecksumMismatch {
+            algorithm: pieces.map_or("", |p| p.algorithm.name()).to_string(),
+            expected: pieces.and_then(|p| p.hashes.get(bad.index)).cloned().unwrap_or_default(),
+            actual: bad.actual,
+        }
+    }
     
     /// Merge all segment temp files into the final file
-    /// Returns a vector of actual sizes for each segment (useful for unknown-size downloads)
-    async fn merge_segments(&self) -> Result<Vec<u64>, DlmanError> {
+    /// Returns a vector of actual sizes for each segment (useful for unknown-size downloads),
+    /// and the hex digest of the merged file when the download has an expected checksum
+    async fn merge_segments(&self) -> Result<(Vec<u64>, Option<String>), DlmanError> {
         let final_path = self.download.destination.join(&self.download.filename);
+        if self.download.direct_write {
+            return self.finish_direct_write(&final_path).await;
+        }
         
         info!("Merging {} segments into {:?}", self.download.segments.len(), final_path);
         
@@ -901,6 +1707,9 @@ impl DownloadTask {
                 }
             };
         
+        // Hash while copying, so verification needs no second read of the file
+        let mut hasher = self.download.checksum.as_ref().map(|c| ChecksumHasher::new(c.algorithm));
+        
         // Copy each segment in order
         for segment in &self.download.segments {
             let temp_path = self.temp_dir.join(format!(
@@ -935,6 +1744,9 @@ impl DownloadTask {
                     error!("Failed to write to final file {:?}: {}", final_path, e);
                     return Err(DlmanError::Io(e));
                 }
+                if let Some(ref mut hasher) = hasher {
+                    hasher.update(&buffer[..n]);
+                }
             }
             
             info!("Segment {} copied successfully", segment.index);
@@ -954,7 +1766,47 @@ impl DownloadTask {
         let _ = tokio::fs::remove_dir(&self.temp_dir).await;
 
         info!("Merge complete: {:?}", final_path);
-        Ok(segment_sizes)
+        Ok((segment_sizes, hasher.map(ChecksumHasher::finalize_hex)))
+    }
+    
+    /// Rename a direct-write download's `.part` file to its final name. Nothing
+    /// is copied; the file is only read again if there's a checksum to take.
+    async fn finish_direct_write(&self, final_path: &Path) -> Result<(Vec<u64>, Option<String>), DlmanError> {
+        let part_path = direct_part_path(&self.download);
+        let length = match tokio::fs::metadata(&part_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                error!("Partial file missing: {:?} - download corrupted: {}", part_path, e);
+                return Err(DlmanError::Unknown(
+                    "Partial file missing - download may be corrupted. Delete and restart.".to_string(),
+                ));
+            }
+        };
+        let segment_sizes = self.download.segments
+            .iter()
+            .map(|s| if s.is_unknown_size() { length.saturating_sub(s.start) } else { s.end - s.start + 1 })
+            .collect();
+        
+        let digest = match &self.download.checksum {
+            Some(checksum) => Some(hash_file(&part_path, checksum.algorithm).await?),
+            None => None,
+        };
+        
+        info!("Moving {:?} into place as {:?}", part_path, final_path);
+        // Renaming over an existing file fails on Windows
+        if tokio::fs::try_exists(final_path).await.unwrap_or(false) {
+            tokio::fs::remove_file(final_path).await?;
+        }
+        if let Err(e) = tokio::fs::rename(&part_path, final_path).await {
+            error!("Failed to rename {:?} to {:?}: {}", part_path, final_path, e);
+            return Err(DlmanError::Io(e));
+        }
+        
+        // Nothing was written to the scratch directory; drop it if it's empty
+        let _ = tokio::fs::remove_dir(&self.temp_dir).await;
+        
+        info!("Download file in place: {:?}", final_path);
+        Ok((segment_sizes, digest))
     }
     
     /// Pause the download
@@ -985,6 +1837,26 @@ impl DownloadTask {
         self.cancelled.load(Ordering::Acquire)
     }
     
+    /// Stop after the server rejected the login: the download waits in
+    /// [`DownloadStatus::AwaitingAuth`] and the user is asked for credentials
+    async fn await_credentials(&mut self, e: DlmanError) -> Result<(), DlmanError> {
+        let error_msg = e.to_string();
+        warn!("Download needs credentials: {} - {}", self.download.filename, error_msg);
+        self.download.status = DownloadStatus::AwaitingAuth;
+        self.download.error = Some(error_msg.clone());
+        self.db.update_download_status(self.download.id, DownloadStatus::AwaitingAuth, Some(error_msg.clone())).await?;
+        self.emit_status_change(DownloadStatus::AwaitingAuth, Some(error_msg)).await;
+        if let DlmanError::AuthenticationRequired { ref domain, ref url, status } = e {
+            let _ = self.event_tx.send(CoreEvent::CredentialRequired {
+                download_id: self.download.id,
+                domain: domain.clone(),
+                url: url.clone(),
+                status_code: status,
+            });
+        }
+        Err(e)
+    }
+    
     /// Emit status change event
     async fn emit_status_change(&self, status: DownloadStatus, error: Option<String>) {
         let _ = self.event_tx.send(CoreEvent::DownloadStatusChanged {
@@ -995,9 +1867,121 @@ impl DownloadTask {
     }
 }
 
+/// How [`range_server`] answers
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ServerOpts {
+    /// Answer every request with a 500
+    pub fail: bool,
+    /// Answer connections beyond this many open at once with a 503
+    pub limit: Option<u64>,
+    /// Only serve requests with this header line (lowercase, e.g.
+    /// `"x-api-key: secret"`); ask the others for a Basic login
+    pub require_header: Option<&'static str>,
+}
+
+/// A server started by [`range_server`]
+#[cfg(test)]
+pub(crate) struct TestServer {
+    pub url: String,
+    /// Connections accepted
+    pub hits: Arc<AtomicU64>,
+    /// Most connections that were open at once
+    pub peak: Arc<AtomicU64>,
+    /// Requests refused for lacking the required header
+    pub refused: Arc<AtomicU64>,
+}
+
+/// Serve `body` with range support for tests, each connection in parallel
+/// and slowly. A connection counts as open from shortly after it is accepted
+/// until the client closes it.
+#[cfg(test)]
+pub(crate) async fn range_server(body: Vec<u8>, opts: ServerOpts) -> TestServer {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = TestServer {
+        url: format!("http://{}/file.bin", listener.local_addr().unwrap()),
+        hits: Arc::new(AtomicU64::new(0)),
+        peak: Arc::new(AtomicU64::new(0)),
+        refused: Arc::new(AtomicU64::new(0)),
+    };
+    let (hits, peak, refused) = (server.hits.clone(), server.peak.clone(), server.refused.clone());
+    let body = Arc::new(body);
+    let open = Arc::new(AtomicU64::new(0));
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            hits.fetch_add(1, Ordering::AcqRel);
+            let (body, open, peak, refused) = (body.clone(), open.clone(), peak.clone(), refused.clone());
+            tokio::spawn(async move {
+                let mut request = vec![0u8; 4096];
+                let n = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]).to_lowercase();
+                if opts.fail {
+                    let _ = socket
+                        .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                        .await;
+                    return;
+                }
+                if opts.require_header.is_some_and(|line| !request.contains(&format!("\r\n{}\r\n", line))) {
+                    refused.fetch_add(1, Ordering::AcqRel);
+                    let _ = socket
+                        .write_all(
+                            b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"test\"\r\n\
+                              Content-Length: 0\r\nConnection: close\r\n\r\n",
+                        )
+                        .await;
+                    return;
+                }
+
+                // Let the close of the connection this one replaces be seen first
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                let now_open = open.fetch_add(1, Ordering::AcqRel) + 1;
+                if opts.limit.is_some_and(|limit| now_open > limit) {
+                    let _ = socket
+                        .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                        .await;
+                    open.fetch_sub(1, Ordering::AcqRel);
+                    return;
+                }
+                peak.fetch_max(now_open, Ordering::AcqRel);
+
+                let (start, end) = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .map(|(a, b)| (a.parse::<usize>().unwrap(), b.parse::<usize>().unwrap_or(body.len() - 1)))
+                    .unwrap_or((0, body.len() - 1));
+                let head = format!(
+                    "HTTP/1.1 206 Partial Content\r\nETag: \"m\"\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    start, end, body.len(), end - start + 1
+                );
+                let body = if request.starts_with("head ") { &[][..] } else { &body[start..=end] };
+                let mut closed = [0u8; 1];
+                let (mut read, mut write) = socket.split();
+                let send = async {
+                    let _ = write.write_all(head.as_bytes()).await;
+                    for chunk in body.chunks(64 * 1024) {
+                        if write.write_all(chunk).await.is_err() {
+                            break;
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                    }
+                    std::future::pending::<()>().await;
+                };
+                tokio::select! {
+                    _ = send => {}
+                    _ = read.read(&mut closed) => {}
+                }
+                open.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+    });
+    server
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dlman_types::{HeaderRule, HostLimitSettings, RequestHeader};
 
     fn policy(mode: &str, custom: Option<&str>) -> TempStorageSettings {
         TempStorageSettings {
@@ -1006,6 +1990,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn split_victim_is_segment_with_most_remaining() {
+        let mib = 1024 * 1024;
+        let victim = pick_split_victim([(0, 3 * mib), (1, 9 * mib), (2, 5 * mib)], mib);
+        assert_eq!(victim, Some(1));
+    }
+
+    #[test]
+    fn split_victim_requires_room_for_two_halves() {
+        let mib = 1024 * 1024;
+        assert_eq!(pick_split_victim([(0, mib), (1, 2 * mib - 1)], mib), None);
+        assert_eq!(pick_split_victim([(0, 2 * mib)], mib), Some(0));
+        assert_eq!(pick_split_victim(std::iter::empty(), mib), None);
+    }
+
     #[test]
     fn destination_mode_uses_hidden_dir_beside_file() {
         let dir = resolve_segment_cache_dir(
@@ -1081,4 +2080,326 @@ mod tests {
         assert!(dirs.contains(&PathBuf::from("/home/u/.local/share/dlman/temp")));
         assert!(dirs.contains(&PathBuf::from("/fast/ssd/scratch")));
     }
+
+    /// What a finished [`run_task`] left behind
+    struct TaskRun {
+        result: Result<(), DlmanError>,
+        /// The download as stored afterwards
+        stored: Download,
+        events: Vec<CoreEvent>,
+        db: DownloadDatabase,
+    }
+
+    /// Save `download` in a database next to its destination and run it with
+    /// four connections, three retries and scratch files in `temp` there.
+    /// `setup` adds what the test needs to the task.
+    async fn run_task(download: &Download, setup: impl FnOnce(DownloadTask) -> DownloadTask) -> TaskRun {
+        std::fs::create_dir_all(&download.destination).unwrap();
+        let db = DownloadDatabase::new(download.destination.join("dlman.db")).await.unwrap();
+        db.upsert_download(download).await.unwrap();
+
+        let (event_tx, mut event_rx) = broadcast::channel(1024);
+        let task = DownloadTask::new(
+            download.clone(), download.destination.join("temp"), Client::builder().no_proxy().build().unwrap(),
+            RateLimiter::unlimited(), db.clone(), event_tx,
+            Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)), 4, 3, 0,
+        );
+        let result = setup(task).run().await;
+
+        let mut events = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            events.push(event);
+        }
+        let stored = db.load_download(download.id).await.unwrap().unwrap();
+        TaskRun { result, stored, events, db }
+    }
+
+    /// A download of `size` bytes from `url` to `destination`, already split
+    /// into four segments
+    fn quartered(url: String, destination: &Path, size: u64) -> Download {
+        let mut download = Download::new(url, destination.to_path_buf(), uuid::Uuid::nil());
+        download.size = Some(size);
+        download.supports_range = true;
+        let quarter = size / 4;
+        download.segments = (0..4).map(|i| Segment::new(i, i as u64 * quarter, (i as u64 + 1) * quarter - 1)).collect();
+        download
+    }
+
+    /// Run a task whose two segments are already downloaded, so it goes
+    /// straight to merging and verification.
+    async fn run_merge_only(checksum: &str) -> (TaskRun, Option<Vec<u8>>) {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = dir.path().join("temp");
+        tokio::fs::create_dir_all(&temp_dir).await.unwrap();
+
+        let mut download = Download::new("https://example.com/abc".to_string(), dir.path().to_path_buf(), uuid::Uuid::nil());
+        download.size = Some(3);
+        download.checksum = Some(checksum.parse().unwrap());
+        download.segments = [(0, b"a".as_slice()), (1, b"bc".as_slice())]
+            .into_iter()
+            .map(|(index, data)| {
+                let path = temp_dir.join(format!("{}_segment_{}.part", download.id, index));
+                std::fs::write(path, data).unwrap();
+                Segment { index, start: index as u64, end: index as u64 * 2, downloaded: data.len() as u64, complete: true }
+            })
+            .collect();
+
+        let run = run_task(&download, |task| task).await;
+        let merged = std::fs::read(dir.path().join(&download.filename)).ok();
+        (run, merged)
+    }
+
+    #[tokio::test]
+    async fn merged_file_is_verified_against_checksum() {
+        let (run, merged) = run_merge_only("md5:900150983cd24fb0d6963f7d28e17f72").await;
+        assert!(run.result.is_ok());
+        assert_eq!(merged.as_deref(), Some(b"abc".as_slice()));
+        assert_eq!(run.stored.status, DownloadStatus::Completed);
+        assert!(run.events.iter().any(|e| matches!(e, CoreEvent::ChecksumVerified { matched: true, .. })));
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_fails_download() {
+        let (run, merged) = run_merge_only("md5:00000000000000000000000000000000").await;
+        assert!(matches!(run.result, Err(DlmanError::ChecksumMismatch { .. })));
+        // The corrupt file is not left at the destination
+        assert!(merged.is_none());
+        assert_eq!(run.stored.status, DownloadStatus::Failed);
+        assert!(run.stored.error.unwrap().contains("900150983cd24fb0d6963f7d28e17f72"));
+        // A retry starts over instead of resuming from the bad data
+        assert!(run.stored.segments.is_empty());
+        assert!(run.events.iter().any(|e| matches!(
+            e,
+            CoreEvent::ChecksumVerified { matched: false, actual, .. } if actual == "900150983cd24fb0d6963f7d28e17f72"
+        )));
+    }
+
+    /// Serve every request with a full `200 OK` of a new version of the file,
+    /// as a server does when `If-Range` no longer matches.
+    async fn serve_replaced_file() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = vec![0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let body = "new contents";
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/file.bin", addr)
+    }
+
+    #[tokio::test]
+    async fn resume_of_changed_file_fails_with_content_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = dir.path().join("temp");
+        tokio::fs::create_dir_all(&temp_dir).await.unwrap();
+
+        let url = serve_replaced_file().await;
+        let mut download = Download::new(url.clone(), dir.path().to_path_buf(), uuid::Uuid::nil());
+        download.size = Some(20);
+        download.etag = Some("\"v1\"".to_string());
+        download.supports_range = true;
+        download.segments = vec![Segment { index: 0, start: 0, end: 19, downloaded: 4, complete: false }];
+        let part = temp_dir.join(format!("{}_segment_0.part", download.id));
+        std::fs::write(&part, b"old ").unwrap();
+
+        let run = run_task(&download, |task| task).await;
+        assert!(matches!(run.result, Err(DlmanError::ContentChanged { .. })));
+        assert_eq!(run.stored.status, DownloadStatus::Failed);
+        assert_eq!(run.stored.etag.as_deref(), Some("\"v1\""));
+        // Nothing was appended to the old data
+        assert_eq!(std::fs::read(&part).unwrap(), b"old ");
+        assert!(run.events.iter().any(|e| matches!(e, CoreEvent::ContentChanged { url: u, .. } if *u == url)));
+    }
+
+    /// Run a four-segment download of `body` from `primary` and `mirrors`
+    async fn run_with_mirrors(body: &[u8], primary: String, mirrors: Vec<String>) -> Download {
+        let dir = tempfile::tempdir().unwrap();
+        let mut download = quartered(primary, dir.path(), body.len() as u64);
+        download.mirrors = mirrors.into_iter().map(Mirror::new).collect();
+        let run = run_task(&download, |task| task).await;
+        run.result.unwrap();
+
+        assert_eq!(std::fs::read(dir.path().join(&download.filename)).unwrap(), body);
+        run.stored
+    }
+
+    #[tokio::test]
+    async fn segments_are_spread_across_matching_mirrors() {
+        let body: Vec<u8> = (0..200u8).collect();
+        let primary = range_server(body.clone(), ServerOpts::default()).await;
+        let mirror = range_server(body.clone(), ServerOpts::default()).await;
+        let other = range_server(vec![0; 100], ServerOpts::default()).await;
+
+        let stored = run_with_mirrors(&body, primary.url, vec![mirror.url, other.url]).await;
+        assert_eq!(stored.status, DownloadStatus::Completed);
+        assert!(primary.hits.load(Ordering::Acquire) >= 2);
+        // One check plus at least one segment
+        assert!(mirror.hits.load(Ordering::Acquire) >= 2);
+        // The mirror with the wrong size is only checked, never used
+        assert_eq!(other.hits.load(Ordering::Acquire), 1);
+        assert_eq!(stored.mirrors[0].etag.as_deref(), Some("\"m\""));
+        assert_eq!(stored.mirrors[1].etag, None);
+    }
+
+    #[tokio::test]
+    async fn ftp_download_is_probed_and_segmented() {
+        let body: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let (url, retrievals) = crate::engine::ftp::serve_ftp(body.clone(), Some(("alice", "hunter2"))).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let download = Download::new(url, dir.path().to_path_buf(), uuid::Uuid::nil());
+        let run = run_task(&download, |mut task| {
+            task.credentials = Some(("alice".to_string(), "hunter2".to_string()));
+            task
+        })
+        .await;
+        run.result.unwrap();
+
+        assert_eq!(std::fs::read(dir.path().join(&download.filename)).unwrap(), body);
+        assert_eq!(run.stored.size, Some(body.len() as u64));
+        assert_eq!(run.stored.last_modified.as_deref(), Some("20240102030405"));
+        assert!(run.stored.segments.len() >= 2);
+        assert!(retrievals.load(Ordering::Acquire) >= 2);
+    }
+
+    #[tokio::test]
+    async fn failing_primary_fails_over_to_mirror() {
+        let body: Vec<u8> = (0..200u8).rev().collect();
+        let primary = range_server(body.clone(), ServerOpts { fail: true, ..Default::default() }).await;
+        let mirror = range_server(body.clone(), ServerOpts::default()).await;
+
+        let stored = run_with_mirrors(&body, primary.url, vec![mirror.url]).await;
+        assert_eq!(stored.status, DownloadStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn downloads_share_host_connection_cap() {
+        let body: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 253) as u8).collect();
+        let server = range_server(body.clone(), ServerOpts::default()).await;
+        let limiter = Arc::new(HostLimiter::new(HostLimitSettings { max_connections_per_host: 3, rules: Vec::new() }));
+        let dir = tempfile::tempdir().unwrap();
+
+        let downloads: Vec<Download> = (0..2)
+            .map(|i| quartered(server.url.clone(), &dir.path().join(format!("download-{i}")), body.len() as u64))
+            .collect();
+        let runs = downloads.iter().map(|download| run_task(download, |task| task.with_host_limiter(limiter.clone())));
+        for run in futures::future::join_all(runs).await {
+            run.result.unwrap();
+        }
+
+        for download in &downloads {
+            assert_eq!(std::fs::read(download.destination.join(&download.filename)).unwrap(), body);
+        }
+        let peak = server.peak.load(Ordering::Acquire);
+        assert!((2..=3).contains(&peak), "peak of {peak} connections");
+    }
+
+    #[tokio::test]
+    async fn probes_and_mirror_checks_wait_for_host_slot() {
+        let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let server = range_server(body.clone(), ServerOpts::default()).await;
+        let limiter = Arc::new(HostLimiter::new(HostLimitSettings { max_connections_per_host: 1, rules: Vec::new() }));
+        let dir = tempfile::tempdir().unwrap();
+
+        // One download already has its segments; the other has to probe first
+        // and check its mirror while the first is running
+        let mut running = Download::new(server.url.clone(), dir.path().join("download-0"), uuid::Uuid::nil());
+        running.size = Some(body.len() as u64);
+        running.supports_range = true;
+        running.segments = vec![Segment::new(0, 0, body.len() as u64 - 1)];
+        let mut probing = Download::new(server.url.clone(), dir.path().join("download-1"), uuid::Uuid::nil());
+        probing.mirrors = vec![Mirror { url: format!("{}?mirror", server.url), etag: None, last_modified: None }];
+
+        let downloads = [running, probing];
+        let runs = downloads.iter().map(|download| run_task(download, |task| task.with_host_limiter(limiter.clone())));
+        for run in futures::future::join_all(runs).await {
+            run.result.unwrap();
+            assert_eq!(run.stored.status, DownloadStatus::Completed);
+        }
+
+        for download in &downloads {
+            assert_eq!(std::fs::read(download.destination.join(&download.filename)).unwrap(), body);
+        }
+        assert_eq!(server.peak.load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn throttled_download_backs_off_and_remembers_host() {
+        let body: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 249) as u8).collect();
+        let server = range_server(body.clone(), ServerOpts { limit: Some(1), ..Default::default() }).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let download = quartered(server.url, dir.path(), body.len() as u64);
+        let run = run_task(&download, |task| task).await;
+        run.result.unwrap();
+
+        assert_eq!(std::fs::read(dir.path().join(&download.filename)).unwrap(), body);
+        assert_eq!(server.peak.load(Ordering::Acquire), 1);
+        assert_eq!(run.db.load_host_connections("127.0.0.1").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn direct_write_restarts_lost_part_file_and_renames_it() {
+        let body: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 241) as u8).collect();
+        let server = range_server(body.clone(), ServerOpts::default()).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        // A direct-write download whose part file is gone, with made-up progress
+        let mut download = quartered(server.url, dir.path(), body.len() as u64);
+        download.direct_write = true;
+        download.segments[1].downloaded = 1000;
+        let run = run_task(&download, |task| task).await;
+        run.result.unwrap();
+
+        let stored = run.stored;
+        assert!(stored.direct_write);
+        assert_eq!(stored.downloaded, body.len() as u64);
+        assert_eq!(std::fs::read(dir.path().join(&stored.filename)).unwrap(), body);
+        assert!(!direct_part_path(&stored).exists());
+        // No segment was ever written to the scratch directory
+        assert!(!dir.path().join("temp").exists());
+    }
+
+    #[tokio::test]
+    async fn header_rules_are_sent_with_every_request() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i % 239) as u8).collect();
+        let server = range_server(body.clone(), ServerOpts { require_header: Some("x-api-key: secret"), ..Default::default() }).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let download = Download::new(server.url.clone(), dir.path().to_path_buf(), uuid::Uuid::nil());
+        // The download's own header replaces the rule's wrong key
+        let headers = RequestHeaders::new(
+            vec![HeaderRule::new("127.0.0.1".to_string(), vec![RequestHeader::new("X-Api-Key", "wrong")])],
+            &server.url,
+            vec![RequestHeader::new("x-api-key", "secret")],
+        );
+        let run = run_task(&download, |task| task.with_headers(headers)).await;
+        run.result.unwrap();
+
+        assert_eq!(server.refused.load(Ordering::Acquire), 0);
+        assert_eq!(std::fs::read(dir.path().join(&download.filename)).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn rejected_login_waits_for_credentials() {
+        let server = range_server(vec![0u8; 1024], ServerOpts { require_header: Some("x-api-key: secret"), ..Default::default() }).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let download = Download::new(server.url, dir.path().to_path_buf(), uuid::Uuid::nil());
+        let run = run_task(&download, |task| task).await;
+        assert!(matches!(run.result, Err(DlmanError::AuthenticationRequired { status: 401, .. })));
+        assert_eq!(run.stored.status, DownloadStatus::AwaitingAuth);
+        assert!(run.events.iter().any(|event| matches!(
+            event,
+            CoreEvent::CredentialRequired { download_id, status_code: 401, .. } if *download_id == download.id
+        )));
+    }
 }
@@ -0,0 +1,318 @@
+//! Logins through HTML forms
+//!
+//! Many file hosts don't take HTTP auth: they want their login form posted
+//! and then recognise the browser by a session cookie. A [`FormLogin`]
+//! describes that request. [`form_login`] sends it, follows the redirects
+//! that usually come after, and collects every cookie set on the way. A
+//! [`FormSession`] keeps the cookies for the credential's domain, saved in the
+//! database so later downloads (and restarts) reuse them, and logs in again
+//! when the server stops accepting them.
+
+use crate::engine::DownloadDatabase;
+use crate::error::DlmanError;
+use dlman_types::{FormLogin, LoginCheck, SiteCredential};
+use reqwest::header::{COOKIE, LOCATION, SET_COOKIE};
+use reqwest::{Client, Method, Response, StatusCode};
+use tracing::{debug, info};
+
+/// Redirects a login may take before we give up on it
+const MAX_LOGIN_REDIRECTS: usize = 10;
+
+/// Cookies collected during a login, in the order they were first set
+#[derive(Debug, Default)]
+struct CookieJar(Vec<(String, String)>);
+
+impl CookieJar {
+    /// Take the `Set-Cookie` headers of `response`. A cookie replaces an
+    /// earlier one of the same name; an empty or expired one removes it.
+    fn collect(&mut self, response: &Response) {
+        for header in response.headers().get_all(SET_COOKIE) {
+            let Ok(header) = header.to_str() else { continue };
+            let mut parts = header.split(';');
+            let Some((name, value)) = parts.next().and_then(|pair| pair.split_once('=')) else {
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim().trim_matches('"'));
+            if name.is_empty() {
+                continue;
+            }
+            let expired = parts.any(|attr| {
+                attr.split_once('=').is_some_and(|(key, age)| {
+                    key.trim().eq_ignore_ascii_case("max-age") && age.trim().parse::<i64>().is_ok_and(|age| age <= 0)
+                })
+            });
+            self.0.retain(|(n, _)| n != name);
+            if !value.is_empty() && !expired {
+                self.0.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, _)| n == name)
+    }
+
+    /// The jar as a `Cookie` header value
+    fn header(&self) -> String {
+        self.0.iter().map(|(n, v)| format!("{}={}", n, v)).collect::<Vec<_>>().join("; ")
+    }
+}
+
+/// Submit `login` as `username` and return the session cookies it set, as a
+/// `Cookie` header value. `client` must not follow redirects itself, or the
+/// cookies set by the redirect responses would be lost.
+pub async fn form_login(client: &Client, login: &FormLogin, username: &str, password: &str) -> Result<String, DlmanError> {
+    let failed = |reason: &str| DlmanError::LoginFailed {
+        url: login.url.clone(),
+        reason: reason.to_string(),
+    };
+    let mut url = url::Url::parse(login.url.trim()).map_err(|e| failed(&e.to_string()))?;
+    let mut method = match login.method.trim().to_uppercase().as_str() {
+        "GET" => Method::GET,
+        "POST" | "" => Method::POST,
+        other => return Err(failed(&format!("unsupported method {}", other))),
+    };
+    let fields: Vec<(String, String)> = login
+        .fields
+        .iter()
+        .map(|field| {
+            let value = field.value.replace("{username}", username).replace("{password}", password);
+            (field.name.clone(), value)
+        })
+        .collect();
+
+    let mut jar = CookieJar::default();
+    // The form data goes along until a redirect turns the request into a GET
+    let mut with_fields = true;
+    for _ in 0..=MAX_LOGIN_REDIRECTS {
+        let mut request = client.request(method.clone(), url.clone());
+        if with_fields {
+            request = if method == Method::GET { request.query(&fields) } else { request.form(&fields) };
+        }
+        if !jar.0.is_empty() {
+            request = request.header(COOKIE, jar.header());
+        }
+        let response = request.send().await?;
+        jar.collect(&response);
+
+        let status = response.status();
+        let location = response.headers().get(LOCATION).and_then(|l| l.to_str().ok());
+        if let (true, Some(location)) = (status.is_redirection(), location) {
+            url = url.join(location).map_err(|e| failed(&format!("bad redirect: {}", e)))?;
+            if !matches!(status, StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT) {
+                method = Method::GET;
+                with_fields = false;
+            }
+            debug!("Login redirected to {}", url);
+            continue;
+        }
+
+        if status.is_client_error() || status.is_server_error() {
+            return Err(failed(&format!("the server answered {}", status)));
+        }
+        let passed = match &login.success {
+            LoginCheck::AnyCookie => !jar.0.is_empty(),
+            LoginCheck::Cookie { name } => jar.contains(name.trim()),
+            LoginCheck::BodyContains { text } => response.text().await?.contains(text.as_str()),
+            LoginCheck::BodyLacks { text } => !response.text().await?.contains(text.as_str()),
+        };
+        if !passed {
+            return Err(failed("the success check did not pass"));
+        }
+        if jar.0.is_empty() {
+            return Err(failed("no session cookie was set"));
+        }
+        return Ok(jar.header());
+    }
+    Err(failed("too many redirects"))
+}
+
+/// The session of one form-login credential, shared by the requests of a
+/// download
+#[derive(Debug)]
+pub struct FormSession {
+    credential: SiteCredential,
+    login: FormLogin,
+    /// Client that doesn't follow redirects, for the login itself
+    client: Client,
+    db: DownloadDatabase,
+    /// Current session cookies, `None` until logged in
+    cookies: tokio::sync::Mutex<Option<String>>,
+}
+
+impl FormSession {
+    /// The session of `credential`, starting from the cookies saved by its
+    /// last login. `None` if the credential has no login form.
+    pub async fn new(credential: SiteCredential, client: Client, db: DownloadDatabase) -> Option<Self> {
+        let login = credential.form_login.clone()?;
+        let cookies = db.load_login_session(credential.id).await.ok().flatten();
+        Some(Self {
+            credential,
+            login,
+            client,
+            db,
+            cookies: tokio::sync::Mutex::new(cookies),
+        })
+    }
+
+    /// Whether requests to `url` should carry the session: the same domain
+    /// the credential is saved for
+    pub fn applies_to(&self, url: &url::Url) -> bool {
+        self.credential.matches_url(url.as_str())
+    }
+
+    /// The session cookies, logging in first if there is no session yet
+    pub async fn cookies(&self) -> Result<String, DlmanError> {
+        let mut cookies = self.cookies.lock().await;
+        if let Some(ref cookies) = *cookies {
+            return Ok(cookies.clone());
+        }
+        let fresh = self.login().await?;
+        *cookies = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    /// Log in again after the server refused the `stale` cookies. If another
+    /// request already renewed them meanwhile, that session is used instead.
+    pub async fn renew(&self, stale: &str) -> Result<String, DlmanError> {
+        let mut cookies = self.cookies.lock().await;
+        if let Some(ref current) = *cookies {
+            if current != stale {
+                return Ok(current.clone());
+            }
+        }
+        let fresh = self.login().await?;
+        *cookies = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    async fn login(&self) -> Result<String, DlmanError> {
+        info!("Logging in to {} for {}", self.login.url, self.credential.domain);
+        let cookies = form_login(&self.client, &self.login, &self.credential.username, &self.credential.password).await?;
+        self.db.save_login_session(self.credential.id, &cookies).await?;
+        Ok(cookies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dlman_types::FormField;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A login form at /login that wants alice/secret, sets a temporary
+    /// cookie and redirects to /home, which sets the session cookie. /file
+    /// is only served to that session.
+    async fn serve_login() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let request = read_request(&mut socket).await;
+                let response = if request.starts_with("POST /login ") {
+                    if request.ends_with("user=alice&pass=secret&remember=1") {
+                        "HTTP/1.1 303 See Other\r\nLocation: /home\r\nSet-Cookie: pending=1; Path=/\r\n\
+                         Content-Length: 0\r\nConnection: close\r\n\r\n"
+                    } else {
+                        "HTTP/1.1 200 OK\r\nContent-Length: 16\r\nConnection: close\r\n\r\nInvalid password"
+                    }
+                } else if request.starts_with("GET /home ") && request.contains("cookie: pending=1") {
+                    "HTTP/1.1 200 OK\r\nSet-Cookie: pending=; Max-Age=0\r\nSet-Cookie: sid=abc; HttpOnly\r\n\
+                     Content-Length: 7\r\nConnection: close\r\n\r\nLog out"
+                } else if request.starts_with("GET /file ") {
+                    if request.contains("cookie: theme=dark; sid=abc\r\n") {
+                        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                    } else {
+                        "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    }
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        base
+    }
+
+    /// Read a request up to the end of its body
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                if n == 0 {
+                    return text;
+                }
+                continue;
+            };
+            let length = head
+                .lines()
+                .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap_or(0)))
+                .unwrap_or(0);
+            if n == 0 || body.len() >= length {
+                return text;
+            }
+        }
+    }
+
+    fn login(base: &str, success: LoginCheck) -> FormLogin {
+        let field = |name: &str, value: &str| FormField { name: name.into(), value: value.into() };
+        FormLogin {
+            url: format!("{}/login", base),
+            method: "POST".into(),
+            fields: vec![field("user", "{username}"), field("pass", "{password}"), field("remember", "1")],
+            success,
+        }
+    }
+
+    fn client() -> Client {
+        Client::builder().no_proxy().redirect(reqwest::redirect::Policy::none()).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn login_follows_redirects_and_keeps_cookies() {
+        let base = serve_login().await;
+        let cookies = form_login(&client(), &login(&base, LoginCheck::AnyCookie), "alice", "secret").await.unwrap();
+        // The cookie removed on the way is gone
+        assert_eq!(cookies, "sid=abc");
+
+        let named = LoginCheck::Cookie { name: "sid".into() };
+        assert!(form_login(&client(), &login(&base, named), "alice", "secret").await.is_ok());
+        let page = LoginCheck::BodyContains { text: "Log out".into() };
+        assert!(form_login(&client(), &login(&base, page), "alice", "secret").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn failed_login_is_reported() {
+        let base = serve_login().await;
+        let check = LoginCheck::BodyLacks { text: "Invalid password".into() };
+        let err = form_login(&client(), &login(&base, check), "alice", "wrong").await.unwrap_err();
+        assert!(matches!(err, DlmanError::LoginFailed { .. }));
+        // No cookie at all fails even the loosest check
+        let err = form_login(&client(), &login(&base, LoginCheck::AnyCookie), "alice", "wrong").await.unwrap_err();
+        assert!(matches!(err, DlmanError::LoginFailed { .. }));
+    }
+
+    #[tokio::test]
+    async fn refused_session_is_renewed() {
+        let base = serve_login().await;
+        let dir = tempfile::tempdir().unwrap();
+        let db = DownloadDatabase::new(dir.path().join("dlman.db")).await.unwrap();
+        let mut credential = SiteCredential::new("127.0.0.1".into(), "alice".into(), "secret".into());
+        credential.auth_scheme = dlman_types::AuthScheme::Form;
+        credential.form_login = Some(login(&base, LoginCheck::AnyCookie));
+        db.upsert_credential(&credential).await.unwrap();
+        db.save_login_session(credential.id, "sid=expired").await.unwrap();
+
+        let url = format!("{}/file", base);
+        let session = FormSession::new(credential.clone(), client(), db.clone()).await.unwrap();
+        let auth = crate::engine::HttpAuth::from_credential(&credential, &url).with_form_session(session.into());
+        let request = Client::builder().no_proxy().build().unwrap().get(&url).header(COOKIE, "theme=dark");
+        let response = auth.send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(db.load_login_session(credential.id).await.unwrap().as_deref(), Some("sid=abc"));
+    }
+}
@@ -0,0 +1,575 @@
+//! FTP client for `ftp://`, `ftps://` and `ftpes://` sources
+//!
+//! Implements just enough of RFC 959 to probe and fetch a file, plus `SIZE`,
+//! `MDTM` and `REST STREAM` (RFC 3659) and `EPSV` (RFC 2428). Every segment
+//! worker opens its own control connection and resumes with `REST`, so a
+//! segmented FTP download looks like several parallel resumed transfers.
+//!
+//! `ftps://` is implicit TLS (port 990 by default); `ftpes://` logs in over a
+//! plain connection upgraded with `AUTH TLS`. `ftpes://` is not a registered
+//! scheme, but FileZilla's spelling for explicit FTPS, which `ftp://` has no
+//! way to ask for; a plain `ftp://` URL never uses TLS. Either way the data connections
+//! are protected as well (`PROT P`), and resume the TLS session of their
+//! control connection, which servers such as vsftpd (`require_ssl_reuse`)
+//! insist on.
+
+use crate::error::DlmanError;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::Resumption;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+/// How long to wait for a connection or a reply before giving up
+const FTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether `url` is served by the FTP client rather than reqwest
+pub fn is_ftp_url(url: &str) -> bool {
+    url::Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "ftp" | "ftps" | "ftpes"))
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// What a probe learned about a file on an FTP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtpFileInfo {
+    /// From `SIZE`
+    pub size: Option<u64>,
+    /// `MDTM` timestamp (`YYYYMMDDhhmmss`), used like `Last-Modified`
+    pub modified: Option<String>,
+    /// Whether the server accepts `REST`, i.e. ranged and resumed transfers
+    pub supports_rest: bool,
+}
+
+/// A logged-in control connection
+pub struct FtpClient {
+    control: BufReader<Box<dyn Connection>>,
+    /// Set when data connections must be wrapped in TLS too. Its session
+    /// cache is this client's own, so they resume the control connection's
+    /// session rather than another client's.
+    tls: Option<TlsConnector>,
+    host: String,
+    /// Address of the server; passive-mode replies are only trusted for the port
+    peer: IpAddr,
+    url: String,
+    path: String,
+}
+
+impl FtpClient {
+    /// Connect to the server of `url` and log in.
+    ///
+    /// A user name and password in the URL win over `credentials`; with
+    /// neither, the login is anonymous. A rejected login is reported as
+    /// [`DlmanError::AuthenticationRequired`] with status 530.
+    pub async fn connect(url: &str, credentials: Option<&(String, String)>) -> Result<Self, DlmanError> {
+        Self::connect_with(url, credentials, None).await
+    }
+
+    /// [`Self::connect`], trusting the roots of `tls_config` instead of the
+    /// system's
+    async fn connect_with(
+        url: &str,
+        credentials: Option<&(String, String)>,
+        tls_config: Option<&ClientConfig>,
+    ) -> Result<Self, DlmanError> {
+        let parsed = url::Url::parse(url).map_err(|_| DlmanError::InvalidUrl(url.to_string()))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| DlmanError::InvalidUrl(url.to_string()))?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+        let implicit_tls = parsed.scheme() == "ftps";
+        let explicit_tls = parsed.scheme() == "ftpes";
+        let port = parsed.port().unwrap_or(if implicit_tls { 990 } else { 21 });
+
+        let tcp = timeout(TcpStream::connect((host.as_str(), port))).await??;
+        let peer = tcp.peer_addr()?.ip();
+        let tls = (implicit_tls || explicit_tls).then(|| tls_connector(tls_config));
+
+        let stream: Box<dyn Connection> = match (&tls, implicit_tls) {
+            (Some(connector), true) => {
+                Box::new(timeout(connector.connect(server_name(&host)?, tcp)).await?.map_err(tls_error)?)
+            }
+            _ => Box::new(tcp),
+        };
+        let mut client = Self {
+            control: BufReader::new(stream),
+            tls,
+            host,
+            peer,
+            url: url.to_string(),
+            path: file_path(&parsed),
+        };
+
+        let (mut code, mut text) = client.reply().await?;
+        if code == 120 {
+            (code, text) = client.reply().await?;
+        }
+        if code != 220 {
+            return Err(client.error(code, &text));
+        }
+
+        if explicit_tls {
+            client.expect("AUTH TLS", &[234]).await?;
+            client = client.upgrade().await?;
+        }
+
+        let decode = |s: &str| urlencoding::decode(s).map(|s| s.into_owned()).unwrap_or_else(|_| s.to_string());
+        let (username, password) = if !parsed.username().is_empty() {
+            (decode(parsed.username()), parsed.password().map(decode).unwrap_or_default())
+        } else if let Some((username, password)) = credentials {
+            (username.clone(), password.clone())
+        } else {
+            ("anonymous".to_string(), "anonymous@".to_string())
+        };
+        client.login(&username, &password).await?;
+
+        if client.tls.is_some() {
+            client.expect("PBSZ 0", &[200]).await?;
+            client.expect("PROT P", &[200]).await?;
+        }
+        client.expect("TYPE I", &[200]).await?;
+        Ok(client)
+    }
+
+    /// Switch the control connection to TLS after `AUTH TLS`
+    async fn upgrade(self) -> Result<Self, DlmanError> {
+        let connector = self.tls.clone().ok_or_else(|| tls_error("no TLS connector"))?;
+        let plain = self.control.into_inner();
+        let upgraded = timeout(connector.connect(server_name(&self.host)?, plain)).await?.map_err(tls_error)?;
+        Ok(Self { control: BufReader::new(Box::new(upgraded)), ..self })
+    }
+
+    async fn login(&mut self, username: &str, password: &str) -> Result<(), DlmanError> {
+        let (mut code, mut text) = self.command(&format!("USER {username}")).await?;
+        if code == 331 {
+            (code, text) = self.command(&format!("PASS {password}")).await?;
+        }
+        match code {
+            230 | 202 => Ok(()),
+            332 | 530 => Err(DlmanError::AuthenticationRequired {
+                domain: self.host.clone(),
+                url: self.url.clone(),
+                status: 530,
+            }),
+            _ => Err(self.error(code, &text)),
+        }
+    }
+
+    /// Size, modification time and `REST` support of the file
+    pub async fn probe(&mut self) -> Result<FtpFileInfo, DlmanError> {
+        let size = self.size().await?;
+        let modified = self.modified().await?;
+        let (code, _) = self.command("REST 0").await?;
+        Ok(FtpFileInfo { size, modified, supports_rest: code == 350 })
+    }
+
+    /// Size of the file, if the server supports `SIZE`
+    pub async fn size(&mut self) -> Result<Option<u64>, DlmanError> {
+        let (code, text) = self.command(&format!("SIZE {}", self.path)).await?;
+        Ok((code == 213).then(|| text.trim().parse().ok()).flatten())
+    }
+
+    /// Modification time of the file, if the server supports `MDTM`
+    pub async fn modified(&mut self) -> Result<Option<String>, DlmanError> {
+        let (code, text) = self.command(&format!("MDTM {}", self.path)).await?;
+        Ok((code == 213).then(|| text.trim().to_string()).filter(|t| !t.is_empty()))
+    }
+
+    /// Start sending the file from byte `offset`. The control connection is
+    /// kept alive by the returned reader and closed with it.
+    pub async fn retrieve(mut self, offset: u64) -> Result<FtpReader, DlmanError> {
+        let data = self.open_data().await?;
+        if offset > 0 {
+            let (code, text) = self.command(&format!("REST {offset}")).await?;
+            if code != 350 {
+                debug!("REST {} refused: {} {}", offset, code, text);
+                return Err(DlmanError::ResumeNotSupported);
+            }
+        }
+        self.expect(&format!("RETR {}", self.path), &[125, 150]).await?;
+
+        let data: Box<dyn Connection> = match &self.tls {
+            Some(connector) => {
+                let data = timeout(connector.connect(server_name(&self.host)?, data)).await?.map_err(tls_error)?;
+                if data.get_ref().1.handshake_kind() != Some(rustls::HandshakeKind::Resumed) {
+                    debug!("FTP {}: data connection did not resume the TLS session", self.host);
+                }
+                Box::new(data)
+            }
+            None => Box::new(data),
+        };
+        Ok(FtpReader { data, _control: self })
+    }
+
+    /// Open a passive data connection, preferring `EPSV`
+    async fn open_data(&mut self) -> Result<TcpStream, DlmanError> {
+        let (code, text) = self.command("EPSV").await?;
+        let port = if code == 229 {
+            parse_epsv(&text)
+        } else {
+            let (code, text) = self.command("PASV").await?;
+            if code != 227 {
+                return Err(self.error(code, &text));
+            }
+            parse_pasv(&text)
+        };
+        let port = port.ok_or_else(|| DlmanError::ServerError {
+            status: code,
+            message: format!("Unreadable passive mode reply: {text}"),
+        })?;
+        Ok(timeout(TcpStream::connect((self.peer, port))).await??)
+    }
+
+    async fn expect(&mut self, command: &str, codes: &[u16]) -> Result<String, DlmanError> {
+        let (code, text) = self.command(command).await?;
+        if codes.contains(&code) {
+            Ok(text)
+        } else {
+            Err(self.error(code, &text))
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<(u16, String), DlmanError> {
+        let shown = if command.starts_with("PASS ") { "PASS ****" } else { command };
+        debug!("FTP {} > {}", self.host, shown);
+        let control = self.control.get_mut();
+        control.write_all(command.as_bytes()).await?;
+        control.write_all(b"\r\n").await?;
+        control.flush().await?;
+        self.reply().await
+    }
+
+    /// Read one (possibly multi-line) reply
+    async fn reply(&mut self) -> Result<(u16, String), DlmanError> {
+        let mut line = String::new();
+        let mut text = String::new();
+        loop {
+            line.clear();
+            if timeout(self.control.read_line(&mut line)).await?? == 0 {
+                return Err(DlmanError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            let line = line.trim_end();
+            let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+            let separator = line.as_bytes().get(3).copied();
+            match (code, separator) {
+                (Some(code), Some(b' ') | None) => {
+                    text.push_str(line.get(4..).unwrap_or(""));
+                    debug!("FTP {} < {} {}", self.host, code, text);
+                    return Ok((code, text));
+                }
+                // First or continuation line of a multi-line reply
+                _ => {
+                    text.push_str(line.get(4..).unwrap_or(line));
+                    text.push('\n');
+                }
+            }
+        }
+    }
+
+    fn error(&self, code: u16, text: &str) -> DlmanError {
+        DlmanError::ServerError { status: code, message: format!("FTP: {}", text.trim()) }
+    }
+}
+
+/// Data connection of a transfer in progress
+pub struct FtpReader {
+    data: Box<dyn Connection>,
+    _control: FtpClient,
+}
+
+impl AsyncRead for FtpReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.data).poll_read(cx, buf)
+    }
+}
+
+async fn timeout<F: std::future::Future>(future: F) -> Result<F::Output, DlmanError> {
+    tokio::time::timeout(FTP_TIMEOUT, future).await.map_err(|_| DlmanError::Timeout)
+}
+
+/// TLS settings for a new client: `base` (or the system's roots), with a
+/// session cache of its own
+fn tls_connector(base: Option<&ClientConfig>) -> TlsConnector {
+    static SYSTEM: OnceLock<ClientConfig> = OnceLock::new();
+    let base = base.unwrap_or_else(|| SYSTEM.get_or_init(|| tls_config(system_roots())));
+    let mut config = base.clone();
+    config.resumption = Resumption::default();
+    TlsConnector::from(Arc::new(config))
+}
+
+fn tls_config(roots: RootCertStore) -> ClientConfig {
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+fn system_roots() -> RootCertStore {
+    let found = rustls_native_certs::load_native_certs();
+    for error in &found.errors {
+        debug!("Skipping system certificates: {}", error);
+    }
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(found.certs);
+    debug!("Loaded {} system root certificates ({} unusable)", added, ignored);
+    roots
+}
+
+fn server_name(host: &str) -> Result<ServerName<'static>, DlmanError> {
+    ServerName::try_from(host.to_string()).map_err(tls_error)
+}
+
+fn tls_error(e: impl std::fmt::Display) -> DlmanError {
+    DlmanError::Io(std::io::Error::other(format!("FTP TLS: {e}")))
+}
+
+/// Server path of the file: relative to the login directory, as RFC 1738
+/// says (`%2F` at the start makes it absolute)
+fn file_path(url: &url::Url) -> String {
+    let path = url.path().strip_prefix('/').unwrap_or(url.path());
+    urlencoding::decode(path).map(|p| p.into_owned()).unwrap_or_else(|_| path.to_string())
+}
+
+/// Port from `229 Entering Extended Passive Mode (|||6446|)`
+fn parse_epsv(text: &str) -> Option<u16> {
+    let inner = &text[text.find('(')? + 1..text.rfind(')')?];
+    let delimiter = inner.chars().next()?;
+    inner.split(delimiter).nth(3)?.parse().ok()
+}
+
+/// Port from `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)`. The address is
+/// ignored: servers behind NAT often report a private one.
+fn parse_pasv(text: &str) -> Option<u16> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let numbers: Vec<u16> = text[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .take(6)
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [_, _, _, _, high, low] if high < 256 && low < 256 => Some(high * 256 + low),
+        _ => None,
+    }
+}
+
+/// Minimal FTP server for tests: serves `body` as every file to the user
+/// `user`/`pass` (or anyone, when `user` is `None`) and counts `RETR`s.
+#[cfg(test)]
+pub(crate) async fn serve_ftp(
+    body: Vec<u8>,
+    user: Option<(&'static str, &'static str)>,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicU64>) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ftp://{}/pub/file.bin", listener.local_addr().unwrap());
+    let body = Arc::new(body);
+    let retrievals = Arc::new(AtomicU64::new(0));
+    let counter = retrievals.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let (body, counter) = (body.clone(), counter.clone());
+            tokio::spawn(async move {
+                let (read, mut write) = socket.into_split();
+                let mut lines = BufReader::new(read).lines();
+                let _ = write.write_all(b"220 test server\r\n").await;
+                let mut offset = 0usize;
+                let mut passive: Option<tokio::net::TcpListener> = None;
+                let mut logged_in = false;
+                let mut given_user = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let (command, arg) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+                    let reply = match command {
+                        "USER" => {
+                            given_user = arg.to_string();
+                            "331 password please".to_string()
+                        }
+                        "PASS" => {
+                            logged_in = user.is_none_or(|(u, p)| u == given_user && p == arg);
+                            if logged_in { "230 logged in" } else { "530 login incorrect" }.to_string()
+                        }
+                        _ if !logged_in => "530 not logged in".to_string(),
+                        "TYPE" => "200 binary".to_string(),
+                        "SIZE" if arg == "pub/file.bin" => format!("213 {}", body.len()),
+                        "SIZE" => "550 no such file".to_string(),
+                        "MDTM" => "213 20240102030405".to_string(),
+                        "REST" => {
+                            offset = arg.parse().unwrap();
+                            "350 restarting".to_string()
+                        }
+                        "EPSV" => {
+                            let data = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                            let port = data.local_addr().unwrap().port();
+                            passive = Some(data);
+                            format!("229 Entering Extended Passive Mode (|||{port}|)")
+                        }
+                        "RETR" => {
+                            counter.fetch_add(1, Ordering::AcqRel);
+                            let _ = write.write_all(b"150 opening data connection\r\n").await;
+                            let (mut data, _) = passive.take().unwrap().accept().await.unwrap();
+                            let _ = data.write_all(&body[offset.min(body.len())..]).await;
+                            drop(data);
+                            offset = 0;
+                            "226 transfer complete".to_string()
+                        }
+                        "QUIT" => break,
+                        _ => "502 not implemented".to_string(),
+                    };
+                    if write.write_all(format!("{reply}\r\n").as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    (url, retrievals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::io::AsyncReadExt;
+
+    /// Minimal `AUTH TLS` server for one anonymous download. Returns its URL,
+    /// client settings trusting its certificate, and whether the data
+    /// connection resumed the control connection's TLS session.
+    async fn serve_ftpes(body: Vec<u8>) -> (String, ClientConfig, Arc<AtomicBool>) {
+        use rustls::pki_types::PrivateKeyDer;
+
+        let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ftpes://{}/pub/file.bin", listener.local_addr().unwrap());
+        let resumed = Arc::new(AtomicBool::new(false));
+        let data_resumed = resumed.clone();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut control: BufReader<Box<dyn Connection>> = BufReader::new(Box::new(socket));
+            let mut passive: Option<tokio::net::TcpListener> = None;
+            let mut line = String::new();
+            control.get_mut().write_all(b"220 test server\r\n").await.unwrap();
+            loop {
+                line.clear();
+                if control.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    break;
+                }
+                let reply = match line.split_whitespace().next().unwrap_or("") {
+                    "AUTH" => {
+                        control.get_mut().write_all(b"234 go ahead\r\n").await.unwrap();
+                        let plain = control.into_inner();
+                        control = BufReader::new(Box::new(acceptor.accept(plain).await.unwrap()));
+                        continue;
+                    }
+                    "USER" => "331 password please".to_string(),
+                    "PASS" => "230 logged in".to_string(),
+                    "PBSZ" | "PROT" | "TYPE" => "200 ok".to_string(),
+                    "EPSV" => {
+                        let data = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                        let port = data.local_addr().unwrap().port();
+                        passive = Some(data);
+                        format!("229 Entering Extended Passive Mode (|||{port}|)")
+                    }
+                    "RETR" => {
+                        control.get_mut().write_all(b"150 opening data connection\r\n").await.unwrap();
+                        let (data, _) = passive.take().unwrap().accept().await.unwrap();
+                        let mut data = acceptor.accept(data).await.unwrap();
+                        let kind = data.get_ref().1.handshake_kind();
+                        data_resumed.store(kind == Some(rustls::HandshakeKind::Resumed), Ordering::Release);
+                        data.write_all(&body).await.unwrap();
+                        data.shutdown().await.unwrap();
+                        "226 transfer complete".to_string()
+                    }
+                    _ => "502 not implemented".to_string(),
+                };
+                control.get_mut().write_all(format!("{reply}\r\n").as_bytes()).await.unwrap();
+                control.get_mut().flush().await.unwrap();
+            }
+        });
+        (url, tls_config(roots), resumed)
+    }
+
+    #[test]
+    fn test_parse_passive_replies() {
+        assert_eq!(parse_epsv("Entering Extended Passive Mode (|||6446|)"), Some(6446));
+        assert_eq!(parse_epsv("Entering Extended Passive Mode (!!!21!)"), Some(21));
+        assert_eq!(parse_pasv("Entering Passive Mode (10,0,0,7,200,14)."), Some(200 * 256 + 14));
+        assert_eq!(parse_pasv("Entering Passive Mode (10,0,0,7,300,14)"), None);
+    }
+
+    #[test]
+    fn test_file_path() {
+        let path = |url: &str| file_path(&url::Url::parse(url).unwrap());
+        assert_eq!(path("ftp://example.com/pub/my%20file.iso"), "pub/my file.iso");
+        assert_eq!(path("ftp://example.com/%2Fetc/motd"), "/etc/motd");
+        assert!(is_ftp_url("ftpes://example.com/a") && !is_ftp_url("https://example.com/a"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_and_resume() {
+        let body: Vec<u8> = (0..=255u8).collect();
+        let (url, _) = serve_ftp(body.clone(), None).await;
+
+        let mut client = FtpClient::connect(&url, None).await.unwrap();
+        let info = client.probe().await.unwrap();
+        assert_eq!(
+            info,
+            FtpFileInfo { size: Some(256), modified: Some("20240102030405".to_string()), supports_rest: true }
+        );
+
+        let mut reader = client.retrieve(200).await.unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, &body[200..]);
+    }
+
+    #[tokio::test]
+    async fn test_login_uses_credentials() {
+        let (url, _) = serve_ftp(b"secret".to_vec(), Some(("alice", "hunter2"))).await;
+
+        let anonymous = FtpClient::connect(&url, None).await;
+        assert!(matches!(anonymous, Err(DlmanError::AuthenticationRequired { status: 530, .. })));
+
+        let credentials = ("alice".to_string(), "hunter2".to_string());
+        assert!(FtpClient::connect(&url, Some(&credentials)).await.is_ok());
+
+        let in_url = url.replace("ftp://", "ftp://alice:hunter2@");
+        assert!(FtpClient::connect(&in_url, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_data_connection_resumes_tls_session() {
+        let body: Vec<u8> = (0..=255u8).collect();
+        let (url, config, resumed) = serve_ftpes(body.clone()).await;
+
+        let client = FtpClient::connect_with(&url, None, Some(&config)).await.unwrap();
+        let mut reader = client.retrieve(0).await.unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, body);
+        assert!(resumed.load(Ordering::Acquire));
+    }
+}
@@ -0,0 +1,176 @@
+//! Extra request headers
+//!
+//! [`HeaderRule`]s add headers to every request to matching hosts, and a
+//! download's own [`RequestHeader`]s override them on the download's host
+//! (like its cookies, they aren't sent to mirrors elsewhere). The resolved
+//! headers go on last, so they replace the client's default `User-Agent` as
+//! well as any `Cookie`, `Referer` or `Authorization` the request already had.
+//! The download's saved login ([`HttpAuth`]) travels along and is only used
+//! when no header rule sets `Authorization`. Header values are stored
+//! encrypted like passwords; those still sealed while saved logins are locked
+//! are left out.
+
+use super::auth::{send_with_auth, HttpAuth};
+use super::vault::is_sealed;
+use dlman_types::{HeaderRule, RequestHeader};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{RequestBuilder, Response};
+use std::sync::Arc;
+use tracing::warn;
+
+/// The header rules and overrides that apply to one download
+#[derive(Debug, Clone, Default)]
+pub struct RequestHeaders {
+    /// Enabled rules, least specific domain first
+    rules: Arc<[HeaderRule]>,
+    /// The download's own headers
+    overrides: Vec<RequestHeader>,
+    /// Host the overrides are sent to
+    host: Option<String>,
+    /// Saved login for the download's host
+    auth: Option<HttpAuth>,
+}
+
+fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+impl RequestHeaders {
+    /// Headers for a download of `url`, from the saved `rules` and the
+    /// download's own `overrides`
+    pub fn new(rules: Vec<HeaderRule>, url: &str, overrides: Vec<RequestHeader>) -> Self {
+        let mut rules: Vec<HeaderRule> = rules.into_iter().filter(|rule| rule.enabled).collect();
+        // A rule for "cdn.example.com" beats one for "example.com"
+        rules.sort_by_key(|rule| rule.domain.trim().trim_start_matches("*.").len());
+        Self {
+            rules: rules.into(),
+            overrides,
+            host: host_of(url),
+            auth: None,
+        }
+    }
+
+    /// Authenticate with `auth` where no header rule sets `Authorization`
+    pub fn with_auth(mut self, auth: Option<HttpAuth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// The download's saved login, if it has one
+    pub fn auth(&self) -> Option<&HttpAuth> {
+        self.auth.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.overrides.is_empty()
+    }
+
+    /// Headers to send to `url`, each name once; more specific rules and
+    /// then the download's overrides win
+    pub fn resolve(&self, url: &str) -> Vec<&RequestHeader> {
+        let rules = self.rules.iter().filter(|rule| rule.matches_url(url)).flat_map(|rule| &rule.headers);
+        let own_host = self.host.is_some() && host_of(url) == self.host;
+        let overrides = self.overrides.iter().filter(|_| own_host);
+
+        let mut headers: Vec<&RequestHeader> = Vec::new();
+        for header in rules.chain(overrides).filter(|header| !is_sealed(&header.value)) {
+            headers.retain(|h| !h.name.trim().eq_ignore_ascii_case(header.name.trim()));
+            headers.push(header);
+        }
+        headers
+    }
+
+    /// [`Self::resolve`] as a header map. Names or values that can't be
+    /// sent are skipped.
+    pub fn for_url(&self, url: &str) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for header in self.resolve(url) {
+            match (
+                HeaderName::from_bytes(header.name.trim().as_bytes()),
+                HeaderValue::from_str(header.value.trim()),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    map.insert(name, value);
+                }
+                _ => warn!("Skipping invalid request header {:?}", header.name),
+            }
+        }
+        map
+    }
+
+    /// Put the headers for `url` on `request`, replacing any it already has
+    pub fn apply(&self, request: RequestBuilder, url: &str) -> RequestBuilder {
+        if self.is_empty() {
+            return request;
+        }
+        request.headers(self.for_url(url))
+    }
+
+    /// Send `request` to `url` with its headers and the saved login
+    pub async fn send(&self, request: RequestBuilder, url: &str) -> reqwest::Result<Response> {
+        send_with_auth(self.auth.as_ref(), self.apply(request, url)).await
+    }
+
+    /// Add the headers for `url` to name/value pairs, as the media handlers
+    /// take them, replacing pairs of the same name
+    pub fn extend_pairs(&self, pairs: &mut Vec<(String, String)>, url: &str) {
+        for header in self.resolve(url) {
+            pairs.retain(|(name, _)| !name.eq_ignore_ascii_case(header.name.trim()));
+            pairs.push((header.name.trim().to_string(), header.value.trim().to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(domain: &str, headers: &[(&str, &str)]) -> HeaderRule {
+        HeaderRule::new(domain.to_string(), headers.iter().map(|&(n, v)| RequestHeader::new(n, v)).collect())
+    }
+
+    fn names_and_values(headers: &RequestHeaders, url: &str) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        headers.extend_pairs(&mut pairs, url);
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn specific_rules_and_overrides_win() {
+        let mut disabled = rule("example.com", &[("X-Disabled", "1")]);
+        disabled.enabled = false;
+        let headers = RequestHeaders::new(
+            vec![
+                rule("cdn.example.com", &[("User-Agent", "Browser/1.0")]),
+                rule("*.example.com", &[("user-agent", "Generic/1.0"), ("X-Api-Key", "rule")]),
+                disabled,
+            ],
+            "https://cdn.example.com/file.iso",
+            vec![RequestHeader::new("x-api-key", "mine")],
+        );
+
+        assert_eq!(
+            names_and_values(&headers, "https://cdn.example.com/file.iso"),
+            vec![("User-Agent".into(), "Browser/1.0".into()), ("x-api-key".into(), "mine".into())]
+        );
+        // The download's own headers stay on its host
+        assert_eq!(
+            names_and_values(&headers, "https://mirror.example.com/file.iso"),
+            vec![("X-Api-Key".into(), "rule".into()), ("user-agent".into(), "Generic/1.0".into())]
+        );
+        assert!(names_and_values(&headers, "https://example.org/file.iso").is_empty());
+    }
+
+    #[test]
+    fn invalid_headers_are_skipped() {
+        let headers = RequestHeaders::new(
+            vec![rule("example.com", &[("Bad Name", "x"), ("X-Ok", "yes"), ("X-Newline", "a\nb")])],
+            "https://example.com/",
+            Vec::new(),
+        );
+        let map = headers.for_url("https://example.com/file");
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["x-ok"], "yes");
+    }
+}
@@ -0,0 +1,138 @@
+//! Per-host connection registry
+//!
+//! Every segment takes a connection slot from its host's pool before opening a
+//! socket, whichever download it belongs to. So do probes, mirror checks and
+//! the playlist, key and segment requests of HLS/DASH streams. A host matched by a rule in
+//! [`HostLimitSettings`] uses the rule's pool (and shares the rule's bandwidth
+//! limit with the other hosts matching it). Any other host gets a pool of
+//! `max_connections_per_host` slots. Pools are FIFO, so a download that has
+//! been waiting longest gets the next free slot.
+
+use crate::engine::rate_limiter::RateLimiter;
+use dlman_types::HostLimitSettings;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+/// Slots and bandwidth shared by the connections to one host or rule
+struct HostPool {
+    /// `None` when the host has no connection cap
+    slots: Option<Arc<Semaphore>>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// Connection caps shared by all downloads of a [`DownloadManager`](crate::engine::DownloadManager)
+pub struct HostLimiter {
+    settings: RwLock<HostLimitSettings>,
+    pools: Mutex<HashMap<String, Arc<HostPool>>>,
+}
+
+/// A connection slot, given back to the host's pool when dropped
+pub struct HostSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl HostSlot {
+    /// Bandwidth limit shared with the other connections to this host, if any
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+}
+
+impl HostLimiter {
+    pub fn new(settings: HostLimitSettings) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the rules. Connections already open keep their slot in the old
+    /// pools, so for a moment a host may see up to both limits combined.
+    pub fn set_settings(&self, settings: HostLimitSettings) {
+        if *self.settings.read() == settings {
+            return;
+        }
+        *self.settings.write() = settings;
+        self.pools.lock().clear();
+    }
+
+    /// Wait for a free connection slot on the host of `url`
+    pub async fn acquire(&self, url: &str) -> HostSlot {
+        let pool = self.pool(url);
+        let permit = match pool.slots {
+            Some(ref slots) => slots.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        HostSlot { _permit: permit, rate_limiter: pool.rate_limiter.clone() }
+    }
+
+    fn pool(&self, url: &str) -> Arc<HostPool> {
+        let Some(host) = url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_lowercase)) else {
+            return Arc::new(HostPool { slots: None, rate_limiter: None });
+        };
+
+        let settings = self.settings.read();
+        let rule = settings.rules.iter().find(|rule| rule.matches_host(&host));
+        let (key, max_connections, speed_limit) = match rule {
+            Some(rule) => (format!("rule:{}", rule.pattern.trim().to_lowercase()), rule.max_connections, rule.speed_limit),
+            None => (format!("host:{host}"), settings.max_connections_per_host, None),
+        };
+
+        self.pools
+            .lock()
+            .entry(key)
+            .or_insert_with_key(|key| {
+                debug!("New connection pool {} ({} slots, speed limit {:?})", key, max_connections, speed_limit);
+                Arc::new(HostPool {
+                    slots: (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections as usize))),
+                    rate_limiter: speed_limit.filter(|&limit| limit > 0).map(RateLimiter::new),
+                })
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dlman_types::HostLimitRule;
+    use std::time::Duration;
+
+    fn limiter(max_connections_per_host: u32, rules: Vec<HostLimitRule>) -> HostLimiter {
+        HostLimiter::new(HostLimitSettings { max_connections_per_host, rules })
+    }
+
+    async fn blocks(limiter: &HostLimiter, url: &str) -> bool {
+        tokio::time::timeout(Duration::from_millis(50), limiter.acquire(url)).await.is_err()
+    }
+
+    #[tokio::test]
+    async fn test_default_cap_is_per_host() {
+        let limiter = limiter(2, Vec::new());
+        let _a = limiter.acquire("https://cdn.example.com/a.iso").await;
+        let b = limiter.acquire("https://CDN.example.com/b.iso").await;
+        assert!(blocks(&limiter, "https://cdn.example.com/c.iso").await);
+        assert!(!blocks(&limiter, "https://other.example.com/c.iso").await);
+
+        drop(b);
+        assert!(!blocks(&limiter, "https://cdn.example.com/c.iso").await);
+    }
+
+    #[tokio::test]
+    async fn test_rule_pools_matching_hosts() {
+        let rule = HostLimitRule { pattern: "*.example.com".to_string(), max_connections: 1, speed_limit: Some(1000) };
+        let limiter = limiter(0, vec![rule]);
+        let slot = limiter.acquire("https://a.example.com/x").await;
+        assert!(slot.rate_limiter().is_some());
+        assert!(blocks(&limiter, "https://b.example.com/y").await);
+        assert!(!blocks(&limiter, "https://example.org/z").await);
+
+        // New rules apply to connections opened from now on
+        limiter.set_settings(HostLimitSettings { max_connections_per_host: 0, rules: Vec::new() });
+        assert!(!blocks(&limiter, "https://b.example.com/y").await);
+    }
+}
@@ -5,11 +5,17 @@
 //! - Manages the global rate limiter
 //! - Handles download queue logic
 
-use crate::engine::download_task::{candidate_cache_dirs, resolve_segment_cache_dir};
-use crate::engine::{DownloadDatabase, DownloadTask, RateLimiter};
+use crate::engine::download_task::{candidate_cache_dirs, direct_part_path, resolve_segment_cache_dir};
+use crate::engine::{
+    form_login, is_ftp_url, is_sftp_url, DownloadDatabase, DownloadTask, FormSession, FtpClient, HostLimiter, HttpAuth,
+    RateLimiter, RequestHeaders, SftpConnection,
+};
 use crate::error::DlmanError;
-use dlman_types::{CoreEvent, Download, DownloadStatus, LinkInfo, ProxySettings, TempStorageSettings};
-use reqwest::Client;
+use dlman_types::{
+    AuthScheme, CoreEvent, Download, DownloadStatus, HostLimitSettings, LinkInfo, ProxySettings, RequestHeader,
+    SiteCredential, SshKey, TempStorageSettings,
+};
+use reqwest::{Client, ClientBuilder};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -24,6 +30,8 @@ pub struct DownloadManager {
     active_tasks: Arc<RwLock<HashMap<Uuid, DownloadTaskHandle>>>,
     /// HTTP client
     client: Client,
+    /// HTTP client for form logins, which follow redirects themselves
+    login_client: Client,
     /// Database
     db: DownloadDatabase,
     /// DLMan's per-user data directory (system disk). Used to resolve the
@@ -33,6 +41,8 @@ pub struct DownloadManager {
     /// Shared so a settings change is picked up by subsequent downloads without
     /// recreating the manager.
     temp_storage: Arc<RwLock<TempStorageSettings>>,
+    /// Connection caps per host, shared by every download this manager runs
+    host_limiter: Arc<HostLimiter>,
     /// Event broadcaster
     event_tx: broadcast::Sender<CoreEvent>,
 }
@@ -50,6 +60,21 @@ struct DownloadTaskHandle {
 
 /// Build an HTTP client with optional proxy settings
 pub fn build_http_client(proxy_settings: Option<&ProxySettings>) -> Result<Client, DlmanError> {
+    http_client_builder(proxy_settings)?
+        .build()
+        .map_err(|e| DlmanError::Unknown(e.to_string()))
+}
+
+/// Build the HTTP client for form logins: the same proxy settings, but
+/// redirects are left to [`form_login`] so it sees every cookie set
+pub fn build_login_client(proxy_settings: Option<&ProxySettings>) -> Result<Client, DlmanError> {
+    http_client_builder(proxy_settings)?
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| DlmanError::Unknown(e.to_string()))
+}
+
+fn http_client_builder(proxy_settings: Option<&ProxySettings>) -> Result<ClientBuilder, DlmanError> {
     let mut builder = Client::builder()
         .user_agent(crate::USER_AGENT)
         .connect_timeout(Duration::from_secs(30))
@@ -109,9 +134,7 @@ pub fn build_http_client(proxy_settings: Option<&ProxySettings>) -> Result<Clien
         }
     }
     
-    builder
-        .build()
-        .map_err(|e| DlmanError::Unknown(e.to_string()))
+    Ok(builder)
 }
 
 impl DownloadManager {
@@ -135,13 +158,16 @@ impl DownloadManager {
         
         // Create HTTP client with proxy settings
         let client = build_http_client(proxy_settings)?;
+        let login_client = build_login_client(proxy_settings)?;
 
         Ok(Self {
             active_tasks: Arc::new(RwLock::new(HashMap::new())),
             client,
+            login_client,
             db,
             data_dir,
             temp_storage: Arc::new(RwLock::new(TempStorageSettings::default())),
+            host_limiter: Arc::new(HostLimiter::new(HostLimitSettings::default())),
             event_tx,
         })
     }
@@ -153,6 +179,57 @@ impl DownloadManager {
         *self.temp_storage.write().await = policy;
     }
 
+    /// Update the per-host connection caps. Called by the core when settings
+    /// are loaded or changed; applies to connections opened from then on.
+    pub fn set_host_limits(&self, limits: HostLimitSettings) {
+        self.host_limiter.set_settings(limits);
+    }
+
+    /// Connection slots shared by every download, for requests made outside
+    /// a [`DownloadTask`] (streams, probes)
+    pub fn host_limiter(&self) -> Arc<HostLimiter> {
+        self.host_limiter.clone()
+    }
+
+    /// Headers for requests of a download of `url`: the saved header rules,
+    /// plus the download's own `overrides`, and the saved logins for its host,
+    /// tried in turn
+    pub async fn request_headers(&self, url: &str, overrides: Vec<RequestHeader>) -> RequestHeaders {
+        let rules = self.db.load_all_header_rules().await.unwrap_or_else(|e| {
+            warn!("Failed to load header rules: {}", e);
+            Vec::new()
+        });
+        let credentials = self.db.find_credentials_for_url(url).await.unwrap_or_else(|e| {
+            warn!("Failed to look up credentials for URL: {}", e);
+            Vec::new()
+        });
+        let mut accounts = Vec::with_capacity(credentials.len());
+        for credential in credentials {
+            let auth = HttpAuth::from_credential(&credential, url);
+            if credential.auth_scheme != AuthScheme::Form {
+                accounts.push(auth);
+                continue;
+            }
+            match FormSession::new(credential, self.login_client.clone(), self.db.clone()).await {
+                Some(session) => accounts.push(auth.with_form_session(Arc::new(session))),
+                None => warn!("Form login credential for {} has no login form", url),
+            }
+        }
+        let auth = HttpAuth::rotating(accounts, self.db.clone());
+        RequestHeaders::new(rules, url, overrides).with_auth(auth)
+    }
+
+    /// Submit the login form of `credential` to check that it works. The
+    /// session isn't kept; downloads log in for themselves.
+    pub async fn test_form_login(&self, credential: &SiteCredential) -> Result<(), DlmanError> {
+        let login = credential.form_login.as_ref().ok_or_else(|| DlmanError::LoginFailed {
+            url: credential.domain.clone(),
+            reason: "no login form configured".to_string(),
+        })?;
+        form_login(&self.login_client, login, &credential.username, &credential.password).await?;
+        Ok(())
+    }
+
     /// Resolve the scratch directory for a download under the current policy.
     async fn resolve_temp_dir(&self, download: &Download) -> PathBuf {
         let policy = self.temp_storage.read().await.clone();
@@ -162,6 +239,7 @@ impl DownloadManager {
     /// Update the HTTP client with new proxy settings
     pub fn update_proxy(&mut self, proxy_settings: Option<&ProxySettings>) -> Result<(), DlmanError> {
         self.client = build_http_client(proxy_settings)?;
+        self.login_client = build_login_client(proxy_settings)?;
         Ok(())
     }
     
@@ -175,18 +253,25 @@ impl DownloadManager {
     /// (some servers like GitHub don't return Content-Length for HEAD on redirected downloads)
     pub async fn probe_url(&self, url: &url::Url) -> Result<LinkInfo, DlmanError> {
         info!("Probing URL: {}", url);
+        let _slot = self.host_limiter.acquire(url.as_str()).await;
+        
+        if is_ftp_url(url.as_str()) {
+            return self.probe_ftp_url(url).await;
+        }
+        if is_sftp_url(url.as_str()) {
+            return self.probe_sftp_url(url).await;
+        }
+        
+        let headers = self.request_headers(url.as_str(), Vec::new()).await;
         
         // Try HEAD first
-        let response = self.client.head(url.as_str()).send().await?;
+        let response = headers.send(self.client.head(url.as_str()), url.as_str()).await?;
         
         // Check for authentication required
         let status = response.status();
         if status.as_u16() == 401 || status.as_u16() == 403 {
             info!("URL requires authentication (HTTP {})", status.as_u16());
-            let filename = url.path_segments()
-                .and_then(|s| s.last())
-                .unwrap_or("download")
-                .to_string();
+            let filename = crate::filename::resolve(url, None, None);
             return Ok(LinkInfo {
                 url: url.to_string(),
                 final_url: Some(response.url().to_string()),
@@ -221,11 +306,8 @@ impl DownloadManager {
         // This is needed for GitHub releases and similar CDNs
         if size.is_none() {
             info!("HEAD didn't return Content-Length, trying partial GET...");
-            match self.client
-                .get(&final_url)
-                .header(reqwest::header::RANGE, "bytes=0-0")
-                .send()
-                .await
+            let request = self.client.get(&final_url).header(reqwest::header::RANGE, "bytes=0-0");
+            match headers.send(request, &final_url).await
             {
                 Ok(range_response) => {
                     let status = range_response.status();
@@ -276,21 +358,16 @@ impl DownloadManager {
             }
         }
         
-        let filename = response
+        // Servers often send UTF-8 names unencoded, which `to_str` rejects
+        let content_disposition = response
             .headers()
             .get(reqwest::header::CONTENT_DISPOSITION)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| {
-                v.split("filename=")
-                    .nth(1)
-                    .map(|s| s.trim_matches('"').to_string())
-            })
-            .unwrap_or_else(|| {
-                url.path_segments()
-                    .and_then(|s| s.last())
-                    .unwrap_or("download")
-                    .to_string()
-            });
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned());
+        let filename = crate::filename::resolve(
+            &url::Url::parse(&final_url).unwrap_or_else(|_| url.clone()),
+            content_disposition.as_deref(),
+            content_type.as_deref(),
+        );
         
         Ok(LinkInfo {
             url: url.to_string(),
@@ -304,7 +381,45 @@ impl DownloadManager {
         })
     }
     
+    /// [`Self::probe_url`] for an FTP source. A rejected anonymous login
+    /// means the site needs credentials.
+    async fn probe_ftp_url(&self, url: &url::Url) -> Result<LinkInfo, DlmanError> {
+        let mut info = link_info_from_path(url);
+        let mut client = match FtpClient::connect(url.as_str(), None).await {
+            Ok(client) => client,
+            Err(DlmanError::AuthenticationRequired { .. }) => {
+                info!("FTP server requires a login");
+                info.requires_auth = true;
+                return Ok(info);
+            }
+            Err(e) => return Err(e),
+        };
+        let probe = client.probe().await?;
+        info.size = probe.size;
+        info.resumable = probe.supports_rest;
+        Ok(info)
+    }
+    
+    /// [`Self::probe_url`] for an SFTP source, logging in with the user's
+    /// default SSH keys. If none is accepted the site needs credentials.
+    async fn probe_sftp_url(&self, url: &url::Url) -> Result<LinkInfo, DlmanError> {
+        let mut info = link_info_from_path(url);
+        let connection = match SftpConnection::connect(url.as_str(), None, None).await {
+            Ok(connection) => connection,
+            Err(DlmanError::AuthenticationRequired { .. }) => {
+                info!("SFTP server requires a login");
+                info.requires_auth = true;
+                return Ok(info);
+            }
+            Err(e) => return Err(e),
+        };
+        info.size = connection.probe().await?.size;
+        info.resumable = true;
+        Ok(info)
+    }
+    
     /// Start or resume a download
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
         &self, 
         download: Download, 
@@ -313,6 +428,7 @@ impl DownloadManager {
         max_retries: u32,
         retry_delay_secs: u32,
         credentials: Option<(String, String)>,
+        ssh_key: Option<SshKey>,
     ) -> Result<(), DlmanError> {
         let id = download.id;
         
@@ -357,6 +473,23 @@ impl DownloadManager {
         // on the user's temp-storage policy (see resolve_segment_cache_dir).
         let temp_dir = self.resolve_temp_dir(&download).await;
         info!("Scratch directory for {}: {:?}", id, temp_dir);
+        let direct_write = self.temp_storage.read().await.mode == "direct";
+        let mut headers = self.request_headers(&download.url, download.headers.clone()).await;
+        // Credentials typed in for this download, but not saved, replace the
+        // saved login in the scheme it uses
+        if let Some((ref username, ref password)) = credentials {
+            if !headers.auth().is_some_and(|auth| auth.logs_in_as(username, password)) {
+                let scheme = match headers.auth().map(HttpAuth::scheme) {
+                    Some(AuthScheme::Form) | None => AuthScheme::Basic,
+                    Some(scheme) => scheme,
+                };
+                let auth = HttpAuth::new(scheme, username.clone(), password.clone(), &download.url);
+                headers = headers.with_auth(Some(auth));
+            }
+        }
+
+        // What this run downloads counts against the account it ends up using
+        let auth = headers.auth().cloned();
 
         // Create download task with its own rate limiter
         let task = DownloadTask::new_with_credentials(
@@ -372,11 +505,21 @@ impl DownloadManager {
             max_retries,
             retry_delay_secs,
             credentials,
-        );
+        )
+        .with_ssh_key(ssh_key)
+        .with_host_limiter(self.host_limiter.clone())
+        .with_direct_write(direct_write)
+        .with_headers(headers);
         
         // Spawn task with cleanup
+        let downloaded = task.downloaded();
+        let downloaded_before = downloaded.load(std::sync::atomic::Ordering::Acquire);
         let task_handle = tokio::spawn(async move {
             let result = task.run().await;
+            if let Some(auth) = auth {
+                let downloaded = downloaded.load(std::sync::atomic::Ordering::Acquire);
+                auth.record_usage(downloaded.saturating_sub(downloaded_before)).await;
+            }
             // Remove from active tasks when done
             active_tasks_for_cleanup.write().await.remove(&task_id);
             result
@@ -426,6 +569,7 @@ impl DownloadManager {
     /// Resume a download
     /// `effective_speed_limit` - the resolved speed limit (download override > queue limit > None for unlimited)
     /// `segment_count` - number of segments for new downloads (ignored if download already has segments)
+    #[allow(clippy::too_many_arguments)]
     pub async fn resume(
         &self, 
         id: Uuid, 
@@ -434,6 +578,7 @@ impl DownloadManager {
         max_retries: u32,
         retry_delay_secs: u32,
         credentials: Option<(String, String)>,
+        ssh_key: Option<SshKey>,
     ) -> Result<(), DlmanError> {
         // Check if task is still running (might be if pause was very recent)
         {
@@ -473,7 +618,7 @@ impl DownloadManager {
             .ok_or(DlmanError::NotFound(id))?;
         
         // Start the download with the effective speed limit
-        self.start(download, effective_speed_limit, segment_count, max_retries, retry_delay_secs, credentials).await?;
+        self.start(download, effective_speed_limit, segment_count, max_retries, retry_delay_secs, credentials, ssh_key).await?;
         
         Ok(())
     }
@@ -527,26 +672,7 @@ impl DownloadManager {
                 }
             }
             
-            // Delete partial segment files from every directory this download's
-            // scratch could live in. We check all candidates (destination,
-            // appdata, custom) rather than just the currently-resolved one, so
-            // changing the temp-storage policy mid-flight never leaves orphans.
-            let policy = self.temp_storage.read().await.clone();
-            let cache_dirs = candidate_cache_dirs(&policy, &download.destination, &self.data_dir);
-            for cache_dir in &cache_dirs {
-                for segment in &download.segments {
-                    let temp_path = cache_dir.join(format!(
-                        "{}_segment_{}.part",
-                        id, segment.index
-                    ));
-                    if temp_path.exists() {
-                        let _ = tokio::fs::remove_file(&temp_path).await;
-                    }
-                }
-                // Best-effort: drop the scratch dir if no other downloads' parts
-                // remain (remove_dir only succeeds when the directory is empty).
-                let _ = tokio::fs::remove_dir(cache_dir).await;
-            }
+            self.remove_segment_files(&download).await;
         }
         
         // Delete from DB (always happens, even if file deletion failed)
@@ -558,6 +684,37 @@ impl DownloadManager {
         Ok(())
     }
     
+    /// Delete a download's partial segment files
+    ///
+    /// Checks every directory its scratch could live in (destination, appdata,
+    /// custom) rather than just the currently-resolved one, so changing the
+    /// temp-storage policy mid-flight never leaves orphans. A direct-write
+    /// download's `.part` file in the destination goes too.
+    pub async fn remove_segment_files(&self, download: &Download) {
+        if download.direct_write {
+            let part_path = direct_part_path(download);
+            if part_path.exists() {
+                let _ = tokio::fs::remove_file(&part_path).await;
+            }
+        }
+        let policy = self.temp_storage.read().await.clone();
+        let cache_dirs = candidate_cache_dirs(&policy, &download.destination, &self.data_dir);
+        for cache_dir in &cache_dirs {
+            for segment in &download.segments {
+                let temp_path = cache_dir.join(format!(
+                    "{}_segment_{}.part",
+                    download.id, segment.index
+                ));
+                if temp_path.exists() {
+                    let _ = tokio::fs::remove_file(&temp_path).await;
+                }
+            }
+            // Best-effort: drop the scratch dir if no other downloads' parts
+            // remain (remove_dir only succeeds when the directory is empty).
+            let _ = tokio::fs::remove_dir(cache_dir).await;
+        }
+    }
+    
     /// Update speed limit for a download
     /// Stores `speed_limit` in DB and applies `effective_limit` to active downloads
     pub async fn update_speed_limit_with_effective(
@@ -619,7 +776,7 @@ impl DownloadManager {
             // Queue resolution should be done at API layer
             let segment_count = if download.segments.is_empty() { 4 } else { download.segments.len() as u32 };
             // Use default retry settings
-            self.resume(download.id, download.speed_limit, segment_count, 5, 30, None).await?;
+            self.resume(download.id, download.speed_limit, segment_count, 5, 30, None, None).await?;
         }
         
         Ok(())
@@ -657,3 +814,18 @@ impl DownloadManager {
         Ok(downloads)
     }
 }
+
+/// Link info named after the last path segment, for sources probed without
+/// HTTP headers
+fn link_info_from_path(url: &url::Url) -> LinkInfo {
+    LinkInfo {
+        url: url.to_string(),
+        final_url: Some(url.to_string()),
+        filename: crate::filename::resolve(url, None, None),
+        size: None,
+        content_type: None,
+        resumable: false,
+        error: None,
+        requires_auth: false,
+    }
+}
@@ -0,0 +1,165 @@
+//! Sources of a multi-mirror download
+//!
+//! A download can list mirrors besides its primary URL. A mirror is only used
+//! once it has reported the same size as the primary (and, from its second use
+//! on, the same validator as the first time). Segments are spread round-robin
+//! over the usable sources, and a segment whose source keeps failing moves on
+//! to the next healthy one.
+
+use std::collections::HashMap;
+
+/// Failures after which a source is only used when nothing else is left
+const MAX_SOURCE_FAILURES: u32 = 3;
+
+/// A URL that segments can be fetched from
+#[derive(Debug, Clone)]
+pub(crate) struct Source {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    failures: u32,
+}
+
+impl Source {
+    pub fn new(url: String, etag: Option<String>, last_modified: Option<String>) -> Self {
+        Self {
+            url,
+            etag,
+            last_modified,
+            failures: 0,
+        }
+    }
+}
+
+/// The usable sources of a download, and which segment is fetched from which
+pub(crate) struct SourcePool {
+    /// The primary URL is always at index 0
+    sources: Vec<Source>,
+    assignments: HashMap<u32, usize>,
+    next: usize,
+}
+
+impl SourcePool {
+    pub fn new(primary: Source) -> Self {
+        Self {
+            sources: vec![primary],
+            assignments: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, mirror: Source) {
+        self.sources.push(mirror);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Source of `segment`; new segments are assigned round-robin
+    pub fn source_for(&mut self, segment: u32) -> &Source {
+        let index = match self.assignments.get(&segment) {
+            Some(&index) => index,
+            None => {
+                let index = self.next_healthy(self.next);
+                self.next = index + 1;
+                self.assignments.insert(segment, index);
+                index
+            }
+        };
+        &self.sources[index]
+    }
+
+    /// Whether `segment` is fetched from a mirror rather than the primary URL
+    pub fn is_mirror(&self, segment: u32) -> bool {
+        self.assignments.get(&segment).is_some_and(|&index| index > 0)
+    }
+
+    /// `segment` finished, so its source is working
+    pub fn succeeded(&mut self, segment: u32) {
+        if let Some(&index) = self.assignments.get(&segment) {
+            self.sources[index].failures = 0;
+        }
+    }
+
+    /// Count a failure against the source of `segment` and move the segment
+    /// to the next healthy source. `permanent` takes the source out of
+    /// rotation at once. Returns `false` if there is nowhere to move.
+    pub fn fail_over(&mut self, segment: u32, permanent: bool) -> bool {
+        let Some(&current) = self.assignments.get(&segment) else {
+            return false;
+        };
+        let source = &mut self.sources[current];
+        source.failures = if permanent {
+            u32::MAX
+        } else {
+            source.failures.saturating_add(1)
+        };
+
+        let next = self.next_healthy(current + 1);
+        if next == current {
+            return false;
+        }
+        self.assignments.insert(segment, next);
+        true
+    }
+
+    fn is_healthy(&self, index: usize) -> bool {
+        self.sources[index].failures < MAX_SOURCE_FAILURES
+    }
+
+    /// First healthy source at or after `from`, wrapping around; the primary
+    /// URL if none is healthy
+    fn next_healthy(&self, from: usize) -> usize {
+        let len = self.sources.len();
+        (0..len)
+            .map(|offset| (from + offset) % len)
+            .find(|&index| self.is_healthy(index))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(mirrors: usize) -> SourcePool {
+        let mut pool = SourcePool::new(Source::new("https://a/f".into(), None, None));
+        for i in 0..mirrors {
+            pool.push(Source::new(format!("https://m{i}/f"), None, None));
+        }
+        pool
+    }
+
+    #[test]
+    fn segments_are_spread_round_robin() {
+        let mut pool = pool(2);
+        let urls: Vec<String> = (0..4).map(|i| pool.source_for(i).url.clone()).collect();
+        assert_eq!(urls, ["https://a/f", "https://m0/f", "https://m1/f", "https://a/f"]);
+        // Assignments are sticky
+        assert_eq!(pool.source_for(1).url, "https://m0/f");
+        assert!(pool.is_mirror(1) && !pool.is_mirror(0));
+    }
+
+    #[test]
+    fn failing_source_is_skipped() {
+        let mut pool = pool(1);
+        pool.source_for(0);
+        assert!(pool.fail_over(0, false));
+        assert_eq!(pool.source_for(0).url, "https://m0/f");
+
+        // A permanently failed mirror gets no new segments
+        assert!(pool.fail_over(0, true));
+        assert_eq!(pool.source_for(0).url, "https://a/f");
+        assert_eq!(pool.source_for(1).url, "https://a/f");
+        assert_eq!(pool.source_for(2).url, "https://a/f");
+    }
+
+    #[test]
+    fn single_source_cannot_fail_over() {
+        let mut pool = pool(0);
+        pool.source_for(0);
+        assert!(!pool.fail_over(0, false));
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -6,15 +6,43 @@
 //! - Token bucket rate limiting
 //! - Clean pause/resume/cancel
 //! - Crash-safe resume
+//! - Checksum verification of merged files
+//! - Segments spread across mirror URLs
+//! - FTP and FTPS sources next to HTTP(S)
+//! - SFTP sources with key-based login and known-hosts checks
+//! - Per-host connection caps shared by all downloads
+//! - Connection counts adapted to measured throughput
+//! - Extra request headers per host and per download
+//! - Basic, Digest and Bearer HTTP authentication
+//! - Form logins whose session cookies are reused and renewed
+//! - Saved passwords encrypted under a master passphrase or keyfile
 
+mod auth;
+mod checksum;
+mod form_login;
+mod ftp;
+mod headers;
+mod host_limits;
+mod mirrors;
 mod persistence;
 mod rate_limiter;
 mod segment_worker;
+mod sftp;
+mod tuning;
+mod vault;
 mod download_task;
 mod manager;
 
+pub use auth::*;
+pub use checksum::*;
+pub use form_login::*;
+pub use ftp::*;
+pub use headers::*;
+pub use host_limits::*;
 pub use persistence::*;
 pub use rate_limiter::*;
 pub use segment_worker::*;
+pub use sftp::*;
+pub use vault::CredentialVault;
 pub use download_task::*;
 pub use manager::*;
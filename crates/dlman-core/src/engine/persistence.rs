@@ -3,16 +3,22 @@
 //! Stores downloads, segments, and settings in a relational database for atomic, transactional updates.
 //! This is the SINGLE SOURCE OF TRUTH for all persistent data.
 
+use super::vault::{is_sealed, CredentialVault, VaultRecord};
+use base64::Engine;
 use crate::error::DlmanError;
-use dlman_types::{Download, DownloadStatus, Segment, Settings, SiteCredential, Theme};
+use dlman_types::{Download, DownloadStatus, HeaderRule, RequestHeader, Segment, Settings, SiteCredential, SshKey, Theme, VaultKey, VaultStatus};
 use sqlx::{sqlite::{SqliteConnectOptions, SqlitePool}, Row, SqlitePool as Pool};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 /// Database connection pool for download persistence
 #[derive(Clone, Debug)]
 pub struct DownloadDatabase {
     pool: Pool,
+    /// Key for the saved passwords, shared by all clones
+    vault: Arc<CredentialVault>,
 }
 
 impl DownloadDatabase {
@@ -30,7 +36,7 @@ impl DownloadDatabase {
             .filename(path)
             .create_if_missing(true);
         
-        let pool = SqlitePool::connect_with(options).await?;
+        let pool = SqlitePool::connect_with(options.clone()).await?;
         
         // Create tables
         sqlx::query(
@@ -86,7 +92,8 @@ impl DownloadDatabase {
                 proxy_settings TEXT,
                 language TEXT NOT NULL DEFAULT 'en',
                 font TEXT,
-                temp_storage TEXT
+                temp_storage TEXT,
+                ffmpeg_remux_fallback INTEGER NOT NULL DEFAULT 0
             );
             
             CREATE TABLE IF NOT EXISTS site_credentials (
@@ -111,13 +118,21 @@ impl DownloadDatabase {
         .await?;
         
         // Run migrations for existing databases
-        Self::run_migrations(&pool).await?;
+        let vault = Arc::new(CredentialVault::default());
+        Self::run_migrations(&pool, &vault).await?;
+        
+        // Connections that ran the migrations may have cached the schema from
+        // before a later ALTER TABLE; their `SELECT *` statements would then
+        // report fewer columns than the rows they return. Start over with
+        // fresh connections.
+        pool.close().await;
+        let pool = SqlitePool::connect_with(options).await?;
         
-        Ok(Self { pool })
+        Ok(Self { pool, vault })
     }
     
     /// Run database migrations for schema updates
-    async fn run_migrations(pool: &sqlx::SqlitePool) -> Result<(), DlmanError> {
+    async fn run_migrations(pool: &sqlx::SqlitePool, vault: &CredentialVault) -> Result<(), DlmanError> {
         // Migration: Add proxy_settings column if it doesn't exist
         sqlx::query(
             r#"
@@ -145,6 +160,18 @@ impl DownloadDatabase {
             .execute(pool)
             .await
             .ok();
+
+        // Migration: Add opt-in ffmpeg fallback for remuxing media downloads
+        sqlx::query("ALTER TABLE settings ADD COLUMN ffmpeg_remux_fallback INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .ok();
+
+        // Migration: Add per-host connection limits (JSON; NULL = defaults)
+        sqlx::query("ALTER TABLE settings ADD COLUMN host_limits TEXT")
+            .execute(pool)
+            .await
+            .ok();
         
         // Migration: Create site_credentials table if it doesn't exist
         sqlx::query(
@@ -185,6 +212,170 @@ impl DownloadDatabase {
         .await
         .ok(); // Ignore error if column already exists
         
+        // Migration: Add expected checksum ("algorithm:hex") to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN checksum TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add mirror URLs (JSON array) to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN mirrors TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add piece hashes (JSON) to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN piece_hashes TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add direct_write column to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN direct_write INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add per-download request headers (JSON array) to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN headers TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add HLS/DASH variant and rendition choice (JSON) to downloads
+        sqlx::query("ALTER TABLE downloads ADD COLUMN stream_selection TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add SSH private key to site credentials
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN ssh_key_path TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN ssh_key_passphrase TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add HTTP auth scheme to site credentials
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN auth_scheme TEXT NOT NULL DEFAULT 'basic'")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Add login form (JSON) to site credentials
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN form_login TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Session cookies captured by form logins
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS login_sessions (
+                credential_id TEXT PRIMARY KEY,
+                cookies TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        
+        // Migration: Remember how many connections paid off per host
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS host_connections (
+                host TEXT PRIMARY KEY,
+                connections INTEGER NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        
+        // Migration: Failures and daily quotas of site credentials
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN failure_count INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .ok();
+        
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN last_failed_at TEXT")
+            .execute(pool)
+            .await
+            .ok();
+        
+        sqlx::query("ALTER TABLE site_credentials ADD COLUMN daily_quota INTEGER")
+            .execute(pool)
+            .await
+            .ok();
+        
+        // Migration: Bytes downloaded per credential per day (UTC)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS credential_usage (
+                credential_id TEXT NOT NULL,
+                day TEXT NOT NULL,
+                bytes INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (credential_id, day)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        
+        // Migration: Create header_rules table if it doesn't exist
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS header_rules (
+                id TEXT PRIMARY KEY,
+                domain TEXT NOT NULL,
+                headers TEXT NOT NULL,
+                enabled INTEGER NOT NULL DEFAULT 1,
+                created_at TEXT NOT NULL,
+                notes TEXT
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        
+        // Migration: Master key that saved passwords are encrypted with
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS credential_vault (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                salt TEXT NOT NULL,
+                m_cost INTEGER NOT NULL,
+                t_cost INTEGER NOT NULL,
+                p_cost INTEGER NOT NULL,
+                check_value TEXT NOT NULL,
+                keyfile TEXT,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        
+        // Migration: Encrypt passwords still stored in plaintext. That takes
+        // the key, which is at hand here only for a keyfile vault; a
+        // passphrase vault does it when unlocked.
+        if let Some(record) = load_vault_record(pool).await? {
+            vault.load(record.clone());
+            if let Some(ref keyfile) = record.keyfile {
+                match record.unlock(&VaultKey::Keyfile(keyfile.clone())).await {
+                    Ok(key) => {
+                        vault.unlock(record, key);
+                        seal_plaintext_credentials(pool, vault).await?;
+                    }
+                    Err(e) => warn!("Saved logins stay locked: {}", e),
+                }
+            }
+        }
+        
         Ok(())
     }
     
@@ -192,6 +383,28 @@ impl DownloadDatabase {
     pub async fn upsert_download(&self, download: &Download) -> Result<(), DlmanError> {
         let mut tx = self.pool.begin().await?;
         
+        let (headers, keep_headers) = if download.headers.is_empty() {
+            (None, false)
+        } else {
+            match seal_headers(&download.headers, &self.vault) {
+                Ok(sealed) => (Some(sealed), false),
+                // Headers opened before the vault was locked can't be sealed
+                // again until it's unlocked, so the stored ones stay. A new
+                // download has none stored and has to wait.
+                Err(DlmanError::CredentialsLocked) => {
+                    let stored: Option<i64> = sqlx::query_scalar("SELECT 1 FROM downloads WHERE id = ?")
+                        .bind(download.id.to_string())
+                        .fetch_optional(&mut *tx)
+                        .await?;
+                    if stored.is_none() {
+                        return Err(DlmanError::CredentialsLocked);
+                    }
+                    (None, true)
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        
         // Upsert download
         sqlx::query(
             r#"
@@ -199,8 +412,8 @@ impl DownloadDatabase {
                 id, url, final_url, filename, destination, size, downloaded,
                 status, queue_id, category_id, color, error, speed_limit,
                 created_at, completed_at, retry_count, etag, last_modified, supports_range,
-                cookies
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                cookies, checksum, mirrors, piece_hashes, direct_write, headers, stream_selection
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 url = excluded.url,
                 final_url = excluded.final_url,
@@ -219,7 +432,13 @@ impl DownloadDatabase {
                 etag = excluded.etag,
                 last_modified = excluded.last_modified,
                 supports_range = excluded.supports_range,
-                cookies = excluded.cookies
+                cookies = excluded.cookies,
+                checksum = excluded.checksum,
+                mirrors = excluded.mirrors,
+                piece_hashes = excluded.piece_hashes,
+                direct_write = excluded.direct_write,
+                headers = CASE WHEN ? THEN downloads.headers ELSE excluded.headers END,
+                stream_selection = excluded.stream_selection
             "#,
         )
         .bind(download.id.to_string())
@@ -238,10 +457,21 @@ impl DownloadDatabase {
         .bind(download.created_at.to_rfc3339())
         .bind(download.completed_at.map(|d| d.to_rfc3339()))
         .bind(download.retry_count as i64)
-        .bind(None::<String>) // etag - will add later
-        .bind(None::<String>) // last_modified - will add later
-        .bind(0i64) // supports_range - will add later
+        .bind(download.etag.as_ref())
+        .bind(download.last_modified.as_ref())
+        .bind(if download.supports_range { 1i64 } else { 0i64 })
         .bind(download.cookies.as_ref())
+        .bind(download.checksum.as_ref().map(|c| c.to_string()))
+        .bind(if download.mirrors.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&download.mirrors).ok()
+        })
+        .bind(download.piece_hashes.as_ref().and_then(|p| serde_json::to_string(p).ok()))
+        .bind(if download.direct_write { 1i64 } else { 0i64 })
+        .bind(headers)
+        .bind(download.stream_selection.as_ref().and_then(|s| serde_json::to_string(s).ok()))
+        .bind(keep_headers)
         .execute(&mut *tx)
         .await?;
         
@@ -292,7 +522,7 @@ impl DownloadDatabase {
         // Load segments
         let segments = self.load_segments(id).await?;
         
-        Ok(Some(row_to_download(row, segments)?))
+        Ok(Some(row_to_download(row, segments, &self.vault)?))
     }
     
     /// Load all downloads (optimized with single segments query)
@@ -326,7 +556,7 @@ impl DownloadDatabase {
         for row in download_rows {
             let id: String = row.get("id");
             let segments = segments_map.remove(&id).unwrap_or_default();
-            downloads.push(row_to_download(row, segments)?);
+            downloads.push(row_to_download(row, segments, &self.vault)?);
         }
         
         Ok(downloads)
@@ -382,6 +612,54 @@ impl DownloadDatabase {
         Ok(())
     }
     
+    /// Persist a work-stealing split: shorten an existing segment and insert
+    /// the segment that took over its tail. Progress columns of the running
+    /// segment are left alone so concurrent `update_segment_progress` calls
+    /// are not clobbered.
+    pub async fn split_segment(
+        &self,
+        download_id: Uuid,
+        segment_index: u32,
+        new_end: u64,
+        stolen: &Segment,
+    ) -> Result<(), DlmanError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE segments
+            SET end_byte = ?
+            WHERE download_id = ? AND segment_index = ?
+            "#,
+        )
+        .bind(new_end as i64)
+        .bind(download_id.to_string())
+        .bind(segment_index as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO segments (
+                download_id, segment_index, start_byte, end_byte,
+                downloaded_bytes, complete, temp_file_path
+            ) VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(download_id.to_string())
+        .bind(stolen.index as i64)
+        .bind(stolen.start as i64)
+        .bind(stolen.end as i64)
+        .bind(stolen.downloaded as i64)
+        .bind(if stolen.complete { 1i64 } else { 0i64 })
+        .bind(None::<String>)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
     /// Update download progress (sum of all segments)
     pub async fn update_download_progress(
         &self,
@@ -484,7 +762,7 @@ impl DownloadDatabase {
         for row in download_rows {
             let id: String = row.get("id");
             let segments = segments_map.remove(&id).unwrap_or_default();
-            downloads.push(row_to_download(row, segments)?);
+            downloads.push(row_to_download(row, segments, &self.vault)?);
         }
         
         Ok(downloads)
@@ -534,6 +812,15 @@ impl DownloadDatabase {
                         .unwrap_or_default(),
                     language: row.try_get::<String, _>("language").unwrap_or_else(|_| "en".to_string()),
                     font: row.try_get::<Option<String>, _>("font").unwrap_or(None),
+                    ffmpeg_remux_fallback: row
+                        .try_get::<i64, _>("ffmpeg_remux_fallback")
+                        .map(|v| v != 0)
+                        .unwrap_or(false),
+                    host_limits: row.try_get::<Option<String>, _>("host_limits")
+                        .ok()
+                        .flatten()
+                        .and_then(|s| serde_json::from_str(&s).ok())
+                        .unwrap_or_default(),
                 })
             }
             None => {
@@ -559,14 +846,17 @@ impl DownloadDatabase {
         let temp_storage_json = serde_json::to_string(&settings.temp_storage)
             .unwrap_or_else(|_| "{}".to_string());
 
+        let host_limits_json = serde_json::to_string(&settings.host_limits)
+            .unwrap_or_else(|_| "{}".to_string());
+
         sqlx::query(
             r#"
             INSERT INTO settings (
                 id, default_download_path, max_concurrent_downloads, default_segments,
                 global_speed_limit, theme, dev_mode, minimize_to_tray, start_on_boot,
                 browser_integration_port, remember_last_path, max_retries, retry_delay_seconds,
-                proxy_settings, language, font, temp_storage
-            ) VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                proxy_settings, language, font, temp_storage, ffmpeg_remux_fallback, host_limits
+            ) VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 default_download_path = excluded.default_download_path,
                 max_concurrent_downloads = excluded.max_concurrent_downloads,
@@ -583,7 +873,9 @@ impl DownloadDatabase {
                 proxy_settings = excluded.proxy_settings,
                 language = excluded.language,
                 font = excluded.font,
-                temp_storage = excluded.temp_storage
+                temp_storage = excluded.temp_storage,
+                ffmpeg_remux_fallback = excluded.ffmpeg_remux_fallback,
+                host_limits = excluded.host_limits
             "#,
         )
         .bind(settings.default_download_path.to_string_lossy().to_string())
@@ -602,6 +894,8 @@ impl DownloadDatabase {
         .bind(&settings.language)
         .bind(&settings.font)
         .bind(temp_storage_json)
+        .bind(if settings.ffmpeg_remux_fallback { 1i64 } else { 0i64 })
+        .bind(host_limits_json)
         .execute(&self.pool)
         .await?;
         
@@ -613,14 +907,16 @@ impl DownloadDatabase {
     // Site Credentials CRUD
     // ========================================================================
     
-    /// Save or update a site credential
+    /// Save or update a site credential. Its failures are left as they are:
+    /// only `record_credential_failure` changes them.
     pub async fn upsert_credential(&self, credential: &SiteCredential) -> Result<(), DlmanError> {
         sqlx::query(
             r#"
             INSERT INTO site_credentials (
                 id, domain, protocol, username, password, enabled,
-                created_at, last_used_at, notes
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                created_at, last_used_at, notes, ssh_key_path, ssh_key_passphrase, auth_scheme,
+                form_login, failure_count, last_failed_at, daily_quota
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 domain = excluded.domain,
                 protocol = excluded.protocol,
@@ -628,33 +924,50 @@ impl DownloadDatabase {
                 password = excluded.password,
                 enabled = excluded.enabled,
                 last_used_at = excluded.last_used_at,
-                notes = excluded.notes
+                notes = excluded.notes,
+                ssh_key_path = excluded.ssh_key_path,
+                ssh_key_passphrase = excluded.ssh_key_passphrase,
+                auth_scheme = excluded.auth_scheme,
+                form_login = excluded.form_login,
+                daily_quota = excluded.daily_quota
             "#,
         )
         .bind(credential.id.to_string())
         .bind(&credential.domain)
         .bind(&credential.protocol)
         .bind(&credential.username)
-        .bind(&credential.password)
+        .bind(self.vault.seal(&credential.password)?)
         .bind(if credential.enabled { 1i64 } else { 0i64 })
         .bind(credential.created_at.to_rfc3339())
         .bind(credential.last_used_at.map(|d| d.to_rfc3339()))
         .bind(credential.notes.as_ref())
+        .bind(credential.ssh_key.as_ref().map(|k| k.path.to_string_lossy().to_string()))
+        .bind(match credential.ssh_key.as_ref().and_then(|k| k.passphrase.as_deref()) {
+            Some(passphrase) => Some(self.vault.seal(passphrase)?),
+            None => None,
+        })
+        .bind(credential.auth_scheme.as_str())
+        .bind(credential.form_login.as_ref().and_then(|f| serde_json::to_string(f).ok()))
+        .bind(credential.failure_count as i64)
+        .bind(credential.last_failed_at.map(|d| d.to_rfc3339()))
+        .bind(credential.daily_quota.map(|q| q as i64))
         .execute(&self.pool)
         .await?;
         
-        Ok(())
+        // The login may have changed; the next request logs in afresh
+        self.delete_login_session(credential.id).await
     }
     
-    /// Load all site credentials
+    /// Load all site credentials, with what each has downloaded today
     pub async fn load_all_credentials(&self) -> Result<Vec<SiteCredential>, DlmanError> {
-        let rows = sqlx::query("SELECT * FROM site_credentials ORDER BY domain ASC")
+        let rows = sqlx::query(&format!("{} ORDER BY c.domain ASC", CREDENTIALS_WITH_USAGE))
+            .bind(usage_day())
             .fetch_all(&self.pool)
             .await?;
         
         let mut credentials = Vec::new();
         for row in rows {
-            credentials.push(row_to_credential(row)?);
+            credentials.push(row_to_credential(row, &self.vault)?);
         }
         
         Ok(credentials)
@@ -662,24 +975,35 @@ impl DownloadDatabase {
     
     /// Load a site credential by ID
     pub async fn load_credential(&self, id: Uuid) -> Result<Option<SiteCredential>, DlmanError> {
-        let row = sqlx::query("SELECT * FROM site_credentials WHERE id = ?")
+        let row = sqlx::query(&format!("{} WHERE c.id = ?", CREDENTIALS_WITH_USAGE))
+            .bind(usage_day())
             .bind(id.to_string())
             .fetch_optional(&self.pool)
             .await?;
         
         match row {
-            Some(r) => Ok(Some(row_to_credential(r)?)),
+            Some(r) => Ok(Some(row_to_credential(r, &self.vault)?)),
             None => Ok(None),
         }
     }
     
-    /// Find credentials matching a URL
+    /// Find credentials matching a URL, in the order to try them: accounts
+    /// with quota left before those that used it up, those that worked last
+    /// time before those that were refused, then the smallest share of their
+    /// quota used and last the accounts without one, least used today first
     pub async fn find_credentials_for_url(&self, url: &str) -> Result<Vec<SiteCredential>, DlmanError> {
         let all = self.load_all_credentials().await?;
-        let matching: Vec<SiteCredential> = all
+        let mut matching: Vec<SiteCredential> = all
             .into_iter()
             .filter(|c| c.matches_url(url))
             .collect();
+        matching.sort_by(|a, b| {
+            let rank = |c: &SiteCredential| (c.quota_exhausted(), c.last_attempt_failed(), c.daily_quota.is_none());
+            rank(a)
+                .cmp(&rank(b))
+                .then_with(|| a.quota_used().unwrap_or(0.0).total_cmp(&b.quota_used().unwrap_or(0.0)))
+                .then(a.used_today.cmp(&b.used_today))
+        });
         Ok(matching)
     }
     
@@ -693,18 +1017,259 @@ impl DownloadDatabase {
         Ok(())
     }
     
+    /// Note that a server refused a credential or said its quota was used up
+    pub async fn record_credential_failure(&self, id: Uuid) -> Result<(), DlmanError> {
+        sqlx::query("UPDATE site_credentials SET failure_count = failure_count + 1, last_failed_at = ? WHERE id = ?")
+            .bind(chrono::Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+    
+    /// Add `bytes` to what a credential downloaded today
+    pub async fn add_credential_usage(&self, id: Uuid, bytes: u64) -> Result<(), DlmanError> {
+        sqlx::query(
+            r#"
+            INSERT INTO credential_usage (credential_id, day, bytes) VALUES (?, ?, ?)
+            ON CONFLICT(credential_id, day) DO UPDATE SET bytes = bytes + excluded.bytes
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(usage_day())
+        .bind(bytes as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+    
+    /// Connections that last paid off for downloads from `host`
+    pub async fn load_host_connections(&self, host: &str) -> Result<Option<u32>, DlmanError> {
+        let connections: Option<i64> = sqlx::query_scalar("SELECT connections FROM host_connections WHERE host = ?")
+            .bind(host)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(connections.map(|c| c.max(1) as u32))
+    }
+    
+    /// Remember how many connections paid off for downloads from `host`
+    pub async fn save_host_connections(&self, host: &str, connections: u32) -> Result<(), DlmanError> {
+        sqlx::query(
+            r#"
+            INSERT INTO host_connections (host, connections, updated_at) VALUES (?, ?, ?)
+            ON CONFLICT(host) DO UPDATE SET
+                connections = excluded.connections,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(host)
+        .bind(connections as i64)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+    
     /// Delete a site credential
     pub async fn delete_credential(&self, id: Uuid) -> Result<(), DlmanError> {
         sqlx::query("DELETE FROM site_credentials WHERE id = ?")
             .bind(id.to_string())
             .execute(&self.pool)
             .await?;
+        sqlx::query("DELETE FROM credential_usage WHERE credential_id = ?")
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
+        self.delete_login_session(id).await
+    }
+    
+    /// Whether saved passwords are encrypted and their key is loaded
+    pub fn vault_status(&self) -> VaultStatus {
+        self.vault.status()
+    }
+    
+    /// Load the master key for this session. Passwords saved in plaintext
+    /// before the vault was set up are encrypted now.
+    pub async fn unlock_vault(&self, key: &VaultKey) -> Result<(), DlmanError> {
+        let record = self.vault.record().ok_or_else(|| {
+            DlmanError::InvalidOperation("Saved logins aren't encrypted; set up a master key first".to_string())
+        })?;
+        let master = record.unlock(key).await?;
+        self.vault.unlock(record, master);
+        seal_plaintext_credentials(&self.pool, &self.vault).await
+    }
+    
+    /// Forget the master key until the vault is unlocked again
+    pub fn lock_vault(&self) {
+        self.vault.lock();
+    }
+    
+    /// Encrypt saved passwords, login sessions and request headers under a new master key: set up encryption the
+    /// first time, re-key it afterwards. Re-keying needs the vault unlocked.
+    pub async fn set_master_key(&self, key: &VaultKey) -> Result<(), DlmanError> {
+        if self.vault.status() == VaultStatus::Locked {
+            return Err(DlmanError::CredentialsLocked);
+        }
+        let (record, master) = VaultRecord::create(key).await?;
+        let next = CredentialVault::default();
+        next.unlock(record.clone(), master);
+        
+        // Every secret is re-encrypted, and the new key recorded, together
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query("SELECT id, password, ssh_key_passphrase FROM site_credentials")
+            .fetch_all(&mut *tx)
+            .await?;
+        for row in rows {
+            let passphrase = match row.get::<Option<String>, _>("ssh_key_passphrase") {
+                Some(stored) => Some(next.seal(&self.vault.open(&stored)?)?),
+                None => None,
+            };
+            sqlx::query("UPDATE site_credentials SET password = ?, ssh_key_passphrase = ? WHERE id = ?")
+                .bind(next.seal(&self.vault.open(row.get::<String, _>("password").as_str())?)?)
+                .bind(passphrase)
+                .bind(row.get::<String, _>("id"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        let rows = sqlx::query("SELECT credential_id, cookies FROM login_sessions")
+            .fetch_all(&mut *tx)
+            .await?;
+        for row in rows {
+            sqlx::query("UPDATE login_sessions SET cookies = ? WHERE credential_id = ?")
+                .bind(next.seal(&self.vault.open(row.get::<String, _>("cookies").as_str())?)?)
+                .bind(row.get::<String, _>("credential_id"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        for table in ["header_rules", "downloads"] {
+            let rows = sqlx::query(&format!("SELECT id, headers FROM {table} WHERE headers IS NOT NULL"))
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in rows {
+                let headers = open_headers(row.get::<String, _>("headers").as_str(), &self.vault)?;
+                sqlx::query(&format!("UPDATE {table} SET headers = ? WHERE id = ?"))
+                    .bind(seal_headers(&headers, &next)?)
+                    .bind(row.get::<String, _>("id"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO credential_vault (id, salt, m_cost, t_cost, p_cost, check_value, keyfile, created_at)
+            VALUES (1, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                salt = excluded.salt,
+                m_cost = excluded.m_cost,
+                t_cost = excluded.t_cost,
+                p_cost = excluded.p_cost,
+                check_value = excluded.check_value,
+                keyfile = excluded.keyfile,
+                created_at = excluded.created_at
+            "#,
+        )
+        .bind(base64::engine::general_purpose::STANDARD.encode(&record.salt))
+        .bind(record.m_cost as i64)
+        .bind(record.t_cost as i64)
+        .bind(record.p_cost as i64)
+        .bind(&record.check)
+        .bind(record.keyfile.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        
+        self.vault.replace(next);
+        info!("Saved logins encrypted under a new master key");
+        Ok(())
+    }
+    
+    /// Session cookies from the last form login with a credential
+    pub async fn load_login_session(&self, credential_id: Uuid) -> Result<Option<String>, DlmanError> {
+        let cookies: Option<String> = sqlx::query_scalar("SELECT cookies FROM login_sessions WHERE credential_id = ?")
+            .bind(credential_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        cookies.map(|c| self.vault.open(&c)).transpose()
+    }
+    
+    /// Keep the session cookies of a form login for later downloads
+    pub async fn save_login_session(&self, credential_id: Uuid, cookies: &str) -> Result<(), DlmanError> {
+        sqlx::query(
+            r#"
+            INSERT INTO login_sessions (credential_id, cookies, created_at) VALUES (?, ?, ?)
+            ON CONFLICT(credential_id) DO UPDATE SET
+                cookies = excluded.cookies,
+                created_at = excluded.created_at
+            "#,
+        )
+        .bind(credential_id.to_string())
+        .bind(self.vault.seal(cookies)?)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+    
+    /// Forget the session of a credential
+    pub async fn delete_login_session(&self, credential_id: Uuid) -> Result<(), DlmanError> {
+        sqlx::query("DELETE FROM login_sessions WHERE credential_id = ?")
+            .bind(credential_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+    
+    // ========================================================================
+    // Header Rules CRUD
+    // ========================================================================
+    
+    /// Save or update a header rule
+    pub async fn upsert_header_rule(&self, rule: &HeaderRule) -> Result<(), DlmanError> {
+        sqlx::query(
+            r#"
+            INSERT INTO header_rules (id, domain, headers, enabled, created_at, notes)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                domain = excluded.domain,
+                headers = excluded.headers,
+                enabled = excluded.enabled,
+                notes = excluded.notes
+            "#,
+        )
+        .bind(rule.id.to_string())
+        .bind(&rule.domain)
+        .bind(seal_headers(&rule.headers, &self.vault)?)
+        .bind(if rule.enabled { 1i64 } else { 0i64 })
+        .bind(rule.created_at.to_rfc3339())
+        .bind(rule.notes.as_ref())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Load all header rules
+    pub async fn load_all_header_rules(&self) -> Result<Vec<HeaderRule>, DlmanError> {
+        let rows = sqlx::query("SELECT * FROM header_rules ORDER BY domain ASC")
+            .fetch_all(&self.pool)
+            .await?;
+        
+        rows.into_iter().map(|row| row_to_header_rule(row, &self.vault)).collect()
+    }
+    
+    /// Delete a header rule
+    pub async fn delete_header_rule(&self, id: Uuid) -> Result<(), DlmanError> {
+        sqlx::query("DELETE FROM header_rules WHERE id = ?")
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
         Ok(())
     }
 }
 
 /// Convert a database row to a Download struct
-fn row_to_download(row: sqlx::sqlite::SqliteRow, segments: Vec<Segment>) -> Result<Download, DlmanError> {
+fn row_to_download(row: sqlx::sqlite::SqliteRow, segments: Vec<Segment>, vault: &CredentialVault) -> Result<Download, DlmanError> {
     use chrono::{DateTime, Utc};
     use std::path::PathBuf;
     
@@ -718,6 +1283,7 @@ fn row_to_download(row: sqlx::sqlite::SqliteRow, segments: Vec<Segment>) -> Resu
         "queued" => DownloadStatus::Queued,
         "cancelled" => DownloadStatus::Cancelled,
         "deleted" => DownloadStatus::Deleted,
+        "awaitingauth" => DownloadStatus::AwaitingAuth,
         _ => DownloadStatus::Pending,
     };
     
@@ -747,20 +1313,167 @@ fn row_to_download(row: sqlx::sqlite::SqliteRow, segments: Vec<Segment>) -> Resu
             .map(|dt| dt.with_timezone(&Utc)),
         retry_count: row.get::<i64, _>("retry_count") as u32,
         cookies: row.get("cookies"),
+        checksum: row.try_get::<Option<String>, _>("checksum").ok().flatten()
+            .and_then(|s| s.parse().ok()),
+        piece_hashes: row.try_get::<Option<String>, _>("piece_hashes").ok().flatten()
+            .and_then(|s| serde_json::from_str(&s).ok()),
+        etag: row.get("etag"),
+        last_modified: row.get("last_modified"),
+        supports_range: row.get::<i64, _>("supports_range") != 0,
+        mirrors: row.try_get::<Option<String>, _>("mirrors").ok().flatten()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+        direct_write: row.try_get::<i64, _>("direct_write").map(|v| v != 0).unwrap_or(false),
+        headers: row.try_get::<Option<String>, _>("headers").ok().flatten()
+            .and_then(|s| open_headers(&s, vault).ok())
+            .unwrap_or_default(),
+        stream_selection: row.try_get::<Option<String>, _>("stream_selection").ok().flatten()
+            .and_then(|s| serde_json::from_str(&s).ok()),
     })
 }
 
+/// How the master key is derived, if a vault is set up
+async fn load_vault_record(pool: &Pool) -> Result<Option<VaultRecord>, DlmanError> {
+    let Some(row) = sqlx::query("SELECT * FROM credential_vault WHERE id = 1")
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let salt = base64::engine::general_purpose::STANDARD
+        .decode(row.get::<String, _>("salt"))
+        .map_err(|e| DlmanError::Vault(e.to_string()))?;
+    Ok(Some(VaultRecord {
+        salt,
+        m_cost: row.get::<i64, _>("m_cost") as u32,
+        t_cost: row.get::<i64, _>("t_cost") as u32,
+        p_cost: row.get::<i64, _>("p_cost") as u32,
+        check: row.get("check_value"),
+        keyfile: row.get::<Option<String>, _>("keyfile").map(PathBuf::from),
+    }))
+}
+
+/// Encrypt the passwords, key passphrases, login sessions and request header
+/// values still stored in plaintext
+async fn seal_plaintext_credentials(pool: &Pool, vault: &CredentialVault) -> Result<(), DlmanError> {
+    let rows = sqlx::query("SELECT id, password, ssh_key_passphrase FROM site_credentials")
+        .fetch_all(pool)
+        .await?;
+    let mut sealed = 0;
+    for row in rows {
+        let password: String = row.get("password");
+        let passphrase: Option<String> = row.get("ssh_key_passphrase");
+        if is_sealed(&password) && passphrase.as_deref().is_none_or(is_sealed) {
+            continue;
+        }
+        let passphrase = match passphrase {
+            Some(p) if !is_sealed(&p) => Some(vault.seal(&p)?),
+            other => other,
+        };
+        let password = if is_sealed(&password) { password } else { vault.seal(&password)? };
+        sqlx::query("UPDATE site_credentials SET password = ?, ssh_key_passphrase = ? WHERE id = ?")
+            .bind(password)
+            .bind(passphrase)
+            .bind(row.get::<String, _>("id"))
+            .execute(pool)
+            .await?;
+        sealed += 1;
+    }
+    let rows = sqlx::query("SELECT credential_id, cookies FROM login_sessions")
+        .fetch_all(pool)
+        .await?;
+    for row in rows {
+        let cookies: String = row.get("cookies");
+        if is_sealed(&cookies) {
+            continue;
+        }
+        sqlx::query("UPDATE login_sessions SET cookies = ? WHERE credential_id = ?")
+            .bind(vault.seal(&cookies)?)
+            .bind(row.get::<String, _>("credential_id"))
+            .execute(pool)
+            .await?;
+    }
+    for table in ["header_rules", "downloads"] {
+        let rows = sqlx::query(&format!("SELECT id, headers FROM {table} WHERE headers IS NOT NULL"))
+            .fetch_all(pool)
+            .await?;
+        for row in rows {
+            let headers = open_headers(row.get::<String, _>("headers").as_str(), vault)?;
+            if headers.iter().all(|header| is_sealed(&header.value)) {
+                continue;
+            }
+            sqlx::query(&format!("UPDATE {table} SET headers = ? WHERE id = ?"))
+                .bind(seal_headers(&headers, vault)?)
+                .bind(row.get::<String, _>("id"))
+                .execute(pool)
+                .await?;
+        }
+    }
+    if sealed > 0 {
+        info!("Encrypted {} saved login(s) stored in plaintext", sealed);
+    }
+    Ok(())
+}
+
+/// Request headers as stored, with each value sealed like a password: they
+/// carry API keys and `Authorization` as often as not. Values still sealed,
+/// because the vault was locked when they were loaded, are kept as they are.
+fn seal_headers(headers: &[RequestHeader], vault: &CredentialVault) -> Result<String, DlmanError> {
+    let sealed = headers
+        .iter()
+        .map(|header| {
+            let value = if is_sealed(&header.value) { header.value.clone() } else { vault.seal(&header.value)? };
+            Ok(RequestHeader::new(header.name.clone(), value))
+        })
+        .collect::<Result<Vec<_>, DlmanError>>()?;
+    serde_json::to_string(&sealed).map_err(|e| DlmanError::Serialization(e.to_string()))
+}
+
+/// Headers stored by [`seal_headers`]. While the vault is locked their
+/// values stay sealed, and aren't sent (see [`super::RequestHeaders`]).
+fn open_headers(stored: &str, vault: &CredentialVault) -> Result<Vec<RequestHeader>, DlmanError> {
+    let headers: Vec<RequestHeader> =
+        serde_json::from_str(stored).map_err(|e| DlmanError::Serialization(e.to_string()))?;
+    headers
+        .into_iter()
+        .map(|header| match vault.open(&header.value) {
+            Ok(value) => Ok(RequestHeader { value, ..header }),
+            Err(DlmanError::CredentialsLocked) => Ok(header),
+            Err(e) => Err(e),
+        })
+        .collect()
+}
+
+/// Credentials with the bytes each downloaded on the day bound first
+const CREDENTIALS_WITH_USAGE: &str = "SELECT c.*, COALESCE(u.bytes, 0) AS used_today FROM site_credentials c \
+     LEFT JOIN credential_usage u ON u.credential_id = c.id AND u.day = ?";
+
+/// The day credential usage is counted for (UTC)
+fn usage_day() -> String {
+    chrono::Utc::now().date_naive().to_string()
+}
+
 /// Convert a database row to a SiteCredential struct
-fn row_to_credential(row: sqlx::sqlite::SqliteRow) -> Result<SiteCredential, DlmanError> {
+fn row_to_credential(row: sqlx::sqlite::SqliteRow, vault: &CredentialVault) -> Result<SiteCredential, DlmanError> {
     use chrono::{DateTime, Utc};
     
+    let ssh_key_passphrase = match row.try_get::<Option<String>, _>("ssh_key_passphrase").ok().flatten() {
+        Some(stored) => Some(vault.open(&stored)?),
+        None => None,
+    };
+    
     Ok(SiteCredential {
         id: Uuid::parse_str(row.get::<String, _>("id").as_str())
             .map_err(|e| DlmanError::Unknown(e.to_string()))?,
         domain: row.get("domain"),
         protocol: row.get("protocol"),
         username: row.get("username"),
-        password: row.get("password"),
+        password: vault.open(row.get::<String, _>("password").as_str())?,
+        auth_scheme: row.try_get::<String, _>("auth_scheme").ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+        form_login: row.try_get::<Option<String>, _>("form_login").ok().flatten()
+            .and_then(|s| serde_json::from_str(&s).ok()),
         enabled: row.get::<i64, _>("enabled") != 0,
         created_at: DateTime::parse_from_rfc3339(row.get::<String, _>("created_at").as_str())
             .map_err(|e| DlmanError::Unknown(e.to_string()))?
@@ -768,6 +1481,35 @@ fn row_to_credential(row: sqlx::sqlite::SqliteRow) -> Result<SiteCredential, Dlm
         last_used_at: row.get::<Option<String>, _>("last_used_at")
             .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
             .map(|dt| dt.with_timezone(&Utc)),
+        failure_count: row.try_get::<i64, _>("failure_count").unwrap_or(0).max(0) as u32,
+        last_failed_at: row.try_get::<Option<String>, _>("last_failed_at").ok().flatten()
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|dt| dt.with_timezone(&Utc)),
+        daily_quota: row.try_get::<Option<i64>, _>("daily_quota").ok().flatten()
+            .map(|q| q.max(0) as u64),
+        used_today: row.try_get::<i64, _>("used_today").unwrap_or(0).max(0) as u64,
+        notes: row.get("notes"),
+        ssh_key: row.try_get::<Option<String>, _>("ssh_key_path").ok().flatten()
+            .map(|path| SshKey {
+                path: PathBuf::from(path),
+                passphrase: ssh_key_passphrase,
+            }),
+    })
+}
+
+/// Convert a database row to a HeaderRule struct
+fn row_to_header_rule(row: sqlx::sqlite::SqliteRow, vault: &CredentialVault) -> Result<HeaderRule, DlmanError> {
+    use chrono::{DateTime, Utc};
+    
+    Ok(HeaderRule {
+        id: Uuid::parse_str(row.get::<String, _>("id").as_str())
+            .map_err(|e| DlmanError::Unknown(e.to_string()))?,
+        domain: row.get("domain"),
+        headers: open_headers(row.get::<String, _>("headers").as_str(), vault)?,
+        enabled: row.get::<i64, _>("enabled") != 0,
+        created_at: DateTime::parse_from_rfc3339(row.get::<String, _>("created_at").as_str())
+            .map_err(|e| DlmanError::Unknown(e.to_string()))?
+            .with_timezone(&Utc),
         notes: row.get("notes"),
     })
 }
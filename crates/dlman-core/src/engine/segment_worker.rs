@@ -1,13 +1,23 @@
 //! Segment worker - downloads a single segment to a temporary file
 //!
-//! Each segment worker is independent and writes to its own temp file.
-//! On completion, all segment files are merged into the final file.
+//! Each segment worker is independent and writes to its own temp file. HTTP(S)
+//! ranges go through reqwest, FTP sources through [`FtpClient`] and SFTP
+//! sources through a channel of an [`SftpConnection`].
+//! On completion, all segment files are merged into the final file. A
+//! direct-write download has no segment files: every worker writes into the
+//! download's `.part` file at its own offsets.
 
+use crate::engine::auth::{send_with_auth, HttpAuth};
+use crate::engine::ftp::{is_ftp_url, FtpClient};
+use crate::engine::host_limits::HostSlot;
 use crate::engine::rate_limiter::RateLimiter;
 use crate::engine::persistence::DownloadDatabase;
+use crate::engine::sftp::{is_sftp_url, SftpConnection};
 use crate::error::DlmanError;
-use dlman_types::{CoreEvent, Segment};
+use dlman_types::{AuthScheme, CoreEvent, Segment, SshKey};
+use futures::stream::BoxStream;
 use futures::StreamExt;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -15,9 +25,13 @@ use std::sync::Arc;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::broadcast;
+use tokio_util::io::ReaderStream;
 use tracing::{debug, info};
 use uuid::Uuid;
 
+/// Body of a segment's response, whatever the protocol
+type ChunkStream = BoxStream<'static, Result<bytes::Bytes, DlmanError>>;
+
 /// Result of a segment download
 pub struct SegmentResult {
     /// Path to the downloaded segment file
@@ -26,6 +40,87 @@ pub struct SegmentResult {
     pub discovered_size: Option<u64>,
 }
 
+/// Live bounds of a running segment, shared between its worker and the
+/// owning [`DownloadTask`](crate::engine::DownloadTask).
+///
+/// The task uses this to split a segment while it is still downloading (work
+/// stealing): it lowers `end`, and the worker stops as soon as it reaches the
+/// new boundary instead of reading to the end of its original range.
+pub struct SegmentHandle {
+    start: u64,
+    end: AtomicU64,
+    downloaded: AtomicU64,
+    /// Set once the worker runs, i.e. it is no longer waiting for a host slot
+    started: AtomicBool,
+}
+
+impl SegmentHandle {
+    fn new(segment: &Segment) -> Self {
+        Self {
+            start: segment.start,
+            end: AtomicU64::new(segment.end),
+            downloaded: AtomicU64::new(segment.downloaded),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the worker has started (see [`HostLimiter`](crate::engine::HostLimiter))
+    pub fn started(&self) -> bool {
+        self.started.load(Ordering::Acquire)
+    }
+
+    /// Current (inclusive) end byte of the segment
+    pub fn end(&self) -> u64 {
+        self.end.load(Ordering::Acquire)
+    }
+
+    /// Bytes written to this segment's temp file so far
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Acquire)
+    }
+
+    /// Bytes still to be fetched (0 for unknown-size segments)
+    pub fn remaining(&self) -> u64 {
+        let end = self.end();
+        if end == u64::MAX {
+            return 0;
+        }
+        (end + 1).saturating_sub(self.start + self.downloaded())
+    }
+
+    /// Give away the second half of the remaining range.
+    ///
+    /// Returns the `(start, end)` of the split-off range, or `None` if less
+    /// than `2 * min_size` bytes are left. The split point always leaves at
+    /// least `min_size` bytes ahead of the worker, so a chunk that is already
+    /// in flight can never cross into the stolen range. It is rounded up to a
+    /// multiple of `align` (the piece length of a download with piece hashes).
+    pub fn try_split(&self, min_size: u64, align: u64) -> Option<(u64, u64)> {
+        loop {
+            let end = self.end();
+            if end == u64::MAX {
+                return None;
+            }
+            let position = self.start + self.downloaded();
+            let remaining = (end + 1).saturating_sub(position);
+            if remaining < min_size.saturating_mul(2) {
+                return None;
+            }
+            let split_at = (position + remaining / 2).div_ceil(align) * align;
+            if end + 1 < split_at.saturating_add(min_size) {
+                return None;
+            }
+            if self
+                .end
+                .compare_exchange(end, split_at - 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Some((split_at, end));
+            }
+        }
+    }
+}
+
 /// A segment worker that downloads a byte range to a temporary file
 pub struct SegmentWorker {
     download_id: Uuid,
@@ -39,10 +134,30 @@ pub struct SegmentWorker {
     paused: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
     downloaded_bytes: Arc<AtomicU64>,
+    /// Live bounds shared with the download task (see [`SegmentHandle`])
+    handle: Arc<SegmentHandle>,
     /// Optional credentials for authenticated downloads
     credentials: Option<(String, String)>, // (username, password)
     /// Optional browser cookies for session-based authentication
     cookies: Option<String>,
+    /// `ETag` recorded when the download was probed
+    etag: Option<String>,
+    /// `Last-Modified` recorded when the download was probed
+    last_modified: Option<String>,
+    /// Private key for SFTP logins
+    ssh_key: Option<SshKey>,
+    /// SSH connection shared with the other segments of an SFTP download
+    sftp: Option<Arc<SftpConnection>>,
+    /// Connection slot on the source's host, held until the worker finishes
+    host_slot: Option<HostSlot>,
+    /// `temp_file_path` is the download's shared `.part` file rather than a
+    /// file of this segment's own
+    in_place: bool,
+    /// Extra headers for HTTP requests, from header rules and the download
+    headers: HeaderMap,
+    /// Login for HTTP requests; Digest nonces are shared with the other
+    /// segments
+    auth: Option<HttpAuth>,
 }
 
 impl SegmentWorker {
@@ -64,6 +179,7 @@ impl SegmentWorker {
             "{}_segment_{}.part",
             download_id, segment.index
         ));
+        let handle = Arc::new(SegmentHandle::new(&segment));
         
         Self {
             download_id,
@@ -77,8 +193,17 @@ impl SegmentWorker {
             paused,
             cancelled,
             downloaded_bytes,
+            handle,
             credentials: None,
             cookies: None,
+            etag: None,
+            last_modified: None,
+            ssh_key: None,
+            sftp: None,
+            host_slot: None,
+            in_place: false,
+            headers: HeaderMap::new(),
+            auth: None,
         }
     }
     
@@ -102,6 +227,11 @@ impl SegmentWorker {
             "{}_segment_{}.part",
             download_id, segment.index
         ));
+        let handle = Arc::new(SegmentHandle::new(&segment));
+        // HTTP Basic unless `with_auth` says otherwise
+        let auth = credentials
+            .clone()
+            .map(|(username, password)| HttpAuth::new(AuthScheme::Basic, username, password, &url));
         
         Self {
             download_id,
@@ -115,9 +245,70 @@ impl SegmentWorker {
             paused,
             cancelled,
             downloaded_bytes,
+            handle,
             credentials,
             cookies,
+            etag: None,
+            last_modified: None,
+            ssh_key: None,
+            sftp: None,
+            host_slot: None,
+            in_place: false,
+            headers: HeaderMap::new(),
+            auth,
+        }
+    }
+    
+    /// Send `headers` with HTTP requests, replacing any the worker would set
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+    
+    /// Log in to HTTP sources with `auth` (Basic, Digest or Bearer)
+    /// instead of Basic auth from the credentials
+    pub fn with_auth(mut self, auth: Option<HttpAuth>) -> Self {
+        self.auth = auth;
+        self
+    }
+    
+    /// Validators from the probe. Ranged requests carry `If-Range`, so a file
+    /// that changed on the server fails with [`DlmanError::ContentChanged`]
+    /// instead of having new bytes appended to old ones.
+    pub fn with_validators(mut self, etag: Option<String>, last_modified: Option<String>) -> Self {
+        self.etag = etag;
+        self.last_modified = last_modified;
+        self
+    }
+    
+    /// SFTP login details: the key to log in with, and the download's open
+    /// connection, if there is one. Without a connection the worker opens its own.
+    pub fn with_sftp(mut self, ssh_key: Option<SshKey>, connection: Option<Arc<SftpConnection>>) -> Self {
+        self.ssh_key = ssh_key;
+        self.sftp = connection;
+        self
+    }
+    
+    /// Connection slot this worker was given by the host limiter. Its
+    /// bandwidth limit, if any, applies on top of the download's own.
+    pub fn with_host_slot(mut self, slot: Option<HostSlot>) -> Self {
+        self.host_slot = slot;
+        self
+    }
+    
+    /// Write into the download's preallocated `.part` file at the segment's
+    /// own offsets instead of into a temp file. `None` keeps the temp file.
+    pub fn with_part_file(mut self, path: Option<PathBuf>) -> Self {
+        if let Some(path) = path {
+            self.temp_file_path = path;
+            self.in_place = true;
         }
+        self
+    }
+    
+    /// Shared handle to this segment's live bounds
+    pub fn handle(&self) -> Arc<SegmentHandle> {
+        self.handle.clone()
     }
     
     /// Run the segment download
@@ -127,8 +318,7 @@ impl SegmentWorker {
             self.segment.index, self.download_id, self.segment.start, self.segment.end
         );
         
-        // Track if we discover the total size during download
-        let mut discovered_size: Option<u64> = None;
+        self.handle.started.store(true, Ordering::Release);
         
         // Check if segment is already complete
         if self.segment.complete {
@@ -147,13 +337,28 @@ impl SegmentWorker {
             .open(&self.temp_file_path)
             .await?;
         
+        // The task may have split this segment before the worker started
+        self.segment.end = self.handle.end();
+        
         // Check existing file size for resume
         // For unknown size segments, we always resume from whatever we have
         let existing_size = file.metadata().await?.len();
-        if existing_size > 0 {
+        if self.in_place {
+            // The shared file is preallocated; only the database knows how
+            // much of this segment is in it
+            self.handle.downloaded.store(self.segment.downloaded, Ordering::Release);
+            file.seek(std::io::SeekFrom::Start(self.segment.start + self.segment.downloaded)).await?;
+            if self.segment.downloaded > 0 {
+                info!(
+                    "Resuming segment {} from byte {}",
+                    self.segment.index, self.segment.downloaded
+                );
+            }
+        } else if existing_size > 0 {
             // For unknown size, always resume; for known size, only if within bounds
             if self.segment.is_unknown_size() || existing_size <= self.segment.size() {
                 self.segment.downloaded = existing_size;
+                self.handle.downloaded.store(existing_size, Ordering::Release);
                 file.seek(std::io::SeekFrom::Start(existing_size)).await?;
                 info!(
                     "Resuming segment {} from byte {}",
@@ -185,6 +390,114 @@ impl SegmentWorker {
             });
         }
         
+        let (mut stream, discovered_size) = if is_ftp_url(&self.url) {
+            self.open_ftp(start_byte).await?
+        } else if is_sftp_url(&self.url) {
+            self.open_sftp(start_byte).await?
+        } else {
+            self.open_http(start_byte, end_byte).await?
+        };
+        
+        // Stream and write chunks
+        let mut last_db_update = tokio::time::Instant::now();
+        let mut last_event_emit = tokio::time::Instant::now();
+        
+        while let Some(chunk_result) = stream.next().await {
+            // Check cancellation first
+            if self.cancelled.load(Ordering::Acquire) {
+                info!("Segment {} cancelled", self.segment.index);
+                self.checkpoint(&mut file).await?;
+                return Err(DlmanError::Cancelled);
+            }
+            
+            // Check pause - save progress and return Paused error
+            // This allows the HTTP connection to be closed and resumed later
+            if self.paused.load(Ordering::Acquire) {
+                info!("Segment {} paused", self.segment.index);
+                self.checkpoint(&mut file).await?;
+                return Err(DlmanError::Paused);
+            }
+            
+            let chunk = chunk_result?;
+            
+            // Clamp to the live end: the task may have handed the tail of this
+            // segment to another worker since the request was sent.
+            let chunk = if unknown_size {
+                &chunk[..]
+            } else {
+                let position = self.segment.start + self.segment.downloaded;
+                let allowed = (self.handle.end() + 1).saturating_sub(position);
+                &chunk[..chunk.len().min(allowed as usize)]
+            };
+            let chunk_len = chunk.len() as u64;
+            
+            // Apply rate limiting
+            self.rate_limiter.acquire(chunk_len).await;
+            if let Some(host_limiter) = self.host_slot.as_ref().and_then(HostSlot::rate_limiter) {
+                host_limiter.acquire(chunk_len).await;
+            }
+            
+            // Write to temp file
+            file.write_all(chunk).await?;
+            
+            // Update progress atomically
+            self.segment.downloaded += chunk_len;
+            self.handle.downloaded.store(self.segment.downloaded, Ordering::Release);
+            self.downloaded_bytes.fetch_add(chunk_len, Ordering::AcqRel);
+            
+            // Emit segment progress event every 300ms (throttled, not per-chunk)
+            if last_event_emit.elapsed().as_millis() >= 300 {
+                let _ = self.event_tx.send(CoreEvent::SegmentProgress {
+                    download_id: self.download_id,
+                    segment_index: self.segment.index,
+                    downloaded: self.segment.downloaded,
+                });
+                last_event_emit = tokio::time::Instant::now();
+            }
+            
+            // Periodically save to database (every 2 seconds)
+            if last_db_update.elapsed().as_secs() >= 2 {
+                self.checkpoint(&mut file).await?;
+                last_db_update = tokio::time::Instant::now();
+            }
+            
+            // Stop once the (possibly shortened) range is done; the rest of the
+            // response body belongs to whichever worker stole it.
+            if !unknown_size && self.segment.start + self.segment.downloaded > self.handle.end() {
+                debug!("Segment {} reached its split boundary", self.segment.index);
+                break;
+            }
+        }
+        
+        // Flush and sync to disk
+        file.flush().await?;
+        file.sync_all().await?;
+        
+        if !unknown_size {
+            self.segment.end = self.handle.end();
+        }
+        
+        // Mark segment as complete
+        self.segment.complete = true;
+        self.save_progress().await?;
+        
+        info!(
+            "Segment {} complete ({} bytes)",
+            self.segment.index, self.segment.downloaded
+        );
+        
+        Ok(SegmentResult {
+            path: self.temp_file_path,
+            discovered_size,
+        })
+    }
+    
+    /// Send the ranged HTTP request for the rest of the segment. Returns the
+    /// body and the total size, if it was unknown and the response revealed it.
+    async fn open_http(&mut self, start_byte: u64, end_byte: u64) -> Result<(ChunkStream, Option<u64>), DlmanError> {
+        let unknown_size = end_byte == u64::MAX;
+        let mut discovered_size = None;
+        
         // Build HTTP request
         // For unknown size (end = MAX), use open-ended range "bytes=N-"
         // For known size, use "bytes=N-M"
@@ -205,9 +518,15 @@ impl SegmentWorker {
             self.client.get(&self.url).header("Range", range_header)
         };
         
-        // Apply credentials if available (HTTP Basic Auth)
-        if let Some((ref username, ref password)) = self.credentials {
-            request = request.basic_auth(username, Some(password));
+        // Only serve the range if the file is still the one we started with;
+        // otherwise the server answers 200 with the whole new file
+        let if_range = if start_byte > 0 {
+            if_range_value(self.etag.as_deref(), self.last_modified.as_deref())
+        } else {
+            None
+        };
+        if let Some(ref validator) = if_range {
+            request = request.header(reqwest::header::IF_RANGE, validator);
         }
         
         // Apply browser cookies if available (session-based auth)
@@ -215,7 +534,13 @@ impl SegmentWorker {
             request = request.header(reqwest::header::COOKIE, cookies);
         }
         
-        let response = request.send().await?;
+        // Header rules and the download's own headers go last, so they win
+        if !self.headers.is_empty() {
+            request = request.headers(self.headers.clone());
+        }
+        
+        // Digest logins may take a challenge round trip
+        let response = send_with_auth(self.auth.as_ref(), request).await?;
         
         // Check response status
         let status = response.status();
@@ -225,7 +550,7 @@ impl SegmentWorker {
                 .ok()
                 .and_then(|u| u.host_str().map(|h| h.to_string()))
                 .unwrap_or_else(|| "unknown".to_string());
-            
+        
             return Err(DlmanError::AuthenticationRequired {
                 domain,
                 url: self.url.clone(),
@@ -238,6 +563,26 @@ impl SegmentWorker {
                 message: format!("Failed to download segment {}", self.segment.index),
             });
         }
+        if if_range.is_some() && status == reqwest::StatusCode::OK {
+            info!("Segment {}: If-Range failed, the file changed on the server", self.segment.index);
+            return Err(DlmanError::ContentChanged { url: self.url.clone() });
+        }
+        // Servers that ignore If-Range still report the current ETag
+        if self.segment.downloaded > 0 {
+            let current = response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|v| v.to_str().ok());
+            if let (Some(expected), Some(current)) = (self.etag.as_deref(), current) {
+                if !etags_match(expected, current) {
+                    info!(
+                        "Segment {}: ETag changed from {} to {}",
+                        self.segment.index, expected, current
+                    );
+                    return Err(DlmanError::ContentChanged { url: self.url.clone() });
+                }
+            }
+        }
         
         // If we have unknown size, try to detect it from response headers
         if unknown_size {
@@ -255,7 +600,7 @@ impl SegmentWorker {
                     }
                 }
             }
-            
+        
             // Try Content-Length for 200 OK responses (full content)
             if self.segment.end == u64::MAX {
                 if let Some(content_length) = response.headers().get(reqwest::header::CONTENT_LENGTH) {
@@ -272,74 +617,80 @@ impl SegmentWorker {
             }
         }
         
-        // Stream and write chunks
-        let mut stream = response.bytes_stream();
-        let mut last_db_update = tokio::time::Instant::now();
-        let mut last_event_emit = tokio::time::Instant::now();
-        
-        while let Some(chunk_result) = stream.next().await {
-            // Check cancellation first
-            if self.cancelled.load(Ordering::Acquire) {
-                info!("Segment {} cancelled", self.segment.index);
-                self.save_progress().await?;
-                return Err(DlmanError::Cancelled);
-            }
-            
-            // Check pause - save progress and return Paused error
-            // This allows the HTTP connection to be closed and resumed later
-            if self.paused.load(Ordering::Acquire) {
-                info!("Segment {} paused", self.segment.index);
-                self.save_progress().await?;
-                return Err(DlmanError::Paused);
-            }
-            
-            let chunk = chunk_result?;
-            let chunk_len = chunk.len() as u64;
-            
-            // Apply rate limiting
-            self.rate_limiter.acquire(chunk_len).await;
-            
-            // Write to temp file
-            file.write_all(&chunk).await?;
-            
-            // Update progress atomically
-            self.segment.downloaded += chunk_len;
-            self.downloaded_bytes.fetch_add(chunk_len, Ordering::AcqRel);
-            
-            // Emit segment progress event every 300ms (throttled, not per-chunk)
-            if last_event_emit.elapsed().as_millis() >= 300 {
-                let _ = self.event_tx.send(CoreEvent::SegmentProgress {
-                    download_id: self.download_id,
-                    segment_index: self.segment.index,
-                    downloaded: self.segment.downloaded,
-                });
-                last_event_emit = tokio::time::Instant::now();
-            }
-            
-            // Periodically save to database (every 2 seconds)
-            if last_db_update.elapsed().as_secs() >= 2 {
-                self.save_progress().await?;
-                last_db_update = tokio::time::Instant::now();
+        Ok((Box::pin(response.bytes_stream().map(|chunk| chunk.map_err(DlmanError::from))), discovered_size))
+    }
+    
+    /// Log in to the FTP server and start the transfer at `start_byte`.
+    ///
+    /// FTP has no `If-Range`, so before appending to earlier data the file's
+    /// modification time is compared with the one seen by the probe.
+    async fn open_ftp(&mut self, start_byte: u64) -> Result<(ChunkStream, Option<u64>), DlmanError> {
+        let mut client = FtpClient::connect(&self.url, self.credentials.as_ref()).await?;
+        if start_byte > 0 {
+            if let Some(ref known) = self.last_modified {
+                if client.modified().await?.is_some_and(|current| current != *known) {
+                    info!("Segment {}: modification time changed on the FTP server", self.segment.index);
+                    return Err(DlmanError::ContentChanged { url: self.url.clone() });
+                }
             }
         }
         
-        // Flush and sync to disk
-        file.flush().await?;
-        file.sync_all().await?;
+        let mut discovered_size = None;
+        if self.segment.is_unknown_size() {
+            if let Some(size) = client.size().await? {
+                info!("Got total size from SIZE: {} bytes", size);
+                self.segment.end = size.saturating_sub(1);
+                discovered_size = Some(size);
+            }
+        }
         
-        // Mark segment as complete
-        self.segment.complete = true;
-        self.save_progress().await?;
+        let reader = client.retrieve(start_byte).await?;
+        let stream = ReaderStream::with_capacity(reader, 64 * 1024).map(|chunk| chunk.map_err(DlmanError::from));
+        Ok((Box::pin(stream), discovered_size))
+    }
+    
+    /// Open the file on a new SFTP channel at `start_byte`, checking the
+    /// modification time before a resume as [`Self::open_ftp`] does
+    async fn open_sftp(&mut self, start_byte: u64) -> Result<(ChunkStream, Option<u64>), DlmanError> {
+        let connection = match self.sftp {
+            Some(ref connection) => connection.clone(),
+            None => Arc::new(
+                SftpConnection::connect(&self.url, self.credentials.as_ref(), self.ssh_key.as_ref()).await?,
+            ),
+        };
         
-        info!(
-            "Segment {} complete ({} bytes)",
-            self.segment.index, self.segment.downloaded
-        );
+        let mut discovered_size = None;
+        if start_byte > 0 || self.segment.is_unknown_size() {
+            let info = connection.probe().await?;
+            if start_byte > 0 {
+                if let (Some(known), Some(current)) = (&self.last_modified, &info.modified) {
+                    if known != current {
+                        info!("Segment {}: modification time changed on the SFTP server", self.segment.index);
+                        return Err(DlmanError::ContentChanged { url: self.url.clone() });
+                    }
+                }
+            }
+            if let Some(size) = info.size.filter(|_| self.segment.is_unknown_size()) {
+                info!("Got total size from SFTP stat: {} bytes", size);
+                self.segment.end = size.saturating_sub(1);
+                discovered_size = Some(size);
+            }
+        }
         
-        Ok(SegmentResult {
-            path: self.temp_file_path,
-            discovered_size,
-        })
+        let reader = connection.open_at(start_byte).await?;
+        let stream = ReaderStream::with_capacity(reader, 64 * 1024).map(|chunk| chunk.map_err(DlmanError::from));
+        Ok((Box::pin(stream), discovered_size))
+    }
+    
+    /// Save progress to the database. A shared `.part` file is synced first:
+    /// its length doesn't show how much was written, so after a crash the
+    /// database must not claim bytes that never reached the disk.
+    async fn checkpoint(&self, file: &mut tokio::fs::File) -> Result<(), DlmanError> {
+        if self.in_place {
+            file.flush().await?;
+            file.sync_data().await?;
+        }
+        self.save_progress().await
     }
     
     /// Save progress to database
@@ -356,3 +707,95 @@ impl SegmentWorker {
         Ok(())
     }
 }
+
+/// Value for `If-Range`: a strong ETag, else the Last-Modified date.
+///
+/// Weak ETags (`W/"..."`) are not allowed in `If-Range` (RFC 9110 §13.1.5).
+fn if_range_value(etag: Option<&str>, last_modified: Option<&str>) -> Option<String> {
+    etag.filter(|e| !e.starts_with("W/"))
+        .or(last_modified)
+        .map(str::to_string)
+}
+
+/// Weak comparison of two ETags (RFC 9110 §8.8.3.2); some servers switch
+/// between strong and weak forms of the same tag when compressing.
+pub(crate) fn etags_match(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_hands_over_second_half() {
+        let handle = SegmentHandle::new(&Segment::new(0, 0, 9_999));
+        handle.downloaded.store(2_000, Ordering::Release);
+
+        assert_eq!(handle.try_split(1_000, 1), Some((6_000, 9_999)));
+        assert_eq!(handle.end(), 5_999);
+        assert_eq!(handle.remaining(), 4_000);
+    }
+
+    #[test]
+    fn split_lands_on_piece_boundary() {
+        let handle = SegmentHandle::new(&Segment::new(0, 0, 9_999));
+        handle.downloaded.store(2_000, Ordering::Release);
+        assert_eq!(handle.try_split(1_000, 1_024), Some((6_144, 9_999)));
+
+        // Rounding up must still leave the new segment `min_size` bytes
+        let handle = SegmentHandle::new(&Segment::new(0, 0, 9_999));
+        assert_eq!(handle.try_split(1_000, 9_216), None);
+        assert_eq!(handle.end(), 9_999);
+    }
+
+    #[test]
+    fn split_refuses_small_or_unknown_ranges() {
+        let handle = SegmentHandle::new(&Segment::new(0, 0, 2_999));
+        handle.downloaded.store(1_500, Ordering::Release);
+        assert_eq!(handle.try_split(1_000, 1), None);
+        assert_eq!(handle.end(), 2_999);
+
+        let unknown = SegmentHandle::new(&Segment::new(0, 0, u64::MAX));
+        assert_eq!(unknown.try_split(1, 1), None);
+    }
+
+    #[test]
+    fn if_range_prefers_strong_etag() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(if_range_value(Some("\"v1\""), Some(date)).as_deref(), Some("\"v1\""));
+        assert_eq!(if_range_value(Some("W/\"v1\""), Some(date)).as_deref(), Some(date));
+        assert_eq!(if_range_value(Some("W/\"v1\""), None), None);
+
+        assert!(etags_match("\"v1\"", "W/\"v1\""));
+        assert!(!etags_match("\"v1\"", "\"v2\""));
+    }
+
+    #[tokio::test]
+    async fn sftp_segment_resumes_over_shared_connection() {
+        let body: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+        let (url, known_hosts, opened) = crate::engine::sftp::serve_sftp(body.clone(), ("alice", "hunter2")).await;
+        let credentials = ("alice".to_string(), "hunter2".to_string());
+        let connection = SftpConnection::connect_with_known_hosts(&url, Some(&credentials), None, known_hosts.path())
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = DownloadDatabase::new(dir.path().join("dlman.db")).await.unwrap();
+        let (event_tx, _event_rx) = broadcast::channel(16);
+        let id = Uuid::new_v4();
+        std::fs::write(dir.path().join(format!("{id}_segment_0.part")), &body[..120_000]).unwrap();
+
+        let worker = SegmentWorker::new(
+            id, Segment::new(0, 0, body.len() as u64 - 1), url, dir.path().to_path_buf(), Client::new(),
+            RateLimiter::unlimited(), db, event_tx, Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)), Arc::new(AtomicU64::new(0)),
+        )
+        .with_validators(None, Some("Tue, 02 Jan 2024 03:04:05 GMT".to_string()))
+        .with_sftp(None, Some(Arc::new(connection)));
+        let result = worker.run().await.unwrap();
+
+        assert_eq!(std::fs::read(result.path).unwrap(), body);
+        assert_eq!(opened.load(Ordering::Acquire), 1);
+    }
+}
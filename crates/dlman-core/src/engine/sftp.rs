@@ -0,0 +1,501 @@
+//! SFTP client for `sftp://` sources
+//!
+//! A download logs in once, when it is probed, and every segment worker opens
+//! its own channel and SFTP session on that connection, reading from its
+//! offset. A segmented SFTP download is therefore several parallel reads over
+//! a single SSH login.
+//!
+//! Server keys are checked against `~/.ssh/known_hosts`. Unknown and changed
+//! keys are refused rather than trusted on first use; add the host with
+//! `ssh-keyscan` (or by connecting once with `ssh`) before downloading.
+
+use crate::error::DlmanError;
+use dlman_types::SshKey;
+use parking_lot::Mutex;
+use russh::client;
+use russh::keys::{self, PrivateKey, PrivateKeyWithHashAlg, PublicKey, PublicKeyOrCertificate};
+use russh_sftp::client::fs::File;
+use russh_sftp::client::SftpSession;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncSeekExt, ReadBuf};
+use tracing::{debug, info};
+
+/// How long to wait for the connection, the login or a channel before giving up
+const SFTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keys tried, in order, when neither a key nor a password was given
+const DEFAULT_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Whether `url` is served by the SFTP client
+pub fn is_sftp_url(url: &str) -> bool {
+    url::Url::parse(url).is_ok_and(|u| u.scheme() == "sftp")
+}
+
+/// What a probe learned about a file on an SFTP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpFileInfo {
+    pub size: Option<u64>,
+    /// Modification time as an HTTP date, used like `Last-Modified`
+    pub modified: Option<String>,
+}
+
+/// Checks the server key against a known_hosts file
+struct KnownHosts {
+    host: String,
+    port: u16,
+    path: PathBuf,
+    /// Why the key was refused, for the error returned by `connect`
+    rejection: Arc<Mutex<Option<String>>>,
+}
+
+impl client::Handler for KnownHosts {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, server_key: &PublicKeyOrCertificate) -> Result<bool, Self::Error> {
+        let key = match server_key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => key.clone(),
+            PublicKeyOrCertificate::Certificate(cert) => PublicKey::from(cert.public_key().clone()),
+        };
+        let rejection = match keys::check_known_hosts_path(&self.host, self.port, &key, &self.path) {
+            Ok(true) => return Ok(true),
+            Ok(false) => format!(
+                "{} key {} is not in {}",
+                key.algorithm(),
+                key.fingerprint(Default::default()),
+                self.path.display()
+            ),
+            Err(keys::Error::KeyChanged { line }) => format!(
+                "key differs from the one on line {} of {}; the host may be impersonated",
+                line,
+                self.path.display()
+            ),
+            Err(e) => e.to_string(),
+        };
+        *self.rejection.lock() = Some(rejection);
+        Ok(false)
+    }
+}
+
+/// A logged-in SSH connection to the server of one URL
+pub struct SftpConnection {
+    handle: client::Handle<KnownHosts>,
+    host: String,
+    url: String,
+    path: String,
+}
+
+impl SftpConnection {
+    /// Connect to the server of `url`, verify its key against
+    /// `~/.ssh/known_hosts` and log in.
+    ///
+    /// The user name comes from the URL, else from `credentials`, else the
+    /// local user. `key` is tried first, then the password (from the URL or
+    /// `credentials`); with neither, the usual `~/.ssh/id_*` keys are tried.
+    /// A rejected login is reported as [`DlmanError::AuthenticationRequired`].
+    pub async fn connect(
+        url: &str,
+        credentials: Option<&(String, String)>,
+        key: Option<&SshKey>,
+    ) -> Result<Self, DlmanError> {
+        Self::connect_with_known_hosts(url, credentials, key, &ssh_dir()?.join("known_hosts")).await
+    }
+
+    /// [`Self::connect`] with server keys checked against `known_hosts`
+    pub async fn connect_with_known_hosts(
+        url: &str,
+        credentials: Option<&(String, String)>,
+        key: Option<&SshKey>,
+        known_hosts: &Path,
+    ) -> Result<Self, DlmanError> {
+        let parsed = url::Url::parse(url).map_err(|_| DlmanError::InvalidUrl(url.to_string()))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| DlmanError::InvalidUrl(url.to_string()))?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+        let port = parsed.port().unwrap_or(22);
+
+        let rejection = Arc::new(Mutex::new(None));
+        let checker = KnownHosts {
+            host: host.clone(),
+            port,
+            path: known_hosts.to_path_buf(),
+            rejection: rejection.clone(),
+        };
+        let config = Arc::new(client::Config {
+            inactivity_timeout: Some(Duration::from_secs(300)),
+            ..Default::default()
+        });
+        let handle = match timeout(client::connect(config, (host.as_str(), port), checker)).await? {
+            Ok(handle) => handle,
+            Err(russh::Error::UnknownKey) => {
+                let reason = rejection.lock().take().unwrap_or_else(|| "unknown key".to_string());
+                return Err(DlmanError::HostKeyRejected { host, reason });
+            }
+            Err(e) => return Err(ssh_error(e)),
+        };
+
+        let mut connection = Self {
+            handle,
+            host,
+            url: url.to_string(),
+            path: file_path(&parsed),
+        };
+        let decode = |s: &str| urlencoding::decode(s).map(|s| s.into_owned()).unwrap_or_else(|_| s.to_string());
+        let username = if !parsed.username().is_empty() {
+            decode(parsed.username())
+        } else if let Some((username, _)) = credentials {
+            username.clone()
+        } else {
+            std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default()
+        };
+        let password = parsed
+            .password()
+            .map(decode)
+            .or_else(|| credentials.map(|(_, password)| password.clone()))
+            .filter(|p| !p.is_empty());
+        connection.login(&username, password.as_deref(), key).await?;
+        Ok(connection)
+    }
+
+    async fn login(&mut self, username: &str, password: Option<&str>, key: Option<&SshKey>) -> Result<(), DlmanError> {
+        if let Some(key) = key {
+            let private = keys::load_secret_key(&key.path, key.passphrase.as_deref()).map_err(|e| {
+                DlmanError::InvalidOperation(format!("Can't load SSH key {}: {}", key.path.display(), e))
+            })?;
+            if self.login_with_key(username, private).await? {
+                return Ok(());
+            }
+        }
+        if let Some(password) = password {
+            debug!("SFTP {}: password login as {}", self.host, username);
+            let result = timeout(self.handle.authenticate_password(username, password)).await?;
+            if result.map_err(ssh_error)?.success() {
+                return Ok(());
+            }
+        }
+        if key.is_none() && password.is_none() {
+            let ssh_dir = ssh_dir()?;
+            for name in DEFAULT_KEYS {
+                // Keys that need a passphrase can't be used without one
+                if let Ok(private) = keys::load_secret_key(ssh_dir.join(name), None) {
+                    if self.login_with_key(username, private).await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Err(DlmanError::AuthenticationRequired {
+            domain: self.host.clone(),
+            url: self.url.clone(),
+            status: 401,
+        })
+    }
+
+    async fn login_with_key(&mut self, username: &str, key: PrivateKey) -> Result<bool, DlmanError> {
+        debug!("SFTP {}: {} key login as {}", self.host, key.algorithm(), username);
+        let hash = timeout(self.handle.best_supported_rsa_hash()).await?.map_err(ssh_error)?.flatten();
+        let key = PrivateKeyWithHashAlg::new(Arc::new(key), hash);
+        let result = timeout(self.handle.authenticate_publickey(username, key)).await?;
+        Ok(result.map_err(ssh_error)?.success())
+    }
+
+    /// Size and modification time of the file
+    pub async fn probe(&self) -> Result<SftpFileInfo, DlmanError> {
+        let sftp = self.session().await?;
+        let attrs = timeout(sftp.metadata(self.path.as_str())).await?.map_err(|e| self.sftp_error(e))?;
+        let info = SftpFileInfo {
+            size: attrs.size,
+            modified: attrs.mtime.and_then(http_date),
+        };
+        info!("SFTP probe of {}: {:?}", self.url, info);
+        Ok(info)
+    }
+
+    /// Open the file on a channel of its own and position it at `offset`.
+    /// The connection is kept alive by the returned reader.
+    pub async fn open_at(self: &Arc<Self>, offset: u64) -> Result<SftpReader, DlmanError> {
+        let session = self.session().await?;
+        let mut file = timeout(session.open(self.path.as_str())).await?.map_err(|e| self.sftp_error(e))?;
+        if offset > 0 {
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+        }
+        Ok(SftpReader { file, _session: session, _connection: self.clone() })
+    }
+
+    /// Start the `sftp` subsystem on a new channel
+    async fn session(&self) -> Result<SftpSession, DlmanError> {
+        let channel = timeout(self.handle.channel_open_session()).await?.map_err(ssh_error)?;
+        timeout(channel.request_subsystem(true, "sftp")).await?.map_err(ssh_error)?;
+        let session = timeout(SftpSession::new(channel.into_stream())).await?;
+        session.map_err(|e| self.sftp_error(e))
+    }
+
+    fn sftp_error(&self, e: russh_sftp::client::error::Error) -> DlmanError {
+        use russh_sftp::protocol::StatusCode;
+        match e {
+            russh_sftp::client::error::Error::Status(status) => DlmanError::ServerError {
+                status: if status.status_code == StatusCode::NoSuchFile { 404 } else { 500 },
+                message: format!("SFTP {}: {}", self.host, status.error_message),
+            },
+            russh_sftp::client::error::Error::Timeout => DlmanError::Timeout,
+            e => DlmanError::Io(std::io::Error::other(format!("SFTP {}: {}", self.host, e))),
+        }
+    }
+}
+
+/// An open remote file. Reads are pipelined by russh-sftp.
+pub struct SftpReader {
+    file: File,
+    _session: SftpSession,
+    _connection: Arc<SftpConnection>,
+}
+
+impl AsyncRead for SftpReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+}
+
+async fn timeout<F: std::future::Future>(future: F) -> Result<F::Output, DlmanError> {
+    tokio::time::timeout(SFTP_TIMEOUT, future).await.map_err(|_| DlmanError::Timeout)
+}
+
+fn ssh_error(e: russh::Error) -> DlmanError {
+    DlmanError::Io(std::io::Error::other(format!("SSH: {e}")))
+}
+
+fn ssh_dir() -> Result<PathBuf, DlmanError> {
+    directories::BaseDirs::new()
+        .map(|dirs| dirs.home_dir().join(".ssh"))
+        .ok_or_else(|| DlmanError::Unknown("Could not determine the home directory".to_string()))
+}
+
+/// Server path of the file. `sftp://host/~/file` is relative to the login
+/// directory; anything else is absolute.
+fn file_path(url: &url::Url) -> String {
+    let path = urlencoding::decode(url.path())
+        .map(|p| p.into_owned())
+        .unwrap_or_else(|_| url.path().to_string());
+    match path.strip_prefix("/~/") {
+        Some(relative) => relative.to_string(),
+        None => path,
+    }
+}
+
+fn http_date(mtime: u32) -> Option<String> {
+    chrono::DateTime::from_timestamp(mtime as i64, 0).map(|t| t.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// Minimal SFTP server for tests: serves `body` as every file, with the key in
+/// `tests/fixtures/sftp/host_ed25519`. Accepts `user`/`pass` by password and
+/// the fixture client key for any user. Returns the URL, a known_hosts file
+/// trusting the server, and a counter of files opened.
+#[cfg(test)]
+pub(crate) async fn serve_sftp(
+    body: Vec<u8>,
+    user: (&'static str, &'static str),
+) -> (String, tempfile::NamedTempFile, Arc<std::sync::atomic::AtomicU64>) {
+    use russh::server::{Auth, Msg, Session};
+    use russh::{Channel, ChannelId};
+    use russh_sftp::protocol::{Attrs, Data, FileAttributes, Handle, OpenFlags, Status, StatusCode, Version};
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sftp");
+
+    struct SshSession {
+        user: (&'static str, &'static str),
+        client_key: PublicKey,
+        channels: HashMap<ChannelId, Channel<Msg>>,
+        body: Arc<Vec<u8>>,
+        opened: Arc<AtomicU64>,
+    }
+
+    impl russh::server::Handler for SshSession {
+        type Error = russh::Error;
+
+        async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+            Ok(if (user, password) == self.user { Auth::Accept } else { Auth::reject() })
+        }
+
+        async fn auth_publickey(&mut self, _: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+            Ok(if key.key_data() == self.client_key.key_data() { Auth::Accept } else { Auth::reject() })
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<Msg>,
+            reply: russh::server::ChannelOpenHandle,
+            _: &mut Session,
+        ) -> Result<(), Self::Error> {
+            self.channels.insert(channel.id(), channel);
+            reply.accept().await;
+            Ok(())
+        }
+
+        async fn subsystem_request(&mut self, id: ChannelId, name: &str, session: &mut Session) -> Result<(), Self::Error> {
+            match self.channels.remove(&id).filter(|_| name == "sftp") {
+                Some(channel) => {
+                    session.channel_success(id)?;
+                    let files = Files { body: self.body.clone(), opened: self.opened.clone() };
+                    russh_sftp::server::run(channel.into_stream(), files).await;
+                }
+                None => session.channel_failure(id)?,
+            }
+            Ok(())
+        }
+    }
+
+    struct Files {
+        body: Arc<Vec<u8>>,
+        opened: Arc<AtomicU64>,
+    }
+
+    impl russh_sftp::server::Handler for Files {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            StatusCode::OpUnsupported
+        }
+
+        async fn init(&mut self, _: u32, _: HashMap<String, String>) -> Result<Version, Self::Error> {
+            Ok(Version::new())
+        }
+
+        async fn open(&mut self, id: u32, path: String, _: OpenFlags, _: FileAttributes) -> Result<Handle, Self::Error> {
+            self.opened.fetch_add(1, Ordering::AcqRel);
+            Ok(Handle { id, handle: path })
+        }
+
+        async fn read(&mut self, id: u32, _: String, offset: u64, len: u32) -> Result<Data, Self::Error> {
+            let start = offset.min(self.body.len() as u64) as usize;
+            let end = (start + len as usize).min(self.body.len());
+            if start == end {
+                return Err(StatusCode::Eof);
+            }
+            Ok(Data { id, data: self.body[start..end].to_vec() })
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            if path != "/pub/file.bin" {
+                return Err(StatusCode::NoSuchFile);
+            }
+            let mut attrs = FileAttributes::empty();
+            attrs.size = Some(self.body.len() as u64);
+            attrs.mtime = Some(1_704_164_645);
+            Ok(Attrs { id, attrs })
+        }
+
+        async fn close(&mut self, id: u32, _: String) -> Result<Status, Self::Error> {
+            Ok(Status {
+                id,
+                status_code: StatusCode::Ok,
+                error_message: "Ok".to_string(),
+                language_tag: "en-US".to_string(),
+            })
+        }
+    }
+
+    let host_key = keys::load_secret_key(format!("{FIXTURES}/host_ed25519"), None).unwrap();
+    let client_key = keys::load_public_key(format!("{FIXTURES}/client_ed25519.pub")).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut known_hosts = tempfile::NamedTempFile::new().unwrap();
+    writeln!(known_hosts, "[127.0.0.1]:{} {}", port, host_key.public_key().to_openssh().unwrap()).unwrap();
+
+    let config = Arc::new(russh::server::Config {
+        keys: vec![host_key],
+        auth_rejection_time: Duration::ZERO,
+        auth_rejection_time_initial: Some(Duration::ZERO),
+        ..Default::default()
+    });
+    let body = Arc::new(body);
+    let opened = Arc::new(AtomicU64::new(0));
+    let counter = opened.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let handler = SshSession {
+                user,
+                client_key: client_key.clone(),
+                channels: HashMap::new(),
+                body: body.clone(),
+                opened: counter.clone(),
+            };
+            let config = config.clone();
+            tokio::spawn(async move {
+                if let Ok(session) = russh::server::run_stream(config, socket, handler).await {
+                    let _ = session.await;
+                }
+            });
+        }
+    });
+    (format!("sftp://127.0.0.1:{port}/pub/file.bin"), known_hosts, opened)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn client_key() -> SshKey {
+        SshKey {
+            path: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sftp/client_ed25519").into(),
+            passphrase: Some("correct horse".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_file_path() {
+        let path = |url: &str| file_path(&url::Url::parse(url).unwrap());
+        assert_eq!(path("sftp://example.com/srv/my%20build.tar"), "/srv/my build.tar");
+        assert_eq!(path("sftp://example.com/~/builds/a.zip"), "builds/a.zip");
+        assert!(is_sftp_url("sftp://bob@example.com/a") && !is_sftp_url("ftp://example.com/a"));
+        assert_eq!(http_date(1_704_164_645).unwrap(), "Tue, 02 Jan 2024 03:04:05 GMT");
+    }
+
+    #[tokio::test]
+    async fn test_probe_and_resume_with_password() {
+        let body: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let (url, known_hosts, _) = serve_sftp(body.clone(), ("alice", "hunter2")).await;
+        let credentials = ("alice".to_string(), "hunter2".to_string());
+
+        let connection = Arc::new(
+            SftpConnection::connect_with_known_hosts(&url, Some(&credentials), None, known_hosts.path())
+                .await
+                .unwrap(),
+        );
+        let info = connection.probe().await.unwrap();
+        assert_eq!(info.size, Some(100_000));
+        assert_eq!(info.modified.as_deref(), Some("Tue, 02 Jan 2024 03:04:05 GMT"));
+
+        let mut rest = Vec::new();
+        connection.open_at(60_000).await.unwrap().read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, &body[60_000..]);
+    }
+
+    #[tokio::test]
+    async fn test_key_login_and_rejections() {
+        let (url, known_hosts, _) = serve_sftp(b"hello".to_vec(), ("alice", "hunter2")).await;
+        let url = url.replace("sftp://", "sftp://deploy@");
+
+        let connection = SftpConnection::connect_with_known_hosts(&url, None, Some(&client_key()), known_hosts.path())
+            .await
+            .unwrap();
+        assert_eq!(connection.probe().await.unwrap().size, Some(5));
+
+        let wrong = ("deploy".to_string(), "nope".to_string());
+        let result = SftpConnection::connect_with_known_hosts(&url, Some(&wrong), None, known_hosts.path()).await;
+        assert!(matches!(result, Err(DlmanError::AuthenticationRequired { .. })));
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let result = SftpConnection::connect_with_known_hosts(&url, None, Some(&client_key()), empty.path()).await;
+        assert!(matches!(result, Err(DlmanError::HostKeyRejected { reason, .. }) if reason.contains("is not in")));
+    }
+}
@@ -0,0 +1,201 @@
+//! Adaptive connection count
+//!
+//! A multi-segment download starts with a few connections (or as many as
+//! last paid off on the same host) and measures its aggregate speed. While
+//! each added connection still makes the download noticeably faster, another
+//! one is added, up to the configured segment count. When the server pushes
+//! back (429/503 or dropped connections) the download sheds a connection and
+//! stops growing. The count that worked best is remembered per host.
+
+use std::time::{Duration, Instant};
+
+/// Connections a download starts with on a host it knows nothing about
+pub const INITIAL_CONNECTIONS: u32 = 2;
+
+/// How often the download's speed is sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a changed connection count gets to ramp up before it is measured
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Length of a speed measurement
+const MEASURE_TIME: Duration = Duration::from_secs(3);
+
+/// Relative speed-up a new connection must bring to be worth keeping
+const MIN_GAIN: f64 = 0.10;
+
+/// What the download should do after a sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuneAction {
+    Hold,
+    /// Open one more connection
+    Grow,
+}
+
+/// Decides how many connections one download runs
+#[derive(Debug)]
+pub struct ConnectionTuner {
+    /// Never run more than this (the segment count setting)
+    max: u32,
+    /// Connections the download should be running now
+    target: u32,
+    /// False once more connections stopped helping or the server objected
+    growing: bool,
+    /// Speed measured at the last count that paid off, bytes/sec
+    baseline: Option<f64>,
+    /// Most connections that still made the download faster
+    best: Option<u32>,
+    /// Connection count being measured, and when it last changed
+    measured: u32,
+    changed_at: Instant,
+    /// Start of the current measurement: time and bytes downloaded
+    window: Option<(Instant, u64)>,
+}
+
+impl ConnectionTuner {
+    /// Start with `start` connections (clamped to `1..=max`)
+    pub fn new(start: u32, max: u32, now: Instant) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            target: start.clamp(1, max),
+            growing: true,
+            baseline: None,
+            best: None,
+            measured: 0,
+            changed_at: now,
+            window: None,
+        }
+    }
+
+    /// Connections the download should be running now
+    pub fn target(&self) -> u32 {
+        self.target
+    }
+
+    /// Most connections that measurably helped, worth starting with next time
+    pub fn best(&self) -> Option<u32> {
+        self.best
+    }
+
+    /// Record that `connections` are running and `downloaded` bytes are done
+    pub fn sample(&mut self, connections: u32, downloaded: u64, now: Instant) -> TuneAction {
+        if !self.growing {
+            return TuneAction::Hold;
+        }
+        // Only a download running at its target tells us what the target is
+        // worth; segments finishing near the end don't count
+        if connections != self.measured || connections != self.target {
+            self.measured = connections;
+            self.changed_at = now;
+            self.window = None;
+            return TuneAction::Hold;
+        }
+        if now.duration_since(self.changed_at) < SETTLE_TIME {
+            return TuneAction::Hold;
+        }
+        let Some((since, bytes)) = self.window else {
+            self.window = Some((now, downloaded));
+            return TuneAction::Hold;
+        };
+        let elapsed = now.duration_since(since);
+        if elapsed < MEASURE_TIME {
+            return TuneAction::Hold;
+        }
+
+        let speed = downloaded.saturating_sub(bytes) as f64 / elapsed.as_secs_f64();
+        self.window = None;
+        match self.baseline {
+            Some(baseline) if speed < baseline * (1.0 + MIN_GAIN) => {
+                // The last connection didn't pay off
+                self.growing = false;
+                TuneAction::Hold
+            }
+            _ => {
+                self.baseline = Some(speed);
+                self.best = Some(connections);
+                if connections < self.max {
+                    self.target = connections + 1;
+                    TuneAction::Grow
+                } else {
+                    self.growing = false;
+                    TuneAction::Hold
+                }
+            }
+        }
+    }
+
+    /// The server refused or dropped a connection while `remaining` others
+    /// were still running: settle for those
+    pub fn throttled(&mut self, remaining: u32) {
+        self.growing = false;
+        self.target = remaining.clamp(1, self.max);
+        if self.best.is_none_or(|best| best > self.target) {
+            self.best = Some(self.target);
+        }
+    }
+
+    /// The download couldn't open the connection asked for; stay at `connections`
+    pub fn cannot_grow(&mut self, connections: u32) {
+        self.growing = false;
+        self.target = connections.clamp(1, self.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `tuner` one sample per second at `speed` bytes/sec until it acts
+    fn run(tuner: &mut ConnectionTuner, now: &mut Instant, bytes: &mut u64, speed: u64) -> TuneAction {
+        for _ in 0..20 {
+            *now += SAMPLE_INTERVAL;
+            *bytes += speed;
+            let connections = tuner.target();
+            if tuner.sample(connections, *bytes, *now) == TuneAction::Grow {
+                return TuneAction::Grow;
+            }
+        }
+        TuneAction::Hold
+    }
+
+    #[test]
+    fn grows_while_speed_improves() {
+        let mut now = Instant::now();
+        let mut bytes = 0;
+        let mut tuner = ConnectionTuner::new(INITIAL_CONNECTIONS, 8, now);
+
+        assert_eq!(run(&mut tuner, &mut now, &mut bytes, 1000), TuneAction::Grow);
+        assert_eq!(tuner.target(), 3);
+        assert_eq!(run(&mut tuner, &mut now, &mut bytes, 1500), TuneAction::Grow);
+        assert_eq!(tuner.target(), 4);
+        // A fourth connection adds almost nothing
+        assert_eq!(run(&mut tuner, &mut now, &mut bytes, 1550), TuneAction::Hold);
+        assert_eq!(tuner.target(), 4);
+        assert_eq!(tuner.best(), Some(3));
+    }
+
+    #[test]
+    fn never_exceeds_max() {
+        let mut now = Instant::now();
+        let mut bytes = 0;
+        let mut tuner = ConnectionTuner::new(5, 3, now);
+        assert_eq!(tuner.target(), 3);
+        assert_eq!(run(&mut tuner, &mut now, &mut bytes, 1000), TuneAction::Hold);
+        assert_eq!(tuner.best(), Some(3));
+    }
+
+    #[test]
+    fn throttling_sheds_connections_and_stops_growth() {
+        let mut now = Instant::now();
+        let mut bytes = 0;
+        let mut tuner = ConnectionTuner::new(4, 8, now);
+        tuner.throttled(2);
+        assert_eq!(tuner.target(), 2);
+        assert_eq!(tuner.best(), Some(2));
+        assert_eq!(run(&mut tuner, &mut now, &mut bytes, 1000), TuneAction::Hold);
+
+        tuner.throttled(0);
+        assert_eq!(tuner.target(), 1);
+    }
+}
@@ -0,0 +1,431 @@
+//! Encryption of saved passwords at rest
+//!
+//! Once a master key is set up, the passwords and SSH key passphrases of site
+//! credentials, form login sessions and the values of request headers are
+//! stored encrypted with AES-256-GCM, under a key derived with
+//! Argon2id from a master passphrase or from the secret in a keyfile. Only a
+//! check value and the salt are kept in the database; the key itself lives in
+//! memory. A passphrase vault starts each session locked, and reading or
+//! saving credentials fails with [`DlmanError::CredentialsLocked`] until it is
+//! unlocked. A keyfile vault unlocks itself when the database opens, so
+//! headless setups need no prompt.
+//!
+//! Encrypted values are stored as `enc:v1:<base64 of nonce and ciphertext>`.
+//! Values without the prefix are plaintext from before the vault was set up,
+//! and are encrypted as soon as the key is at hand.
+
+use crate::error::DlmanError;
+use aes_gcm::aead::{Aead, Generate, KeyInit, Nonce};
+use aes_gcm::{Aes256Gcm, Key};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use dlman_types::{VaultKey, VaultStatus};
+use parking_lot::RwLock;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+/// Prefix of encrypted values
+const SEALED_PREFIX: &str = "enc:v1:";
+/// Encrypted into the check value, to tell a wrong key from a right one
+const KEY_CHECK: &[u8] = b"dlman credential vault";
+const NONCE_LEN: usize = 12;
+
+/// The master key and its state, shared by every clone of the database
+#[derive(Default)]
+pub struct CredentialVault {
+    state: RwLock<VaultState>,
+}
+
+#[derive(Default)]
+enum VaultState {
+    #[default]
+    Disabled,
+    Locked(VaultRecord),
+    Unlocked(VaultRecord, MasterKey),
+}
+
+/// How the master key is derived, as stored in the database
+#[derive(Debug, Clone)]
+pub(crate) struct VaultRecord {
+    pub salt: Vec<u8>,
+    /// Argon2 memory cost (KiB), iterations and parallelism
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    /// [`KEY_CHECK`] sealed with the key
+    pub check: String,
+    /// Keyfile the key is derived from, if not a passphrase
+    pub keyfile: Option<PathBuf>,
+}
+
+/// A derived key, wiped from memory when dropped
+pub(crate) struct MasterKey([u8; 32]);
+
+impl Drop for MasterKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl MasterKey {
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&Key::<Aes256Gcm>::from(self.0))
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<String, DlmanError> {
+        let nonce_bytes = <[u8; NONCE_LEN]>::generate();
+        let ciphertext = self
+            .cipher()
+            .encrypt(&Nonce::<Aes256Gcm>::from(nonce_bytes), plaintext)
+            .map_err(|_| DlmanError::Vault("encryption failed".to_string()))?;
+        let mut data = nonce_bytes.to_vec();
+        data.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", SEALED_PREFIX, base64::engine::general_purpose::STANDARD.encode(data)))
+    }
+
+    fn open(&self, sealed: &str) -> Result<Vec<u8>, DlmanError> {
+        let corrupt = || DlmanError::Vault("a saved password can't be decrypted".to_string());
+        let encoded = sealed.strip_prefix(SEALED_PREFIX).ok_or_else(corrupt)?;
+        let data = base64::engine::general_purpose::STANDARD.decode(encoded).map_err(|_| corrupt())?;
+        if data.len() < NONCE_LEN {
+            return Err(corrupt());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::<Aes256Gcm>::try_from(nonce).map_err(|_| corrupt())?;
+        self.cipher().decrypt(&nonce, ciphertext).map_err(|_| corrupt())
+    }
+}
+
+/// Whether a stored value is encrypted
+pub(crate) fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+impl CredentialVault {
+    pub fn status(&self) -> VaultStatus {
+        match *self.state.read() {
+            VaultState::Disabled => VaultStatus::Disabled,
+            VaultState::Locked(_) => VaultStatus::Locked,
+            VaultState::Unlocked(..) => VaultStatus::Unlocked,
+        }
+    }
+
+    /// How the current key is derived, unless no vault is set up
+    pub(crate) fn record(&self) -> Option<VaultRecord> {
+        match *self.state.read() {
+            VaultState::Disabled => None,
+            VaultState::Locked(ref record) | VaultState::Unlocked(ref record, _) => Some(record.clone()),
+        }
+    }
+
+    /// Take the vault set up in the database, locked
+    pub(crate) fn load(&self, record: VaultRecord) {
+        *self.state.write() = VaultState::Locked(record);
+    }
+
+    /// Use `key`, checked against `record`, from now on
+    pub(crate) fn unlock(&self, record: VaultRecord, key: MasterKey) {
+        *self.state.write() = VaultState::Unlocked(record, key);
+    }
+
+    /// Switch to the key and state of `other`
+    pub(crate) fn replace(&self, other: CredentialVault) {
+        *self.state.write() = other.state.into_inner();
+    }
+
+    /// Forget the key until the vault is unlocked again
+    pub fn lock(&self) {
+        let mut state = self.state.write();
+        if let VaultState::Unlocked(ref record, _) = *state {
+            *state = VaultState::Locked(record.clone());
+        }
+    }
+
+    /// The value to store for a secret: encrypted if a vault is set up
+    pub fn seal(&self, plaintext: &str) -> Result<String, DlmanError> {
+        match *self.state.read() {
+            VaultState::Disabled => Ok(plaintext.to_string()),
+            VaultState::Locked(_) => Err(DlmanError::CredentialsLocked),
+            VaultState::Unlocked(_, ref key) => key.seal(plaintext.as_bytes()),
+        }
+    }
+
+    /// The secret behind a stored value. Plaintext values are returned as
+    /// they are.
+    pub fn open(&self, stored: &str) -> Result<String, DlmanError> {
+        if !is_sealed(stored) {
+            return Ok(stored.to_string());
+        }
+        match *self.state.read() {
+            VaultState::Unlocked(_, ref key) => String::from_utf8(key.open(stored)?)
+                .map_err(|_| DlmanError::Vault("a saved password can't be decrypted".to_string())),
+            _ => Err(DlmanError::CredentialsLocked),
+        }
+    }
+}
+
+impl std::fmt::Debug for CredentialVault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CredentialVault").field("status", &self.status()).finish()
+    }
+}
+
+impl VaultRecord {
+    /// A fresh salt and key for `key`. A keyfile that doesn't exist yet is
+    /// created with a random secret.
+    pub(crate) async fn create(key: &VaultKey) -> Result<(Self, MasterKey), DlmanError> {
+        let keyfile = match key {
+            VaultKey::Passphrase(passphrase) if passphrase.is_empty() => {
+                return Err(DlmanError::Vault("the master passphrase can't be empty".to_string()));
+            }
+            VaultKey::Passphrase(_) => None,
+            VaultKey::Keyfile(path) => {
+                if !path.exists() {
+                    create_keyfile(path).await?;
+                }
+                Some(path.clone())
+            }
+        };
+        let params = Params::default();
+        let mut record = Self {
+            salt: <[u8; 16]>::generate().to_vec(),
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            check: String::new(),
+            keyfile,
+        };
+        let master = record.derive(key).await?;
+        record.check = master.seal(KEY_CHECK)?;
+        Ok((record, master))
+    }
+
+    /// The key for `key`, if it's the one this vault was set up with
+    pub(crate) async fn unlock(&self, key: &VaultKey) -> Result<MasterKey, DlmanError> {
+        let master = self.derive(key).await?;
+        match master.open(&self.check) {
+            Ok(check) if check == KEY_CHECK => Ok(master),
+            _ => Err(DlmanError::Vault("wrong master passphrase or keyfile".to_string())),
+        }
+    }
+
+    async fn derive(&self, key: &VaultKey) -> Result<MasterKey, DlmanError> {
+        let mut secret = match key {
+            VaultKey::Passphrase(passphrase) => passphrase.as_bytes().to_vec(),
+            VaultKey::Keyfile(path) => read_keyfile(path).await?,
+        };
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| DlmanError::Vault(e.to_string()))?;
+        let salt = self.salt.clone();
+        // Argon2 takes a while on purpose; keep it off the async workers
+        tokio::task::spawn_blocking(move || {
+            let mut key = MasterKey([0; 32]);
+            let result = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(&secret, &salt, &mut key.0)
+                .map_err(|e| DlmanError::Vault(e.to_string()));
+            secret.zeroize();
+            result.map(|_| key)
+        })
+        .await
+        .map_err(|e| DlmanError::Unknown(e.to_string()))?
+    }
+}
+
+/// The secret in a keyfile, without surrounding whitespace
+async fn read_keyfile(path: &Path) -> Result<Vec<u8>, DlmanError> {
+    let mut data = tokio::fs::read(path)
+        .await
+        .map_err(|e| DlmanError::Vault(format!("can't read keyfile {}: {}", path.display(), e)))?;
+    let secret = data.trim_ascii().to_vec();
+    data.zeroize();
+    if secret.is_empty() {
+        return Err(DlmanError::Vault(format!("keyfile {} is empty", path.display())));
+    }
+    Ok(secret)
+}
+
+/// Write a new keyfile holding 32 random bytes (base64), readable only by
+/// the user
+async fn create_keyfile(path: &Path) -> Result<(), DlmanError> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let secret = base64::engine::general_purpose::STANDARD.encode(<[u8; 32]>::generate());
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, format!("{}\n", secret).as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DownloadDatabase, RequestHeaders};
+    use dlman_types::{Download, HeaderRule, RequestHeader, SiteCredential};
+
+    #[tokio::test]
+    async fn sealed_values_need_the_right_key() {
+        let passphrase = VaultKey::Passphrase("correct horse".into());
+        let (record, master) = VaultRecord::create(&passphrase).await.unwrap();
+        let vault = CredentialVault::default();
+        vault.unlock(record.clone(), master);
+
+        let sealed = vault.seal("hunter2").unwrap();
+        assert!(is_sealed(&sealed) && !sealed.contains("hunter2"));
+        assert_ne!(sealed, vault.seal("hunter2").unwrap());
+        assert_eq!(vault.open(&sealed).unwrap(), "hunter2");
+        // Rows from before the vault read as they are
+        assert_eq!(vault.open("plain").unwrap(), "plain");
+
+        vault.lock();
+        assert_eq!(vault.status(), VaultStatus::Locked);
+        assert!(matches!(vault.open(&sealed), Err(DlmanError::CredentialsLocked)));
+        assert!(matches!(vault.seal("x"), Err(DlmanError::CredentialsLocked)));
+
+        let wrong = record.unlock(&VaultKey::Passphrase("wrong".into())).await;
+        assert!(matches!(wrong, Err(DlmanError::Vault(_))));
+        vault.unlock(record.clone(), record.unlock(&passphrase).await.unwrap());
+        assert_eq!(vault.open(&sealed).unwrap(), "hunter2");
+    }
+
+    #[tokio::test]
+    async fn keyfiles_are_created_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys").join("dlman.key");
+        let keyfile = VaultKey::Keyfile(path.clone());
+        let (record, _) = VaultRecord::create(&keyfile).await.unwrap();
+        assert_eq!(record.keyfile.as_deref(), Some(path.as_path()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(record.unlock(&keyfile).await.is_ok());
+
+        std::fs::write(&path, "another secret\n").unwrap();
+        assert!(record.unlock(&keyfile).await.is_err());
+    }
+
+    async fn stored_password(path: &Path) -> String {
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        sqlx::query_scalar("SELECT password FROM site_credentials").fetch_one(&pool).await.unwrap()
+    }
+
+    async fn stored_cookies(path: &Path) -> String {
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        sqlx::query_scalar("SELECT cookies FROM login_sessions").fetch_one(&pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn plaintext_logins_are_encrypted_and_rekeyed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("dlman.db");
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        let credential = SiteCredential::new("example.com".into(), "alice".into(), "hunter2".into());
+        db.upsert_credential(&credential).await.unwrap();
+        db.save_login_session(credential.id, "sid=abc").await.unwrap();
+        assert_eq!(stored_password(&db_path).await, "hunter2");
+        assert_eq!(stored_cookies(&db_path).await, "sid=abc");
+
+        let passphrase = VaultKey::Passphrase("correct horse".into());
+        db.set_master_key(&passphrase).await.unwrap();
+        assert!(is_sealed(&stored_password(&db_path).await));
+        assert!(is_sealed(&stored_cookies(&db_path).await));
+        assert_eq!(db.load_credential(credential.id).await.unwrap().unwrap().password, "hunter2");
+        assert_eq!(db.load_login_session(credential.id).await.unwrap().as_deref(), Some("sid=abc"));
+
+        // A new session starts locked
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        assert_eq!(db.vault_status(), VaultStatus::Locked);
+        assert!(matches!(db.load_all_credentials().await, Err(DlmanError::CredentialsLocked)));
+        assert!(db.unlock_vault(&VaultKey::Passphrase("wrong".into())).await.is_err());
+        db.unlock_vault(&passphrase).await.unwrap();
+        assert_eq!(db.load_all_credentials().await.unwrap()[0].password, "hunter2");
+
+        // Moved to a keyfile, the next session unlocks by itself
+        let keyfile = VaultKey::Keyfile(dir.path().join("dlman.key"));
+        db.set_master_key(&keyfile).await.unwrap();
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        assert_eq!(db.vault_status(), VaultStatus::Unlocked);
+        assert_eq!(db.load_all_credentials().await.unwrap()[0].password, "hunter2");
+        assert_eq!(db.load_login_session(credential.id).await.unwrap().as_deref(), Some("sid=abc"));
+        assert!(db.unlock_vault(&passphrase).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn plaintext_sessions_are_encrypted_on_unlock() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("dlman.db");
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        let credential = SiteCredential::new("example.com".into(), "alice".into(), "hunter2".into());
+        db.upsert_credential(&credential).await.unwrap();
+        let keyfile = VaultKey::Keyfile(dir.path().join("dlman.key"));
+        db.set_master_key(&keyfile).await.unwrap();
+
+        // A session saved in plaintext, as before sessions were encrypted
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_path.display())).await.unwrap();
+        sqlx::query("INSERT INTO login_sessions (credential_id, cookies, created_at) VALUES (?, 'sid=old', '')")
+            .bind(credential.id.to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        assert!(is_sealed(&stored_cookies(&db_path).await));
+        assert_eq!(db.load_login_session(credential.id).await.unwrap().as_deref(), Some("sid=old"));
+    }
+
+    async fn stored_headers(path: &Path, table: &str) -> String {
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", path.display())).await.unwrap();
+        sqlx::query_scalar(&format!("SELECT headers FROM {table}")).fetch_one(&pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn header_values_are_kept_like_passwords() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("dlman.db");
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        let rule = HeaderRule::new("example.com".into(), vec![RequestHeader::new("X-Api-Key", "secret")]);
+        db.upsert_header_rule(&rule).await.unwrap();
+        let mut download = Download::new("https://example.com/a.iso".into(), dir.path().to_path_buf(), uuid::Uuid::nil());
+        download.headers = vec![RequestHeader::new("Authorization", "Bearer token")];
+        db.upsert_download(&download).await.unwrap();
+
+        let passphrase = VaultKey::Passphrase("correct horse".into());
+        db.set_master_key(&passphrase).await.unwrap();
+        for table in ["header_rules", "downloads"] {
+            let stored = stored_headers(&db_path, table).await;
+            assert!(!stored.contains("secret") && !stored.contains("token"), "{table}: {stored}");
+        }
+        assert_eq!(db.load_all_header_rules().await.unwrap()[0].headers[0].value, "secret");
+        assert_eq!(db.load_download(download.id).await.unwrap().unwrap().headers[0].value, "Bearer token");
+
+        // Locked, the values stay sealed and aren't sent
+        let db = DownloadDatabase::new(&db_path).await.unwrap();
+        let rules = db.load_all_header_rules().await.unwrap();
+        let mut loaded = db.load_download(download.id).await.unwrap().unwrap();
+        let headers = RequestHeaders::new(rules, &loaded.url, loaded.headers.clone());
+        assert!(headers.for_url(&loaded.url).is_empty());
+        // Progress is still saved, without losing the headers
+        loaded.downloaded = 10;
+        db.upsert_download(&loaded).await.unwrap();
+        // Headers opened before the vault was locked aren't written in the
+        // clear; a new download with headers has to wait for the unlock
+        let open = db.load_download(download.id).await.unwrap().unwrap();
+        db.upsert_download(&Download { headers: download.headers.clone(), ..open }).await.unwrap();
+        assert!(!stored_headers(&db_path, "downloads").await.contains("token"));
+        let new = Download::new("https://example.com/b.iso".into(), dir.path().to_path_buf(), uuid::Uuid::nil());
+        let new = Download { headers: download.headers.clone(), ..new };
+        assert!(matches!(db.upsert_download(&new).await, Err(DlmanError::CredentialsLocked)));
+        assert!(matches!(db.upsert_header_rule(&rule).await, Err(DlmanError::CredentialsLocked)));
+
+        db.unlock_vault(&passphrase).await.unwrap();
+        let loaded = db.load_download(download.id).await.unwrap().unwrap();
+        assert_eq!(loaded.downloaded, 10);
+        assert_eq!(loaded.headers[0].value, "Bearer token");
+        let headers = RequestHeaders::new(db.load_all_header_rules().await.unwrap(), &loaded.url, Vec::new());
+        assert_eq!(headers.for_url(&loaded.url)["x-api-key"], "secret");
+    }
+}
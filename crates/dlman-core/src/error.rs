@@ -45,6 +45,24 @@ pub enum DlmanError {
     #[error("Authentication required for {domain} (HTTP {status})")]
     AuthenticationRequired { domain: String, url: String, status: u16 },
 
+    #[error("Checksum mismatch ({algorithm}): expected {expected}, got {actual}")]
+    ChecksumMismatch { algorithm: String, expected: String, actual: String },
+
+    #[error("The file at {url} changed on the server since the download started")]
+    ContentChanged { url: String },
+
+    #[error("Login at {url} failed: {reason}")]
+    LoginFailed { url: String, reason: String },
+
+    #[error("SSH host key for {host} rejected: {reason}")]
+    HostKeyRejected { host: String, reason: String },
+
+    #[error("Saved logins are locked; unlock them with the master passphrase")]
+    CredentialsLocked,
+
+    #[error("Credential vault: {0}")]
+    Vault(String),
+
     #[error("Timeout")]
     Timeout,
 
@@ -62,6 +80,34 @@ impl DlmanError {
             _ => false,
         }
     }
+
+    /// Whether the server is pushing back on our connections: it asked us to
+    /// slow down (HTTP 429/503) or refused or reset the connection
+    pub fn is_throttling(&self) -> bool {
+        match self {
+            DlmanError::ServerError { status, .. } => matches!(status, 429 | 503),
+            DlmanError::Network(e) => {
+                let mut source = std::error::Error::source(e);
+                while let Some(error) = source {
+                    if let Some(io) = error.downcast_ref::<std::io::Error>() {
+                        return is_dropped_connection(io);
+                    }
+                    source = error.source();
+                }
+                e.is_connect()
+            }
+            DlmanError::Io(e) => is_dropped_connection(e),
+            _ => false,
+        }
+    }
+}
+
+fn is_dropped_connection(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
 }
 
 // Allow converting to String for Tauri commands
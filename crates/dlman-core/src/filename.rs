@@ -0,0 +1,317 @@
+//! Filename resolution
+//!
+//! Picks the name a download is saved under: the server's
+//! `Content-Disposition` (RFC 6266, including RFC 5987 `filename*`), else the
+//! last segment of the URL path, with an extension inferred from
+//! `Content-Type` when the name has none. Every name goes through
+//! [`sanitize`] so it is a single, valid file name on Linux, Windows and
+//! macOS.
+
+use std::path::Path;
+
+/// Used when neither the server nor the URL suggest a name
+pub const FALLBACK_NAME: &str = "download";
+
+/// Longest name we produce, in bytes. Most filesystems allow 255; the rest is
+/// left for " (1)"-style suffixes added to avoid collisions.
+const MAX_NAME_BYTES: usize = 240;
+
+/// Characters that are invalid in a file name on at least one platform
+const RESERVED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Extensions for the content types commonly served without a named file
+const MIME_EXTENSIONS: &[(&str, &str)] = &[
+    ("application/epub+zip", "epub"),
+    ("application/gzip", "gz"),
+    ("application/json", "json"),
+    ("application/metalink4+xml", "meta4"),
+    ("application/msword", "doc"),
+    ("application/pdf", "pdf"),
+    ("application/vnd.android.package-archive", "apk"),
+    ("application/vnd.debian.binary-package", "deb"),
+    ("application/vnd.microsoft.portable-executable", "exe"),
+    ("application/vnd.openxmlformats-officedocument.presentationml.presentation", "pptx"),
+    ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "xlsx"),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", "docx"),
+    ("application/vnd.rar", "rar"),
+    ("application/x-7z-compressed", "7z"),
+    ("application/x-apple-diskimage", "dmg"),
+    ("application/x-bittorrent", "torrent"),
+    ("application/x-bzip2", "bz2"),
+    ("application/x-gzip", "gz"),
+    ("application/x-iso9660-image", "iso"),
+    ("application/x-msdownload", "exe"),
+    ("application/x-msi", "msi"),
+    ("application/x-rar-compressed", "rar"),
+    ("application/x-rpm", "rpm"),
+    ("application/x-tar", "tar"),
+    ("application/x-xz", "xz"),
+    ("application/x-zip-compressed", "zip"),
+    ("application/xml", "xml"),
+    ("application/zip", "zip"),
+    ("audio/flac", "flac"),
+    ("audio/mp4", "m4a"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("audio/wav", "wav"),
+    ("image/avif", "avif"),
+    ("image/gif", "gif"),
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/svg+xml", "svg"),
+    ("image/webp", "webp"),
+    ("text/csv", "csv"),
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    ("text/xml", "xml"),
+    ("video/mp2t", "ts"),
+    ("video/mp4", "mp4"),
+    ("video/quicktime", "mov"),
+    ("video/webm", "webm"),
+    ("video/x-matroska", "mkv"),
+    ("video/x-msvideo", "avi"),
+];
+
+/// Name for a download of `url`, from the response headers when there are any
+pub fn resolve(url: &url::Url, content_disposition: Option<&str>, content_type: Option<&str>) -> String {
+    let name = content_disposition
+        .and_then(from_content_disposition)
+        .or_else(|| from_url(url))
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_NAME.to_string());
+    sanitize(&with_mime_extension(name, content_type))
+}
+
+/// The file name a `Content-Disposition` header suggests. `filename*` wins
+/// over `filename`, and any directories in the name are dropped.
+pub fn from_content_disposition(header: &str) -> Option<String> {
+    let params = parameters(header);
+    let extended = params
+        .iter()
+        .filter(|(name, _)| name == "filename*")
+        .find_map(|(_, value)| decode_ext_value(value));
+    let name = extended.or_else(|| {
+        params.iter().find(|(name, _)| name == "filename").map(|(_, value)| {
+            // Some servers percent-encode plain `filename` the way browsers
+            // accept it
+            match urlencoding::decode(value) {
+                Ok(decoded) if value.contains('%') => decoded.into_owned(),
+                _ => value.clone(),
+            }
+        })
+    })?;
+
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The last segment of the URL path, percent-decoded. `None` for a path
+/// ending in `/`.
+pub fn from_url(url: &url::Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back().filter(|s| !s.is_empty())?;
+    Some(urlencoding::decode(segment).map(|s| s.into_owned()).unwrap_or_else(|_| segment.to_string()))
+}
+
+/// Extension for a `Content-Type`, ignoring parameters such as `charset`
+pub fn extension_for_mime(content_type: &str) -> Option<&'static str> {
+    let essence = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    MIME_EXTENSIONS.iter().find(|(mime, _)| *mime == essence).map(|(_, ext)| *ext)
+}
+
+/// `name` with the extension of `content_type` appended, if it has none
+pub fn with_mime_extension(name: String, content_type: Option<&str>) -> String {
+    if Path::new(&name).extension().is_some() {
+        return name;
+    }
+    match content_type.and_then(extension_for_mime) {
+        Some(ext) => format!("{}.{}", name, ext),
+        None => name,
+    }
+}
+
+/// Make `name` a single valid file name everywhere: reserved and control
+/// characters become `_`, leading dots and trailing dots or spaces go,
+/// Windows device names get a `_` prefix and long names are shortened,
+/// keeping the extension.
+pub fn sanitize(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| if c.is_control() || RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    let trimmed = replaced.trim().trim_start_matches('.').trim_end_matches(['.', ' ']).trim();
+    if trimmed.is_empty() {
+        return FALLBACK_NAME.to_string();
+    }
+
+    let mut name = trimmed.to_string();
+    let device = name.split('.').next().unwrap_or_default().trim_end().to_ascii_uppercase();
+    if WINDOWS_RESERVED_NAMES.contains(&device.as_str()) {
+        name.insert(0, '_');
+    }
+    truncate(name)
+}
+
+/// [`sanitize`] each component of a `/`-separated relative path, such as a
+/// file name in a Metalink document
+pub fn sanitize_path(path: &str) -> Vec<String> {
+    path.split('/').filter(|part| !part.is_empty()).map(sanitize).collect()
+}
+
+/// Shorten `name` to [`MAX_NAME_BYTES`], cutting the stem and keeping a
+/// short extension
+fn truncate(name: String) -> String {
+    if name.len() <= MAX_NAME_BYTES {
+        return name;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && ext.len() <= 16 => (stem, Some(ext)),
+        _ => (name.as_str(), None),
+    };
+    let budget = MAX_NAME_BYTES - ext.map_or(0, |ext| ext.len() + 1);
+    let mut end = budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let stem = stem[..end].trim_end_matches(['.', ' ']);
+    match ext {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem.to_string(),
+    }
+}
+
+/// `name=value` parameters of a header, names lowercased and quoted values
+/// unescaped. The leading disposition type has no `=` and is skipped.
+fn parameters(header: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = header.chars().peekable();
+    loop {
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == ';' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        match chars.next() {
+            None => break,
+            Some(';') => continue,
+            _ => {}
+        }
+
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            // Skip anything between the closing quote and the next parameter
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+                value.push(c);
+            }
+            value = value.trim().to_string();
+        }
+        params.push((name.trim().to_ascii_lowercase(), value));
+    }
+    params
+}
+
+/// Decode an RFC 5987 `charset'language'percent-encoded` value. Only UTF-8
+/// and ISO-8859-1 are required by the RFC; other charsets give `None`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim().to_ascii_lowercase();
+    let _language = parts.next()?;
+    let bytes = urlencoding::decode_binary(parts.next()?.as_bytes());
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes.into_owned()).ok(),
+        "iso-8859-1" => Some(bytes.iter().map(|&b| b as char).collect()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> url::Url {
+        url::Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(from_content_disposition("attachment; filename=\"report.pdf\"").as_deref(), Some("report.pdf"));
+        assert_eq!(from_content_disposition("attachment; filename=report.pdf; size=42").as_deref(), Some("report.pdf"));
+        assert_eq!(
+            from_content_disposition("attachment; filename=\"a \\\"b\\\"; c.txt\"; creation-date=\"x\"").as_deref(),
+            Some("a \"b\"; c.txt")
+        );
+        assert_eq!(
+            from_content_disposition("attachment; filename=\"fallback.zip\"; filename*=UTF-8''%E2%82%AC%20rates.zip").as_deref(),
+            Some("€ rates.zip")
+        );
+        assert_eq!(from_content_disposition("attachment; FILENAME*=iso-8859-1'en'%A3%20rates.txt").as_deref(), Some("£ rates.txt"));
+        assert_eq!(from_content_disposition("attachment; filename=\"../../etc/passwd\"").as_deref(), Some("passwd"));
+        assert_eq!(from_content_disposition("attachment; filename=C:\\temp\\x.exe").as_deref(), Some("x.exe"));
+        assert_eq!(from_content_disposition("attachment; filename=%E4%B8%AD.zip").as_deref(), Some("中.zip"));
+        assert_eq!(from_content_disposition("inline"), None);
+        assert_eq!(from_content_disposition("attachment; filename=\"\""), None);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("a/b\\c:d*e?f\"g<h>i|j\u{7}.txt"), "a_b_c_d_e_f_g_h_i_j_.txt");
+        assert_eq!(sanitize("  ..hidden.tar.gz. . "), "hidden.tar.gz");
+        assert_eq!(sanitize("CON"), "_CON");
+        assert_eq!(sanitize("lpt1.txt"), "_lpt1.txt");
+        assert_eq!(sanitize("console.txt"), "console.txt");
+        assert_eq!(sanitize(".."), FALLBACK_NAME);
+        assert_eq!(sanitize(""), FALLBACK_NAME);
+
+        let long = sanitize(&format!("{}.iso", "é".repeat(200)));
+        assert!(long.len() <= MAX_NAME_BYTES);
+        assert!(long.ends_with("é.iso"));
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(sanitize_path("a:b/CON/x.iso"), vec!["a_b", "_CON", "x.iso"]);
+        assert_eq!(sanitize_path("dir//x.iso"), vec!["dir", "x.iso"]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(&url("https://e.com/files/My%20File.zip?x=1"), None, Some("text/html")), "My File.zip");
+        assert_eq!(resolve(&url("https://e.com/get?id=5"), None, Some("application/zip")), "get.zip");
+        assert_eq!(resolve(&url("https://e.com/"), None, Some("application/pdf; charset=binary")), "download.pdf");
+        assert_eq!(resolve(&url("https://e.com/"), None, Some("application/octet-stream")), "download");
+        assert_eq!(
+            resolve(&url("https://e.com/get"), Some("attachment; filename*=UTF-8''report"), Some("application/pdf")),
+            "report.pdf"
+        );
+        assert_eq!(resolve(&url("https://e.com/a%2F..%2Fnul"), None, None), "a_.._nul");
+    }
+}
@@ -15,7 +15,9 @@
 
 mod engine;
 mod error;
+pub mod filename;
 pub mod media;
+pub mod metalink;
 mod queue;
 mod scheduler;
 mod storage;
@@ -26,7 +28,7 @@ pub use queue::*;
 pub use scheduler::*;
 pub use storage::*;
 
-use dlman_types::{CoreEvent, Download, DownloadStatus, LinkInfo, Queue, QueueOptions, Settings, SiteCredential};
+use dlman_types::{AddDownloadOptions, CoreEvent, Download, DownloadStatus, HeaderRule, LinkInfo, Mirror, Queue, QueueOptions, RecordingOptions, RequestHeader, Settings, SiteCredential, SshKey, StreamSelection, TrackSelection, VaultKey, VaultStatus};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -53,6 +55,17 @@ struct HlsTaskHandle {
     /// Abort handle for the management tokio task. Calling abort() immediately
     /// cancels ALL child futures, including in-flight HTTP requests.
     abort_handle: tokio::task::AbortHandle,
+    /// Live recording: stopping it finalizes the file instead of aborting.
+    recording: bool,
+}
+
+/// One rendition (video, or a separate audio track) of a live recording.
+struct LiveTrack {
+    /// Variant to re-resolve on each manifest refresh
+    variant: dlman_types::MediaVariant,
+    /// Segments from the latest manifest poll
+    list: crate::media::SegmentList,
+    out_path: PathBuf,
 }
 
 /// The main DLMan core instance
@@ -73,6 +86,9 @@ pub struct DlmanCore {
     /// Active HLS/DASH download tasks (keyed by download UUID).
     /// Used for pause/cancel — abort_handle kills all in-flight segment requests.
     hls_tasks: Arc<RwLock<HashMap<Uuid, HlsTaskHandle>>>,
+    /// Credentials supplied for a download without saving them, kept until
+    /// the app exits
+    supplied_credentials: Arc<RwLock<HashMap<Uuid, (String, String)>>>,
 }
 
 impl DlmanCore {
@@ -94,6 +110,7 @@ impl DlmanCore {
         // Apply the temp-storage policy to the manager so downloads write their
         // scratch files to the user's chosen location (see TempStorageSettings).
         download_manager.set_temp_storage(settings.temp_storage.clone()).await;
+        download_manager.set_host_limits(settings.host_limits.clone());
         
         // Restore downloads from database (resets Downloading → Paused for crash recovery)
         let downloads = download_manager.restore_downloads().await?;
@@ -114,6 +131,7 @@ impl DlmanCore {
             settings: Arc::new(RwLock::new(settings)),
             event_tx,
             hls_tasks: Arc::new(RwLock::new(HashMap::new())),
+            supplied_credentials: Arc::new(RwLock::new(HashMap::new())),
         };
         
         // Start the scheduler background task
@@ -145,8 +163,11 @@ impl DlmanCore {
             loop {
                 match rx.recv().await {
                     Ok(CoreEvent::DownloadStatusChanged { id, status, .. }) => {
-                        // When a download completes, fails, or is cancelled, try to start next in queue
-                        if matches!(status, DownloadStatus::Completed | DownloadStatus::Failed | DownloadStatus::Cancelled) {
+                        // When a download completes, fails, stops for credentials, or is cancelled, try to start next in queue
+                        if matches!(
+                            status,
+                            DownloadStatus::Completed | DownloadStatus::Failed | DownloadStatus::Cancelled | DownloadStatus::AwaitingAuth
+                        ) {
                             // Look up the download's queue
                             if let Ok(Some(download)) = core.download_manager.db().load_download(id).await {
                                 let queue_id = download.queue_id;
@@ -223,35 +244,53 @@ impl DlmanCore {
     ///
     /// When `auto_start` is true the download begins immediately.
     /// When false, it stays in `Queued` status until the user manually starts it.
-    /// Streaming URLs (m3u8/mpd) are transparently redirected to the HLS/DASH pipeline.
+    /// With a `checksum` in `options`, the merged file is verified and the
+    /// download fails on a mismatch. Its `mirrors` are further URLs for the same
+    /// file; segments are spread across the ones that report the same size. Its
+    /// `headers` go with every request to the download's host, on top of the
+    /// header rules.
+    /// Streaming URLs (m3u8/mpd) are transparently redirected to the HLS/DASH pipeline;
+    /// those are remuxed after download, so they can't take a checksum.
     pub async fn add_download(
         &self,
         url: &str,
         destination: PathBuf,
         queue_id: Uuid,
         category_id: Option<Uuid>,
-        cookies: Option<String>,
+        options: AddDownloadOptions,
         auto_start: bool,
     ) -> Result<Download, DlmanError> {
+        let AddDownloadOptions { cookies, checksum, mirrors, headers } = options;
         // Safety net: redirect streaming URLs to the HLS/DASH pipeline.
         if is_streaming_url(url) {
+            if checksum.is_some() {
+                return Err(DlmanError::InvalidOperation(
+                    "A checksum can't be verified for an HLS/DASH stream".to_string(),
+                ));
+            }
             info!("[add_download] Intercepted streaming URL → HLS pipeline");
-            return self.download_hls_stream(url, None, None, None, cookies, None, auto_start).await;
+            return self.download_hls_stream_with_id(
+                None, url, None, None, None, cookies, None, auto_start,
+                TrackSelection::default(), RecordingOptions::default(), headers,
+            ).await;
         }
 
         // Validate URL
         let parsed_url = url::Url::parse(url)
             .map_err(|_| DlmanError::InvalidUrl(url.to_string()))?;
 
-        // Extract filename from URL path
-        let filename = parsed_url.path_segments()
-            .and_then(|s| s.last())
-            .filter(|s| !s.is_empty())
-            .unwrap_or("download")
-            .to_string();
-        let filename = urlencoding::decode(&filename)
-            .map(|s| s.into_owned())
-            .unwrap_or(filename);
+        // Name after the URL path until a probe finds a better one
+        let filename = filename::resolve(&parsed_url, None, None);
+
+        let mut mirror_urls: Vec<String> = Vec::new();
+        for mirror in mirrors {
+            let mirror = mirror.trim();
+            if mirror.is_empty() || mirror == url || mirror_urls.iter().any(|m| m == mirror) {
+                continue;
+            }
+            url::Url::parse(mirror).map_err(|_| DlmanError::InvalidUrl(mirror.to_string()))?;
+            mirror_urls.push(mirror.to_string());
+        }
 
         let unique_filename = Self::get_unique_filename(&destination, &filename, self.download_manager.db()).await;
 
@@ -262,7 +301,56 @@ impl DlmanCore {
         download.final_url = None;
         download.status = DownloadStatus::Queued;
         download.cookies = cookies;
+        download.checksum = checksum;
+        download.mirrors = mirror_urls.into_iter().map(Mirror::new).collect();
+        download.headers = headers.into_iter().filter(|h| !h.name.trim().is_empty()).collect();
+
+        self.insert_new_download(download, auto_start).await
+    }
+
+    /// Add every file of a Metalink (`.meta4` / `.metalink`) document.
+    ///
+    /// Each file becomes one download from its best URL, with the other URLs
+    /// as mirrors. Its size, whole-file hash and piece hashes are kept, so
+    /// segments are verified as they complete. Paths inside the document are
+    /// placed under `destination`.
+    pub async fn add_metalink(
+        &self,
+        document: &str,
+        destination: PathBuf,
+        queue_id: Uuid,
+        category_id: Option<Uuid>,
+        auto_start: bool,
+    ) -> Result<Vec<Download>, DlmanError> {
+        let files = metalink::parse_metalink(document)?;
+        let mut downloads = Vec::with_capacity(files.len());
+        for file in files {
+            let mut urls = file.urls.into_iter().map(|u| u.url);
+            let Some(url) = urls.next() else { continue };
+
+            // `name` is relative and free of `..` (see `parse_metalink`);
+            // its directories must be valid names as much as the file
+            let mut parts = filename::sanitize_path(&file.name);
+            let filename = parts.pop().unwrap_or_else(|| filename::sanitize(""));
+            let directory = parts.iter().fold(destination.clone(), |dir, part| dir.join(part));
+            let unique_filename = Self::get_unique_filename(&directory, &filename, self.download_manager.db()).await;
+
+            let mut download = Download::new(url, directory, queue_id);
+            download.category_id = category_id;
+            download.filename = unique_filename;
+            download.size = file.size;
+            download.status = DownloadStatus::Queued;
+            download.checksum = file.hash;
+            download.piece_hashes = file.pieces;
+            download.mirrors = urls.map(Mirror::new).collect();
 
+            downloads.push(self.insert_new_download(download, auto_start).await?);
+        }
+        Ok(downloads)
+    }
+
+    /// Persist a newly created download, announce it and optionally start it
+    async fn insert_new_download(&self, download: Download, auto_start: bool) -> Result<Download, DlmanError> {
         self.download_manager.db().upsert_download(&download).await?;
         self.emit(CoreEvent::DownloadAdded { download: download.clone() });
 
@@ -288,7 +376,7 @@ impl DlmanCore {
         category_id: Option<Uuid>,
         cookies: Option<String>,
     ) -> Result<Download, DlmanError> {
-        self.add_download(url, destination, queue_id, category_id, cookies, false).await
+        self.add_download(url, destination, queue_id, category_id, AddDownloadOptions { cookies, ..Default::default() }, false).await
     }
     
     /// Get a download by ID
@@ -303,7 +391,13 @@ impl DlmanCore {
     }
     
     /// Pause a download (works for both regular and HLS/DASH downloads)
+    ///
+    /// Live recordings can't be resumed, so pausing one stops it and keeps
+    /// what has been recorded (see [`Self::stop_recording`]).
     pub async fn pause_download(&self, id: Uuid) -> Result<(), DlmanError> {
+        if self.stop_recording(id).await? {
+            return Ok(());
+        }
         // Check if this is an active HLS streaming download
         if let Some(task) = self.hls_tasks.write().await.remove(&id) {
             // 1. Signal the cancel flag (cooperative)
@@ -338,6 +432,23 @@ impl DlmanCore {
         Ok(())
     }
     
+    /// Stop a live recording gracefully. The recorder finishes the segment in
+    /// flight, finalizes the output file and marks the download completed.
+    ///
+    /// Returns `false` if `id` is not an active recording.
+    pub async fn stop_recording(&self, id: Uuid) -> Result<bool, DlmanError> {
+        let mut tasks = self.hls_tasks.write().await;
+        match tasks.get(&id) {
+            Some(task) if task.recording => {
+                task.cancel.store(true, Ordering::Release);
+                tasks.remove(&id);
+                info!("Stopping live recording {}", id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     /// Resume a download
     pub async fn resume_download(&self, id: Uuid) -> Result<(), DlmanError> {
         let download = self.get_download(id).await?;
@@ -350,15 +461,19 @@ impl DlmanCore {
             }
 
             info!("[resume] Routing streaming download to HLS pipeline");
+            let selection = download.stream_selection.unwrap_or_default();
             let _dl = self.download_hls_stream_with_id(
                 Some(id),
                 &download.url,
-                None,
+                selection.variant,
                 Some(download.filename.clone()),
                 None, // page_title lost on resume, but filename is already set
                 download.cookies.clone(),
                 None,
                 true, // auto_start: resume always starts immediately
+                selection.tracks,
+                RecordingOptions::default(),
+                download.headers.clone(),
             ).await?;
             return Ok(());
         }
@@ -385,10 +500,10 @@ impl DlmanCore {
         );
         
         // Look up saved credentials for this URL
-        let credentials = self.find_credentials_for_download(&download.url).await;
+        let (credentials, ssh_key) = self.find_credentials_for_download(id, &download.url).await;
         
         // Note: manager.resume() already emits DownloadStatusChanged event
-        self.download_manager.resume(id, effective_speed_limit, segment_count, max_retries, retry_delay_secs, credentials).await?;
+        self.download_manager.resume(id, effective_speed_limit, segment_count, max_retries, retry_delay_secs, credentials, ssh_key).await?;
         
         Ok(())
     }
@@ -435,15 +550,19 @@ impl DlmanCore {
         // ── Streaming URL guard ─────────────────────────────────────────
         if is_streaming_url(&download.url) {
             info!("[retry] Routing streaming download to HLS pipeline");
+            let selection = download.stream_selection.unwrap_or_default();
             let _dl = self.download_hls_stream_with_id(
                 Some(id),
                 &download.url,
-                None,
+                selection.variant,
                 Some(download.filename.clone()),
                 None,
                 download.cookies.clone(),
                 None,
                 true, // auto_start: retries always start immediately
+                selection.tracks,
+                RecordingOptions::default(),
+                download.headers.clone(),
             ).await?;
             return Ok(());
         }
@@ -491,7 +610,7 @@ impl DlmanCore {
         drop(settings);
         
         // Start immediately
-        let credentials = self.find_credentials_for_download(&download.url).await;
+        let (credentials, ssh_key) = self.find_credentials_for_download(id, &download.url).await;
         self.download_manager.start(
             download,
             effective_limit,
@@ -499,11 +618,43 @@ impl DlmanCore {
             max_retries,
             retry_delay,
             credentials,
+            ssh_key,
         ).await?;
         
         Ok(())
     }
     
+    /// Restart a download from scratch, discarding its partial data
+    ///
+    /// This is the way out of [`DlmanError::ContentChanged`]: the segments on
+    /// disk belong to an older version of the file and can't be resumed.
+    pub async fn restart_download(&self, id: Uuid) -> Result<(), DlmanError> {
+        if self.download_manager.is_active(id).await {
+            return Err(DlmanError::InvalidOperation(
+                "Cannot restart a download that is still running".to_string(),
+            ));
+        }
+        let mut download = self.get_download(id).await?;
+        info!("Restarting download {} from scratch", id);
+
+        self.download_manager.remove_segment_files(&download).await;
+        download.segments.clear();
+        download.downloaded = 0;
+        // Everything learned from the old probe is stale
+        download.size = None;
+        download.final_url = None;
+        download.etag = None;
+        download.last_modified = None;
+        download.supports_range = false;
+        for mirror in &mut download.mirrors {
+            *mirror = Mirror::new(std::mem::take(&mut mirror.url));
+        }
+        self.download_manager.db().upsert_download(&download).await?;
+
+        // With no segments left, retry starts over
+        self.retry_download(id).await
+    }
+    
     /// Delete a download
     pub async fn delete_download(&self, id: Uuid, delete_file: bool) -> Result<(), DlmanError> {
         self.download_manager.delete(id, delete_file).await
@@ -740,8 +891,98 @@ impl DlmanCore {
         self.download_manager.db().delete_credential(id).await
     }
     
-    /// Find matching credentials for a download URL and return (username, password) if found
-    async fn find_credentials_for_download(&self, url: &str) -> Option<(String, String)> {
+    /// Supply credentials for a download waiting in
+    /// [`DownloadStatus::AwaitingAuth`] and resume it. With `remember` they are
+    /// saved as the login for the download's site (replacing the one that was
+    /// rejected); otherwise they are only used for this download until the
+    /// app exits.
+    pub async fn provide_credentials(
+        &self,
+        download_id: Uuid,
+        username: String,
+        password: String,
+        remember: bool,
+    ) -> Result<(), DlmanError> {
+        let download = self.get_download(download_id).await?;
+        if download.status != DownloadStatus::AwaitingAuth || self.download_manager.is_active(download_id).await {
+            return Err(DlmanError::InvalidOperation(
+                "Credentials can only be supplied to a download that is waiting for them".to_string(),
+            ));
+        }
+        if remember {
+            self.remember_credentials(&download.url, username, password).await?;
+            self.supplied_credentials.write().await.remove(&download_id);
+        } else {
+            self.supplied_credentials.write().await.insert(download_id, (username, password));
+        }
+        info!("Credentials supplied for download {}, resuming", download_id);
+        self.retry_download(download_id).await
+    }
+    
+    /// Save `username` / `password` as the login for the site of `url`,
+    /// updating the saved login that matches it if there is one. Of several
+    /// accounts for the site, only the one with that username is updated.
+    async fn remember_credentials(&self, url: &str, username: String, password: String) -> Result<(), DlmanError> {
+        let db = self.download_manager.db();
+        let mut matching = db.find_credentials_for_url(url).await?;
+        let existing = match matching.iter().position(|c| c.username == username) {
+            Some(index) => Some(matching.swap_remove(index)),
+            None if matching.len() == 1 => matching.pop(),
+            None => None,
+        };
+        let credential = match existing {
+            Some(existing) => SiteCredential { username, password, ..existing },
+            None => {
+                let parsed = url::Url::parse(url).map_err(|e| DlmanError::InvalidUrl(e.to_string()))?;
+                let domain = parsed.host_str().unwrap_or_default().to_lowercase();
+                let mut credential = SiteCredential::new(domain, username, password);
+                credential.protocol = match parsed.scheme() {
+                    "ftps" | "ftpes" => "ftp".to_string(),
+                    scheme => scheme.to_string(),
+                };
+                credential
+            }
+        };
+        db.upsert_credential(&credential).await
+    }
+    
+    /// Whether saved passwords are encrypted, and whether they're unlocked
+    pub fn credential_vault_status(&self) -> VaultStatus {
+        self.download_manager.db().vault_status()
+    }
+    
+    /// Unlock saved logins for this session with the master passphrase or
+    /// keyfile. Until then, downloads go without their saved logins.
+    pub async fn unlock_credentials(&self, key: VaultKey) -> Result<(), DlmanError> {
+        self.download_manager.db().unlock_vault(&key).await?;
+        info!("Saved logins unlocked");
+        Ok(())
+    }
+    
+    /// Forget the master key until the next unlock
+    pub fn lock_credentials(&self) {
+        self.download_manager.db().lock_vault();
+    }
+    
+    /// Encrypt saved passwords with a key derived from `key`: sets up
+    /// encryption, or changes the passphrase or keyfile when already set up
+    /// (and unlocked)
+    pub async fn set_master_key(&self, key: VaultKey) -> Result<(), DlmanError> {
+        self.download_manager.db().set_master_key(&key).await
+    }
+    
+    /// Try the login form of a credential without saving it
+    pub async fn test_credential_login(&self, credential: SiteCredential) -> Result<(), DlmanError> {
+        self.download_manager.test_form_login(&credential).await
+    }
+    
+    /// Find matching credentials for a download URL and return (username, password)
+    /// and the SSH key, if found. Credentials supplied for the download itself
+    /// come first.
+    async fn find_credentials_for_download(&self, id: Uuid, url: &str) -> (Option<(String, String)>, Option<SshKey>) {
+        if let Some(credentials) = self.supplied_credentials.read().await.get(&id) {
+            return (Some(credentials.clone()), None);
+        }
         match self.download_manager.db().find_credentials_for_url(url).await {
             Ok(creds) => {
                 if let Some(cred) = creds.first() {
@@ -751,18 +992,39 @@ impl DlmanCore {
                     tokio::spawn(async move {
                         let _ = db.touch_credential(cred_id).await;
                     });
-                    Some((cred.username.clone(), cred.password.clone()))
+                    (Some((cred.username.clone(), cred.password.clone())), cred.ssh_key.clone())
                 } else {
-                    None
+                    (None, None)
                 }
             }
             Err(e) => {
                 tracing::warn!("Failed to look up credentials for URL: {}", e);
-                None
+                (None, None)
             }
         }
     }
     
+    // ========================================================================
+    // Header Rules
+    // ========================================================================
+    
+    /// Get all header rules
+    pub async fn get_all_header_rules(&self) -> Result<Vec<HeaderRule>, DlmanError> {
+        self.download_manager.db().load_all_header_rules().await
+    }
+    
+    /// Add or update a header rule. It applies to requests made from now on.
+    pub async fn upsert_header_rule(&self, mut rule: HeaderRule) -> Result<HeaderRule, DlmanError> {
+        rule.headers.retain(|h| !h.name.trim().is_empty());
+        self.download_manager.db().upsert_header_rule(&rule).await?;
+        Ok(rule)
+    }
+    
+    /// Delete a header rule
+    pub async fn delete_header_rule(&self, id: Uuid) -> Result<(), DlmanError> {
+        self.download_manager.db().delete_header_rule(id).await
+    }
+    
     // ========================================================================
     // Settings
     // ========================================================================
@@ -779,6 +1041,7 @@ impl DlmanCore {
         self.download_manager.db().save_settings(&settings).await?;
         // Propagate the temp-storage policy so subsequent downloads honor it.
         self.download_manager.set_temp_storage(settings.temp_storage.clone()).await;
+        self.download_manager.set_host_limits(settings.host_limits.clone());
         // Update in-memory cache
         *self.settings.write().await = settings;
         Ok(())
@@ -792,6 +1055,9 @@ impl DlmanCore {
     ///
     /// When `auto_start` is true, downloading begins immediately.
     /// When false, the record is created in `Queued` status and no segment work starts.
+    /// `tracks` picks the alternate audio rendition downloaded (and muxed)
+    /// alongside the variant.
+    #[allow(clippy::too_many_arguments)]
     pub async fn download_hls_stream(
         &self,
         master_url: &str,
@@ -800,9 +1066,30 @@ impl DlmanCore {
         page_title: Option<String>,
         cookies: Option<String>,
         referrer: Option<String>,
+        tracks: TrackSelection,
         auto_start: bool,
     ) -> Result<Download, DlmanError> {
-        self.download_hls_stream_with_id(None, master_url, variant_index, filename, page_title, cookies, referrer, auto_start).await
+        self.download_hls_stream_with_id(None, master_url, variant_index, filename, page_title, cookies, referrer, auto_start, tracks, RecordingOptions::default(), Vec::new()).await
+    }
+
+    /// Record a live HLS/DASH stream, stopping at the end of the stream, on
+    /// [`Self::stop_recording`], or when one of the `options` limits is hit.
+    ///
+    /// Progress is reported as [`CoreEvent::RecordingProgress`] (elapsed time)
+    /// since the final size is unknown. VOD streams are downloaded normally.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record_live_stream(
+        &self,
+        master_url: &str,
+        variant_index: Option<usize>,
+        filename: Option<String>,
+        page_title: Option<String>,
+        cookies: Option<String>,
+        referrer: Option<String>,
+        tracks: TrackSelection,
+        options: RecordingOptions,
+    ) -> Result<Download, DlmanError> {
+        self.download_hls_stream_with_id(None, master_url, variant_index, filename, page_title, cookies, referrer, true, tracks, options, Vec::new()).await
     }
 
     /// Core HLS/DASH download implementation.
@@ -821,6 +1108,9 @@ impl DlmanCore {
         cookies: Option<String>,
         referrer: Option<String>,
         auto_start: bool,
+        tracks: TrackSelection,
+        recording: RecordingOptions,
+        headers: Vec<RequestHeader>,
     ) -> Result<Download, DlmanError> {
         use crate::media::MediaResolver;
         use dlman_types::MediaProtocol;
@@ -841,19 +1131,22 @@ impl DlmanCore {
             {
                 None
             } else {
-                Some(f)
+                Some(crate::filename::sanitize(&f))
             }
         });
 
         // 1. Build a DetectedMedia struct so the resolver can work
+        let is_dash = url::Url::parse(master_url)
+            .map(|u| u.path().to_lowercase().ends_with(".mpd"))
+            .unwrap_or(false);
         let detected = dlman_types::DetectedMedia {
             id: Uuid::new_v4().to_string(),
             page_url: referrer.clone().unwrap_or_default(),
             page_title: page_title.clone(),
             master_url: master_url.to_string(),
-            protocol: MediaProtocol::Hls,
+            protocol: if is_dash { MediaProtocol::Dash } else { MediaProtocol::Hls },
             variants: vec![],
-            mime_type: Some("application/vnd.apple.mpegurl".to_string()),
+            mime_type: Some(if is_dash { "application/dash+xml" } else { "application/vnd.apple.mpegurl" }.to_string()),
             filename: filename.clone(),
             duration: None,
             thumbnail: None,
@@ -866,7 +1159,11 @@ impl DlmanCore {
             .user_agent(USER_AGENT)
             .build()
             .unwrap_or_default();
-        let resolver = MediaResolver::new(http_client.clone());
+        // The rules' User-Agent, if any, replaces the browser one above
+        let request_headers = self.download_manager.request_headers(master_url, headers.clone()).await;
+        let resolver = MediaResolver::new(http_client.clone())
+            .with_headers(request_headers.clone())
+            .with_host_limiter(self.download_manager.host_limiter());
         let variants = resolver.resolve(&detected).await?;
 
         if variants.is_empty() {
@@ -876,11 +1173,8 @@ impl DlmanCore {
         }
 
         // Pick the requested variant (or best quality = index 0)
-        let chosen = if let Some(idx) = variant_index {
-            variants.get(idx).unwrap_or(&variants[0])
-        } else {
-            &variants[0]
-        };
+        let chosen_index = variant_index.filter(|&i| i < variants.len()).unwrap_or(0);
+        let chosen = &variants[chosen_index];
 
         info!(
             "[HLS] {} variants, chose: {}",
@@ -888,16 +1182,70 @@ impl DlmanCore {
             chosen.label,
         );
 
-        // 3. Get segment URLs for the chosen variant
-        let segment_urls = resolver.get_segments(&detected, chosen).await?;
+        // 3. Get segments (byte ranges, keys, init section) for the chosen variant
+        let segment_list = resolver.get_segments(&detected, chosen).await?;
 
-        if segment_urls.is_empty() {
+        // A live window may momentarily be empty; the recorder will re-poll
+        let is_live = segment_list.live.is_some();
+        if segment_list.segments.is_empty() && !is_live {
             return Err(DlmanError::InvalidOperation(
                 "No segments found in HLS media playlist".to_string(),
             ));
         }
 
-        info!("[HLS] {} segments to download", segment_urls.len());
+        info!(
+            "[HLS] {} segments to {}{}",
+            segment_list.segments.len(),
+            if is_live { "record (live)" } else { "download" },
+            if segment_list.is_fmp4() { " (fMP4)" } else { "" }
+        );
+
+        // 3b. Separate audio track (HLS EXT-X-MEDIA rendition, or the DASH
+        // audio representation paired with the video), downloaded next to
+        // the video and muxed into it once both are complete
+        let audio_rendition = crate::media::select_audio_rendition(&chosen.audio_tracks, tracks.audio);
+        // What was actually picked, so a resume fetches the same tracks even
+        // if the request left the choice to the manifest's defaults
+        let selection = StreamSelection {
+            variant: Some(chosen_index),
+            tracks: TrackSelection {
+                audio: audio_rendition
+                    .and_then(|r| chosen.audio_tracks.iter().position(|t| std::ptr::eq(t, r)))
+                    .or(tracks.audio),
+                subtitles: tracks.subtitles,
+            },
+        };
+        let audio = match audio_rendition.and_then(|r| crate::media::rendition_variant(r, true)) {
+            Some(rendition) => {
+                let list = resolver.get_segments(&detected, &rendition).await?;
+                info!("[HLS] Audio rendition '{}': {} segments", rendition.label, list.segments.len());
+                Some((rendition, list))
+            }
+            None => None,
+        };
+
+        // 3c. Subtitle renditions, stitched into sidecar files once the
+        // video is complete. A subtitle that can't be resolved is skipped
+        // rather than failing the download.
+        let mut subtitles = Vec::new();
+        if is_live {
+            if !chosen.subtitle_tracks.is_empty() {
+                info!("[HLS] Subtitles are not recorded for live streams; skipping");
+            }
+        } else {
+            for rendition in crate::media::select_subtitle_renditions(&chosen.subtitle_tracks, tracks.subtitles) {
+                let Some(variant) = crate::media::rendition_variant(rendition, false) else {
+                    continue;
+                };
+                match resolver.get_segments(&detected, &variant).await {
+                    Ok(list) => {
+                        info!("[HLS] Subtitle rendition '{}': {} segments", rendition.name, list.segments.len());
+                        subtitles.push((rendition.clone(), list));
+                    }
+                    Err(e) => tracing::warn!("[HLS] Skipping subtitle rendition '{}': {}", rendition.name, e),
+                }
+            }
+        }
 
         // 4. Determine output filename — prefer provided filename > page_title > URL-derived
         // Build a quality suffix from the chosen variant label (e.g. " [720p]")
@@ -911,16 +1259,8 @@ impl DlmanCore {
         };
 
         let out_filename = filename.unwrap_or_else(|| {
-            if let Some(ref title) = page_title {
-                // Sanitize page title: remove illegal filename characters
-                let sanitized: String = title
-                    .chars()
-                    .map(|c| if "/\\:*?\"<>|".contains(c) { '_' } else { c })
-                    .collect();
-                let sanitized = sanitized.trim().to_string();
-                if !sanitized.is_empty() && sanitized.len() <= 200 {
-                    return format!("{}{}.ts", sanitized, quality_suffix);
-                }
+            if let Some(title) = page_title.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+                return crate::filename::sanitize(&format!("{}{}.ts", title, quality_suffix));
             }
             // Fallback: derive a meaningful name from the URL path
             // e.g. https://cdn.example.com/hls/.../720P_4000K_12345.mp4/master.m3u8
@@ -937,7 +1277,8 @@ impl DlmanCore {
                             continue;
                         }
                         // Found a non-manifest segment — use it
-                        let name = seg.to_string();
+                        let name = urlencoding::decode(seg).map(|s| s.into_owned()).unwrap_or_else(|_| seg.to_string());
+                        let name = crate::filename::sanitize(&name);
                         if name.ends_with(".mp4") || name.ends_with(".ts") {
                             return name;
                         }
@@ -955,6 +1296,12 @@ impl DlmanCore {
             out_filename
         };
 
+        // Init section + fMP4 fragments concatenate into a playable MP4 as-is
+        let out_filename = match out_filename.strip_suffix(".ts") {
+            Some(stem) if segment_list.is_fmp4() => format!("{}.mp4", stem),
+            _ => out_filename,
+        };
+
         // 5. Get download destination from settings
         let settings = self.get_settings().await;
         let destination = settings.default_download_path.clone();
@@ -981,6 +1328,14 @@ impl DlmanCore {
                         info!("[HLS] Upgrading bad filename '{}' → '{}'", dl.filename, out_filename);
                         dl.filename = out_filename.clone();
                     }
+                    // Segment files on disk belong to the tracks picked
+                    // before; with a different (or unknown) pick they'd be
+                    // mixed into the wrong rendition
+                    if dl.stream_selection != Some(selection) {
+                        info!("[HLS] Track selection changed for {}; discarding partial segments", dl.id);
+                        let _ = tokio::fs::remove_dir_all(destination.join(format!(".dlman_hls_{}", dl.id))).await;
+                        dl.stream_selection = Some(selection);
+                    }
                     let fname = dl.filename.clone();
                     dl.status = initial_status;
                     dl.error = None;
@@ -1000,6 +1355,8 @@ impl DlmanCore {
                     download.filename = unique_filename.clone();
                     download.status = initial_status;
                     download.cookies = cookies.clone();
+                    download.headers = headers.clone();
+                    download.stream_selection = Some(selection);
                     download.size = None;
                     download.downloaded = 0;
                     self.download_manager.db().upsert_download(&download).await?;
@@ -1020,6 +1377,8 @@ impl DlmanCore {
             download.filename = unique_filename.clone();
             download.status = initial_status;
             download.cookies = cookies.clone();
+            download.headers = headers;
+            download.stream_selection = Some(selection);
             download.size = None;
             download.downloaded = 0;
             self.download_manager.db().upsert_download(&download).await?;
@@ -1039,6 +1398,11 @@ impl DlmanCore {
 
         let download_id = download.id;
         let out_path = destination.join(&unique_filename);
+        // Audio track file, e.g. "Talk [720p].audio.ts"; removed once muxed
+        let audio = audio.map(|(rendition, list)| {
+            let ext = if list.is_fmp4() { "m4a" } else { "ts" };
+            (out_path.with_extension(format!("audio.{}", ext)), rendition, list)
+        });
 
         // 7. Create a cancel token and register the task handle
         let cancel_token = Arc::new(AtomicBool::new(false));
@@ -1048,19 +1412,52 @@ impl DlmanCore {
         let cookies_clone = cookies.clone();
         let referrer_clone = referrer.clone();
         let cancel_for_task = cancel_token.clone();
+        let chosen = chosen.clone();
 
         let join_handle = tokio::spawn(async move {
-            let result = Self::download_hls_segments(
-                &core,
-                download_id,
-                &http_client,
-                &segment_urls,
-                &out_path,
-                cookies_clone.as_deref(),
-                referrer_clone.as_deref(),
-                &cancel_for_task,
-            )
-            .await;
+            let result = if is_live {
+                let mut live_tracks = vec![LiveTrack {
+                    variant: chosen.clone(),
+                    list: segment_list.clone(),
+                    out_path: out_path.clone(),
+                }];
+                if let Some((ref audio_path, ref rendition, ref audio_list)) = audio {
+                    live_tracks.push(LiveTrack {
+                        variant: rendition.clone(),
+                        list: audio_list.clone(),
+                        out_path: audio_path.clone(),
+                    });
+                }
+                Self::record_live_segments(
+                    &core,
+                    download_id,
+                    &http_client,
+                    &detected,
+                    live_tracks,
+                    cookies_clone.as_deref(),
+                    referrer_clone.as_deref(),
+                    &request_headers,
+                    &cancel_for_task,
+                    &recording,
+                )
+                .await
+            } else {
+                let mut tracks = vec![(&segment_list, out_path.as_path())];
+                if let Some((ref audio_path, _, ref audio_list)) = audio {
+                    tracks.push((audio_list, audio_path.as_path()));
+                }
+                Self::download_hls_segments(
+                    &core,
+                    download_id,
+                    &http_client,
+                    &tracks,
+                    cookies_clone.as_deref(),
+                    referrer_clone.as_deref(),
+                    &request_headers,
+                    &cancel_for_task,
+                )
+                .await
+            };
 
             // Task is done — remove ourselves from hls_tasks
             core.hls_tasks.write().await.remove(&download_id);
@@ -1071,18 +1468,41 @@ impl DlmanCore {
                         "HLS download complete: {} ({} bytes, {} segments)",
                         out_path.display(),
                         total_bytes,
-                        segment_urls.len()
+                        segment_list.segments.len()
                     );
 
-                    // Try to remux .ts → .mp4 using ffmpeg (lossless, fast)
-                    let final_path = Self::try_remux_to_mp4(&out_path).await
+                    // Remux to a plain .mp4, muxing in the separate audio
+                    // track if any (lossless, no re-encoding). fMP4 video
+                    // alone plays as downloaded, but remuxed it gets a sample
+                    // index, so players know its duration and seek at once.
+                    let audio_path = audio.as_ref().map(|(path, _, _)| path.as_path());
+                    let ffmpeg_fallback = core.get_settings().await.ffmpeg_remux_fallback;
+                    let final_path = Self::try_remux_to_mp4(&out_path, audio_path, ffmpeg_fallback)
+                        .await
                         .unwrap_or_else(|| out_path.clone());
+                    // Remuxing changes the size slightly; record what's on disk
+                    let total_bytes = tokio::fs::metadata(&final_path)
+                        .await
+                        .map(|m| m.len())
+                        .unwrap_or(total_bytes);
                     let final_filename = final_path
                         .file_name()
                         .and_then(|n| n.to_str())
                         .unwrap_or(&unique_filename)
                         .to_string();
 
+                    // Subtitle sidecars next to the final file, e.g. "Talk [720p].en.vtt"
+                    Self::download_subtitles(
+                        &http_client,
+                        &core.download_manager.host_limiter(),
+                        &subtitles,
+                        &final_path,
+                        cookies_clone.as_deref(),
+                        referrer_clone.as_deref(),
+                        &request_headers,
+                    )
+                    .await;
+
                     // Update download record as completed
                     if let Ok(Some(mut dl)) =
                         core.download_manager.db().load_download(download_id).await
@@ -1138,6 +1558,7 @@ impl DlmanCore {
         self.hls_tasks.write().await.insert(download_id, HlsTaskHandle {
             cancel: cancel_token,
             abort_handle: join_handle.abort_handle(),
+            recording: is_live,
         });
 
         Ok(download)
@@ -1152,14 +1573,20 @@ impl DlmanCore {
     /// - When pause/cancel calls `abort_handle.abort()`, all in-flight HTTP
     ///   requests are instantly dropped (including bytes().await).
     /// - Already-downloaded segments in temp_dir are kept for resume.
+    /// - AES-128 encrypted segments are decrypted before being written, so
+    ///   temp files (and the merge phase) only ever see plaintext.
+    /// - Each `(segment list, output path)` in `tracks` (e.g. video plus an
+    ///   alternate audio rendition) is merged into its own file. Segments of
+    ///   all tracks share the concurrency limit and are interleaved by
+    ///   playback position, so the tracks progress together.
     async fn download_hls_segments(
         core: &DlmanCore,
         download_id: Uuid,
         client: &reqwest::Client,
-        segment_urls: &[String],
-        out_path: &std::path::Path,
+        tracks: &[(&crate::media::SegmentList, &std::path::Path)],
         cookies: Option<&str>,
         referrer: Option<&str>,
+        headers: &RequestHeaders,
         cancel_token: &AtomicBool,
     ) -> Result<u64, DlmanError> {
         use std::sync::atomic::AtomicU64;
@@ -1170,12 +1597,52 @@ impl DlmanCore {
         const MAX_CONCURRENT: usize = 8;
         const MAX_RETRIES: usize = 3;
 
-        let total_segments = segment_urls.len();
+        let total_segments: usize = tracks.iter().map(|(list, _)| list.segments.len()).sum();
         let start = Instant::now();
+        let out_path = tracks[0].1;
+
+        // Temp file names; the first track keeps the historical names so
+        // segments from earlier single-track attempts are still resumed
+        let seg_name = |track: usize, i: usize| match track {
+            0 => format!("seg_{:06}.ts", i),
+            t => format!("t{}_seg_{:06}.ts", t, i),
+        };
+        let init_name = |track: usize| match track {
+            0 => "init.mp4".to_string(),
+            t => format!("t{}_init.mp4", t),
+        };
+
+        // Every request takes a connection slot like a regular download's
+        // segments, so a stream can't flood its host
+        let host_limiter = core.download_manager.host_limiter();
+
+        // Fetch each distinct key once, with the same cookie/referrer headers
+        // as the segments (key servers usually check the session too)
+        let key_handler = crate::media::hls::HlsHandler::new(client.clone())
+            .with_headers(headers.clone())
+            .with_host_limiter(Some(host_limiter.clone()));
+        let mut keys: HashMap<String, [u8; 16]> = HashMap::new();
+        let mut key_uris: Vec<&str> = Vec::new();
+        for (list, _) in tracks {
+            for segment in list.init.iter().chain(&list.segments) {
+                if let Some(ref key) = segment.key {
+                    if !key_uris.contains(&key.uri.as_str()) {
+                        key_uris.push(&key.uri);
+                    }
+                }
+            }
+        }
+        for uri in key_uris {
+            let bytes = key_handler.fetch_key(uri, &Self::stream_headers(cookies, referrer)).await?;
+            keys.insert(uri.to_string(), bytes);
+        }
+        if !keys.is_empty() {
+            info!("[HLS] Stream is encrypted ({} key(s))", keys.len());
+        }
 
         info!(
-            "[HLS] Starting segment download: {} segments, {} parallel, to {}",
-            total_segments, MAX_CONCURRENT, out_path.display()
+            "[HLS] Starting segment download: {} segments in {} track(s), {} parallel, to {}",
+            total_segments, tracks.len(), MAX_CONCURRENT, out_path.display()
         );
 
         // Create temp directory for individual segment files
@@ -1183,6 +1650,31 @@ impl DlmanCore {
             .join(format!(".dlman_hls_{}", download_id));
         tokio::fs::create_dir_all(&temp_dir).await?;
 
+        // fMP4/CMAF: fetch the init sections first; they are prepended on merge
+        for (t, (list, _)) in tracks.iter().enumerate() {
+            let init_path = temp_dir.join(init_name(t));
+            if let Some(ref init) = list.init {
+                if !init_path.exists() {
+                    let key = init.key.as_ref().map(|k| keys[&k.uri]);
+                    let bytes =
+                        Self::fetch_media_segment(client, &host_limiter, init, key, cookies, referrer, headers).await?;
+                    tokio::fs::write(&init_path, &bytes).await?;
+                    info!("[HLS] Init section downloaded ({} bytes)", bytes.len());
+                }
+            }
+        }
+
+        // Interleave the tracks by playback position: job (t, i) sorts by
+        // i / len(t), compared by cross-multiplying
+        let mut jobs: Vec<(usize, usize, &crate::media::MediaSegment)> = Vec::with_capacity(total_segments);
+        for (t, (list, _)) in tracks.iter().enumerate() {
+            for (i, segment) in list.segments.iter().enumerate() {
+                jobs.push((t, i, segment));
+            }
+        }
+        let track_len = |t: usize| tracks[t].0.segments.len();
+        jobs.sort_by(|a, b| (a.1 * track_len(b.0)).cmp(&(b.1 * track_len(a.0))));
+
         // Shared progress counters
         let downloaded_bytes = Arc::new(AtomicU64::new(0));
         let completed_segments = Arc::new(AtomicU64::new(0));
@@ -1195,7 +1687,7 @@ impl DlmanCore {
         // Feed segments into the JoinSet. The semaphore limits how many run at once.
         // We acquire the permit HERE (on the feeder) so that we block before spawning
         // more tasks than MAX_CONCURRENT, and we check cancel between each spawn.
-        for (i, seg_url) in segment_urls.iter().enumerate() {
+        for (i, &(track, index, segment)) in jobs.iter().enumerate() {
             // Check cancel BEFORE acquiring permit / spawning
             if cancel_token.load(Ordering::Acquire) {
                 info!("[HLS] Cancel detected before segment {}, aborting remaining", i + 1);
@@ -1204,7 +1696,7 @@ impl DlmanCore {
             }
 
             // Skip segments already downloaded in a previous attempt
-            let seg_path = temp_dir.join(format!("seg_{:06}.ts", i));
+            let seg_path = temp_dir.join(seg_name(track, index));
             if seg_path.exists() {
                 // Count the existing file towards progress
                 if let Ok(meta) = tokio::fs::metadata(&seg_path).await {
@@ -1226,13 +1718,16 @@ impl DlmanCore {
             })?;
 
             let client = client.clone();
-            let seg_url = seg_url.clone();
+            let host_limiter = host_limiter.clone();
+            let segment = segment.clone();
+            let key = segment.key.as_ref().map(|k| keys[&k.uri]);
             let seg_path_clone = seg_path.clone();
             let bytes_counter = downloaded_bytes.clone();
             let seg_counter = completed_segments.clone();
             let core_clone = core.clone();
             let cookies_owned = cookies.map(|s| s.to_string());
             let referrer_owned = referrer.map(|s| s.to_string());
+            let headers = headers.clone();
             let failed_flag = failed.clone();
 
             join_set.spawn(async move {
@@ -1240,61 +1735,61 @@ impl DlmanCore {
 
                 let mut last_err = None;
                 for attempt in 0..MAX_RETRIES {
-                    let mut req = client.get(&seg_url);
-                    if let Some(ref c) = cookies_owned {
-                        req = req.header("Cookie", c.as_str());
-                    }
-                    if let Some(ref r) = referrer_owned {
-                        req = req.header("Referer", r.as_str());
-                    }
+                    match Self::fetch_media_segment(
+                        &client,
+                        &host_limiter,
+                        &segment,
+                        key,
+                        cookies_owned.as_deref(),
+                        referrer_owned.as_deref(),
+                        &headers,
+                    )
+                    .await
+                    {
+                        Ok(bytes) => {
+                            let len = bytes.len() as u64;
+                            if let Err(e) = tokio::fs::write(&seg_path_clone, &bytes).await {
+                                last_err = Some(format!("write error: {}", e));
+                                continue;
+                            }
 
-                    match req.send().await {
-                        Ok(resp) if resp.status().is_success() => {
-                            match resp.bytes().await {
-                                Ok(bytes) => {
-                                    let len = bytes.len() as u64;
-                                    if let Err(e) = tokio::fs::write(&seg_path_clone, &bytes).await {
-                                        last_err = Some(format!("write error: {}", e));
-                                        continue;
-                                    }
-
-                                    // Update progress
-                                    let prev = bytes_counter.fetch_add(len, Ordering::Relaxed);
-                                    let done = seg_counter.fetch_add(1, Ordering::Relaxed) + 1;
-                                    let total_dl = prev + len;
-                                    let elapsed = start.elapsed().as_secs_f64().max(0.01);
-                                    let speed = (total_dl as f64 / elapsed) as u64;
-                                    let avg_size = total_dl / done;
-                                    let est_total = avg_size * total_segments as u64;
-                                    let remaining = est_total.saturating_sub(total_dl);
-                                    let eta = if speed > 0 { Some(remaining / speed) } else { None };
-
-                                    core_clone.emit(CoreEvent::DownloadProgress {
-                                        id: download_id,
-                                        downloaded: total_dl,
-                                        total: Some(est_total),
-                                        speed,
-                                        eta,
-                                    });
-
-                                    if i < 3 || (i + 1) % 50 == 0 || i == total_segments - 1 {
-                                        info!(
-                                            "[HLS] Segment {}/{} done ({} bytes, {:.1} MB/s)",
-                                            i + 1, total_segments, len,
-                                            speed as f64 / 1_048_576.0
-                                        );
-                                    }
-
-                                    return Ok((i, len));
-                                }
-                                Err(e) => { last_err = Some(format!("body error: {}", e)); }
+                            // Update progress
+                            let prev = bytes_counter.fetch_add(len, Ordering::Relaxed);
+                            let done = seg_counter.fetch_add(1, Ordering::Relaxed) + 1;
+                            let total_dl = prev + len;
+                            let elapsed = start.elapsed().as_secs_f64().max(0.01);
+                            let speed = (total_dl as f64 / elapsed) as u64;
+                            let avg_size = total_dl / done;
+                            let est_total = avg_size * total_segments as u64;
+                            let remaining = est_total.saturating_sub(total_dl);
+                            let eta = if speed > 0 { Some(remaining / speed) } else { None };
+
+                            core_clone.emit(CoreEvent::DownloadProgress {
+                                id: download_id,
+                                downloaded: total_dl,
+                                total: Some(est_total),
+                                speed,
+                                eta,
+                            });
+
+                            if i < 3 || (i + 1) % 50 == 0 || i == total_segments - 1 {
+                                info!(
+                                    "[HLS] Segment {}/{} done ({} bytes, {:.1} MB/s)",
+                                    i + 1, total_segments, len,
+                                    speed as f64 / 1_048_576.0
+                                );
                             }
+
+                            return Ok((i, len));
                         }
-                        Ok(resp) => {
-                            let status = resp.status().as_u16();
-                            last_err = Some(format!("HTTP {} for segment {}/{}", status, i + 1, total_segments));
+                        // A bad key or a malformed response won't get better on retry
+                        Err(e @ DlmanError::InvalidOperation(_)) => {
+                            failed_flag.store(true, Ordering::Release);
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            last_err = Some(format!("segment {}/{}: {}", i + 1, total_segments, e));
                         }
-                        Err(e) => { last_err = Some(format!("network error: {}", e)); }
                     }
 
                     // Backoff before retry
@@ -1345,12 +1840,12 @@ impl DlmanCore {
         }
 
         // Verify all segments exist
-        for i in 0..total_segments {
-            let seg_path = temp_dir.join(format!("seg_{:06}.ts", i));
+        for &(track, index, _) in &jobs {
+            let seg_path = temp_dir.join(seg_name(track, index));
             if !seg_path.exists() {
                 let _ = tokio::fs::remove_dir_all(&temp_dir).await;
                 return Err(DlmanError::InvalidOperation(format!(
-                    "Missing segment {} after download", i + 1
+                    "Missing segment {} after download", index + 1
                 )));
             }
         }
@@ -1358,26 +1853,33 @@ impl DlmanCore {
         // ========== Merge phase: concatenate temp segments in order ==========
         info!("[HLS] All {} segments downloaded, merging...", total_segments);
 
-        let mut out_file = tokio::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(out_path)
-            .await?;
-
         let mut total_bytes: u64 = 0;
-        for i in 0..total_segments {
-            let seg_path = temp_dir.join(format!("seg_{:06}.ts", i));
-            let data = tokio::fs::read(&seg_path).await.map_err(|e| {
-                DlmanError::Io(std::io::Error::new(
-                    e.kind(),
-                    format!("Failed to read segment {}: {}", i, e),
-                ))
-            })?;
-            out_file.write_all(&data).await?;
-            total_bytes += data.len() as u64;
+        for (t, (list, track_path)) in tracks.iter().enumerate() {
+            let mut out_file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(track_path)
+                .await?;
+
+            if list.init.is_some() {
+                let data = tokio::fs::read(temp_dir.join(init_name(t))).await?;
+                out_file.write_all(&data).await?;
+                total_bytes += data.len() as u64;
+            }
+            for i in 0..list.segments.len() {
+                let seg_path = temp_dir.join(seg_name(t, i));
+                let data = tokio::fs::read(&seg_path).await.map_err(|e| {
+                    DlmanError::Io(std::io::Error::new(
+                        e.kind(),
+                        format!("Failed to read segment {}: {}", i, e),
+                    ))
+                })?;
+                out_file.write_all(&data).await?;
+                total_bytes += data.len() as u64;
+            }
+            out_file.flush().await?;
         }
-        out_file.flush().await?;
 
         // Clean up temp directory (segments successfully merged)
         let _ = tokio::fs::remove_dir_all(&temp_dir).await;
@@ -1391,38 +1893,507 @@ impl DlmanCore {
         Ok(total_bytes)
     }
 
-    /// Try to remux a .ts file to .mp4 using ffmpeg (lossless copy, fast).
-    /// Returns the .mp4 path on success, or None if ffmpeg is unavailable.
-    async fn try_remux_to_mp4(ts_path: &std::path::Path) -> Option<std::path::PathBuf> {
-        let mp4_path = ts_path.with_extension("mp4");
-        let ts = ts_path.to_path_buf();
-        let mp4 = mp4_path.clone();
+    /// Internal: record a live stream by re-polling its manifest.
+    ///
+    /// Segments are appended to each track's output file as they appear
+    /// (there is nothing to resume for a live stream, so no per-segment temp
+    /// files). Recording starts near the live edge and stops when the stream
+    /// ends, when `stop` is set, when a `limits` cap is reached, or when the
+    /// manifest stops producing new segments. Individual segments that keep
+    /// failing are skipped — a short gap beats losing the whole recording.
+    #[allow(clippy::too_many_arguments)]
+    async fn record_live_segments(
+        core: &DlmanCore,
+        download_id: Uuid,
+        client: &reqwest::Client,
+        detected: &dlman_types::DetectedMedia,
+        tracks: Vec<LiveTrack>,
+        cookies: Option<&str>,
+        referrer: Option<&str>,
+        headers: &RequestHeaders,
+        stop: &AtomicBool,
+        limits: &RecordingOptions,
+    ) -> Result<u64, DlmanError> {
+        use crate::media::{ByteRange, MediaResolver, MediaSegment};
+        use std::collections::HashSet;
+        use std::time::{Duration, Instant};
+        use tokio::io::AsyncWriteExt;
+
+        /// Segments kept from the first manifest (start near the live edge)
+        const LIVE_EDGE_SEGMENTS: usize = 3;
+        const MAX_RETRIES: usize = 3;
+        /// Consecutive failed manifest refreshes before giving up
+        const MAX_REFRESH_FAILURES: u32 = 5;
+        /// Consecutive refreshes without new segments before assuming the
+        /// stream ended without an EXT-X-ENDLIST
+        const MAX_IDLE_REFRESHES: u32 = 6;
+
+        /// Per-track recording state
+        struct Recorder {
+            track: LiveTrack,
+            out_file: tokio::fs::File,
+            seen: HashSet<(String, Option<ByteRange>)>,
+            wrote_init: bool,
+        }
+
+        let host_limiter = core.download_manager.host_limiter();
+        let resolver = MediaResolver::new(client.clone())
+            .with_headers(headers.clone())
+            .with_host_limiter(host_limiter.clone());
+        let key_handler = crate::media::hls::HlsHandler::new(client.clone())
+            .with_headers(headers.clone())
+            .with_host_limiter(Some(host_limiter.clone()));
+        let mut keys: HashMap<String, [u8; 16]> = HashMap::new();
+
+        let start = Instant::now();
+        let limit_reached = |bytes: u64| {
+            limits.max_bytes.is_some_and(|max| bytes >= max)
+                || limits
+                    .max_duration_secs
+                    .is_some_and(|max| start.elapsed().as_secs() >= max)
+        };
+
+        let mut recorders = Vec::with_capacity(tracks.len());
+        for track in tracks {
+            info!(
+                "[LIVE] Recording {} to {} (limits: {:?})",
+                track.variant.label,
+                track.out_path.display(),
+                limits
+            );
+            let out_file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&track.out_path)
+                .await?;
+            let skip = track.list.segments.len().saturating_sub(LIVE_EDGE_SEGMENTS);
+            let seen = track.list.segments[..skip]
+                .iter()
+                .map(|s| (s.url.clone(), s.byte_range))
+                .collect();
+            recorders.push(Recorder { track, out_file, seen, wrote_init: false });
+        }
+
+        let mut total_bytes: u64 = 0;
+        let mut recorded_segments: u64 = 0;
+        let mut refresh_failures = 0;
+        let mut idle_refreshes = 0;
+
+        'record: loop {
+            let mut new_segments = 0;
+            for rec in recorders.iter_mut() {
+                if !rec.wrote_init {
+                    if let Some(ref init) = rec.track.list.init {
+                        let bytes = Self::fetch_live_segment(
+                            client, &host_limiter, &key_handler, &mut keys, init, cookies, referrer, headers,
+                        )
+                        .await?;
+                        rec.out_file.write_all(&bytes).await?;
+                        total_bytes += bytes.len() as u64;
+                    }
+                    rec.wrote_init = true;
+                }
+
+                for segment in &rec.track.list.segments {
+                    if stop.load(Ordering::Acquire) || limit_reached(total_bytes) {
+                        break 'record;
+                    }
+                    if !rec.seen.insert((segment.url.clone(), segment.byte_range)) {
+                        continue;
+                    }
+                    new_segments += 1;
+
+                    let mut fetched: Option<Vec<u8>> = None;
+                    for attempt in 0..MAX_RETRIES {
+                        match Self::fetch_live_segment(
+                            client, &host_limiter, &key_handler, &mut keys, segment, cookies, referrer, headers,
+                        )
+                        .await
+                        {
+                            Ok(bytes) => {
+                                fetched = Some(bytes);
+                                break;
+                            }
+                            Err(e) => {
+                                tracing::warn!(
+                                    "[LIVE] Segment {} attempt {} failed: {}",
+                                    segment.url, attempt + 1, e
+                                );
+                                tokio::time::sleep(Duration::from_millis(500 * (1 << attempt))).await;
+                            }
+                        }
+                    }
+                    let Some(bytes) = fetched else {
+                        tracing::warn!("[LIVE] Skipping segment {} after {} attempts", segment.url, MAX_RETRIES);
+                        continue;
+                    };
+
+                    rec.out_file.write_all(&bytes).await?;
+                    total_bytes += bytes.len() as u64;
+                    recorded_segments += 1;
+
+                    let elapsed = start.elapsed();
+                    core.emit(CoreEvent::DownloadProgress {
+                        id: download_id,
+                        downloaded: total_bytes,
+                        total: None,
+                        speed: (total_bytes as f64 / elapsed.as_secs_f64().max(0.01)) as u64,
+                        eta: None,
+                    });
+                    core.emit(CoreEvent::RecordingProgress {
+                        id: download_id,
+                        elapsed_secs: elapsed.as_secs(),
+                        recorded: total_bytes,
+                        segments: recorded_segments,
+                    });
+                }
+            }
+
+            // EXT-X-ENDLIST / static MPD on every track: the stream is over
+            let Some(refresh_interval) = recorders
+                .iter()
+                .filter_map(|rec| rec.track.list.live)
+                .map(|live| live.refresh_interval)
+                .reduce(f64::min)
+            else {
+                info!("[LIVE] Stream ended");
+                break;
+            };
+
+            idle_refreshes = if new_segments == 0 { idle_refreshes + 1 } else { 0 };
+            if idle_refreshes >= MAX_IDLE_REFRESHES {
+                info!("[LIVE] No new segments for {} refreshes, assuming the stream ended", idle_refreshes);
+                break;
+            }
+
+            // Wait for the next refresh, staying responsive to stop requests
+            let wake_at = Instant::now() + Duration::from_secs_f64(refresh_interval);
+            while Instant::now() < wake_at {
+                if stop.load(Ordering::Acquire) || limit_reached(total_bytes) {
+                    break 'record;
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+
+            let mut refresh_error = None;
+            for rec in recorders.iter_mut().filter(|rec| rec.track.list.live.is_some()) {
+                match resolver.get_segments(detected, &rec.track.variant).await {
+                    Ok(refreshed) => rec.track.list = refreshed,
+                    Err(e) => {
+                        tracing::warn!("[LIVE] Manifest refresh failed ({}), retrying", e);
+                        // Retry the same (already seen) list after the next wait
+                        let seen = &rec.seen;
+                        rec.track
+                            .list
+                            .segments
+                            .retain(|s: &MediaSegment| seen.contains(&(s.url.clone(), s.byte_range)));
+                        refresh_error = Some(e);
+                    }
+                }
+            }
+            match refresh_error {
+                Some(e) => {
+                    refresh_failures += 1;
+                    if refresh_failures >= MAX_REFRESH_FAILURES {
+                        return Err(e);
+                    }
+                }
+                None => refresh_failures = 0,
+            }
+        }
+
+        for rec in recorders.iter_mut() {
+            rec.out_file.flush().await?;
+        }
+        info!(
+            "[LIVE] Recording finished: {} bytes, {} segments, {:.0}s",
+            total_bytes,
+            recorded_segments,
+            start.elapsed().as_secs_f64()
+        );
+        Ok(total_bytes)
+    }
+
+    /// Fetch one live segment, fetching (and caching) its key on first use.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_live_segment(
+        client: &reqwest::Client,
+        host_limiter: &HostLimiter,
+        key_handler: &crate::media::hls::HlsHandler,
+        keys: &mut HashMap<String, [u8; 16]>,
+        segment: &crate::media::MediaSegment,
+        cookies: Option<&str>,
+        referrer: Option<&str>,
+        headers: &RequestHeaders,
+    ) -> Result<Vec<u8>, DlmanError> {
+        let key = match segment.key {
+            Some(ref k) => match keys.get(&k.uri) {
+                Some(key) => Some(*key),
+                None => {
+                    let key = key_handler.fetch_key(&k.uri, &Self::stream_headers(cookies, referrer)).await?;
+                    keys.insert(k.uri.clone(), key);
+                    Some(key)
+                }
+            },
+            None => None,
+        };
+        Self::fetch_media_segment(client, host_limiter, segment, key, cookies, referrer, headers).await
+    }
 
-        let result = tokio::process::Command::new("ffmpeg")
-            .args(["-y", "-i"])
-            .arg(&ts)
+    /// Cookie/Referer headers for a key request. The key handler adds those
+    /// of the header rules for the key's URL.
+    fn stream_headers(cookies: Option<&str>, referrer: Option<&str>) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(c) = cookies {
+            headers.push(("Cookie".to_string(), c.to_string()));
+        }
+        if let Some(r) = referrer {
+            headers.push(("Referer".to_string(), r.to_string()));
+        }
+        headers
+    }
+
+    /// Fetch one media segment (or init section), honoring its byte range and
+    /// decrypting it if a key is given. Makes a single attempt; retries are
+    /// up to the caller.
+    async fn fetch_media_segment(
+        client: &reqwest::Client,
+        host_limiter: &HostLimiter,
+        segment: &crate::media::MediaSegment,
+        key: Option<[u8; 16]>,
+        cookies: Option<&str>,
+        referrer: Option<&str>,
+        headers: &RequestHeaders,
+    ) -> Result<Vec<u8>, DlmanError> {
+        let slot = host_limiter.acquire(&segment.url).await;
+        let mut req = client.get(&segment.url);
+        if let Some(c) = cookies {
+            req = req.header("Cookie", c);
+        }
+        if let Some(r) = referrer {
+            req = req.header("Referer", r);
+        }
+        if let Some(range) = segment.byte_range {
+            req = req.header("Range", range.header_value());
+        }
+
+        let resp = headers.send(req, &segment.url).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(DlmanError::ServerError {
+                status: status.as_u16(),
+                message: format!("Failed to fetch segment {}", segment.url),
+            });
+        }
+        let body = resp.bytes().await?;
+        if let Some(limiter) = slot.rate_limiter() {
+            limiter.acquire(body.len() as u64).await;
+        }
+        drop(slot);
+
+        let mut bytes = match segment.byte_range {
+            // Server ignored the Range header and sent the whole resource
+            Some(range) if status != reqwest::StatusCode::PARTIAL_CONTENT => {
+                let end = range.offset.saturating_add(range.length) as usize;
+                body.get(range.offset as usize..end)
+                    .ok_or_else(|| {
+                        DlmanError::InvalidOperation(format!(
+                            "Segment {} is shorter than its byte range",
+                            segment.url
+                        ))
+                    })?
+                    .to_vec()
+            }
+            _ => body.to_vec(),
+        };
+
+        if let (Some(key), Some(seg_key)) = (key, segment.key.as_ref()) {
+            match seg_key.method {
+                crate::media::KeyMethod::Aes128 => {
+                    crate::media::hls::decrypt_aes128(&mut bytes, &key, &seg_key.iv)?
+                }
+                crate::media::KeyMethod::SampleAes => {
+                    bytes = crate::media::sample_aes::decrypt_segment(&bytes, &key, &seg_key.iv)?
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Fetch each subtitle rendition and stitch its segments into a sidecar
+    /// file next to `video_path`, named after its language. Failures only
+    /// skip that track: the video itself is already complete.
+    async fn download_subtitles(
+        client: &reqwest::Client,
+        host_limiter: &HostLimiter,
+        tracks: &[(dlman_types::MediaRendition, crate::media::SegmentList)],
+        video_path: &std::path::Path,
+        cookies: Option<&str>,
+        referrer: Option<&str>,
+        headers: &RequestHeaders,
+    ) {
+        let mut written: Vec<PathBuf> = Vec::new();
+        for (rendition, list) in tracks {
+            let stitcher = match Self::stitch_subtitles(client, host_limiter, list, cookies, referrer, headers).await {
+                Ok(stitcher) if stitcher.is_empty() => {
+                    info!("[Subtitles] '{}' has no cues; skipping", rendition.name);
+                    continue;
+                }
+                Ok(stitcher) => stitcher,
+                Err(e) => {
+                    tracing::warn!("[Subtitles] Failed to download '{}': {}", rendition.name, e);
+                    continue;
+                }
+            };
+            let extension = stitcher
+                .format()
+                .map_or("vtt", crate::media::subtitles::SubtitleFormat::extension);
+            let path = crate::media::subtitles::sidecar_path(
+                video_path,
+                rendition.language.as_deref(),
+                extension,
+                &written,
+            );
+            match tokio::fs::write(&path, stitcher.render()).await {
+                Ok(()) => {
+                    info!("[Subtitles] Saved '{}' to {}", rendition.name, path.display());
+                    written.push(path);
+                }
+                Err(e) => tracing::warn!("[Subtitles] Failed to write {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Download a subtitle rendition's segments in order and stitch them.
+    async fn stitch_subtitles(
+        client: &reqwest::Client,
+        host_limiter: &HostLimiter,
+        list: &crate::media::SegmentList,
+        cookies: Option<&str>,
+        referrer: Option<&str>,
+        headers: &RequestHeaders,
+    ) -> Result<crate::media::subtitles::SubtitleStitcher, DlmanError> {
+        const MAX_RETRIES: usize = 3;
+
+        if list.is_fmp4() {
+            return Err(DlmanError::InvalidOperation(
+                "Subtitles packaged in fragmented MP4 are not supported".to_string(),
+            ));
+        }
+        let mut stitcher = crate::media::subtitles::SubtitleStitcher::new();
+        for segment in &list.segments {
+            let mut attempt = 0;
+            let bytes = loop {
+                match Self::fetch_media_segment(client, host_limiter, segment, None, cookies, referrer, headers).await {
+                    Ok(bytes) => break bytes,
+                    Err(e) if attempt + 1 >= MAX_RETRIES => return Err(e),
+                    Err(_) => {
+                        tokio::time::sleep(std::time::Duration::from_millis(500 * (1 << attempt))).await;
+                        attempt += 1;
+                    }
+                }
+            };
+            stitcher.push_segment(&bytes)?;
+        }
+        Ok(stitcher)
+    }
+
+    /// Remux a downloaded stream to .mp4 (lossless, no re-encoding), muxing
+    /// in a separately downloaded audio track when one is given.
+    ///
+    /// Uses the built-in remuxer; with `ffmpeg_fallback` set, streams it
+    /// can't handle are retried with an external ffmpeg. Returns the .mp4
+    /// path on success, or None if remuxing failed (the inputs are then left
+    /// as they are).
+    async fn try_remux_to_mp4(
+        video_path: &std::path::Path,
+        audio_path: Option<&std::path::Path>,
+        ffmpeg_fallback: bool,
+    ) -> Option<std::path::PathBuf> {
+        let mp4_path = video_path.with_extension("mp4");
+        // An .mp4 video (fMP4 segments) can't be the input and output at once
+        let out_path = if mp4_path == video_path {
+            video_path.with_extension("muxing.mp4")
+        } else {
+            mp4_path.clone()
+        };
+
+        let inputs: Vec<std::path::PathBuf> = std::iter::once(video_path)
+            .chain(audio_path)
+            .map(std::path::Path::to_path_buf)
+            .collect();
+        let output = out_path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let inputs: Vec<&std::path::Path> = inputs.iter().map(|p| p.as_path()).collect();
+            crate::media::remux::remux_to_mp4(&inputs, &output)
+        })
+        .await
+        .unwrap_or_else(|e| Err(DlmanError::Unknown(e.to_string())));
+
+        let remuxed = match result {
+            Ok(()) => true,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&out_path).await;
+                if ffmpeg_fallback {
+                    tracing::warn!("Built-in remux failed ({}), trying ffmpeg", e);
+                    Self::ffmpeg_remux(video_path, audio_path, &out_path).await
+                } else {
+                    tracing::warn!("Remux failed ({}), keeping original files", e);
+                    false
+                }
+            }
+        };
+        if !remuxed {
+            return None;
+        }
+
+        info!("Remuxed to MP4: {}", mp4_path.display());
+        // Delete the source files
+        if let Some(audio) = audio_path {
+            let _ = tokio::fs::remove_file(audio).await;
+        }
+        if out_path != mp4_path {
+            if let Err(e) = tokio::fs::rename(&out_path, &mp4_path).await {
+                tracing::warn!("Failed to replace {} with muxed output: {}", mp4_path.display(), e);
+                return None;
+            }
+        } else {
+            let _ = tokio::fs::remove_file(video_path).await;
+        }
+        Some(mp4_path)
+    }
+
+    /// Remux with an external ffmpeg (stream copy). Returns whether `out_path`
+    /// was written.
+    async fn ffmpeg_remux(
+        video_path: &std::path::Path,
+        audio_path: Option<&std::path::Path>,
+        out_path: &std::path::Path,
+    ) -> bool {
+        let mut command = tokio::process::Command::new("ffmpeg");
+        command.args(["-y", "-i"]).arg(video_path);
+        if let Some(audio) = audio_path {
+            command.arg("-i").arg(audio).args(["-map", "0:v", "-map", "1:a"]);
+        }
+        let result = command
             .args(["-c", "copy", "-movflags", "+faststart"])
-            .arg(&mp4)
+            .arg(out_path)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null())
             .status()
             .await;
 
         match result {
-            Ok(status) if status.success() => {
-                info!("Remuxed to MP4: {}", mp4.display());
-                // Delete the .ts source file
-                let _ = tokio::fs::remove_file(&ts).await;
-                Some(mp4_path)
-            }
+            Ok(status) if status.success() => true,
             Ok(status) => {
-                tracing::warn!("ffmpeg remux failed with exit code {:?}, keeping .ts", status.code());
-                None
+                tracing::warn!("ffmpeg remux failed with exit code {:?}, keeping original files", status.code());
+                let _ = tokio::fs::remove_file(out_path).await;
+                false
             }
             Err(e) => {
-                // ffmpeg not found or failed to execute — that's fine, keep .ts
-                tracing::debug!("ffmpeg not available ({}), keeping .ts file", e);
-                None
+                tracing::warn!("ffmpeg not available ({}), keeping original files", e);
+                false
             }
         }
     }
@@ -1495,3 +2466,88 @@ impl std::fmt::Debug for DlmanCore {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{range_server, ServerOpts, TestServer};
+
+    /// A server that only serves `alice:secret` over Basic auth
+    async fn serve_alice() -> TestServer {
+        range_server(
+            b"for alice only".to_vec(),
+            ServerOpts { require_header: Some("authorization: basic ywxpy2u6c2vjcmv0"), ..Default::default() },
+        )
+        .await
+    }
+
+    /// Poll until the download reaches `status`
+    async fn wait_for(core: &DlmanCore, id: Uuid, status: DownloadStatus) -> Download {
+        for _ in 0..200 {
+            let download = core.get_download(id).await.unwrap();
+            if download.status == status {
+                return download;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+        }
+        panic!("download never became {:?}", status);
+    }
+
+    /// Start a download from `server` and wait until it asks for a login
+    async fn start_rejected(core: &DlmanCore, server: &TestServer, destination: PathBuf) -> Download {
+        let download = core
+            .add_download(&server.url, destination, Uuid::nil(), None, AddDownloadOptions::default(), true)
+            .await
+            .unwrap();
+        wait_for(core, download.id, DownloadStatus::AwaitingAuth).await
+    }
+
+    #[tokio::test]
+    async fn supplied_credentials_are_kept_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let core = DlmanCore::new(dir.path().join("data")).await.unwrap();
+        let server = serve_alice().await;
+        let download = start_rejected(&core, &server, dir.path().join("out")).await;
+
+        core.provide_credentials(download.id, "alice".to_string(), "secret".to_string(), false).await.unwrap();
+        let done = wait_for(&core, download.id, DownloadStatus::Completed).await;
+
+        assert_eq!(std::fs::read(done.destination.join(&done.filename)).unwrap(), b"for alice only");
+        assert!(core.get_all_credentials().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remembered_credentials_replace_rejected_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let core = DlmanCore::new(dir.path().join("data")).await.unwrap();
+        let saved = core
+            .upsert_credential(SiteCredential::new("127.0.0.1".to_string(), "alice".to_string(), "wrong".to_string()))
+            .await
+            .unwrap();
+        let server = serve_alice().await;
+        let download = start_rejected(&core, &server, dir.path().join("out")).await;
+
+        core.provide_credentials(download.id, "alice".to_string(), "secret".to_string(), true).await.unwrap();
+        wait_for(&core, download.id, DownloadStatus::Completed).await;
+
+        let credentials = core.get_all_credentials().await.unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].id, saved.id);
+        assert_eq!(credentials[0].password, "secret");
+    }
+
+    #[tokio::test]
+    async fn credentials_are_only_taken_while_awaiting_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let core = DlmanCore::new(dir.path().join("data")).await.unwrap();
+        let server = range_server(b"public".to_vec(), ServerOpts::default()).await;
+        let download = core
+            .add_download(&server.url, dir.path().join("out"), Uuid::nil(), None, AddDownloadOptions::default(), false)
+            .await
+            .unwrap();
+
+        let result = core.provide_credentials(download.id, "alice".to_string(), "secret".to_string(), true).await;
+        assert!(matches!(result, Err(DlmanError::InvalidOperation(_))));
+        assert!(core.get_all_credentials().await.unwrap().is_empty());
+    }
+}
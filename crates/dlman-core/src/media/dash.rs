@@ -2,21 +2,53 @@
 //!
 //! Parses MPD XML manifests to extract quality variants and segment URLs.
 //! Uses quick-xml for lightweight XML parsing.
+//!
+//! Segment addressing: SegmentTemplate with a fixed `duration` or a
+//! SegmentTimeline (`$Number$`, `$Time$`, `$RepresentationID$`,
+//! `$Bandwidth$`, with `%0Nd` widths), SegmentList (URLs and byte ranges)
+//! and single-file BaseURL. Multi-Period presentations are concatenated,
+//! matching the chosen representation in every period; where a period
+//! brings its own init section, the remuxer picks up its sample entries.
+//!
+//! Audio and plain-text subtitle (WebVTT/TTML) adaptation sets are offered
+//! as renditions of the video variants rather than as variants themselves.
+//!
+//! Dynamic (live) MPDs are supported for recording: `$Number$` templates are
+//! mapped onto wall-clock time via `availabilityStartTime`, and the returned
+//! [`SegmentList`] is flagged live so the caller re-polls the manifest.
 
+use crate::engine::{HostLimiter, RequestHeaders};
 use crate::error::DlmanError;
-use crate::media::ProtocolHandler;
-use dlman_types::{MediaProtocol, MediaVariant};
-use quick_xml::events::Event;
+use crate::media::{host_slot, ByteRange, LiveInfo, MediaSegment, ProtocolHandler, SegmentList};
+use chrono::{DateTime, Utc};
+use dlman_types::{MediaProtocol, MediaRendition, MediaVariant};
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
+use std::sync::Arc;
 use url::Url;
 
 pub struct DashHandler {
     client: reqwest::Client,
+    headers: RequestHeaders,
+    host_limiter: Option<Arc<HostLimiter>>,
 }
 
 impl DashHandler {
     pub fn new(client: reqwest::Client) -> Self {
-        Self { client }
+        Self { client, headers: RequestHeaders::default(), host_limiter: None }
+    }
+
+    /// Send `headers` (header rules, the download's own and its login) with
+    /// each request, matched against the URL of that request
+    pub fn with_headers(mut self, headers: RequestHeaders) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Take a connection slot from `limiter` for each request
+    pub fn with_host_limiter(mut self, limiter: Option<Arc<HostLimiter>>) -> Self {
+        self.host_limiter = limiter;
+        self
     }
 
     async fn fetch_mpd(
@@ -24,11 +56,12 @@ impl DashHandler {
         url: &str,
         headers: &[(String, String)],
     ) -> Result<String, DlmanError> {
+        let _slot = host_slot(self.host_limiter.as_deref(), url).await;
         let mut req = self.client.get(url);
         for (k, v) in headers {
             req = req.header(k.as_str(), v.as_str());
         }
-        let resp = req.send().await?;
+        let resp = self.headers.send(req, url).await?;
         if !resp.status().is_success() {
             return Err(DlmanError::ServerError {
                 status: resp.status().as_u16(),
@@ -41,6 +74,60 @@ impl DashHandler {
 
 // ── Parsed intermediate structs ──────────────────────────────────────────
 
+/// One `<S t d r>` entry of a SegmentTimeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimelineEntry {
+    t: Option<u64>,
+    d: u64,
+    /// Repeat count; negative repeats until the next entry or the period end
+    r: i64,
+}
+
+/// SegmentTemplate attributes. Unset fields inherit from the template of
+/// the enclosing element (Period → AdaptationSet → Representation).
+#[derive(Debug, Default, Clone)]
+struct SegmentTemplate {
+    /// Media pattern (e.g. "seg-$Number$.m4s")
+    media: Option<String>,
+    /// Init pattern
+    initialization: Option<String>,
+    start_number: Option<u64>,
+    timescale: Option<u64>,
+    /// Fixed segment duration (in timescale units) when there's no timeline
+    duration: Option<u64>,
+    presentation_time_offset: Option<u64>,
+    timeline: Vec<TimelineEntry>,
+}
+
+impl SegmentTemplate {
+    /// This template with its unset fields filled in from `parent`.
+    fn inherit(&self, parent: &SegmentTemplate) -> SegmentTemplate {
+        SegmentTemplate {
+            media: self.media.clone().or_else(|| parent.media.clone()),
+            initialization: self.initialization.clone().or_else(|| parent.initialization.clone()),
+            start_number: self.start_number.or(parent.start_number),
+            timescale: self.timescale.or(parent.timescale),
+            duration: self.duration.or(parent.duration),
+            presentation_time_offset: self
+                .presentation_time_offset
+                .or(parent.presentation_time_offset),
+            timeline: if self.timeline.is_empty() {
+                parent.timeline.clone()
+            } else {
+                self.timeline.clone()
+            },
+        }
+    }
+}
+
+/// A `SegmentURL` (or SegmentList `Initialization`): a URL, a byte range of
+/// the BaseURL, or both.
+#[derive(Debug, Default, Clone)]
+struct SegmentUrl {
+    media: Option<String>,
+    range: Option<ByteRange>,
+}
+
 #[derive(Debug, Default, Clone)]
 struct Representation {
     id: Option<String>,
@@ -50,214 +137,366 @@ struct Representation {
     codecs: Option<String>,
     mime_type: Option<String>,
     base_url: Option<String>,
-    /// SegmentTemplate media pattern (e.g. "seg-$Number$.m4s")
-    seg_tpl_media: Option<String>,
-    /// SegmentTemplate init pattern
-    seg_tpl_init: Option<String>,
-    seg_tpl_start: u64,
-    seg_tpl_timescale: u64,
-    seg_tpl_duration: u64,
-    /// Explicit SegmentList URLs
-    segment_urls: Vec<String>,
+    template: Option<SegmentTemplate>,
+    /// Explicit SegmentList entries
+    segment_urls: Vec<SegmentUrl>,
+    /// SegmentList initialization section
+    list_init: Option<SegmentUrl>,
+}
+
+impl Representation {
+    fn is_audio(&self) -> bool {
+        self.mime_type.as_deref().is_some_and(|m| m.starts_with("audio"))
+    }
+
+    fn is_video(&self) -> bool {
+        match self.mime_type.as_deref() {
+            Some(m) => m.starts_with("video"),
+            None => self.height.is_some(),
+        }
+    }
+
+    /// Plain-text subtitles (WebVTT or TTML). Text wrapped in fMP4
+    /// (`wvtt`/`stpp` in `application/mp4`) isn't counted.
+    fn is_text(&self) -> bool {
+        matches!(self.mime_type.as_deref(), Some("text/vtt" | "application/ttml+xml"))
+    }
+
+    /// Whether the representation is split into segments; otherwise its
+    /// BaseURL is the whole media file.
+    fn is_segmented(&self) -> bool {
+        self.template.is_some() || !self.segment_urls.is_empty()
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 struct AdaptationSet {
     mime_type: Option<String>,
     codecs: Option<String>,
-    /// Inherited SegmentTemplate at AdaptationSet level
-    seg_tpl_media: Option<String>,
-    seg_tpl_init: Option<String>,
-    seg_tpl_start: u64,
-    seg_tpl_timescale: u64,
-    seg_tpl_duration: u64,
+    /// Language tag (`lang`)
+    lang: Option<String>,
+    base_url: Option<String>,
+    template: Option<SegmentTemplate>,
     representations: Vec<Representation>,
 }
 
+#[derive(Debug, Default, Clone)]
+struct Period {
+    id: Option<String>,
+    /// Start offset within the presentation, in seconds
+    start: Option<f64>,
+    /// Duration in seconds
+    duration: Option<f64>,
+    base_url: Option<String>,
+    template: Option<SegmentTemplate>,
+    adaptation_sets: Vec<AdaptationSet>,
+}
+
+#[derive(Debug, Default)]
+struct Mpd {
+    periods: Vec<Period>,
+    /// mediaPresentationDuration in seconds (0 if absent, e.g. live)
+    duration: f64,
+    /// `type="dynamic"`: a live presentation that keeps growing
+    dynamic: bool,
+    /// minimumUpdatePeriod in seconds
+    minimum_update_period: Option<f64>,
+    availability_start_time: Option<DateTime<Utc>>,
+    base_url: Option<String>,
+}
+
+impl Mpd {
+    /// Start and duration (seconds) of period `idx`. Missing values follow
+    /// from the neighbouring periods and the presentation duration.
+    fn period_timing(&self, idx: usize) -> (f64, Option<f64>) {
+        let mut start = 0.0;
+        for (i, period) in self.periods.iter().enumerate() {
+            if let Some(s) = period.start {
+                start = s;
+            }
+            let duration = period
+                .duration
+                .or_else(|| self.periods.get(i + 1).and_then(|next| next.start).map(|s| s - start))
+                .or_else(|| (self.duration > 0.0 && i + 1 == self.periods.len()).then_some(self.duration - start))
+                .filter(|d| *d > 0.0);
+            if i == idx {
+                return (start, duration);
+            }
+            start += duration.unwrap_or(0.0);
+        }
+        (start, None)
+    }
+
+    /// The period variants are listed from: the longest one of a VOD
+    /// presentation (not a pre-roll ad), or the current (last) one of a
+    /// live stream.
+    fn primary_period(&self) -> Option<&Period> {
+        if self.dynamic {
+            return self.periods.last();
+        }
+        let mut best: Option<(usize, f64)> = None;
+        for idx in 0..self.periods.len() {
+            let duration = self.period_timing(idx).1.unwrap_or(0.0);
+            if best.is_none_or(|(_, d)| duration > d) {
+                best = Some((idx, duration));
+            }
+        }
+        best.map(|(idx, _)| &self.periods[idx])
+    }
+}
+
+/// Number of segments offered per poll of a live `$Number$` template; the
+/// recorder only keeps the newest few on its first poll anyway.
+const LIVE_WINDOW_SEGMENTS: u64 = 10;
+
 // ── XML Parsing ──────────────────────────────────────────────────────────
 
-fn parse_mpd(xml: &str) -> Result<(Vec<AdaptationSet>, f64), DlmanError> {
+fn parse_mpd(xml: &str) -> Result<Mpd, DlmanError> {
     let mut reader = Reader::from_str(xml);
     reader.config_mut().trim_text(true);
 
-    let mut adaptation_sets: Vec<AdaptationSet> = Vec::new();
-    let mut current_as: Option<AdaptationSet> = None;
-    let mut current_rep: Option<Representation> = None;
-    let mut in_segment_list = false;
-    let mut duration_secs: f64 = 0.0;
+    let mut parser = MpdParser::default();
     let mut buf = Vec::new();
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Eof) => break,
-            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                match name.as_str() {
-                    "MPD" => {
-                        for attr in e.attributes().flatten() {
-                            if attr.key.as_ref() == b"mediaPresentationDuration" {
-                                let val = String::from_utf8_lossy(&attr.value);
-                                duration_secs = parse_iso8601_duration(&val);
-                            }
-                        }
-                    }
-                    "AdaptationSet" => {
-                        let mut a = AdaptationSet::default();
-                        for attr in e.attributes().flatten() {
-                            let key = attr.key.as_ref();
-                            let val = String::from_utf8_lossy(&attr.value).to_string();
-                            match key {
-                                b"mimeType" => a.mime_type = Some(val),
-                                b"codecs" => a.codecs = Some(val),
-                                _ => {}
-                            }
+            Ok(Event::Start(ref e)) => parser.start(e),
+            // Self-closing elements (`<Representation .../>`) open and close at once
+            Ok(Event::Empty(ref e)) => {
+                parser.start(e);
+                parser.end(e.name().as_ref());
+            }
+            Ok(Event::Text(ref t)) => {
+                let text = t.unescape().unwrap_or_default();
+                parser.text(text.trim());
+            }
+            Ok(Event::End(ref e)) => parser.end(e.name().as_ref()),
+            Err(e) => {
+                return Err(DlmanError::InvalidOperation(
+                    format!("MPD XML parse error: {e}"),
+                ));
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(parser.finish())
+}
+
+/// Streaming MPD parser state: the element currently open at each level.
+#[derive(Default)]
+struct MpdParser {
+    mpd: Mpd,
+    period: Option<Period>,
+    a_set: Option<AdaptationSet>,
+    rep: Option<Representation>,
+    /// SegmentTemplate being read (its SegmentTimeline follows as children)
+    template: Option<SegmentTemplate>,
+    in_segment_list: bool,
+    in_base_url: bool,
+}
+
+impl MpdParser {
+    fn start(&mut self, e: &BytesStart) {
+        match e.name().as_ref() {
+            b"MPD" => {
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "mediaPresentationDuration" => {
+                            self.mpd.duration = parse_iso8601_duration(&val);
                         }
-                        current_as = Some(a);
-                    }
-                    "Representation" => {
-                        let mut r = Representation::default();
-                        // Inherit from AdaptationSet
-                        if let Some(ref a) = current_as {
-                            r.mime_type = a.mime_type.clone();
-                            r.codecs = a.codecs.clone();
-                            r.seg_tpl_media = a.seg_tpl_media.clone();
-                            r.seg_tpl_init = a.seg_tpl_init.clone();
-                            r.seg_tpl_start = a.seg_tpl_start;
-                            r.seg_tpl_timescale = a.seg_tpl_timescale;
-                            r.seg_tpl_duration = a.seg_tpl_duration;
+                        "type" => self.mpd.dynamic = val == "dynamic",
+                        "minimumUpdatePeriod" => {
+                            self.mpd.minimum_update_period = Some(parse_iso8601_duration(&val));
                         }
-                        for attr in e.attributes().flatten() {
-                            let key = attr.key.as_ref();
-                            let val = String::from_utf8_lossy(&attr.value).to_string();
-                            match key {
-                                b"id" => r.id = Some(val),
-                                b"width" => r.width = val.parse().ok(),
-                                b"height" => r.height = val.parse().ok(),
-                                b"bandwidth" => r.bandwidth = val.parse().ok(),
-                                b"codecs" => r.codecs = Some(val),
-                                b"mimeType" => r.mime_type = Some(val),
-                                _ => {}
-                            }
+                        "availabilityStartTime" => {
+                            self.mpd.availability_start_time = DateTime::parse_from_rfc3339(&val)
+                                .ok()
+                                .map(|t| t.with_timezone(&Utc));
                         }
-                        current_rep = Some(r);
+                        _ => {}
                     }
-                    "SegmentTemplate" => {
-                        let mut target = if current_rep.is_some() {
-                            current_rep.as_mut()
-                        } else {
-                            None
-                        };
-                        for attr in e.attributes().flatten() {
-                            let key = attr.key.as_ref();
-                            let val = String::from_utf8_lossy(&attr.value).to_string();
-                            match key {
-                                b"media" => {
-                                    if let Some(ref mut r) = target {
-                                        r.seg_tpl_media = Some(val.clone());
-                                    }
-                                    if let Some(ref mut a) = current_as {
-                                        a.seg_tpl_media = Some(val);
-                                    }
-                                }
-                                b"initialization" => {
-                                    if let Some(ref mut r) = target {
-                                        r.seg_tpl_init = Some(val.clone());
-                                    }
-                                    if let Some(ref mut a) = current_as {
-                                        a.seg_tpl_init = Some(val);
-                                    }
-                                }
-                                b"startNumber" => {
-                                    let n = val.parse().unwrap_or(1);
-                                    if let Some(ref mut r) = target {
-                                        r.seg_tpl_start = n;
-                                    }
-                                    if let Some(ref mut a) = current_as {
-                                        a.seg_tpl_start = n;
-                                    }
-                                }
-                                b"timescale" => {
-                                    let n = val.parse().unwrap_or(1);
-                                    if let Some(ref mut r) = target {
-                                        r.seg_tpl_timescale = n;
-                                    }
-                                    if let Some(ref mut a) = current_as {
-                                        a.seg_tpl_timescale = n;
-                                    }
-                                }
-                                b"duration" => {
-                                    let n = val.parse().unwrap_or(0);
-                                    if let Some(ref mut r) = target {
-                                        r.seg_tpl_duration = n;
-                                    }
-                                    if let Some(ref mut a) = current_as {
-                                        a.seg_tpl_duration = n;
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
+                }
+            }
+            b"Period" => {
+                let mut p = Period::default();
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "id" => p.id = Some(val),
+                        "start" => p.start = Some(parse_iso8601_duration(&val)),
+                        "duration" => p.duration = Some(parse_iso8601_duration(&val)),
+                        _ => {}
                     }
-                    "BaseURL" => { /* text content handled in Event::Text */ }
-                    "SegmentList" => {
-                        in_segment_list = true;
+                }
+                self.period = Some(p);
+            }
+            b"AdaptationSet" => {
+                let mut a = AdaptationSet::default();
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "mimeType" => a.mime_type = Some(val),
+                        "codecs" => a.codecs = Some(val),
+                        "lang" => a.lang = Some(val),
+                        _ => {}
                     }
-                    "SegmentURL" => {
-                        if in_segment_list {
-                            for attr in e.attributes().flatten() {
-                                if attr.key.as_ref() == b"media" {
-                                    let val =
-                                        String::from_utf8_lossy(&attr.value).to_string();
-                                    if let Some(ref mut r) = current_rep {
-                                        r.segment_urls.push(val);
-                                    }
-                                }
-                            }
-                        }
+                }
+                self.a_set = Some(a);
+            }
+            b"Representation" => {
+                let mut r = Representation::default();
+                // Inherit from AdaptationSet
+                if let Some(ref a) = self.a_set {
+                    r.mime_type = a.mime_type.clone();
+                    r.codecs = a.codecs.clone();
+                }
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "id" => r.id = Some(val),
+                        "width" => r.width = val.parse().ok(),
+                        "height" => r.height = val.parse().ok(),
+                        "bandwidth" => r.bandwidth = val.parse().ok(),
+                        "codecs" => r.codecs = Some(val),
+                        "mimeType" => r.mime_type = Some(val),
+                        _ => {}
                     }
-                    _ => {}
                 }
+                self.rep = Some(r);
             }
-            Ok(Event::Text(ref t)) => {
-                let text = t.unescape().unwrap_or_default().to_string();
-                if !text.trim().is_empty() {
-                    if let Some(ref mut r) = current_rep {
-                        r.base_url = Some(text.trim().to_string());
+            b"SegmentTemplate" => {
+                let mut t = SegmentTemplate::default();
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "media" => t.media = Some(val),
+                        "initialization" => t.initialization = Some(val),
+                        "startNumber" => t.start_number = val.parse().ok(),
+                        "timescale" => t.timescale = val.parse().ok(),
+                        "duration" => t.duration = val.parse().ok(),
+                        "presentationTimeOffset" => t.presentation_time_offset = val.parse().ok(),
+                        _ => {}
                     }
                 }
+                self.template = Some(t);
             }
-            Ok(Event::End(ref e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                match name.as_str() {
-                    "Representation" => {
-                        if let Some(r) = current_rep.take() {
-                            if let Some(ref mut a) = current_as {
-                                a.representations.push(r);
-                            }
+            b"S" => {
+                if let Some(ref mut t) = self.template {
+                    let mut entry = TimelineEntry { t: None, d: 0, r: 0 };
+                    for (key, val) in attributes(e) {
+                        match key.as_str() {
+                            "t" => entry.t = val.parse().ok(),
+                            "d" => entry.d = val.parse().unwrap_or(0),
+                            "r" => entry.r = val.parse().unwrap_or(0),
+                            _ => {}
                         }
                     }
-                    "AdaptationSet" => {
-                        if let Some(a) = current_as.take() {
-                            adaptation_sets.push(a);
-                        }
+                    t.timeline.push(entry);
+                }
+            }
+            b"BaseURL" => self.in_base_url = true,
+            b"SegmentList" => self.in_segment_list = true,
+            b"Initialization" | b"SegmentURL" if self.in_segment_list => {
+                let is_init = e.name().as_ref() == b"Initialization";
+                let mut url = SegmentUrl::default();
+                for (key, val) in attributes(e) {
+                    match key.as_str() {
+                        "media" | "sourceURL" => url.media = Some(val),
+                        "mediaRange" | "range" => url.range = parse_byte_range(&val),
+                        _ => {}
                     }
-                    "SegmentList" => {
-                        in_segment_list = false;
+                }
+                if let Some(ref mut r) = self.rep {
+                    if is_init {
+                        r.list_init = Some(url);
+                    } else {
+                        r.segment_urls.push(url);
                     }
-                    _ => {}
                 }
             }
-            Err(e) => {
-                return Err(DlmanError::InvalidOperation(
-                    format!("MPD XML parse error: {e}"),
-                ));
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &[u8]) {
+        match name {
+            b"Representation" => {
+                if let (Some(r), Some(a)) = (self.rep.take(), self.a_set.as_mut()) {
+                    a.representations.push(r);
+                }
+            }
+            b"AdaptationSet" => {
+                if let Some(a) = self.a_set.take() {
+                    // Tolerate (invalid) MPDs without a Period element
+                    self.period.get_or_insert_with(Period::default).adaptation_sets.push(a);
+                }
             }
+            b"Period" => {
+                if let Some(p) = self.period.take() {
+                    self.mpd.periods.push(p);
+                }
+            }
+            b"SegmentTemplate" => {
+                let template = self.template.take();
+                if let Some(ref mut r) = self.rep {
+                    r.template = template;
+                } else if let Some(ref mut a) = self.a_set {
+                    a.template = template;
+                } else if let Some(ref mut p) = self.period {
+                    p.template = template;
+                }
+            }
+            b"BaseURL" => self.in_base_url = false,
+            b"SegmentList" => self.in_segment_list = false,
             _ => {}
         }
-        buf.clear();
     }
 
-    Ok((adaptation_sets, duration_secs))
+    fn text(&mut self, text: &str) {
+        if !self.in_base_url || text.is_empty() {
+            return;
+        }
+        let base_url = Some(text.to_string());
+        if let Some(ref mut r) = self.rep {
+            r.base_url = base_url;
+        } else if let Some(ref mut a) = self.a_set {
+            a.base_url = base_url;
+        } else if let Some(ref mut p) = self.period {
+            p.base_url = base_url;
+        } else {
+            self.mpd.base_url = base_url;
+        }
+    }
+
+    fn finish(mut self) -> Mpd {
+        if let Some(p) = self.period.take() {
+            self.mpd.periods.push(p);
+        }
+        self.mpd
+    }
+}
+
+/// Attributes of an element as (name, value) strings.
+fn attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .map(|attr| {
+            (
+                String::from_utf8_lossy(attr.key.as_ref()).to_string(),
+                String::from_utf8_lossy(&attr.value).to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Parse an inclusive `first-last` byte range (`mediaRange`, `range`).
+fn parse_byte_range(s: &str) -> Option<ByteRange> {
+    let (first, last) = s.split_once('-')?;
+    let first: u64 = first.trim().parse().ok()?;
+    let last: u64 = last.trim().parse().ok()?;
+    (last >= first).then(|| ByteRange {
+        offset: first,
+        length: last - first + 1,
+    })
 }
 
 /// Parse ISO 8601 duration like "PT1H2M3.4S" into seconds.
@@ -285,6 +524,38 @@ fn parse_iso8601_duration(s: &str) -> f64 {
     secs
 }
 
+/// Segment numbers of a live `$Number$` template that are fully available
+/// `elapsed_secs` after `availabilityStartTime`, limited to the newest `window`.
+fn live_segment_numbers(
+    elapsed_secs: f64,
+    seg_dur_secs: f64,
+    start_number: u64,
+    window: u64,
+) -> std::ops::Range<u64> {
+    if elapsed_secs <= 0.0 || seg_dur_secs <= 0.0 {
+        return start_number..start_number;
+    }
+    // Segment `start + k` becomes available once it has been fully produced
+    let available = (elapsed_secs / seg_dur_secs).floor() as u64;
+    let end = start_number + available;
+    end.saturating_sub(window).max(start_number)..end
+}
+
+/// Live refresh info for a dynamic MPD. Number-template streams have to be
+/// re-evaluated every segment even if the manifest itself rarely changes.
+fn live_info(mpd: &Mpd, segment_duration: Option<f64>) -> Option<LiveInfo> {
+    if !mpd.dynamic {
+        return None;
+    }
+    let refresh = match (mpd.minimum_update_period, segment_duration) {
+        (Some(p), Some(d)) => p.min(d),
+        (Some(p), None) => p,
+        (None, Some(d)) => d,
+        (None, None) => 2.0,
+    };
+    Some(LiveInfo { refresh_interval: refresh.max(1.0) })
+}
+
 /// Resolve a possibly-relative URL against a base.
 fn resolve_url(base: &str, relative: &str) -> Result<String, DlmanError> {
     if relative.starts_with("http://") || relative.starts_with("https://") {
@@ -298,11 +569,362 @@ fn resolve_url(base: &str, relative: &str) -> Result<String, DlmanError> {
     Ok(resolved.to_string())
 }
 
-/// Expand SegmentTemplate pattern ($Number$, $RepresentationID$, $Bandwidth$).
-fn expand_template(tpl: &str, number: u64, rep_id: &str, bandwidth: u64) -> String {
-    tpl.replace("$Number$", &number.to_string())
-        .replace("$RepresentationID$", rep_id)
-        .replace("$Bandwidth$", &bandwidth.to_string())
+/// Expand a SegmentTemplate pattern: `$Number$`, `$Time$`, `$Bandwidth$`
+/// and `$RepresentationID$`, with an optional `%0<width>d` format tag on the
+/// numeric ones (e.g. `$Number%05d$`), and `$$` for a literal `$`.
+fn expand_template(tpl: &str, number: u64, time: u64, rep_id: &str, bandwidth: u64) -> String {
+    let mut out = String::with_capacity(tpl.len() + 16);
+    let mut rest = tpl;
+    while let Some(open) = rest.find('$') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('$') else {
+            // Unterminated identifier: keep it verbatim
+            out.push_str(&rest[open..]);
+            return out;
+        };
+        let ident = &after[..close];
+        let (name, format) = match ident.split_once('%') {
+            Some((name, format)) => (name, Some(format)),
+            None => (ident, None),
+        };
+        let value = match name {
+            "" => Some("$".to_string()),
+            "Number" => Some(number.to_string()),
+            "Time" => Some(time.to_string()),
+            "Bandwidth" => Some(bandwidth.to_string()),
+            "RepresentationID" => Some(rep_id.to_string()),
+            _ => None,
+        };
+        let width = format
+            .and_then(|f| f.strip_suffix('d'))
+            .and_then(|w| w.parse::<usize>().ok());
+        match (value, width) {
+            (Some(v), Some(width)) => out.push_str(&format!("{v:0>width$}")),
+            (Some(v), None) => out.push_str(&v),
+            (None, _) => {
+                out.push('$');
+                out.push_str(ident);
+                out.push('$');
+            }
+        }
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Expand a SegmentTimeline into `(time, duration)` pairs, in timescale
+/// units. `period_end` bounds open-ended (`r="-1"`) repeats in the last entry.
+fn expand_timeline(entries: &[TimelineEntry], period_end: Option<u64>) -> Vec<(u64, u64)> {
+    let mut out = Vec::new();
+    let mut time = 0u64;
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(t) = entry.t {
+            time = t;
+        }
+        if entry.d == 0 {
+            continue;
+        }
+        let repeats = if entry.r >= 0 {
+            entry.r as u64
+        } else {
+            match entries.get(i + 1).and_then(|next| next.t).or(period_end) {
+                Some(end) if end > time => (end - time).div_ceil(entry.d) - 1,
+                _ => 0,
+            }
+        };
+        for _ in 0..=repeats {
+            out.push((time, entry.d));
+            time += entry.d;
+        }
+    }
+    out
+}
+
+/// Segments of one representation within one period.
+#[derive(Debug, Default)]
+struct PeriodSegments {
+    init: Option<MediaSegment>,
+    segments: Vec<MediaSegment>,
+    /// Duration of the newest segment, in seconds (live refresh pacing)
+    segment_duration: Option<f64>,
+}
+
+/// Resolve the segments of `rep` in period `period_idx`.
+///
+/// `now` positions `$Number$` templates of a live stream; a live
+/// SegmentTimeline already lists what is available, so only its newest
+/// [`LIVE_WINDOW_SEGMENTS`] entries are kept.
+fn representation_segments(
+    mpd: &Mpd,
+    period_idx: usize,
+    a_set: &AdaptationSet,
+    rep: &Representation,
+    mpd_url: &str,
+    now: DateTime<Utc>,
+) -> Result<PeriodSegments, DlmanError> {
+    let period = &mpd.periods[period_idx];
+    let (period_start, period_duration) = mpd.period_timing(period_idx);
+
+    // BaseURLs nest: MPD → Period → AdaptationSet → Representation. A
+    // Representation's own BaseURL is a directory only when it's segmented.
+    let mut base = mpd_url.to_string();
+    let rep_base = rep.base_url.as_ref().filter(|_| rep.is_segmented());
+    for b in [&mpd.base_url, &period.base_url, &a_set.base_url]
+        .into_iter()
+        .flatten()
+        .chain(rep_base)
+    {
+        base = resolve_url(&base, b)?;
+    }
+
+    let rep_id = rep.id.as_deref().unwrap_or("1");
+    let bw = rep.bandwidth.unwrap_or(0);
+    let mut out = PeriodSegments::default();
+
+    let template = [&a_set.template, &period.template]
+        .into_iter()
+        .fold(rep.template.clone(), |tpl, parent| match (tpl, parent) {
+            (Some(t), Some(p)) => Some(t.inherit(p)),
+            (t, p) => t.or_else(|| p.clone()),
+        });
+
+    if let Some(tpl) = template.filter(|t| t.media.is_some()) {
+        let media_tpl = tpl.media.as_deref().unwrap_or_default();
+        let timescale = tpl.timescale.unwrap_or(1).max(1);
+        let start_number = tpl.start_number.unwrap_or(1);
+        let pto = tpl.presentation_time_offset.unwrap_or(0);
+
+        if let Some(ref init_tpl) = tpl.initialization {
+            let init = expand_template(init_tpl, 0, 0, rep_id, bw);
+            out.init = Some(MediaSegment::new(resolve_url(&base, &init)?));
+        }
+
+        // (number, time) of each segment
+        let mut numbered: Vec<(u64, u64)> = Vec::new();
+        if !tpl.timeline.is_empty() {
+            let period_end =
+                period_duration.map(|d| pto + (d * timescale as f64).round() as u64);
+            let timeline = expand_timeline(&tpl.timeline, period_end);
+            out.segment_duration = timeline.last().map(|&(_, d)| d as f64 / timescale as f64);
+            numbered = timeline
+                .iter()
+                .enumerate()
+                .map(|(i, &(time, _))| (start_number + i as u64, time))
+                .collect();
+            if mpd.dynamic {
+                let skip = numbered.len().saturating_sub(LIVE_WINDOW_SEGMENTS as usize);
+                numbered.drain(..skip);
+            }
+        } else if let Some(duration) = tpl.duration.filter(|d| *d > 0) {
+            let seg_dur_secs = duration as f64 / timescale as f64;
+            out.segment_duration = Some(seg_dur_secs);
+            let numbers = if mpd.dynamic {
+                let ast = mpd.availability_start_time.ok_or_else(|| {
+                    DlmanError::InvalidOperation(
+                        "Live DASH manifest has no availabilityStartTime".to_string(),
+                    )
+                })?;
+                let elapsed = (now - ast).num_milliseconds() as f64 / 1000.0 - period_start;
+                live_segment_numbers(elapsed, seg_dur_secs, start_number, LIVE_WINDOW_SEGMENTS)
+            } else {
+                let count = period_duration
+                    .map(|d| (d / seg_dur_secs).ceil() as u64)
+                    .unwrap_or(0);
+                start_number..start_number + count
+            };
+            numbered = numbers
+                .map(|n| (n, pto + (n - start_number) * duration))
+                .collect();
+        }
+
+        for (number, time) in numbered {
+            let seg = expand_template(media_tpl, number, time, rep_id, bw);
+            out.segments.push(MediaSegment::new(resolve_url(&base, &seg)?));
+        }
+        return Ok(out);
+    }
+
+    // SegmentList: explicit URLs and/or byte ranges of the BaseURL
+    if !rep.segment_urls.is_empty() {
+        let resolve = |url: &SegmentUrl| -> Result<MediaSegment, DlmanError> {
+            let mut segment = match url.media {
+                Some(ref media) => MediaSegment::new(resolve_url(&base, media)?),
+                None => MediaSegment::new(base.clone()),
+            };
+            segment.byte_range = url.range;
+            Ok(segment)
+        };
+        out.init = rep.list_init.as_ref().map(resolve).transpose()?;
+        for url in &rep.segment_urls {
+            out.segments.push(resolve(url)?);
+        }
+        return Ok(out);
+    }
+
+    // BaseURL (single file)
+    if let Some(ref bu) = rep.base_url {
+        out.segments.push(MediaSegment::new(resolve_url(&base, bu)?));
+    }
+    Ok(out)
+}
+
+/// Audio representations as renditions to pair with the video variants,
+/// best bitrate first (and marked default).
+fn audio_renditions(a_sets: &[AdaptationSet], mpd_url: &str) -> Vec<MediaRendition> {
+    let mut renditions: Vec<MediaRendition> = a_sets
+        .iter()
+        .flat_map(|a| a.representations.iter().map(move |r| (a, r)))
+        .filter(|(_, r)| r.is_audio())
+        .map(|(a, r)| MediaRendition {
+            url: Some(mpd_url.to_string()),
+            name: match a.lang {
+                Some(ref lang) => format!("{} ({})", build_label(r), lang),
+                None => build_label(r),
+            },
+            language: a.lang.clone(),
+            default: false,
+            bandwidth: r.bandwidth,
+            codecs: r.codecs.clone(),
+        })
+        .collect();
+    renditions.sort_by_key(|r| std::cmp::Reverse(r.bandwidth.unwrap_or(0)));
+    if let Some(best) = renditions.first_mut() {
+        best.default = true;
+    }
+    renditions
+}
+
+/// Name of a text representation's rendition. It identifies the
+/// representation when the rendition is resolved, so the ID is included.
+fn subtitle_name(a: &AdaptationSet, r: &Representation) -> String {
+    let lang = a.lang.as_deref().unwrap_or("Subtitles");
+    match r.id {
+        Some(ref id) => format!("{lang} ({id})"),
+        None => lang.to_string(),
+    }
+}
+
+/// Text representations as subtitle renditions, in manifest order. Each is
+/// a single file at its BaseURL or resolved through the MPD like a variant.
+fn subtitle_renditions(a_sets: &[AdaptationSet], mpd_url: &str) -> Vec<MediaRendition> {
+    a_sets
+        .iter()
+        .flat_map(|a| a.representations.iter().map(move |r| (a, r)))
+        .filter(|(_, r)| r.is_text())
+        .filter_map(|(a, r)| {
+            let url = match r.base_url {
+                Some(ref bu) if !r.is_segmented() => resolve_url(mpd_url, bu).ok()?,
+                _ => mpd_url.to_string(),
+            };
+            Some(MediaRendition {
+                url: Some(url),
+                name: subtitle_name(a, r),
+                language: a.lang.clone(),
+                default: false,
+                bandwidth: r.bandwidth,
+                codecs: r.codecs.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Find the representation in `period` that corresponds to `variant`.
+///
+/// Later periods (e.g. inserted ads) may use a different encoding ladder,
+/// so without an exact match the closest representation of the same kind
+/// is used. Subtitle renditions (`text`) only ever match by name.
+fn find_representation<'a>(
+    period: &'a Period,
+    variant: &MediaVariant,
+    text: bool,
+) -> Option<(&'a AdaptationSet, &'a Representation)> {
+    let all = || {
+        period
+            .adaptation_sets
+            .iter()
+            .flat_map(|a| a.representations.iter().map(move |r| (a, r)))
+    };
+    if text {
+        return all().find(|(a, r)| r.is_text() && subtitle_name(a, r) == variant.label);
+    }
+    all()
+        .find(|(_, r)| {
+            !r.is_text()
+                && r.height == variant.height
+                && r.bandwidth == variant.bandwidth
+                && r.codecs == variant.codecs
+        })
+        .or_else(|| {
+            all()
+                .filter(|(_, r)| if variant.audio_only { r.is_audio() } else { r.is_video() })
+                .min_by_key(|(_, r)| {
+                    (
+                        r.height.unwrap_or(0).abs_diff(variant.height.unwrap_or(0)),
+                        r.bandwidth.unwrap_or(0).abs_diff(variant.bandwidth.unwrap_or(0)),
+                    )
+                })
+        })
+}
+
+/// Build the segment list of `variant`: every period of a VOD presentation
+/// back to back, or the current period of a live one.
+///
+/// A period whose init section differs from the previous one's gets it
+/// inserted at the period boundary. The downloaded file then has an init
+/// section per period, which the remuxer turns into one sample entry each.
+fn variant_segments(
+    mpd: &Mpd,
+    variant: &MediaVariant,
+    mpd_url: &str,
+    now: DateTime<Utc>,
+) -> Result<SegmentList, DlmanError> {
+    let periods = if mpd.dynamic {
+        mpd.periods.len().saturating_sub(1)..mpd.periods.len()
+    } else {
+        0..mpd.periods.len()
+    };
+
+    let mut list = SegmentList::default();
+    let mut current_init: Option<MediaSegment> = None;
+    let mut segment_duration = None;
+    let mut found = false;
+    let text = mpd.periods.iter().flat_map(|p| &p.adaptation_sets).any(|a| {
+        a.representations.iter().any(|r| r.is_text() && subtitle_name(a, r) == variant.label)
+    });
+
+    for idx in periods {
+        let Some((a_set, rep)) = find_representation(&mpd.periods[idx], variant, text) else {
+            tracing::warn!(
+                "[DASH] Period {} has no representation for {}, skipping",
+                mpd.periods[idx].id.as_deref().unwrap_or("?"),
+                variant.label
+            );
+            continue;
+        };
+        found = true;
+
+        let part = representation_segments(mpd, idx, a_set, rep, mpd_url, now)?;
+        if let Some(init) = part.init {
+            if list.init.is_none() && list.segments.is_empty() {
+                list.init = Some(init.clone());
+            } else if current_init.as_ref() != Some(&init) {
+                list.segments.push(init.clone());
+            }
+            current_init = Some(init);
+        }
+        list.segments.extend(part.segments);
+        segment_duration = part.segment_duration.or(segment_duration);
+    }
+
+    if !found {
+        return Err(DlmanError::InvalidOperation(
+            "Could not find matching DASH representation".to_string(),
+        ));
+    }
+    list.live = live_info(mpd, segment_duration);
+    Ok(list)
 }
 
 fn build_label(r: &Representation) -> String {
@@ -337,21 +959,19 @@ impl ProtocolHandler for DashHandler {
         headers: &[(String, String)],
     ) -> Result<Vec<MediaVariant>, DlmanError> {
         let xml = self.fetch_mpd(url, headers).await?;
-        let (adaptation_sets, _duration) = parse_mpd(&xml)?;
+        let mpd = parse_mpd(&xml)?;
+
+        let a_sets = mpd.primary_period().map(|p| p.adaptation_sets.as_slice()).unwrap_or_default();
+        let audio_tracks = audio_renditions(a_sets, url);
+        let subtitle_tracks = subtitle_renditions(a_sets, url);
 
         let mut variants = Vec::new();
-        for a_set in &adaptation_sets {
-            for rep in &a_set.representations {
-                let is_audio = rep
-                    .mime_type
-                    .as_deref()
-                    .map(|m| m.starts_with("audio"))
-                    .unwrap_or(false);
-
-                let variant_url = if let Some(ref bu) = rep.base_url {
-                    resolve_url(url, bu)?
-                } else {
-                    url.to_string()
+        for a_set in a_sets {
+            for rep in a_set.representations.iter().filter(|r| !r.is_text()) {
+                // Unsegmented representations are plain files at their BaseURL
+                let variant_url = match rep.base_url {
+                    Some(ref bu) if !rep.is_segmented() => resolve_url(url, bu)?,
+                    _ => url.to_string(),
                 };
 
                 variants.push(MediaVariant {
@@ -361,8 +981,11 @@ impl ProtocolHandler for DashHandler {
                     height: rep.height,
                     bandwidth: rep.bandwidth,
                     codecs: rep.codecs.clone(),
-                    audio_only: is_audio,
+                    audio_only: rep.is_audio(),
                     estimated_size: None,
+                    // Video is usually video-only in DASH: pair it with audio
+                    audio_tracks: if rep.is_video() { audio_tracks.clone() } else { Vec::new() },
+                    subtitle_tracks: if rep.is_video() { subtitle_tracks.clone() } else { Vec::new() },
                 });
             }
         }
@@ -379,88 +1002,25 @@ impl ProtocolHandler for DashHandler {
         Ok(variants)
     }
 
-    async fn get_segment_urls(
+    async fn get_segments(
         &self,
         variant: &MediaVariant,
         headers: &[(String, String)],
-    ) -> Result<Vec<String>, DlmanError> {
+    ) -> Result<SegmentList, DlmanError> {
         let mpd_url = &variant.url;
 
         // If variant URL is a direct media file (BaseURL case), return it
         if !mpd_url.ends_with(".mpd") && !mpd_url.contains(".mpd?") {
-            return Ok(vec![mpd_url.clone()]);
+            return Ok(SegmentList {
+                init: None,
+                segments: vec![MediaSegment::new(mpd_url.clone())],
+                live: None,
+            });
         }
 
         let xml = self.fetch_mpd(mpd_url, headers).await?;
-        let (adaptation_sets, duration) = parse_mpd(&xml)?;
-
-        // Find matching representation
-        for a_set in &adaptation_sets {
-            for rep in &a_set.representations {
-                let matches = rep.height == variant.height
-                    && rep.bandwidth == variant.bandwidth
-                    && rep.codecs == variant.codecs;
-                if !matches {
-                    continue;
-                }
-
-                let rep_id = rep.id.as_deref().unwrap_or("1");
-                let bw = rep.bandwidth.unwrap_or(0);
-                let mut urls = Vec::new();
-
-                // SegmentTemplate with duration
-                if let Some(ref media_tpl) = rep.seg_tpl_media {
-                    let timescale = if rep.seg_tpl_timescale > 0 {
-                        rep.seg_tpl_timescale
-                    } else {
-                        1
-                    };
-
-                    if let Some(ref init_tpl) = rep.seg_tpl_init {
-                        let init = expand_template(init_tpl, 0, rep_id, bw);
-                        urls.push(resolve_url(mpd_url, &init)?);
-                    }
-
-                    if rep.seg_tpl_duration > 0 && duration > 0.0 {
-                        let seg_dur_secs =
-                            rep.seg_tpl_duration as f64 / timescale as f64;
-                        let seg_count = (duration / seg_dur_secs).ceil() as u64;
-                        let start = if rep.seg_tpl_start > 0 {
-                            rep.seg_tpl_start
-                        } else {
-                            1
-                        };
-
-                        for i in 0..seg_count {
-                            let num = start + i;
-                            let seg = expand_template(media_tpl, num, rep_id, bw);
-                            urls.push(resolve_url(mpd_url, &seg)?);
-                        }
-                    }
-
-                    if !urls.is_empty() {
-                        return Ok(urls);
-                    }
-                }
-
-                // SegmentList
-                if !rep.segment_urls.is_empty() {
-                    for seg_url in &rep.segment_urls {
-                        urls.push(resolve_url(mpd_url, seg_url)?);
-                    }
-                    return Ok(urls);
-                }
-
-                // BaseURL (single file)
-                if let Some(ref bu) = rep.base_url {
-                    return Ok(vec![resolve_url(mpd_url, bu)?]);
-                }
-            }
-        }
-
-        Err(DlmanError::InvalidOperation(
-            "Could not find matching DASH representation".to_string(),
-        ))
+        let mpd = parse_mpd(&xml)?;
+        variant_segments(&mpd, variant, mpd_url, Utc::now())
     }
 }
 
@@ -480,8 +1040,58 @@ mod tests {
     #[test]
     fn test_expand_template() {
         let tpl = "video/$RepresentationID$/seg-$Number$.m4s";
-        let result = expand_template(tpl, 5, "v1", 2000000);
+        let result = expand_template(tpl, 5, 0, "v1", 2000000);
         assert_eq!(result, "video/v1/seg-5.m4s");
+
+        assert_eq!(expand_template("t$Time$.m4s", 1, 90000, "v", 0), "t90000.m4s");
+        assert_eq!(expand_template("$Number%05d$.m4s", 42, 0, "v", 0), "00042.m4s");
+        assert_eq!(expand_template("$Bandwidth$/$$x$Unknown$", 1, 0, "v", 800), "800/$x$Unknown$");
+    }
+
+    #[test]
+    fn test_expand_timeline() {
+        let entries = [
+            TimelineEntry { t: Some(100), d: 10, r: 1 },
+            TimelineEntry { t: None, d: 5, r: 0 },
+            // Open-ended repeat up to the next explicit time
+            TimelineEntry { t: None, d: 20, r: -1 },
+            TimelineEntry { t: Some(165), d: 7, r: 0 },
+        ];
+        assert_eq!(
+            expand_timeline(&entries, None),
+            vec![(100, 10), (110, 10), (120, 5), (125, 20), (145, 20), (165, 7)]
+        );
+
+        // Open-ended repeat in the last entry runs to the period end
+        let entries = [TimelineEntry { t: Some(0), d: 4, r: -1 }];
+        assert_eq!(expand_timeline(&entries, Some(10)).len(), 3);
+        assert_eq!(expand_timeline(&entries, None).len(), 1);
+    }
+
+    fn variant_for(mpd: &Mpd, height: Option<u32>, bandwidth: u64) -> MediaVariant {
+        let rep = mpd
+            .periods
+            .iter()
+            .flat_map(|p| &p.adaptation_sets)
+            .flat_map(|a| &a.representations)
+            .find(|r| r.height == height && r.bandwidth == Some(bandwidth))
+            .expect("representation in fixture");
+        MediaVariant {
+            url: "https://example.com/manifest.mpd".to_string(),
+            label: build_label(rep),
+            width: rep.width,
+            height: rep.height,
+            bandwidth: rep.bandwidth,
+            codecs: rep.codecs.clone(),
+            audio_only: rep.is_audio(),
+            estimated_size: None,
+            audio_tracks: Vec::new(),
+            subtitle_tracks: Vec::new(),
+        }
+    }
+
+    fn urls(list: &SegmentList) -> Vec<&str> {
+        list.segments.iter().map(|s| s.url.as_str()).collect()
     }
 
     #[test]
@@ -496,12 +1106,232 @@ mod tests {
     </AdaptationSet>
   </Period>
 </MPD>"#;
-        let (sets, dur) = parse_mpd(xml).unwrap();
-        assert!((dur - 10.0).abs() < 0.01);
+        let mpd = parse_mpd(xml).unwrap();
+        assert!((mpd.duration - 10.0).abs() < 0.01);
+        assert!(!mpd.dynamic);
+        assert_eq!(mpd.periods.len(), 1);
+        let sets = &mpd.periods[0].adaptation_sets;
         assert_eq!(sets.len(), 1);
         assert_eq!(sets[0].representations.len(), 2);
         assert_eq!(sets[0].representations[0].height, Some(720));
         assert_eq!(sets[0].representations[1].height, Some(480));
+
+        let variant = variant_for(&mpd, Some(480), 800000);
+        let list = variant_segments(&mpd, &variant, "https://example.com/v/manifest.mpd", Utc::now()).unwrap();
+        assert_eq!(list.init.unwrap().url, "https://example.com/v/init.m4s");
+        assert_eq!(list.segments.len(), 5);
+        assert_eq!(list.segments[4].url, "https://example.com/v/seg-5.m4s");
+        assert!(list.live.is_none());
+    }
+
+    #[test]
+    fn test_segment_timeline_mpd() {
+        let mpd = parse_mpd(include_str!("../../tests/fixtures/dash/timeline.mpd")).unwrap();
+        let mpd_url = "https://origin.example.com/vod/manifest.mpd";
+
+        let video = variant_for(&mpd, Some(720), 2400000);
+        let list = variant_segments(&mpd, &video, mpd_url, Utc::now()).unwrap();
+        assert_eq!(list.init.as_ref().unwrap().url, "https://cdn.example.com/vod/v720/init.mp4");
+        // 3×4s, 1×2s, then 3s repeated up to the 20s period end
+        assert_eq!(
+            urls(&list),
+            vec![
+                "https://cdn.example.com/vod/v720/t0.m4s",
+                "https://cdn.example.com/vod/v720/t360000.m4s",
+                "https://cdn.example.com/vod/v720/t720000.m4s",
+                "https://cdn.example.com/vod/v720/t1080000.m4s",
+                "https://cdn.example.com/vod/v720/t1260000.m4s",
+                "https://cdn.example.com/vod/v720/t1530000.m4s",
+            ]
+        );
+
+        let audio = variant_for(&mpd, None, 128000);
+        assert!(audio.audio_only);
+        let list = variant_segments(&mpd, &audio, mpd_url, Utc::now()).unwrap();
+        assert_eq!(list.segments.len(), 5);
+        assert_eq!(list.segments[0].url, "https://cdn.example.com/vod/audio/00000.m4s");
+        assert_eq!(list.segments[4].url, "https://cdn.example.com/vod/audio/00004.m4s");
+    }
+
+    #[test]
+    fn test_audio_renditions_pair_with_video() {
+        let xml = r#"<MPD mediaPresentationDuration="PT4S"><Period>
+  <AdaptationSet mimeType="video/mp4">
+    <SegmentTemplate media="$RepresentationID$/$Number$.m4s" timescale="1" duration="2"/>
+    <Representation id="v" bandwidth="3000000" height="1080" codecs="avc1.640028"/>
+  </AdaptationSet>
+  <AdaptationSet mimeType="audio/mp4" lang="en">
+    <SegmentTemplate media="$RepresentationID$/$Number$.m4s" timescale="1" duration="2"/>
+    <Representation id="a64" bandwidth="64000" codecs="mp4a.40.5"/>
+    <Representation id="a128" bandwidth="128000" codecs="mp4a.40.2"/>
+  </AdaptationSet>
+</Period></MPD>"#;
+        let mpd = parse_mpd(xml).unwrap();
+        let mpd_url = "https://example.com/manifest.mpd";
+        let renditions = audio_renditions(&mpd.periods[0].adaptation_sets, mpd_url);
+        assert_eq!(renditions.len(), 2);
+        // Best bitrate first, and picked by default
+        assert_eq!(renditions[0].bandwidth, Some(128000));
+        assert!(renditions[0].default && !renditions[1].default);
+        assert_eq!(renditions[0].name, "Audio 128kbps (en)");
+
+        // The picked rendition resolves to its own representation's segments
+        let picked = crate::media::select_audio_rendition(&renditions, None).unwrap();
+        let variant = crate::media::rendition_variant(picked, true).unwrap();
+        let list = variant_segments(&mpd, &variant, mpd_url, Utc::now()).unwrap();
+        assert_eq!(urls(&list), vec!["https://example.com/a128/1.m4s", "https://example.com/a128/2.m4s"]);
+    }
+
+    #[test]
+    fn test_subtitle_renditions() {
+        let xml = r#"<MPD mediaPresentationDuration="PT4S"><Period>
+  <AdaptationSet mimeType="video/mp4">
+    <SegmentTemplate media="$RepresentationID$/$Number$.m4s" timescale="1" duration="2"/>
+    <Representation id="v" bandwidth="3000000" height="1080" codecs="avc1.640028"/>
+  </AdaptationSet>
+  <AdaptationSet mimeType="text/vtt" lang="en">
+    <Representation id="sub-en" bandwidth="256"><BaseURL>subs/en.vtt</BaseURL></Representation>
+  </AdaptationSet>
+  <AdaptationSet mimeType="text/vtt" lang="fr">
+    <SegmentTemplate media="$RepresentationID$/$Number$.vtt" timescale="1" duration="2"/>
+    <Representation id="sub-fr" bandwidth="256"/>
+  </AdaptationSet>
+  <AdaptationSet mimeType="application/mp4" lang="de" codecs="stpp">
+    <Representation id="sub-de" bandwidth="256"/>
+  </AdaptationSet>
+</Period></MPD>"#;
+        let mpd = parse_mpd(xml).unwrap();
+        let mpd_url = "https://example.com/manifest.mpd";
+        let renditions = subtitle_renditions(&mpd.periods[0].adaptation_sets, mpd_url);
+        assert_eq!(renditions.len(), 2);
+        assert_eq!(renditions[0].name, "en (sub-en)");
+        assert_eq!(renditions[0].url.as_deref(), Some("https://example.com/subs/en.vtt"));
+        assert_eq!(renditions[1].language.as_deref(), Some("fr"));
+        assert_eq!(renditions[1].url.as_deref(), Some(mpd_url));
+
+        // Both text representations share a bandwidth: only the name tells
+        // them apart, and a text rendition never falls back to the video
+        let variant = crate::media::rendition_variant(&renditions[1], false).unwrap();
+        let list = variant_segments(&mpd, &variant, mpd_url, Utc::now()).unwrap();
+        assert_eq!(urls(&list), vec!["https://example.com/sub-fr/1.vtt", "https://example.com/sub-fr/2.vtt"]);
+    }
+
+    #[test]
+    fn test_multi_period_mpd() {
+        let mpd = parse_mpd(include_str!("../../tests/fixtures/dash/multi_period.mpd")).unwrap();
+        assert_eq!(mpd.periods.len(), 2);
+        assert_eq!(mpd.period_timing(0), (0.0, Some(6.0)));
+        assert_eq!(mpd.period_timing(1), (6.0, Some(24.0)));
+        // Variants come from the main content, not the pre-roll
+        assert_eq!(mpd.primary_period().unwrap().id.as_deref(), Some("content"));
+
+        let variant = variant_for(&mpd, Some(720), 2400000);
+        let list = variant_segments(&mpd, &variant, "https://example.com/show/manifest.mpd", Utc::now()).unwrap();
+        assert_eq!(list.init.as_ref().unwrap().url, "https://example.com/show/ads/ad-init.mp4");
+        let urls = urls(&list);
+        assert_eq!(urls.len(), 3 + 1 + 6);
+        assert_eq!(urls[0], "https://example.com/show/ads/ad-1.m4s");
+        assert_eq!(urls[2], "https://example.com/show/ads/ad-3.m4s");
+        // The content period's init section starts the second period
+        assert_eq!(urls[3], "https://example.com/show/main720/init.mp4");
+        assert_eq!(urls[4], "https://example.com/show/main720/1.m4s");
+        assert_eq!(urls[9], "https://example.com/show/main720/6.m4s");
+    }
+
+    #[test]
+    fn test_periods_with_own_base_url() {
+        // Content split into periods that differ only in their BaseURL
+        let mpd = parse_mpd(include_str!("../../tests/fixtures/dash/base_url_periods.mpd")).unwrap();
+        let variant = variant_for(&mpd, Some(720), 2400000);
+        let list = variant_segments(&mpd, &variant, "https://example.com/show/manifest.mpd", Utc::now()).unwrap();
+        assert_eq!(list.init.as_ref().unwrap().url, "https://cdn.example.com/show/part1/v720/init.mp4");
+        assert_eq!(
+            urls(&list),
+            vec![
+                "https://cdn.example.com/show/part1/v720/1.m4s",
+                "https://cdn.example.com/show/part1/v720/2.m4s",
+                "https://cdn.example.com/show/part2/v720/init.mp4",
+                "https://cdn.example.com/show/part2/v720/1.m4s",
+                "https://cdn.example.com/show/part2/v720/2.m4s",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segment_list_byte_ranges() {
+        let xml = r#"<MPD mediaPresentationDuration="PT4S"><Period>
+  <AdaptationSet mimeType="video/mp4">
+    <Representation id="v" bandwidth="500000" height="360">
+      <BaseURL>video.mp4</BaseURL>
+      <SegmentList>
+        <Initialization range="0-799"/>
+        <SegmentURL mediaRange="800-1799"/>
+        <SegmentURL mediaRange="1800-2299"/>
+      </SegmentList>
+    </Representation>
+  </AdaptationSet>
+</Period></MPD>"#;
+        let mpd = parse_mpd(xml).unwrap();
+        let variant = variant_for(&mpd, Some(360), 500000);
+        let list = variant_segments(&mpd, &variant, "https://example.com/a/manifest.mpd", Utc::now()).unwrap();
+        let init = list.init.unwrap();
+        assert_eq!(init.url, "https://example.com/a/video.mp4");
+        assert_eq!(init.byte_range, Some(ByteRange { offset: 0, length: 800 }));
+        assert_eq!(list.segments.len(), 2);
+        assert_eq!(list.segments[1].byte_range, Some(ByteRange { offset: 1800, length: 500 }));
+    }
+
+    #[test]
+    fn test_parse_dynamic_mpd() {
+        let xml = r#"<?xml version="1.0"?>
+<MPD type="dynamic" availabilityStartTime="2024-05-01T12:00:00Z" minimumUpdatePeriod="PT30S">
+  <Period start="PT0S">
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate media="live-$Number$.m4s" initialization="init.m4s" startNumber="1" timescale="1" duration="2"/>
+      <Representation id="v" bandwidth="1000000" height="720"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mpd = parse_mpd(xml).unwrap();
+        assert!(mpd.dynamic);
+        assert_eq!(mpd.minimum_update_period, Some(30.0));
+        assert_eq!(
+            mpd.availability_start_time,
+            Some(DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc))
+        );
+        // Number templates are polled per segment, not per manifest update
+        assert_eq!(live_info(&mpd, Some(2.0)), Some(LiveInfo { refresh_interval: 2.0 }));
+    }
+
+    #[test]
+    fn test_live_segment_timeline_window() {
+        let xml = r#"<MPD type="dynamic" availabilityStartTime="2024-05-01T12:00:00Z" minimumUpdatePeriod="PT10S">
+  <Period id="p0" start="PT0S">
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate media="$Time$.m4s" timescale="1000">
+        <SegmentTimeline><S t="1000" d="2000" r="24"/></SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="v" bandwidth="1000000" height="720"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mpd = parse_mpd(xml).unwrap();
+        let variant = variant_for(&mpd, Some(720), 1000000);
+        let list = variant_segments(&mpd, &variant, "https://live.example.com/m.mpd", Utc::now()).unwrap();
+        // Only the newest entries of the 25-segment window are offered
+        assert_eq!(list.segments.len(), LIVE_WINDOW_SEGMENTS as usize);
+        assert_eq!(list.segments.last().unwrap().url, "https://live.example.com/49000.m4s");
+        assert_eq!(list.live, Some(LiveInfo { refresh_interval: 2.0 }));
+    }
+
+    #[test]
+    fn test_live_segment_numbers() {
+        // 9s in with 2s segments: numbers 1..=4 are complete
+        assert_eq!(live_segment_numbers(9.0, 2.0, 1, 10), 1..5);
+        // Only the newest `window` segments are offered
+        assert_eq!(live_segment_numbers(100.0, 2.0, 1, 3), 48..51);
+        // Nothing before the stream starts
+        assert_eq!(live_segment_numbers(-5.0, 2.0, 1, 3), 1..1);
     }
 
     #[test]
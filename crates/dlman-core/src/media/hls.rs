@@ -8,25 +8,87 @@
 //! - Media playlists with segment lists
 //! - Relative and absolute URL resolution
 //! - EXT-X-STREAM-INF attributes (BANDWIDTH, RESOLUTION, CODECS)
+//! - Alternate audio and subtitle renditions (EXT-X-MEDIA groups)
+//! - AES-128 encrypted segments (EXT-X-KEY), including the implicit IV
+//!   derived from the media sequence number
+//! - SAMPLE-AES encrypted MPEG-TS and packed audio segments (H.264, AAC)
+//! - Byte-range segments (EXT-X-BYTERANGE)
+//! - fMP4/CMAF init sections (EXT-X-MAP)
+//! - Live playlists (no EXT-X-ENDLIST), flagged for periodic re-polling
 //!
 //! Does NOT support (yet):
-//! - AES-128 encrypted segments (EXT-X-KEY)
-//! - Byte-range requests (EXT-X-BYTERANGE)
-//! - Live streams (only VOD)
+//! - SAMPLE-AES in fMP4 segments, SAMPLE-AES-CTR and DRM key systems
+//!   (FairPlay, Widevine, ...)
+//! - More than one distinct init section per playlist
 
+use crate::engine::{HostLimiter, RequestHeaders};
 use crate::error::DlmanError;
-use crate::media::ProtocolHandler;
-use dlman_types::{MediaProtocol, MediaVariant};
+use crate::media::{host_slot, ByteRange, KeyMethod, LiveInfo, MediaSegment, ProtocolHandler, SegmentKey, SegmentList};
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use dlman_types::{MediaProtocol, MediaRendition, MediaVariant};
+use std::collections::HashMap;
+use std::sync::Arc;
 use url::Url;
 
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// Decrypt an AES-128 encrypted segment in place.
+pub fn decrypt_aes128(data: &mut Vec<u8>, key: &[u8; 16], iv: &[u8; 16]) -> Result<(), DlmanError> {
+    let len = Aes128CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_mut::<Pkcs7>(data)
+        .map_err(|_| {
+            DlmanError::InvalidOperation(
+                "Failed to decrypt HLS segment (wrong key or corrupt data)".to_string(),
+            )
+        })?
+        .len();
+    data.truncate(len);
+    Ok(())
+}
+
+/// The EXT-X-KEY in effect while parsing a media playlist.
+#[derive(Debug, Clone)]
+struct PlaylistKey {
+    method: KeyMethod,
+    uri: String,
+    iv: Option<[u8; 16]>,
+}
+
+impl PlaylistKey {
+    /// Key for one segment: the explicit IV, or the media sequence number as
+    /// a 128-bit big-endian integer (RFC 8216 §5.2).
+    fn for_sequence(&self, sequence: u64) -> SegmentKey {
+        SegmentKey {
+            method: self.method,
+            uri: self.uri.clone(),
+            iv: self.iv.unwrap_or_else(|| (sequence as u128).to_be_bytes()),
+        }
+    }
+}
+
 /// Handler for HLS (m3u8) streams.
 pub struct HlsHandler {
     client: reqwest::Client,
+    headers: RequestHeaders,
+    host_limiter: Option<Arc<HostLimiter>>,
 }
 
 impl HlsHandler {
     pub fn new(client: reqwest::Client) -> Self {
-        Self { client }
+        Self { client, headers: RequestHeaders::default(), host_limiter: None }
+    }
+
+    /// Send `headers` (header rules, the download's own and its login) with
+    /// each request, matched against the URL of that request
+    pub fn with_headers(mut self, headers: RequestHeaders) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Take a connection slot from `limiter` for each request
+    pub fn with_host_limiter(mut self, limiter: Option<Arc<HostLimiter>>) -> Self {
+        self.host_limiter = limiter;
+        self
     }
 
     /// Fetch and parse a playlist, returning its content.
@@ -35,11 +97,12 @@ impl HlsHandler {
         url: &str,
         headers: &[(String, String)],
     ) -> Result<String, DlmanError> {
+        let _slot = host_slot(self.host_limiter.as_deref(), url).await;
         let mut request = self.client.get(url);
         for (key, value) in headers {
             request = request.header(key.as_str(), value.as_str());
         }
-        let response = request.send().await?;
+        let response = self.headers.send(request, url).await?;
         if !response.status().is_success() {
             return Err(DlmanError::ServerError {
                 status: response.status().as_u16(),
@@ -60,15 +123,52 @@ impl HlsHandler {
         Ok(text)
     }
 
+    /// Fetch a 16-byte AES-128 key.
+    pub async fn fetch_key(
+        &self,
+        uri: &str,
+        headers: &[(String, String)],
+    ) -> Result<[u8; 16], DlmanError> {
+        let _slot = host_slot(self.host_limiter.as_deref(), uri).await;
+        let mut request = self.client.get(uri);
+        for (key, value) in headers {
+            request = request.header(key.as_str(), value.as_str());
+        }
+        let response = self.headers.send(request, uri).await?;
+        if !response.status().is_success() {
+            return Err(DlmanError::ServerError {
+                status: response.status().as_u16(),
+                message: format!("Failed to fetch HLS key: {}", uri),
+            });
+        }
+        let bytes = response.bytes().await?;
+        <[u8; 16]>::try_from(bytes.as_ref()).map_err(|_| {
+            DlmanError::InvalidOperation(format!(
+                "HLS key {} is {} bytes, expected 16",
+                uri,
+                bytes.len()
+            ))
+        })
+    }
+
     /// Check if a playlist is a master playlist (contains EXT-X-STREAM-INF).
     fn is_master_playlist(content: &str) -> bool {
         content.contains("#EXT-X-STREAM-INF")
     }
 
     /// Parse a master playlist into quality variants.
+    ///
+    /// Alternate renditions (EXT-X-MEDIA) are attached to every variant that
+    /// references their group through its AUDIO / SUBTITLES attribute.
     fn parse_master_playlist(content: &str, base_url: &Url) -> Vec<MediaVariant> {
         let mut variants = Vec::new();
         let lines: Vec<&str> = content.lines().collect();
+        let renditions = Self::parse_renditions(&lines, base_url);
+        let group = |attrs: &str, kind: &str, name: &str| -> Vec<MediaRendition> {
+            Self::parse_attribute(attrs, name)
+                .and_then(|id| renditions.get(&(kind.to_string(), id)).cloned())
+                .unwrap_or_default()
+        };
 
         let mut i = 0;
         while i < lines.len() {
@@ -80,6 +180,8 @@ impl HlsHandler {
                     .and_then(|v| v.parse::<u64>().ok());
                 let (width, height) = Self::parse_resolution(attrs);
                 let codecs = Self::parse_attribute(attrs, "CODECS");
+                let audio_tracks = group(attrs, "AUDIO", "AUDIO");
+                let subtitle_tracks = group(attrs, "SUBTITLES", "SUBTITLES");
 
                 // Next non-comment, non-empty line is the variant URL
                 i += 1;
@@ -98,6 +200,8 @@ impl HlsHandler {
                             codecs,
                             audio_only: width.is_none() && height.is_none(),
                             estimated_size: None,
+                            audio_tracks,
+                            subtitle_tracks,
                         });
                         break;
                     }
@@ -113,21 +217,211 @@ impl HlsHandler {
         variants
     }
 
-    /// Parse a media playlist into segment URLs.
-    fn parse_media_playlist(content: &str, base_url: &Url) -> Vec<String> {
-        let mut segments = Vec::new();
+    /// Collect EXT-X-MEDIA renditions, keyed by (TYPE, GROUP-ID).
+    fn parse_renditions(
+        lines: &[&str],
+        base_url: &Url,
+    ) -> HashMap<(String, String), Vec<MediaRendition>> {
+        let mut groups: HashMap<(String, String), Vec<MediaRendition>> = HashMap::new();
+        for line in lines {
+            let Some(attrs) = line.trim().strip_prefix("#EXT-X-MEDIA:") else {
+                continue;
+            };
+            let (Some(kind), Some(group_id)) = (
+                Self::parse_attribute(attrs, "TYPE"),
+                Self::parse_attribute(attrs, "GROUP-ID"),
+            ) else {
+                continue;
+            };
+            if kind != "AUDIO" && kind != "SUBTITLES" {
+                continue;
+            }
+            let language = Self::parse_attribute(attrs, "LANGUAGE");
+            groups.entry((kind, group_id)).or_default().push(MediaRendition {
+                url: Self::parse_attribute(attrs, "URI").map(|u| Self::resolve_url(base_url, &u)),
+                name: Self::parse_attribute(attrs, "NAME")
+                    .or_else(|| language.clone())
+                    .unwrap_or_else(|| "Unknown".to_string()),
+                language,
+                default: Self::parse_attribute(attrs, "DEFAULT").as_deref() == Some("YES"),
+                bandwidth: None,
+                codecs: None,
+            });
+        }
+        groups
+    }
+
+    /// Parse a media playlist into segments.
+    ///
+    /// Tracks the state that carries over between segment lines: the media
+    /// sequence number (the implicit AES IV), the EXT-X-KEY in effect, and
+    /// the end of the previous byte range (EXT-X-BYTERANGE without an offset
+    /// continues where the last sub-range of the same resource stopped).
+    fn parse_media_playlist(content: &str, base_url: &Url) -> Result<SegmentList, DlmanError> {
+        let mut list = SegmentList::default();
+        let mut sequence: u64 = 0;
+        let mut key: Option<PlaylistKey> = None;
+        let mut pending_range: Option<(u64, Option<u64>)> = None;
+        // (url, next offset) of the last byte-range segment
+        let mut last_range_end: Option<(String, u64)> = None;
+        let mut target_duration: f64 = 0.0;
+        let mut ended = false;
 
         for line in content.lines() {
             let line = line.trim();
+            if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+                target_duration = value.trim().parse().unwrap_or(0.0);
+                continue;
+            }
+            if line == "#EXT-X-ENDLIST" || line == "#EXT-X-PLAYLIST-TYPE:VOD" {
+                ended = true;
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                sequence = value.trim().parse().unwrap_or(0);
+                continue;
+            }
+            if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+                key = Self::parse_key(attrs, base_url)?;
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+                pending_range = Self::parse_byte_range(value);
+                continue;
+            }
+            if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+                let init = Self::parse_map(attrs, base_url, key.as_ref(), sequence)?;
+                // The key's implicit IV follows the sequence number, so a
+                // repeated map only has to point at the same bytes
+                match &list.init {
+                    Some(existing) if existing.url != init.url || existing.byte_range != init.byte_range => {
+                        return Err(DlmanError::InvalidOperation(
+                            "HLS playlists with multiple init sections (EXT-X-MAP) are not supported"
+                                .to_string(),
+                        ));
+                    }
+                    // The init section is fetched once, as the first map says
+                    Some(_) => {}
+                    None => list.init = Some(init),
+                }
+                continue;
+            }
             // Skip empty lines and comments
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
             // This is a segment URL
-            segments.push(Self::resolve_url(base_url, line));
+            let url = Self::resolve_url(base_url, line);
+            let byte_range = pending_range.take().map(|(length, offset)| {
+                let offset = offset.unwrap_or_else(|| match &last_range_end {
+                    Some((prev_url, end)) if *prev_url == url => *end,
+                    _ => 0,
+                });
+                ByteRange { offset, length }
+            });
+            last_range_end = byte_range.map(|r| (url.clone(), r.offset + r.length));
+
+            list.segments.push(MediaSegment {
+                url,
+                byte_range,
+                key: key.as_ref().map(|k| k.for_sequence(sequence)),
+            });
+            sequence += 1;
+        }
+
+        // Encrypted fMP4 samples are described by the init section and the
+        // fragments' own boxes, which this doesn't read
+        let sample_aes = list.segments.iter().any(|s| s.key.as_ref().is_some_and(|k| k.method == KeyMethod::SampleAes));
+        if sample_aes && list.is_fmp4() {
+            return Err(DlmanError::InvalidOperation(
+                "SAMPLE-AES encrypted fMP4 streams are not supported".to_string(),
+            ));
+        }
+
+        if !ended {
+            list.live = Some(LiveInfo {
+                refresh_interval: if target_duration > 0.0 { target_duration } else { 6.0 },
+            });
+        }
+
+        Ok(list)
+    }
+
+    /// Parse EXT-X-KEY attributes. Returns `None` for `METHOD=NONE` and an
+    /// error for methods we can't decrypt, so those streams fail up front
+    /// instead of producing an unplayable file.
+    fn parse_key(attrs: &str, base_url: &Url) -> Result<Option<PlaylistKey>, DlmanError> {
+        let method = Self::parse_attribute(attrs, "METHOD").unwrap_or_default();
+        let method = match method.as_str() {
+            "AES-128" => KeyMethod::Aes128,
+            "SAMPLE-AES" => KeyMethod::SampleAes,
+            "NONE" => return Ok(None),
+            _ => {
+                return Err(DlmanError::InvalidOperation(format!(
+                    "{} encrypted HLS streams are not supported",
+                    method
+                )));
+            }
+        };
+        if let Some(format) = Self::parse_attribute(attrs, "KEYFORMAT") {
+            if format != "identity" {
+                return Err(DlmanError::InvalidOperation(format!(
+                    "DRM-protected HLS stream (KEYFORMAT=\"{}\") cannot be downloaded",
+                    format
+                )));
+            }
+        }
+        let uri = Self::parse_attribute(attrs, "URI").ok_or_else(|| {
+            DlmanError::InvalidOperation("EXT-X-KEY without a URI".to_string())
+        })?;
+        Ok(Some(PlaylistKey {
+            method,
+            uri: Self::resolve_url(base_url, &uri),
+            iv: Self::parse_attribute(attrs, "IV").and_then(|v| Self::parse_iv(&v)),
+        }))
+    }
+
+    /// Parse a hexadecimal IV attribute (`0x` + 32 hex digits).
+    fn parse_iv(value: &str) -> Option<[u8; 16]> {
+        let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X"))?;
+        // Shorter values are zero-padded on the left
+        if hex.is_empty() || hex.len() > 32 {
+            return None;
         }
+        let n = u128::from_str_radix(hex, 16).ok()?;
+        Some(n.to_be_bytes())
+    }
+
+    /// Parse an EXT-X-BYTERANGE value (`<length>[@<offset>]`).
+    fn parse_byte_range(value: &str) -> Option<(u64, Option<u64>)> {
+        let mut parts = value.trim().splitn(2, '@');
+        let length = parts.next()?.parse().ok()?;
+        let offset = match parts.next() {
+            Some(o) => Some(o.parse().ok()?),
+            None => None,
+        };
+        Some((length, offset))
+    }
 
-        segments
+    /// Parse EXT-X-MAP attributes into the init section descriptor.
+    fn parse_map(
+        attrs: &str,
+        base_url: &Url,
+        key: Option<&PlaylistKey>,
+        sequence: u64,
+    ) -> Result<MediaSegment, DlmanError> {
+        let uri = Self::parse_attribute(attrs, "URI").ok_or_else(|| {
+            DlmanError::InvalidOperation("EXT-X-MAP without a URI".to_string())
+        })?;
+        // Unlike EXT-X-BYTERANGE, the offset is mandatory here
+        let byte_range = Self::parse_attribute(attrs, "BYTERANGE")
+            .and_then(|v| Self::parse_byte_range(&v))
+            .map(|(length, offset)| ByteRange { offset: offset.unwrap_or(0), length });
+        Ok(MediaSegment {
+            url: Self::resolve_url(base_url, &uri),
+            byte_range,
+            key: key.map(|k| k.for_sequence(sequence)),
+        })
     }
 
     /// Resolve a potentially relative URL against a base URL.
@@ -240,15 +534,17 @@ impl ProtocolHandler for HlsHandler {
                 codecs: None,
                 audio_only: false,
                 estimated_size: None,
+                audio_tracks: Vec::new(),
+                subtitle_tracks: Vec::new(),
             }])
         }
     }
 
-    async fn get_segment_urls(
+    async fn get_segments(
         &self,
         variant: &MediaVariant,
         headers: &[(String, String)],
-    ) -> Result<Vec<String>, DlmanError> {
+    ) -> Result<SegmentList, DlmanError> {
         let content = self.fetch_playlist(&variant.url, headers).await?;
         let base_url =
             Url::parse(&variant.url).map_err(|e| DlmanError::InvalidUrl(e.to_string()))?;
@@ -260,14 +556,14 @@ impl ProtocolHandler for HlsHandler {
                 let media_content = self.fetch_playlist(&first.url, headers).await?;
                 let media_base =
                     Url::parse(&first.url).map_err(|e| DlmanError::InvalidUrl(e.to_string()))?;
-                return Ok(Self::parse_media_playlist(&media_content, &media_base));
+                return Self::parse_media_playlist(&media_content, &media_base);
             }
             return Err(DlmanError::InvalidOperation(
                 "Empty HLS master playlist".to_string(),
             ));
         }
 
-        Ok(Self::parse_media_playlist(&content, &base_url))
+        Self::parse_media_playlist(&content, &base_url)
     }
 }
 
@@ -316,15 +612,199 @@ segment003.ts
         assert!(variants[0].url.contains("1080p.m3u8"));
     }
 
+    #[test]
+    fn test_parse_master_playlist_renditions() {
+        let playlist = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aud",NAME="English",LANGUAGE="en",DEFAULT=YES,URI="audio/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aud",NAME="Deutsch",LANGUAGE="de",URI="audio/de.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English CC",LANGUAGE="en",URI="subs/en.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720,AUDIO="aud",SUBTITLES="subs"
+720p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=500000,RESOLUTION=640x360
+360p.m3u8
+"#;
+        let base = Url::parse("https://example.com/stream/master.m3u8").unwrap();
+        let variants = HlsHandler::parse_master_playlist(playlist, &base);
+
+        assert_eq!(variants.len(), 2);
+        let audio = &variants[0].audio_tracks;
+        assert_eq!(audio.len(), 2);
+        assert_eq!(audio[0].name, "English");
+        assert_eq!(audio[0].language.as_deref(), Some("en"));
+        assert!(audio[0].default);
+        assert_eq!(audio[1].url.as_deref(), Some("https://example.com/stream/audio/de.m3u8"));
+        assert!(!audio[1].default);
+        assert_eq!(variants[0].subtitle_tracks.len(), 1);
+        assert_eq!(variants[0].subtitle_tracks[0].name, "English CC");
+
+        // A variant without group references carries no renditions
+        assert!(variants[1].audio_tracks.is_empty());
+        assert!(variants[1].subtitle_tracks.is_empty());
+    }
+
     #[test]
     fn test_parse_media_playlist() {
         let base = Url::parse("https://example.com/stream/720p.m3u8").unwrap();
-        let segments = HlsHandler::parse_media_playlist(MEDIA_PLAYLIST, &base);
+        let list = HlsHandler::parse_media_playlist(MEDIA_PLAYLIST, &base).unwrap();
+        let segments = &list.segments;
+
+        assert_eq!(segments.len(), 3);
+        assert!(segments[0].url.ends_with("segment001.ts"));
+        assert!(segments[1].url.ends_with("segment002.ts"));
+        assert!(segments[2].url.ends_with("segment003.ts"));
+        assert!(segments.iter().all(|s| s.key.is_none() && s.byte_range.is_none()));
+        assert!(!list.is_fmp4());
+        assert!(list.live.is_none());
+    }
+
+    #[test]
+    fn test_parse_live_playlist() {
+        let base = Url::parse("https://example.com/live/720p.m3u8").unwrap();
+        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:1500\n#EXTINF:4,\nseg1500.ts\n#EXTINF:4,\nseg1501.ts\n";
+        let list = HlsHandler::parse_media_playlist(playlist, &base).unwrap();
+        assert_eq!(list.live, Some(LiveInfo { refresh_interval: 4.0 }));
+        assert_eq!(list.segments.len(), 2);
+    }
+
+    const ENCRYPTED_PLAYLIST: &str = r#"#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:7
+#EXT-X-KEY:METHOD=AES-128,URI="keys/k1.bin"
+#EXTINF:10.0,
+a.ts
+#EXT-X-KEY:METHOD=AES-128,URI="https://keys.example.com/k2",IV=0x000102030405060708090A0B0C0D0E0F
+#EXTINF:10.0,
+b.ts
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:10.0,
+c.ts
+#EXT-X-ENDLIST
+"#;
 
+    #[test]
+    fn test_parse_encrypted_media_playlist() {
+        let base = Url::parse("https://example.com/stream/720p.m3u8").unwrap();
+        let segments = HlsHandler::parse_media_playlist(ENCRYPTED_PLAYLIST, &base)
+            .unwrap()
+            .segments;
         assert_eq!(segments.len(), 3);
-        assert!(segments[0].ends_with("segment001.ts"));
-        assert!(segments[1].ends_with("segment002.ts"));
-        assert!(segments[2].ends_with("segment003.ts"));
+
+        // Implicit IV is the media sequence number
+        let first = segments[0].key.as_ref().unwrap();
+        assert_eq!(first.uri, "https://example.com/stream/keys/k1.bin");
+        let mut expected = [0u8; 16];
+        expected[15] = 7;
+        assert_eq!(first.iv, expected);
+
+        let second = segments[1].key.as_ref().unwrap();
+        assert_eq!(second.uri, "https://keys.example.com/k2");
+        assert_eq!(second.iv, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+
+        assert!(segments[2].key.is_none());
+    }
+
+    #[test]
+    fn test_sample_aes_keys() {
+        let base = Url::parse("https://example.com/stream/720p.m3u8").unwrap();
+        let playlist = "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key.bin\",KEYFORMAT=\"identity\"\n#EXTINF:10,\na.ts\n";
+        let segments = HlsHandler::parse_media_playlist(playlist, &base).unwrap().segments;
+        let key = segments[0].key.as_ref().unwrap();
+        assert_eq!(key.method, KeyMethod::SampleAes);
+        assert_eq!(key.uri, "https://example.com/stream/key.bin");
+
+        // FairPlay keys can't be fetched, and fMP4 samples aren't decrypted
+        let fairplay = "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"skd://key\",KEYFORMAT=\"com.apple.streamingkeydelivery\"\n#EXTINF:10,\na.ts\n";
+        assert!(HlsHandler::parse_media_playlist(fairplay, &base).is_err());
+        let fmp4 = "#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key.bin\"\n#EXTINF:4,\nseg1.m4s\n";
+        assert!(HlsHandler::parse_media_playlist(fmp4, &base).is_err());
+    }
+
+    const BYTE_RANGE_PLAYLIST: &str = r#"#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-VERSION:7
+#EXT-X-MAP:URI="main.mp4",BYTERANGE="720@0"
+#EXTINF:6.0,
+#EXT-X-BYTERANGE:5000@720
+main.mp4
+#EXTINF:6.0,
+#EXT-X-BYTERANGE:4000
+main.mp4
+#EXTINF:6.0,
+#EXT-X-BYTERANGE:3000
+main.mp4
+#EXT-X-ENDLIST
+"#;
+
+    #[test]
+    fn test_parse_byte_range_playlist() {
+        let base = Url::parse("https://example.com/stream/720p.m3u8").unwrap();
+        let list = HlsHandler::parse_media_playlist(BYTE_RANGE_PLAYLIST, &base).unwrap();
+
+        let init = list.init.as_ref().unwrap();
+        assert_eq!(init.url, "https://example.com/stream/main.mp4");
+        assert_eq!(init.byte_range, Some(ByteRange { offset: 0, length: 720 }));
+
+        let ranges: Vec<_> = list.segments.iter().map(|s| s.byte_range.unwrap()).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ByteRange { offset: 720, length: 5000 },
+                // No offset: continues after the previous sub-range
+                ByteRange { offset: 5720, length: 4000 },
+                ByteRange { offset: 9720, length: 3000 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_fmp4_playlist() {
+        let base = Url::parse("https://example.com/cmaf/video.m3u8").unwrap();
+        let playlist = "#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4,\nseg1.m4s\n#EXTINF:4,\nseg2.m4s\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4,\nseg3.m4s\n";
+        let list = HlsHandler::parse_media_playlist(playlist, &base).unwrap();
+        assert!(list.is_fmp4());
+        assert_eq!(list.init.unwrap().url, "https://example.com/cmaf/init.mp4");
+        assert_eq!(list.segments.len(), 3);
+
+        let switching = "#EXTM3U\n#EXT-X-MAP:URI=\"a.mp4\"\n#EXTINF:4,\nseg1.m4s\n#EXT-X-MAP:URI=\"b.mp4\"\n#EXTINF:4,\nseg2.m4s\n";
+        assert!(HlsHandler::parse_media_playlist(switching, &base).is_err());
+    }
+
+    #[test]
+    fn test_repeated_map_with_implicit_iv() {
+        // The same map after a discontinuity, encrypted without an IV: its
+        // key's IV differs from the first map's
+        let base = Url::parse("https://example.com/cmaf/video.m3u8").unwrap();
+        let playlist = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:10\n#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+                        #EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4,\nseg1.m4s\n#EXTINF:4,\nseg2.m4s\n\
+                        #EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4,\nseg3.m4s\n#EXT-X-ENDLIST\n";
+        let list = HlsHandler::parse_media_playlist(playlist, &base).unwrap();
+        assert_eq!(list.segments.len(), 3);
+        let init = list.init.unwrap();
+        assert_eq!(init.url, "https://example.com/cmaf/init.mp4");
+        assert_eq!(init.key.unwrap().iv[15], 10);
+    }
+
+    #[test]
+    fn test_decrypt_aes128() {
+        use aes::cipher::BlockEncryptMut;
+        type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+        let key = [0x2bu8; 16];
+        let iv = (42u128).to_be_bytes();
+        let plaintext = b"G@\x00\x10 transport stream payload".to_vec();
+        let mut buf = vec![0u8; plaintext.len() + 16];
+        buf[..plaintext.len()].copy_from_slice(&plaintext);
+        let mut data = Aes128CbcEnc::new(&key.into(), &iv.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plaintext.len())
+            .unwrap()
+            .to_vec();
+        assert_ne!(data, plaintext);
+
+        decrypt_aes128(&mut data, &key, &iv).unwrap();
+        assert_eq!(data, plaintext);
+
+        let mut garbage = vec![0u8; 32];
+        assert!(decrypt_aes128(&mut garbage, &key, &iv).is_err());
     }
 
     #[test]
@@ -19,9 +19,153 @@
 
 pub mod hls;
 pub mod dash;
+pub mod remux;
+pub mod sample_aes;
+pub mod subtitles;
 
+use crate::engine::{HostLimiter, HostSlot, RequestHeaders};
 use crate::error::DlmanError;
-use dlman_types::{DetectedMedia, MediaProtocol, MediaVariant};
+use dlman_types::{DetectedMedia, MediaProtocol, MediaRendition, MediaVariant};
+use std::sync::Arc;
+
+// ============================================================================
+// Segment Descriptors
+// ============================================================================
+
+/// A byte range within a resource (`offset` is the first byte).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl ByteRange {
+    /// Value for an HTTP `Range` request header.
+    pub fn header_value(&self) -> String {
+        format!(
+            "bytes={}-{}",
+            self.offset,
+            self.offset + self.length.saturating_sub(1)
+        )
+    }
+}
+
+/// How a segment is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMethod {
+    /// The whole segment, AES-128-CBC with PKCS#7 padding
+    Aes128,
+    /// Parts of each video/audio sample (see [`sample_aes`])
+    SampleAes,
+}
+
+/// AES-128-CBC key reference for an encrypted segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    pub method: KeyMethod,
+    /// Absolute key URI
+    pub uri: String,
+    /// IV to decrypt this segment with
+    pub iv: [u8; 16],
+}
+
+/// One downloadable piece of a media stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSegment {
+    pub url: String,
+    /// Sub-range of `url` to fetch; `None` means the whole resource
+    pub byte_range: Option<ByteRange>,
+    /// Decryption key, if the segment is encrypted
+    pub key: Option<SegmentKey>,
+}
+
+impl MediaSegment {
+    /// A plain, unencrypted segment covering a whole resource.
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            byte_range: None,
+            key: None,
+        }
+    }
+}
+
+/// Refresh information for a live manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiveInfo {
+    /// Seconds to wait before re-fetching the manifest
+    pub refresh_interval: f64,
+}
+
+/// Everything needed to download one rendition of a stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentList {
+    /// Initialization section (fMP4/CMAF); written before the first segment
+    pub init: Option<MediaSegment>,
+    /// Media segments in playback order
+    pub segments: Vec<MediaSegment>,
+    /// Set while the stream is live: the manifest must be re-polled for new
+    /// segments. `None` once the stream is complete (VOD, EXT-X-ENDLIST,
+    /// static MPD).
+    pub live: Option<LiveInfo>,
+}
+
+impl SegmentList {
+    /// Whether the segments are fragmented MP4 rather than MPEG-TS.
+    pub fn is_fmp4(&self) -> bool {
+        self.init.is_some()
+    }
+}
+
+// ============================================================================
+// Alternate Renditions
+// ============================================================================
+
+/// Pick the audio rendition to download alongside a variant.
+///
+/// Honors `requested` when it is a valid index, otherwise falls back to the
+/// manifest's DEFAULT rendition, then the first one. Returns `None` when the
+/// picked rendition has no URI — its audio is already muxed into the variant.
+pub fn select_audio_rendition(
+    tracks: &[MediaRendition],
+    requested: Option<usize>,
+) -> Option<&MediaRendition> {
+    let picked = requested
+        .and_then(|i| tracks.get(i))
+        .or_else(|| tracks.iter().find(|t| t.default))
+        .or_else(|| tracks.first())?;
+    picked.url.is_some().then_some(picked)
+}
+
+/// Pick the subtitle renditions to download alongside a variant: the
+/// requested one, or every track when none was requested (so captions are
+/// archived by default). Renditions without a URI can't be fetched and are
+/// left out.
+pub fn select_subtitle_renditions(
+    tracks: &[MediaRendition],
+    requested: Option<usize>,
+) -> Vec<&MediaRendition> {
+    match requested {
+        Some(i) => tracks.get(i).into_iter().filter(|t| t.url.is_some()).collect(),
+        None => tracks.iter().filter(|t| t.url.is_some()).collect(),
+    }
+}
+
+/// A resolvable variant for a standalone rendition playlist.
+pub fn rendition_variant(rendition: &MediaRendition, audio_only: bool) -> Option<MediaVariant> {
+    Some(MediaVariant {
+        url: rendition.url.clone()?,
+        label: rendition.name.clone(),
+        width: None,
+        height: None,
+        bandwidth: rendition.bandwidth,
+        codecs: rendition.codecs.clone(),
+        audio_only,
+        estimated_size: None,
+        audio_tracks: Vec::new(),
+        subtitle_tracks: Vec::new(),
+    })
+}
 
 // ============================================================================
 // Protocol Handler Trait
@@ -46,18 +190,26 @@ pub trait ProtocolHandler: Send + Sync {
         headers: &[(String, String)],
     ) -> Result<Vec<MediaVariant>, DlmanError>;
 
-    /// Given a chosen variant, return the list of segment URLs
-    /// to download (for HLS/DASH) or the single direct URL.
-    async fn get_segment_urls(
+    /// Given a chosen variant, return the segments to download (for
+    /// HLS/DASH) or the single direct URL, plus any init section.
+    async fn get_segments(
         &self,
         variant: &MediaVariant,
         headers: &[(String, String)],
-    ) -> Result<Vec<String>, DlmanError>;
+    ) -> Result<SegmentList, DlmanError>;
 
     /// The protocol this handler supports.
     fn protocol(&self) -> MediaProtocol;
 }
 
+/// Wait for a connection slot on the host of `url`, if connections are capped
+pub(crate) async fn host_slot(limiter: Option<&HostLimiter>, url: &str) -> Option<HostSlot> {
+    match limiter {
+        Some(limiter) => Some(limiter.acquire(url).await),
+        None => None,
+    }
+}
+
 // ============================================================================
 // Media Downloader (Facade)
 // ============================================================================
@@ -69,11 +221,27 @@ pub trait ProtocolHandler: Send + Sync {
 /// the detected protocol.
 pub struct MediaResolver {
     http_client: reqwest::Client,
+    headers: RequestHeaders,
+    host_limiter: Option<Arc<HostLimiter>>,
 }
 
 impl MediaResolver {
     pub fn new(http_client: reqwest::Client) -> Self {
-        Self { http_client }
+        Self { http_client, headers: RequestHeaders::default(), host_limiter: None }
+    }
+
+    /// Take a connection slot from `limiter` for each manifest request
+    pub fn with_host_limiter(mut self, limiter: Arc<HostLimiter>) -> Self {
+        self.host_limiter = Some(limiter);
+        self
+    }
+
+    /// Send `headers` (header rules and the download's own) and the saved
+    /// login with manifest and playlist requests. They are matched against
+    /// the URL of each request.
+    pub fn with_headers(mut self, headers: RequestHeaders) -> Self {
+        self.headers = headers;
+        self
     }
 
     /// Resolve available variants for a detected media stream.
@@ -99,41 +267,56 @@ impl MediaResolver {
                     codecs: None,
                     audio_only: false,
                     estimated_size: None,
+                    audio_tracks: Vec::new(),
+                    subtitle_tracks: Vec::new(),
                 }])
             }
             MediaProtocol::Hls => {
-                let handler = hls::HlsHandler::new(self.http_client.clone());
+                let handler = hls::HlsHandler::new(self.http_client.clone())
+                    .with_headers(self.headers.clone())
+                    .with_host_limiter(self.host_limiter.clone());
                 handler.resolve_variants(&media.master_url, &headers).await
             }
             MediaProtocol::Dash => {
-                let handler = dash::DashHandler::new(self.http_client.clone());
+                let handler = dash::DashHandler::new(self.http_client.clone())
+                    .with_headers(self.headers.clone())
+                    .with_host_limiter(self.host_limiter.clone());
                 handler.resolve_variants(&media.master_url, &headers).await
             }
         }
     }
 
-    /// Get the downloadable segment URLs for a chosen variant.
+    /// Get the downloadable segments for a chosen variant.
     pub async fn get_segments(
         &self,
         media: &DetectedMedia,
         variant: &MediaVariant,
-    ) -> Result<Vec<String>, DlmanError> {
+    ) -> Result<SegmentList, DlmanError> {
         let headers = self.build_headers(media);
 
         match media.protocol {
-            MediaProtocol::Direct => Ok(vec![variant.url.clone()]),
+            MediaProtocol::Direct => Ok(SegmentList {
+                init: None,
+                segments: vec![MediaSegment::new(variant.url.clone())],
+                live: None,
+            }),
             MediaProtocol::Hls => {
-                let handler = hls::HlsHandler::new(self.http_client.clone());
-                handler.get_segment_urls(variant, &headers).await
+                let handler = hls::HlsHandler::new(self.http_client.clone())
+                    .with_headers(self.headers.clone())
+                    .with_host_limiter(self.host_limiter.clone());
+                handler.get_segments(variant, &headers).await
             }
             MediaProtocol::Dash => {
-                let handler = dash::DashHandler::new(self.http_client.clone());
-                handler.get_segment_urls(variant, &headers).await
+                let handler = dash::DashHandler::new(self.http_client.clone())
+                    .with_headers(self.headers.clone())
+                    .with_host_limiter(self.host_limiter.clone());
+                handler.get_segments(variant, &headers).await
             }
         }
     }
 
-    /// Build HTTP headers from media metadata (cookies, referrer).
+    /// Build HTTP headers from media metadata (cookies, referrer). Header
+    /// rules go on top of them per request.
     fn build_headers(&self, media: &DetectedMedia) -> Vec<(String, String)> {
         let mut headers = Vec::new();
         if let Some(ref cookies) = media.cookies {
@@ -149,6 +332,7 @@ impl MediaResolver {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dlman_types::{HeaderRule, RequestHeader};
 
     #[test]
     fn test_direct_protocol_returns_single_variant() {
@@ -173,4 +357,124 @@ mod tests {
         assert_eq!(variants.len(), 1);
         assert_eq!(variants[0].url, "https://example.com/video.mp4");
     }
+
+    fn rendition(name: &str, url: Option<&str>, default: bool) -> MediaRendition {
+        MediaRendition {
+            url: url.map(str::to_string),
+            name: name.to_string(),
+            language: None,
+            default,
+            bandwidth: None,
+            codecs: None,
+        }
+    }
+
+    #[test]
+    fn test_select_audio_rendition() {
+        let tracks = vec![
+            rendition("English", Some("https://example.com/en.m3u8"), false),
+            rendition("French", Some("https://example.com/fr.m3u8"), true),
+            rendition("Muxed", None, false),
+        ];
+
+        assert_eq!(select_audio_rendition(&tracks, Some(0)).unwrap().name, "English");
+        // Default rendition wins when nothing (or nothing valid) was requested
+        assert_eq!(select_audio_rendition(&tracks, None).unwrap().name, "French");
+        assert_eq!(select_audio_rendition(&tracks, Some(9)).unwrap().name, "French");
+        // Audio already inside the variant stream needs no separate download
+        assert!(select_audio_rendition(&tracks, Some(2)).is_none());
+        assert!(select_audio_rendition(&[], None).is_none());
+    }
+
+    #[test]
+    fn test_select_subtitle_renditions() {
+        let tracks = vec![
+            rendition("English CC", Some("https://example.com/en.m3u8"), true),
+            rendition("Closed captions (in-band)", None, false),
+            rendition("German", Some("https://example.com/de.m3u8"), false),
+        ];
+
+        let names = |picked: Vec<&MediaRendition>| picked.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(select_subtitle_renditions(&tracks, None)), vec!["English CC", "German"]);
+        assert_eq!(names(select_subtitle_renditions(&tracks, Some(2))), vec!["German"]);
+        assert!(select_subtitle_renditions(&tracks, Some(1)).is_empty());
+        assert!(select_subtitle_renditions(&tracks, Some(9)).is_empty());
+    }
+
+    /// Serve a master playlist on 127.0.0.1 whose variant is on "localhost",
+    /// the CDN. Only requests to the CDN may carry its token.
+    async fn serve_cdn_stream() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let token = request.contains("x-cdn-token: t0ken\r\n");
+                let body = if request.starts_with("get /master.m3u8 ") && !token {
+                    format!(
+                        "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1000000\nhttp://localhost:{}/cdn/720p.m3u8\n",
+                        port
+                    )
+                } else if request.starts_with("get /cdn/720p.m3u8 ") && token {
+                    "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nseg1.ts\n#EXT-X-ENDLIST\n".to_string()
+                } else {
+                    let _ = socket
+                        .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                        .await;
+                    continue;
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://127.0.0.1:{}/master.m3u8", port)
+    }
+
+    #[tokio::test]
+    async fn test_header_rules_match_each_playlist_url() {
+        let master_url = serve_cdn_stream().await;
+        let media = DetectedMedia {
+            id: "test".to_string(),
+            page_url: "https://example.com".to_string(),
+            page_title: None,
+            master_url: master_url.clone(),
+            protocol: MediaProtocol::Hls,
+            variants: vec![],
+            mime_type: None,
+            filename: None,
+            duration: None,
+            thumbnail: None,
+            cookies: None,
+            referrer: None,
+        };
+        let rule = HeaderRule::new("localhost".to_string(), vec![RequestHeader::new("X-Cdn-Token", "t0ken")]);
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let resolver = MediaResolver::new(client).with_headers(RequestHeaders::new(vec![rule], &master_url, Vec::new()));
+
+        let variants = resolver.resolve(&media).await.unwrap();
+        assert!(variants[0].url.starts_with("http://localhost:"));
+        let list = resolver.get_segments(&media, &variants[0]).await.unwrap();
+        assert_eq!(list.segments.len(), 1);
+        assert!(list.segments[0].url.ends_with("/cdn/seg1.ts"));
+    }
+
+    #[test]
+    fn test_byte_range_header() {
+        let range = ByteRange { offset: 1000, length: 500 };
+        assert_eq!(range.header_value(), "bytes=1000-1499");
+    }
 }
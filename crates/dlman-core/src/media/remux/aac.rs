@@ -0,0 +1,223 @@
+//! AAC in ADTS framing: header parsing and the `esds` decoder configuration.
+
+use super::mp4::{full_box, mp4_box};
+use super::{SampleSink, Track, TrackKind};
+use crate::error::DlmanError;
+use std::io::Read;
+
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// PCM samples per AAC frame.
+pub(super) const SAMPLES_PER_FRAME: u32 = 1024;
+
+/// Decoder configuration carried by every ADTS header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct AudioConfig {
+    /// MPEG-4 audio object type (2 = AAC-LC)
+    pub object_type: u8,
+    pub frequency_index: u8,
+    pub channel_config: u8,
+}
+
+impl AudioConfig {
+    pub(super) fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES
+            .get(self.frequency_index as usize)
+            .copied()
+            .unwrap_or(48000)
+    }
+
+    /// AudioSpecificConfig (ISO/IEC 14496-3).
+    fn audio_specific_config(&self) -> [u8; 2] {
+        [
+            (self.object_type << 3) | (self.frequency_index >> 1),
+            ((self.frequency_index & 1) << 7) | (self.channel_config << 3),
+        ]
+    }
+
+    /// An `mp4a` sample entry box.
+    pub(super) fn sample_entry(&self) -> Vec<u8> {
+        let asc = self.audio_specific_config();
+        let mut dec_specific = vec![0x05, asc.len() as u8];
+        dec_specific.extend_from_slice(&asc);
+
+        let mut dec_config = vec![0x40, 0x15, 0, 0, 0];
+        dec_config.extend_from_slice(&[0; 8]); // max / average bitrate unknown
+        dec_config.extend_from_slice(&dec_specific);
+        let mut es = vec![0, 0, 0, 0x04, dec_config.len() as u8];
+        es.extend_from_slice(&dec_config);
+        es.extend_from_slice(&[0x06, 1, 0x02]);
+        let mut esds = vec![0x03, es.len() as u8];
+        esds.extend_from_slice(&es);
+
+        let mut body = vec![0; 6];
+        body.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        body.extend_from_slice(&[0; 8]);
+        body.extend_from_slice(&u16::from(self.channel_config.max(1)).to_be_bytes());
+        body.extend_from_slice(&16u16.to_be_bytes());
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(&(self.sample_rate().min(0xffff) << 16).to_be_bytes());
+        body.extend_from_slice(&full_box(b"esds", 0, 0, &esds));
+        mp4_box(b"mp4a", &body)
+    }
+}
+
+/// One ADTS frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct AdtsHeader {
+    pub config: AudioConfig,
+    pub header_len: usize,
+    /// Whole frame length, header included
+    pub frame_len: usize,
+}
+
+/// Parse the ADTS header at the start of `data`.
+pub(super) fn parse_adts(data: &[u8]) -> Option<AdtsHeader> {
+    if data.len() < 7 || data[0] != 0xff || data[1] & 0xf6 != 0xf0 {
+        return None;
+    }
+    let protection_absent = data[1] & 1 == 1;
+    let header_len = if protection_absent { 7 } else { 9 };
+    let frame_len =
+        (((data[3] & 0x03) as usize) << 11) | ((data[4] as usize) << 3) | ((data[5] >> 5) as usize);
+    if frame_len < header_len {
+        return None;
+    }
+    Some(AdtsHeader {
+        config: AudioConfig {
+            object_type: (data[2] >> 6) + 1,
+            frequency_index: (data[2] >> 2) & 0x0f,
+            channel_config: ((data[2] & 1) << 2) | (data[3] >> 6),
+        },
+        header_len,
+        frame_len,
+    })
+}
+
+/// ID3 PRIV owner carrying the 90 kHz MPEG-TS timestamp of the first frame
+/// in an HLS packed audio segment.
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+
+/// Length of the ID3v2 tag at the start of `data`, once its header is there.
+fn id3_len(data: &[u8]) -> Option<usize> {
+    let header = data.get(..10)?;
+    let size = header[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | (b & 0x7f) as usize);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+/// Demux raw ADTS (optionally with ID3 tags between segments) into a track.
+pub(super) fn demux(mut input: impl Read, sink: &mut SampleSink) -> Result<Vec<Track>, DlmanError> {
+    let mut track: Option<Track> = None;
+    let mut start = None;
+    let mut dts = 0i64;
+    let mut buf = Vec::new();
+    let mut pos = 0;
+    let mut eof = false;
+
+    loop {
+        // Keep enough buffered for the largest ADTS frame or ID3 header
+        if !eof && buf.len() - pos < 1 << 14 {
+            buf.drain(..pos);
+            pos = 0;
+            let mut chunk = [0u8; 1 << 16];
+            let n = input.read(&mut chunk)?;
+            eof = n == 0;
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let rest = &buf[pos..];
+        if rest.len() < 10 && eof {
+            break;
+        }
+
+        if rest.starts_with(b"ID3") {
+            let Some(len) = id3_len(rest) else {
+                break;
+            };
+            if len > rest.len() {
+                if eof {
+                    break;
+                }
+                // A large tag: read it in whole
+                let mut tag = vec![0u8; len - rest.len()];
+                input.read_exact(&mut tag)?;
+                buf.extend_from_slice(&tag);
+                continue;
+            }
+            let tag = &rest[..len];
+            if start.is_none() && track.is_none() {
+                start = tag
+                    .windows(TIMESTAMP_OWNER.len())
+                    .position(|w| w == TIMESTAMP_OWNER)
+                    .and_then(|p| tag.get(p + TIMESTAMP_OWNER.len()..p + TIMESTAMP_OWNER.len() + 8))
+                    .map(|ts| {
+                        (u64::from_be_bytes(ts.try_into().unwrap()) & 0x1_ffff_ffff) as f64
+                            / 90_000.0
+                    });
+            }
+            pos += len;
+            continue;
+        }
+
+        let Some(header) = parse_adts(rest) else {
+            pos += 1;
+            continue;
+        };
+        if header.frame_len > rest.len() {
+            if eof {
+                break;
+            }
+            continue;
+        }
+        let track = track.get_or_insert_with(|| {
+            Track::new(
+                TrackKind::Audio,
+                header.config.sample_rate(),
+                SAMPLES_PER_FRAME,
+            )
+        });
+        track.use_sample_entry(header.config.sample_entry());
+        track.push(
+            sink,
+            &rest[header.header_len..header.frame_len],
+            dts,
+            Some(SAMPLES_PER_FRAME),
+            0,
+            true,
+        )?;
+        dts += SAMPLES_PER_FRAME as i64;
+        pos += header.frame_len;
+    }
+
+    Ok(track
+        .map(|mut track| {
+            // Without an ID3 timestamp the start is unknown, not zero
+            track.start = start;
+            track
+        })
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_adts() {
+        // AAC-LC, 44.1 kHz, stereo, 371-byte frame
+        let header = [0xff, 0xf1, 0x50, 0x80, 0x2e, 0x7f, 0xfc];
+        let parsed = parse_adts(&header).unwrap();
+        assert_eq!(parsed.config.object_type, 2);
+        assert_eq!(parsed.config.sample_rate(), 44100);
+        assert_eq!(parsed.config.channel_config, 2);
+        assert_eq!(parsed.header_len, 7);
+        assert_eq!(parsed.frame_len, 371);
+        assert_eq!(parsed.config.audio_specific_config(), [0x12, 0x10]);
+        assert!(parse_adts(&[0x47, 0x40, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+//! Bit-level reading of codec headers (parameter sets).
+
+use crate::error::DlmanError;
+
+/// Strip emulation-prevention bytes (`00 00 03` → `00 00`) from a NAL unit.
+pub(super) fn to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// MSB-first bit reader with Exp-Golomb support.
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(super) fn read_bit(&mut self) -> Result<u32, DlmanError> {
+        let byte = self.data.get(self.pos / 8).ok_or_else(|| {
+            DlmanError::InvalidOperation("Truncated codec parameter set".to_string())
+        })?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    pub(super) fn read_bits(&mut self, n: u32) -> Result<u64, DlmanError> {
+        let mut value = 0u64;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+
+    pub(super) fn skip_bits(&mut self, n: usize) -> Result<(), DlmanError> {
+        if self.pos + n > self.data.len() * 8 {
+            return Err(DlmanError::InvalidOperation(
+                "Truncated codec parameter set".to_string(),
+            ));
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    /// Unsigned Exp-Golomb code (`ue(v)`).
+    pub(super) fn read_ue(&mut self) -> Result<u32, DlmanError> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(DlmanError::InvalidOperation(
+                    "Invalid Exp-Golomb code".to_string(),
+                ));
+            }
+        }
+        let rest = self.read_bits(leading_zeros)?;
+        Ok(((1u64 << leading_zeros) - 1 + rest) as u32)
+    }
+
+    /// Signed Exp-Golomb code (`se(v)`).
+    pub(super) fn read_se(&mut self) -> Result<i32, DlmanError> {
+        let k = self.read_ue()? as i64;
+        Ok(if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) } as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exp_golomb() {
+        // 1 | 010 | 011 | 00100 | 00101 → ue: 0, 1, 2, 3; se(4) = -2
+        let data = [0b1010_0110, 0b0100_0010, 0b1000_0000];
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.read_ue().unwrap(), 0);
+        assert_eq!(r.read_ue().unwrap(), 1);
+        assert_eq!(r.read_ue().unwrap(), 2);
+        assert_eq!(r.read_ue().unwrap(), 3);
+        assert_eq!(r.read_se().unwrap(), -2);
+        assert!(r.read_bits(16).is_err());
+    }
+
+    #[test]
+    fn test_to_rbsp() {
+        assert_eq!(
+            to_rbsp(&[0x67, 0, 0, 3, 1, 0, 0, 3]),
+            vec![0x67, 0, 0, 1, 0, 0]
+        );
+    }
+}
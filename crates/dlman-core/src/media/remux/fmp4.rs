@@ -0,0 +1,433 @@
+//! Fragmented MP4 demuxing: `moov` track setup and `moof`/`mdat` fragments.
+//!
+//! Sample entries are copied verbatim from the init section, so any codec
+//! the source uses carries over. A later `moov` (e.g. a DASH period with a
+//! different init section) adds new sample entries to the same tracks.
+
+use super::{SampleSink, Track, TrackKind};
+use crate::error::DlmanError;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// `trex` / `tfhd` per-sample defaults.
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleDefaults {
+    description: u32,
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// A track of the current init section.
+struct InputTrack {
+    /// Index into the output tracks
+    track: usize,
+    timescale: u32,
+    defaults: SampleDefaults,
+    /// Output sample description for each input `stsd` entry
+    descriptions: Vec<Vec<u8>>,
+    /// Decode time of the next sample when a fragment has no `tfdt`
+    next_dts: i64,
+}
+
+/// A sample located by a `moof`, waiting for its `mdat`.
+struct PendingSample {
+    track_id: u32,
+    offset: u64,
+    size: u32,
+    dts: i64,
+    duration: u32,
+    cts_offset: i32,
+    sync: bool,
+    description: u32,
+}
+
+/// Demux a fragmented MP4 stream into tracks.
+pub(super) fn demux(mut input: impl Read, sink: &mut SampleSink) -> Result<Vec<Track>, DlmanError> {
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut inputs: HashMap<u32, InputTrack> = HashMap::new();
+    let mut pending: Vec<PendingSample> = Vec::new();
+    let mut pos = 0u64;
+
+    while let Some(BoxHeader {
+        kind,
+        header_len,
+        body_len,
+    }) = read_box_header(&mut input)?
+    {
+        let box_start = pos;
+        pos += header_len;
+        match &kind {
+            b"moov" | b"moof" | b"mdat" => {
+                let mut body = Vec::new();
+                match body_len {
+                    Some(len) => {
+                        (&mut input).take(len).read_to_end(&mut body)?;
+                        if (body.len() as u64) < len {
+                            tracing::warn!(
+                                "Truncated '{}' box at the end of the stream",
+                                String::from_utf8_lossy(&kind)
+                            );
+                        }
+                    }
+                    None => {
+                        input.read_to_end(&mut body)?;
+                    }
+                }
+                match &kind {
+                    b"moov" => parse_moov(&body, &mut tracks, &mut inputs)?,
+                    b"moof" => pending = parse_moof(&body, box_start, &mut inputs)?,
+                    _ => {
+                        for sample in pending.drain(..) {
+                            let start = sample.offset.checked_sub(pos);
+                            let data = start.and_then(|s| {
+                                body.get(s as usize..s as usize + sample.size as usize)
+                            });
+                            let (Some(data), Some(input_track)) =
+                                (data, inputs.get(&sample.track_id))
+                            else {
+                                continue;
+                            };
+                            let track = &mut tracks[input_track.track];
+                            let entry = input_track
+                                .descriptions
+                                .get(sample.description.saturating_sub(1) as usize)
+                                .or(input_track.descriptions.first())
+                                .cloned()
+                                .unwrap_or_default();
+                            track.use_sample_entry(entry);
+                            let (from, to) = (input_track.timescale, track.timescale);
+                            let scale = |v: i64| rescale(v, from, to);
+                            track.push(
+                                sink,
+                                data,
+                                scale(sample.dts),
+                                Some(scale(sample.duration as i64) as u32),
+                                scale(sample.cts_offset as i64) as i32,
+                                sample.sync,
+                            )?;
+                        }
+                    }
+                }
+                pos += body.len() as u64;
+            }
+            _ => {
+                let skipped = match body_len {
+                    Some(len) => std::io::copy(&mut (&mut input).take(len), &mut std::io::sink())?,
+                    None => std::io::copy(&mut input, &mut std::io::sink())?,
+                };
+                pos += skipped;
+            }
+        }
+    }
+    Ok(tracks)
+}
+
+/// A top-level box header read from the stream.
+struct BoxHeader {
+    kind: [u8; 4],
+    header_len: u64,
+    /// `None` when the box extends to the end of the stream
+    body_len: Option<u64>,
+}
+
+fn read_box_header(input: &mut impl Read) -> Result<Option<BoxHeader>, DlmanError> {
+    let mut header = [0u8; 8];
+    let mut filled = 0;
+    while filled < 8 {
+        let n = input.read(&mut header[filled..])?;
+        if n == 0 {
+            return Ok(None);
+        }
+        filled += n;
+    }
+    let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+    let kind: [u8; 4] = header[4..8].try_into().unwrap();
+    let (header_len, body_len) = match size {
+        0 => (8, None),
+        1 => {
+            let mut large = [0u8; 8];
+            input.read_exact(&mut large)?;
+            (16, Some(u64::from_be_bytes(large).saturating_sub(16)))
+        }
+        _ => (8, Some(size.saturating_sub(8))),
+    };
+    Ok(Some(BoxHeader {
+        kind,
+        header_len,
+        body_len,
+    }))
+}
+
+/// Iterate over the child boxes of `data` as `(type, body)`.
+fn children(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, rest.len()),
+            1 if rest.len() >= 16 => (
+                16,
+                u64::from_be_bytes(rest[8..16].try_into().unwrap()) as usize,
+            ),
+            _ => (8, size),
+        };
+        if size < header || size > rest.len() {
+            return None;
+        }
+        let item = (&rest[4..8], &rest[header..size]);
+        rest = &rest[size..];
+        Some(item)
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data)
+        .find(|(k, _)| k == kind)
+        .map(|(_, body)| body)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn malformed(what: &str) -> DlmanError {
+    DlmanError::InvalidOperation(format!("Malformed fragmented MP4: {}", what))
+}
+
+fn parse_moov(
+    moov: &[u8],
+    tracks: &mut Vec<Track>,
+    inputs: &mut HashMap<u32, InputTrack>,
+) -> Result<(), DlmanError> {
+    let mut trex = HashMap::new();
+    if let Some(mvex) = child(moov, b"mvex") {
+        for (kind, body) in children(mvex) {
+            if kind == b"trex" {
+                let track_id = read_u32(body, 4).ok_or_else(|| malformed("trex"))?;
+                trex.insert(
+                    track_id,
+                    SampleDefaults {
+                        description: read_u32(body, 8).unwrap_or(1),
+                        duration: read_u32(body, 12).unwrap_or(0),
+                        size: read_u32(body, 16).unwrap_or(0),
+                        flags: read_u32(body, 20).unwrap_or(0),
+                    },
+                );
+            }
+        }
+    }
+
+    let previous: HashMap<TrackKind, usize> = inputs
+        .values()
+        .map(|input| (tracks[input.track].kind, input.track))
+        .collect();
+    inputs.clear();
+
+    for (kind, trak) in children(moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let tkhd = child(trak, b"tkhd").ok_or_else(|| malformed("missing tkhd"))?;
+        let (track_id, size_offset) = if tkhd.first() == Some(&1) {
+            (read_u32(tkhd, 20), 88)
+        } else {
+            (read_u32(tkhd, 12), 76)
+        };
+        let track_id = track_id.ok_or_else(|| malformed("tkhd"))?;
+        let width = read_u32(tkhd, size_offset).unwrap_or(0) >> 16;
+        let height = read_u32(tkhd, size_offset + 4).unwrap_or(0) >> 16;
+
+        let mdia = child(trak, b"mdia").ok_or_else(|| malformed("missing mdia"))?;
+        let mdhd = child(mdia, b"mdhd").ok_or_else(|| malformed("missing mdhd"))?;
+        let timescale = if mdhd.first() == Some(&1) {
+            read_u32(mdhd, 20)
+        } else {
+            read_u32(mdhd, 12)
+        }
+        .filter(|&t| t > 0)
+        .ok_or_else(|| malformed("mdhd"))?;
+        let handler = child(mdia, b"hdlr").and_then(|h| h.get(8..12));
+        let kind = match handler {
+            Some(b"vide") => TrackKind::Video,
+            Some(b"soun") => TrackKind::Audio,
+            _ => continue,
+        };
+
+        let stsd = child(mdia, b"minf")
+            .and_then(|minf| child(minf, b"stbl"))
+            .and_then(|stbl| child(stbl, b"stsd"))
+            .ok_or_else(|| malformed("missing stsd"))?;
+        let mut descriptions = Vec::new();
+        let mut rest = stsd.get(8..).unwrap_or_default();
+        while let Some(size) = read_u32(rest, 0).map(|s| s as usize) {
+            let Some(entry) = rest.get(..size).filter(|_| size >= 8) else {
+                break;
+            };
+            if matches!(&entry[4..8], b"encv" | b"enca") {
+                return Err(DlmanError::InvalidOperation(
+                    "Encrypted (DRM-protected) streams can't be remuxed".to_string(),
+                ));
+            }
+            descriptions.push(entry.to_vec());
+            rest = &rest[size..];
+        }
+
+        // Continue the same output track across init sections
+        let track = match previous.get(&kind) {
+            Some(&index) if !inputs.values().any(|i| i.track == index) => index,
+            _ => {
+                let default_duration = if kind == TrackKind::Audio {
+                    1024
+                } else {
+                    timescale / 25
+                };
+                let mut track = Track::new(kind, timescale, default_duration.max(1));
+                track.width = width;
+                track.height = height;
+                tracks.push(track);
+                tracks.len() - 1
+            }
+        };
+        inputs.insert(
+            track_id,
+            InputTrack {
+                track,
+                timescale,
+                defaults: trex.get(&track_id).copied().unwrap_or(SampleDefaults {
+                    description: 1,
+                    ..Default::default()
+                }),
+                descriptions,
+                next_dts: 0,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Locate the samples of a `moof` (starting at file offset `moof_start`).
+fn parse_moof(
+    moof: &[u8],
+    moof_start: u64,
+    inputs: &mut HashMap<u32, InputTrack>,
+) -> Result<Vec<PendingSample>, DlmanError> {
+    let mut samples = Vec::new();
+    for (kind, traf) in children(moof) {
+        if kind != b"traf" {
+            continue;
+        }
+        let tfhd = child(traf, b"tfhd").ok_or_else(|| malformed("missing tfhd"))?;
+        let flags = read_u32(tfhd, 0).ok_or_else(|| malformed("tfhd"))? & 0x00ff_ffff;
+        let track_id = read_u32(tfhd, 4).ok_or_else(|| malformed("tfhd"))?;
+        let Some(input) = inputs.get_mut(&track_id) else {
+            continue;
+        };
+
+        let mut defaults = input.defaults;
+        let mut pos = 8;
+        let mut base_offset = moof_start;
+        if flags & 0x01 != 0 {
+            base_offset = read_u64(tfhd, pos).ok_or_else(|| malformed("tfhd"))?;
+            pos += 8;
+        }
+        for (flag, field) in [
+            (0x02, &mut defaults.description),
+            (0x08, &mut defaults.duration),
+            (0x10, &mut defaults.size),
+            (0x20, &mut defaults.flags),
+        ] {
+            if flags & flag != 0 {
+                *field = read_u32(tfhd, pos).ok_or_else(|| malformed("tfhd"))?;
+                pos += 4;
+            }
+        }
+
+        if let Some(tfdt) = child(traf, b"tfdt") {
+            input.next_dts = if tfdt.first() == Some(&1) {
+                read_u64(tfdt, 4).map(|t| t as i64)
+            } else {
+                read_u32(tfdt, 4).map(i64::from)
+            }
+            .ok_or_else(|| malformed("tfdt"))?;
+        }
+
+        let mut data_offset = base_offset;
+        for (kind, trun) in children(traf) {
+            if kind != b"trun" {
+                continue;
+            }
+            let header = read_u32(trun, 0).ok_or_else(|| malformed("trun"))?;
+            let (version, flags) = (header >> 24, header & 0x00ff_ffff);
+            let count = read_u32(trun, 4).ok_or_else(|| malformed("trun"))?;
+            let mut pos = 8;
+            if flags & 0x01 != 0 {
+                let offset = read_u32(trun, pos).ok_or_else(|| malformed("trun"))? as i32;
+                data_offset = base_offset.wrapping_add_signed(offset as i64);
+                pos += 4;
+            }
+            let mut first_flags = None;
+            if flags & 0x04 != 0 {
+                first_flags = read_u32(trun, pos);
+                pos += 4;
+            }
+
+            for i in 0..count {
+                let mut field = |flag: u32, default: u32| -> Result<u32, DlmanError> {
+                    if flags & flag == 0 {
+                        return Ok(default);
+                    }
+                    let value = read_u32(trun, pos).ok_or_else(|| malformed("trun"))?;
+                    pos += 4;
+                    Ok(value)
+                };
+                let duration = field(0x100, defaults.duration)?;
+                let size = field(0x200, defaults.size)?;
+                let mut sample_flags = field(0x400, defaults.flags)?;
+                let cts = field(0x800, 0)?;
+                if i == 0 {
+                    sample_flags = first_flags.unwrap_or(sample_flags);
+                }
+                let cts_offset = if version == 0 {
+                    cts.min(i32::MAX as u32) as i32
+                } else {
+                    cts as i32
+                };
+
+                samples.push(PendingSample {
+                    track_id,
+                    offset: data_offset,
+                    size,
+                    dts: input.next_dts,
+                    duration,
+                    cts_offset,
+                    // sample_is_non_sync_sample
+                    sync: sample_flags & 0x0001_0000 == 0,
+                    description: defaults.description,
+                });
+                data_offset += size as u64;
+                input.next_dts += duration as i64;
+            }
+        }
+    }
+    Ok(samples)
+}
+
+fn rescale(value: i64, from: u32, to: u32) -> i64 {
+    if from == to {
+        value
+    } else {
+        (value as i128 * to as i128 / from as i128) as i64
+    }
+}
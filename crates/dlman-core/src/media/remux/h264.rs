@@ -0,0 +1,189 @@
+//! H.264 / AVC: SPS parsing and the `avcC` decoder configuration.
+
+use super::bits::{to_rbsp, BitReader};
+use crate::error::DlmanError;
+
+pub(super) const NAL_IDR: u8 = 5;
+pub(super) const NAL_SPS: u8 = 7;
+pub(super) const NAL_PPS: u8 = 8;
+pub(super) const NAL_AUD: u8 = 9;
+
+pub(super) fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |b| b & 0x1f)
+}
+
+/// The parts of a sequence parameter set needed for the sample entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Profiles whose SPS carries chroma format / bit depth fields.
+fn has_chroma_info(profile_idc: u8) -> bool {
+    matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    )
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Result<(), DlmanError> {
+    let mut last_scale = 8i32;
+    let mut next_scale = 8i32;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = r.read_se()?;
+            next_scale = (last_scale + delta + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+/// Parse an SPS NAL unit (including its one-byte NAL header).
+pub(super) fn parse_sps(nal: &[u8]) -> Result<Sps, DlmanError> {
+    let rbsp = to_rbsp(nal.get(1..).unwrap_or_default());
+    let mut r = BitReader::new(&rbsp);
+
+    let profile_idc = r.read_bits(8)? as u8;
+    let constraint_flags = r.read_bits(8)? as u8;
+    let level_idc = r.read_bits(8)? as u8;
+    let _sps_id = r.read_ue()?;
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    let mut bit_depth_luma = 8;
+    let mut bit_depth_chroma = 8;
+    if has_chroma_info(profile_idc) {
+        chroma_format_idc = r.read_ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_bit()? == 1;
+        }
+        bit_depth_luma = r.read_ue()? + 8;
+        bit_depth_chroma = r.read_ue()? + 8;
+        let _qpprime_y_zero_transform_bypass = r.read_bit()?;
+        if r.read_bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.read_bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    let _log2_max_frame_num = r.read_ue()?;
+    match r.read_ue()? {
+        0 => {
+            let _log2_max_poc_lsb = r.read_ue()?;
+        }
+        1 => {
+            let _delta_pic_order_always_zero = r.read_bit()?;
+            let _offset_for_non_ref_pic = r.read_se()?;
+            let _offset_for_top_to_bottom_field = r.read_se()?;
+            for _ in 0..r.read_ue()? {
+                let _offset_for_ref_frame = r.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    let _max_num_ref_frames = r.read_ue()?;
+    let _gaps_in_frame_num_allowed = r.read_bit()?;
+    let width_in_mbs = r.read_ue()? + 1;
+    let height_in_map_units = r.read_ue()? + 1;
+    let frame_mbs_only = r.read_bit()?;
+    if frame_mbs_only == 0 {
+        let _mb_adaptive_frame_field = r.read_bit()?;
+    }
+    let _direct_8x8_inference = r.read_bit()?;
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if r.read_bit()? == 1 {
+        crop_left = r.read_ue()?;
+        crop_right = r.read_ue()?;
+        crop_top = r.read_ue()?;
+        crop_bottom = r.read_ue()?;
+    }
+
+    let (crop_unit_x, crop_unit_y) = if chroma_format_idc == 0 || separate_colour_plane {
+        (1, 2 - frame_mbs_only)
+    } else {
+        let sub_width = if chroma_format_idc == 3 { 1 } else { 2 };
+        let sub_height = if chroma_format_idc == 1 { 2 } else { 1 };
+        (sub_width, sub_height * (2 - frame_mbs_only))
+    };
+    let width = (width_in_mbs * 16).saturating_sub(crop_unit_x * (crop_left + crop_right));
+    let height = ((2 - frame_mbs_only) * height_in_map_units * 16)
+        .saturating_sub(crop_unit_y * (crop_top + crop_bottom));
+
+    Ok(Sps {
+        profile_idc,
+        constraint_flags,
+        level_idc,
+        chroma_format_idc,
+        bit_depth_luma,
+        bit_depth_chroma,
+        width,
+        height,
+    })
+}
+
+/// Build an `avcC` box body (AVCDecoderConfigurationRecord).
+pub(super) fn avcc(sps: &Sps, sps_nals: &[Vec<u8>], pps_nals: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![
+        1,
+        sps.profile_idc,
+        sps.constraint_flags,
+        sps.level_idc,
+        0xfc | 3, // 4-byte NAL length prefixes
+        0xe0 | sps_nals.len() as u8,
+    ];
+    for nal in sps_nals {
+        out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    out.push(pps_nals.len() as u8);
+    for nal in pps_nals {
+        out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    if has_chroma_info(sps.profile_idc) {
+        out.push(0xfc | sps.chroma_format_idc as u8);
+        out.push(0xf8 | (sps.bit_depth_luma - 8) as u8);
+        out.push(0xf8 | (sps.bit_depth_chroma - 8) as u8);
+        out.push(0); // no SPS extensions
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sps_with_cropping() {
+        // High profile, 1920x1080 (1088 coded, cropped by 8 lines)
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x84, 0x00, 0x00,
+            0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xf0, 0x3c, 0x60, 0xc6, 0x58,
+        ];
+        let parsed = parse_sps(&sps).unwrap();
+        assert_eq!(parsed.profile_idc, 100);
+        assert_eq!(parsed.level_idc, 40);
+        assert_eq!((parsed.width, parsed.height), (1920, 1080));
+        assert_eq!(parsed.chroma_format_idc, 1);
+        assert_eq!(parsed.bit_depth_luma, 8);
+
+        let config = avcc(&parsed, &[sps.to_vec()], &[vec![0x68, 0xeb, 0xe3, 0xcb]]);
+        assert_eq!(&config[..6], &[1, 0x64, 0x00, 0x28, 0xff, 0xe1]);
+        assert_eq!(&config[config.len() - 4..], &[0xfd, 0xf8, 0xf8, 0]);
+    }
+}
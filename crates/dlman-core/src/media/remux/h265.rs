@@ -0,0 +1,160 @@
+//! H.265 / HEVC: SPS parsing and the `hvcC` decoder configuration.
+
+use super::bits::{to_rbsp, BitReader};
+use crate::error::DlmanError;
+
+pub(super) const NAL_VPS: u8 = 32;
+pub(super) const NAL_SPS: u8 = 33;
+pub(super) const NAL_PPS: u8 = 34;
+pub(super) const NAL_AUD: u8 = 35;
+
+pub(super) fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |b| (b >> 1) & 0x3f)
+}
+
+/// IRAP pictures (BLA, IDR, CRA) are random access points.
+pub(super) fn is_irap(nal_type: u8) -> bool {
+    (16..=23).contains(&nal_type)
+}
+
+/// The parts of a sequence parameter set needed for the sample entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Sps {
+    /// general_profile_space … general_level_idc, verbatim (12 bytes)
+    pub general_ptl: [u8; 12],
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parse an SPS NAL unit (including its two-byte NAL header).
+pub(super) fn parse_sps(nal: &[u8]) -> Result<Sps, DlmanError> {
+    let rbsp = to_rbsp(nal.get(2..).unwrap_or_default());
+    if rbsp.len() < 13 {
+        return Err(DlmanError::InvalidOperation(
+            "Truncated HEVC parameter set".to_string(),
+        ));
+    }
+    let mut general_ptl = [0u8; 12];
+    general_ptl.copy_from_slice(&rbsp[1..13]);
+
+    let mut r = BitReader::new(&rbsp);
+    let _vps_id = r.read_bits(4)?;
+    let max_sub_layers_minus1 = r.read_bits(3)? as usize;
+    let temporal_id_nesting = r.read_bit()? == 1;
+
+    // profile_tier_level(1, max_sub_layers_minus1)
+    r.skip_bits(96)?;
+    let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((r.read_bit()? == 1, r.read_bit()? == 1));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip_bits(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present {
+            r.skip_bits(88)?;
+        }
+        if level_present {
+            r.skip_bits(8)?;
+        }
+    }
+
+    let _sps_id = r.read_ue()?;
+    let chroma_format_idc = r.read_ue()?;
+    if chroma_format_idc == 3 {
+        let _separate_colour_plane = r.read_bit()?;
+    }
+    let mut width = r.read_ue()?;
+    let mut height = r.read_ue()?;
+    if r.read_bit()? == 1 {
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let (left, right) = (r.read_ue()?, r.read_ue()?);
+        let (top, bottom) = (r.read_ue()?, r.read_ue()?);
+        width = width.saturating_sub(sub_width * (left + right));
+        height = height.saturating_sub(sub_height * (top + bottom));
+    }
+    let bit_depth_luma = r.read_ue()? + 8;
+    let bit_depth_chroma = r.read_ue()? + 8;
+
+    Ok(Sps {
+        general_ptl,
+        max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+        temporal_id_nesting,
+        chroma_format_idc,
+        bit_depth_luma,
+        bit_depth_chroma,
+        width,
+        height,
+    })
+}
+
+/// Build an `hvcC` box body (HEVCDecoderConfigurationRecord).
+pub(super) fn hvcc(sps: &Sps, vps: &[Vec<u8>], sps_nals: &[Vec<u8>], pps: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![1];
+    out.extend_from_slice(&sps.general_ptl);
+    out.extend_from_slice(&[
+        0xf0,
+        0x00, // min_spatial_segmentation_idc
+        0xfc, // parallelismType unknown
+        0xfc | sps.chroma_format_idc as u8,
+        0xf8 | (sps.bit_depth_luma - 8) as u8,
+        0xf8 | (sps.bit_depth_chroma - 8) as u8,
+        0,
+        0, // avgFrameRate unspecified
+        (sps.max_sub_layers << 3) | (u8::from(sps.temporal_id_nesting) << 2) | 3,
+    ]);
+
+    let arrays = [(NAL_VPS, vps), (NAL_SPS, sps_nals), (NAL_PPS, pps)];
+    out.push(arrays.iter().filter(|(_, nals)| !nals.is_empty()).count() as u8);
+    for (nal_type, nals) in arrays {
+        if nals.is_empty() {
+            continue;
+        }
+        out.push(0x80 | nal_type);
+        out.extend_from_slice(&(nals.len() as u16).to_be_bytes());
+        for nal in nals {
+            out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            out.extend_from_slice(nal);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sps() {
+        // Main profile, level 3.1, 1280x720
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x5d, 0xa0, 0x02, 0x80, 0x80, 0x2d, 0x16, 0x59, 0x59, 0xa4, 0x93,
+            0x2b, 0xc0, 0x40, 0x40, 0x00, 0x00, 0x03, 0x00, 0x40, 0x00, 0x00, 0x07, 0x82,
+        ];
+        let parsed = parse_sps(&sps).unwrap();
+        assert_eq!((parsed.width, parsed.height), (1280, 720));
+        assert_eq!(parsed.general_ptl[0], 0x01); // Main profile
+        assert_eq!(parsed.general_ptl[11], 93); // level 3.1
+        assert_eq!(parsed.chroma_format_idc, 1);
+
+        let config = hvcc(
+            &parsed,
+            &[vec![0x40, 0x01]],
+            &[sps.to_vec()],
+            &[vec![0x44, 0x01]],
+        );
+        assert_eq!(config[22], 3);
+        assert_eq!(config[23], 0x80 | NAL_VPS);
+    }
+}
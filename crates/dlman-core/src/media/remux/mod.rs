@@ -0,0 +1,570 @@
+//! In-process remuxing to MP4
+//!
+//! Turns what the stream handlers download into a regular (progressive)
+//! MP4 without re-encoding and without any external tools:
+//!
+//! - MPEG-TS (HLS segments concatenated): H.264, H.265 and AAC streams
+//! - Fragmented MP4 (init section + fragments, HLS fMP4 and DASH): any codec,
+//!   sample entries are carried over verbatim
+//! - Raw ADTS AAC (HLS "packed audio" renditions, ID3 tags allowed)
+//!
+//! Every input is demuxed into tracks whose sample data is streamed straight
+//! into the output's `mdat`; the `moov` index is written last. Output is
+//! deterministic — no wall-clock timestamps end up in the file — so the same
+//! inputs always produce the same bytes.
+
+mod aac;
+mod bits;
+mod fmp4;
+mod h264;
+mod h265;
+mod mp4;
+mod ts;
+
+use crate::error::DlmanError;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Timestamp jumps larger than this (in seconds) are treated as a
+/// discontinuity and stitched onto the previous sample.
+const MAX_TIMESTAMP_GAP_SECS: i64 = 10;
+
+/// Remux one or more downloaded streams into a single MP4 file.
+///
+/// Each input contributes its own tracks, so a video file and a separately
+/// downloaded audio rendition end up muxed together. Tracks are aligned by
+/// their source presentation timestamps. On error a partial `output` may be
+/// left behind; the inputs are never touched.
+pub fn remux_to_mp4(inputs: &[&Path], output: &Path) -> Result<(), DlmanError> {
+    let mut out = BufWriter::new(File::create(output)?);
+    let ftyp = mp4::ftyp();
+    out.write_all(&ftyp)?;
+
+    // mdat with a 64-bit size, patched once all samples are written
+    let mdat_start = ftyp.len() as u64;
+    out.write_all(&[0, 0, 0, 1, b'm', b'd', b'a', b't'])?;
+    out.write_all(&[0; 8])?;
+
+    let mut sink = SampleSink {
+        out: &mut out,
+        pos: mdat_start + 16,
+    };
+    let mut tracks = Vec::new();
+    for input in inputs {
+        tracks.extend(demux_file(input, &mut sink)?);
+    }
+    let mdat_end = sink.pos;
+
+    tracks.retain(|t| !t.samples.is_empty());
+    if tracks.is_empty() {
+        return Err(DlmanError::InvalidOperation(
+            "No supported audio or video streams to remux".to_string(),
+        ));
+    }
+    for track in &mut tracks {
+        track.finish();
+    }
+
+    out.write_all(&mp4::moov(&tracks))?;
+    out.seek(SeekFrom::Start(mdat_start + 8))?;
+    out.write_all(&(mdat_end - mdat_start).to_be_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Container format of a downloaded stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    MpegTs,
+    Fmp4,
+    Adts,
+}
+
+fn sniff(head: &[u8]) -> Option<Container> {
+    if head.first() == Some(&0x47) && head.get(188).is_none_or(|&b| b == 0x47) {
+        return Some(Container::MpegTs);
+    }
+    if head.len() >= 8
+        && matches!(
+            &head[4..8],
+            b"ftyp" | b"styp" | b"moov" | b"moof" | b"sidx" | b"free" | b"emsg" | b"prft"
+        )
+    {
+        return Some(Container::Fmp4);
+    }
+    if head.starts_with(b"ID3") || aac::parse_adts(head).is_some() {
+        return Some(Container::Adts);
+    }
+    None
+}
+
+fn demux_file(path: &Path, sink: &mut SampleSink) -> Result<Vec<Track>, DlmanError> {
+    let mut file = File::open(path)?;
+    let mut head = Vec::with_capacity(512);
+    (&mut file).take(512).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let reader = BufReader::with_capacity(1 << 16, file);
+    match sniff(&head) {
+        Some(Container::MpegTs) => ts::demux(reader, sink),
+        Some(Container::Fmp4) => fmp4::demux(reader, sink),
+        Some(Container::Adts) => aac::demux(reader, sink),
+        None => Err(DlmanError::InvalidOperation(format!(
+            "Unrecognized media container: {}",
+            path.display()
+        ))),
+    }
+}
+
+// ============================================================================
+// Tracks & Samples
+// ============================================================================
+
+/// Writes sample data into the output's `mdat`, tracking file offsets.
+pub(super) struct SampleSink<'a> {
+    out: &'a mut dyn Write,
+    pos: u64,
+}
+
+impl SampleSink<'_> {
+    /// Append sample data; returns its absolute offset in the output.
+    fn write(&mut self, data: &[u8]) -> Result<u64, DlmanError> {
+        let offset = self.pos;
+        self.out.write_all(data)?;
+        self.pos += data.len() as u64;
+        Ok(offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TrackKind {
+    Video,
+    Audio,
+}
+
+/// One access unit / audio frame already written to the output.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    offset: u64,
+    size: u32,
+    /// Decode time in the track timescale (continuous across discontinuities)
+    dts: i64,
+    /// Explicit duration; otherwise derived from the next sample's `dts`
+    duration: Option<u32>,
+    /// Presentation time minus decode time
+    cts_offset: i32,
+    sync: bool,
+    /// 1-based index into the track's sample entries
+    description: u32,
+}
+
+/// A demuxed track: its sample entries and the samples written so far.
+struct Track {
+    kind: TrackKind,
+    timescale: u32,
+    width: u32,
+    height: u32,
+    /// Complete sample entry boxes (`avc1`, `mp4a`, …) in `stsd` order
+    sample_entries: Vec<Vec<u8>>,
+    /// Sample entry (1-based) that new samples refer to
+    description: u32,
+    samples: Vec<Sample>,
+    /// Source presentation time of the first sample in seconds, when known;
+    /// used to line tracks up against each other
+    start: Option<f64>,
+    /// Offset applied to incoming timestamps to bridge discontinuities
+    shift: i64,
+    /// Duration assumed for a sample when nothing better is known
+    default_duration: u32,
+}
+
+impl Track {
+    fn new(kind: TrackKind, timescale: u32, default_duration: u32) -> Self {
+        Self {
+            kind,
+            timescale,
+            width: 0,
+            height: 0,
+            sample_entries: Vec::new(),
+            description: 0,
+            samples: Vec::new(),
+            start: None,
+            shift: 0,
+            default_duration,
+        }
+    }
+
+    /// Switch to `entry`, adding it to the sample descriptions if it is new.
+    fn use_sample_entry(&mut self, entry: Vec<u8>) {
+        self.description = match self.sample_entries.iter().position(|e| *e == entry) {
+            Some(i) => i as u32 + 1,
+            None => {
+                self.sample_entries.push(entry);
+                self.sample_entries.len() as u32
+            }
+        };
+    }
+
+    /// Write a sample's data and index it.
+    ///
+    /// `dts` is in the track timescale on the source's clock; timestamps that
+    /// go backwards or jump ahead (stream discontinuities, period changes) are
+    /// shifted so the timeline continues right after the previous sample.
+    fn push(
+        &mut self,
+        sink: &mut SampleSink,
+        data: &[u8],
+        dts: i64,
+        duration: Option<u32>,
+        cts_offset: i32,
+        sync: bool,
+    ) -> Result<(), DlmanError> {
+        if self.start.is_none() && self.samples.is_empty() {
+            self.start = Some((dts + cts_offset as i64) as f64 / self.timescale as f64);
+        }
+
+        let mut dts = dts + self.shift;
+        if let Some(last) = self.samples.last() {
+            let gap = dts - last.dts;
+            if gap <= 0 || gap > MAX_TIMESTAMP_GAP_SECS * self.timescale as i64 {
+                let expected = last.dts + self.last_duration() as i64;
+                self.shift += expected - dts;
+                dts = expected;
+            }
+        }
+
+        let offset = sink.write(data)?;
+        self.samples.push(Sample {
+            offset,
+            size: data.len() as u32,
+            dts,
+            duration,
+            cts_offset,
+            sync,
+            description: self.description,
+        });
+        Ok(())
+    }
+
+    /// Best guess for the duration of the most recent sample.
+    fn last_duration(&self) -> u32 {
+        match self.samples.as_slice() {
+            [.., prev, last] => last
+                .duration
+                .unwrap_or_else(|| (last.dts - prev.dts).clamp(1, u32::MAX as i64) as u32),
+            [last] => last.duration.unwrap_or(self.default_duration),
+            [] => self.default_duration,
+        }
+    }
+
+    /// Resolve every sample's duration once all samples are known.
+    fn finish(&mut self) {
+        let mut previous = self.default_duration;
+        for i in 0..self.samples.len() {
+            let duration = match (self.samples[i].duration, self.samples.get(i + 1)) {
+                (Some(d), _) => d,
+                (None, Some(next)) => {
+                    (next.dts - self.samples[i].dts).clamp(0, u32::MAX as i64) as u32
+                }
+                (None, None) => previous,
+            };
+            self.samples[i].duration = Some(duration);
+            previous = duration;
+        }
+    }
+
+    fn duration(&self, sample: &Sample) -> u32 {
+        sample.duration.unwrap_or(self.default_duration)
+    }
+
+    /// Total media duration in the track timescale.
+    fn media_duration(&self) -> u64 {
+        self.samples.iter().map(|s| self.duration(s) as u64).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/remux");
+
+    /// Find the body of the first box at `path` (container boxes only).
+    fn find_box<'a>(mut data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        for (depth, kind) in path.iter().enumerate() {
+            let mut found = None;
+            let mut rest = data;
+            while rest.len() >= 8 {
+                let mut size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as u64;
+                let mut header = 8;
+                if size == 1 {
+                    size = u64::from_be_bytes(rest[8..16].try_into().unwrap());
+                    header = 16;
+                }
+                let size = size as usize;
+                if &rest[4..8] == *kind {
+                    found = Some(&rest[header..size]);
+                    break;
+                }
+                rest = &rest[size..];
+            }
+            data = found?;
+            // stsd is a full box with an entry count before its children
+            if **kind == *b"stsd" && depth + 1 < path.len() {
+                data = &data[8..];
+            }
+        }
+        Some(data)
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn traks(moov: &[u8]) -> Vec<&[u8]> {
+        let mut out = Vec::new();
+        let mut rest = moov;
+        while rest.len() >= 8 {
+            let size = u32_at(rest, 0) as usize;
+            if &rest[4..8] == b"trak" {
+                out.push(&rest[8..size]);
+            }
+            rest = &rest[size..];
+        }
+        out
+    }
+
+    fn remux(inputs: &[&str]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.mp4");
+        let paths: Vec<_> = inputs.iter().map(|i| Path::new(FIXTURES).join(i)).collect();
+        let refs: Vec<_> = paths.iter().map(|p| p.as_path()).collect();
+        remux_to_mp4(&refs, &output).unwrap();
+        std::fs::read(output).unwrap()
+    }
+
+    #[test]
+    fn test_remux_transport_stream() {
+        let out = remux(&["av.ts"]);
+        assert_eq!(&out[4..8], b"ftyp");
+
+        let moov = find_box(&out, &[b"moov"]).unwrap();
+        let traks = traks(moov);
+        assert_eq!(traks.len(), 2);
+
+        // Video: 320x240 H.264, 10 frames with keyframes at 1 and 6
+        let video = traks[0];
+        let avc1 = find_box(video, &[b"mdia", b"minf", b"stbl", b"stsd", b"avc1"]).unwrap();
+        assert_eq!(u16::from_be_bytes([avc1[24], avc1[25]]), 320);
+        assert_eq!(u16::from_be_bytes([avc1[26], avc1[27]]), 240);
+        let stsz = find_box(video, &[b"mdia", b"minf", b"stbl", b"stsz"]).unwrap();
+        assert_eq!(u32_at(stsz, 8), 10);
+        let stss = find_box(video, &[b"mdia", b"minf", b"stbl", b"stss"]).unwrap();
+        assert_eq!(
+            (u32_at(stss, 4), u32_at(stss, 8), u32_at(stss, 12)),
+            (2, 1, 6)
+        );
+        let mdhd = find_box(video, &[b"mdia", b"mdhd"]).unwrap();
+        assert_eq!(u32_at(mdhd, 12), 90000);
+        assert_eq!(u32_at(mdhd, 16), 36000);
+        // B-frame reordering delay is trimmed by the edit list
+        let elst = find_box(video, &[b"edts", b"elst"]).unwrap();
+        assert_eq!((u32_at(elst, 4), u32_at(elst, 12)), (1, 3600));
+
+        // Audio: 48 kHz AAC starting 20 ms after the video
+        let audio = traks[1];
+        assert!(find_box(audio, &[b"mdia", b"minf", b"stbl", b"stsd", b"mp4a"]).is_some());
+        let mdhd = find_box(audio, &[b"mdia", b"mdhd"]).unwrap();
+        assert_eq!(u32_at(mdhd, 12), 48000);
+        assert_eq!(u32_at(mdhd, 16), 12 * 1024);
+        let elst = find_box(audio, &[b"edts", b"elst"]).unwrap();
+        assert_eq!(u32_at(elst, 4), 2);
+        assert_eq!(u32_at(elst, 8), 20);
+        assert_eq!(u32_at(elst, 12), u32::MAX); // empty edit
+    }
+
+    #[test]
+    fn test_remux_is_deterministic() {
+        assert_eq!(remux(&["av.ts"]), remux(&["av.ts"]));
+    }
+
+    #[test]
+    fn test_remux_fmp4_with_packed_audio() {
+        let out = remux(&["video.mp4", "audio.aac"]);
+        let moov = find_box(&out, &[b"moov"]).unwrap();
+        let traks = traks(moov);
+        assert_eq!(traks.len(), 2);
+
+        // Two fragments of 5 frames each, sample entry copied from the init
+        let video = traks[0];
+        assert!(find_box(video, &[b"mdia", b"minf", b"stbl", b"stsd", b"avc1"]).is_some());
+        let stsz = find_box(video, &[b"mdia", b"minf", b"stbl", b"stsz"]).unwrap();
+        assert_eq!(u32_at(stsz, 8), 10);
+        let stts = find_box(video, &[b"mdia", b"minf", b"stbl", b"stts"]).unwrap();
+        assert_eq!(
+            (u32_at(stts, 4), u32_at(stts, 8), u32_at(stts, 12)),
+            (1, 10, 512)
+        );
+
+        // Packed audio is placed by its ID3 timestamp (100 ms in)
+        let audio = traks[1];
+        let stsz = find_box(audio, &[b"mdia", b"minf", b"stbl", b"stsz"]).unwrap();
+        assert_eq!(u32_at(stsz, 8), 12);
+        let elst = find_box(audio, &[b"edts", b"elst"]).unwrap();
+        assert_eq!(u32_at(elst, 8), 100);
+    }
+
+    /// Top-level boxes of `data`: type, offset and size
+    fn top_level_boxes(data: &[u8]) -> Vec<([u8; 4], usize, usize)> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let mut size = u32_at(data, offset) as usize;
+            if size == 1 {
+                size = u64::from_be_bytes(data[offset + 8..offset + 16].try_into().unwrap()) as usize;
+            }
+            boxes.push((data[offset + 4..offset + 8].try_into().unwrap(), offset, size));
+            offset += size;
+        }
+        boxes
+    }
+
+    /// ADTS frames in `data`, skipping ID3 tags between them
+    fn adts_frame_count(mut data: &[u8]) -> u32 {
+        let mut count = 0;
+        while !data.is_empty() {
+            if data.starts_with(b"ID3") {
+                let size = data[6..10].iter().fold(0usize, |acc, &b| (acc << 7) | (b & 0x7f) as usize);
+                data = &data[10 + size..];
+                continue;
+            }
+            assert!(data[0] == 0xff && data[1] & 0xf0 == 0xf0, "lost ADTS sync");
+            let length = ((data[3] as usize & 0x03) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+            data = &data[length..];
+            count += 1;
+        }
+        count
+    }
+
+    /// Video and audio frames in a transport stream: one access unit per
+    /// video PES packet, and the ADTS frames in the audio PES payloads
+    fn ts_frame_counts(data: &[u8]) -> Vec<u32> {
+        let mut video = 0;
+        let mut audio_pid = None;
+        let mut audio = Vec::new();
+        for packet in data.chunks(188) {
+            assert_eq!(packet[0], 0x47);
+            if packet[3] & 0x10 == 0 {
+                continue;
+            }
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let mut payload = &packet[4..];
+            if packet[3] & 0x20 != 0 {
+                payload = &payload[1 + payload[0] as usize..];
+            }
+            if packet[1] & 0x40 != 0 && payload.starts_with(&[0, 0, 1]) {
+                match payload[3] {
+                    0xe0..=0xef => video += 1,
+                    0xc0..=0xdf => {
+                        audio_pid = Some(pid);
+                        audio.extend_from_slice(&payload[9 + payload[8] as usize..]);
+                    }
+                    _ => {}
+                }
+            } else if audio_pid == Some(pid) {
+                audio.extend_from_slice(payload);
+            }
+        }
+        vec![video, adts_frame_count(&audio)]
+    }
+
+    /// Samples listed by the `trun` boxes of a fragmented MP4
+    fn fmp4_sample_count(data: &[u8]) -> u32 {
+        top_level_boxes(data)
+            .into_iter()
+            .filter(|(kind, _, _)| kind == b"moof")
+            .map(|(_, offset, size)| {
+                let trun = find_box(&data[offset + 8..offset + size], &[b"traf", b"trun"]).unwrap();
+                u32_at(trun, 4)
+            })
+            .sum()
+    }
+
+    /// Sample count of each output track, after checking that its sample
+    /// tables agree with one another and that all samples fill the mdat
+    fn sample_counts(out: &[u8]) -> Vec<u32> {
+        let (_, mdat_offset, mdat_size) = top_level_boxes(out)
+            .into_iter()
+            .find(|(kind, _, _)| kind == b"mdat")
+            .unwrap();
+        let mdat = mdat_offset as u64 + 16..(mdat_offset + mdat_size) as u64;
+
+        let mut counts = Vec::new();
+        let mut total_size = 0u64;
+        for trak in traks(find_box(out, &[b"moov"]).unwrap()) {
+            let stbl = find_box(trak, &[b"mdia", b"minf", b"stbl"]).unwrap();
+            let stsz = find_box(stbl, &[b"stsz"]).unwrap();
+            let count = u32_at(stsz, 8);
+            total_size += (0..count as usize).map(|i| u32_at(stsz, 12 + 4 * i) as u64).sum::<u64>();
+
+            let stts = find_box(stbl, &[b"stts"]).unwrap();
+            let timed: u32 = (0..u32_at(stts, 4) as usize).map(|i| u32_at(stts, 8 + 8 * i)).sum();
+            assert_eq!(timed, count);
+
+            let offsets: Vec<u64> = match find_box(stbl, &[b"stco"]) {
+                Some(stco) => (0..u32_at(stco, 4) as usize).map(|i| u32_at(stco, 8 + 4 * i) as u64).collect(),
+                None => {
+                    let co64 = find_box(stbl, &[b"co64"]).unwrap();
+                    (0..u32_at(co64, 4) as usize)
+                        .map(|i| u64::from_be_bytes(co64[8 + 8 * i..16 + 8 * i].try_into().unwrap()))
+                        .collect()
+                }
+            };
+            assert!(offsets.iter().all(|o| mdat.contains(o)));
+            counts.push(count);
+        }
+        assert_eq!(total_size, mdat.end - mdat.start);
+        counts
+    }
+
+    #[test]
+    fn test_sample_tables_match_inputs() {
+        let read = |name: &str| std::fs::read(Path::new(FIXTURES).join(name)).unwrap();
+        let (ts, video, audio) = (read("av.ts"), read("video.mp4"), read("audio.aac"));
+
+        assert_eq!(sample_counts(&remux(&["av.ts"])), ts_frame_counts(&ts));
+        assert_eq!(
+            sample_counts(&remux(&["video.mp4", "audio.aac"])),
+            vec![fmp4_sample_count(&video), adts_frame_count(&audio)]
+        );
+        // fMP4 video on its own, as a DASH stream without separate audio
+        assert_eq!(sample_counts(&remux(&["video.mp4"])), vec![fmp4_sample_count(&video)]);
+    }
+
+    #[test]
+    fn test_remux_fmp4_with_init_per_period() {
+        // Two DASH periods downloaded back to back, each with its init section
+        let dir = tempfile::tempdir().unwrap();
+        let video = std::fs::read(Path::new(FIXTURES).join("video.mp4")).unwrap();
+        let input = dir.path().join("periods.mp4");
+        std::fs::write(&input, [video.as_slice(), video.as_slice()].concat()).unwrap();
+        let output = dir.path().join("out.mp4");
+        remux_to_mp4(&[&input], &output).unwrap();
+
+        let out = std::fs::read(output).unwrap();
+        assert_eq!(sample_counts(&out), vec![2 * fmp4_sample_count(&video)]);
+        // The second period continues the timeline rather than restarting it
+        let video_trak = traks(find_box(&out, &[b"moov"]).unwrap())[0];
+        let stts = find_box(video_trak, &[b"mdia", b"minf", b"stbl", b"stts"]).unwrap();
+        assert_eq!((u32_at(stts, 4), u32_at(stts, 8), u32_at(stts, 12)), (1, 20, 512));
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(&[0x47, 0x40, 0x00, 0x10]), Some(Container::MpegTs));
+        assert_eq!(sniff(b"\0\0\0\x18ftypiso6"), Some(Container::Fmp4));
+        assert_eq!(sniff(b"ID3\x04\0\0\0\0\0\x3f"), Some(Container::Adts));
+        assert_eq!(sniff(b"<html>"), None);
+    }
+}
@@ -0,0 +1,356 @@
+//! MP4 box writing: `ftyp`, sample entries and the `moov` index.
+
+use super::{Track, TrackKind};
+
+/// Timescale of the movie header and edit lists (milliseconds).
+const MOVIE_TIMESCALE: u32 = 1000;
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub(super) fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+pub(super) fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(body.len() + 4);
+    payload.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+    payload.extend_from_slice(body);
+    mp4_box(kind, &payload)
+}
+
+fn container(kind: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    mp4_box(kind, &children.concat())
+}
+
+trait Put {
+    fn u16(&mut self, v: u16);
+    fn u32(&mut self, v: u32);
+    fn u64(&mut self, v: u64);
+}
+
+impl Put for Vec<u8> {
+    fn u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+    fn u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+    fn u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+pub(super) fn ftyp() -> Vec<u8> {
+    let mut body = b"isom".to_vec();
+    body.u32(0x200);
+    body.extend_from_slice(b"isomiso2avc1mp41");
+    mp4_box(b"ftyp", &body)
+}
+
+/// A visual sample entry (`avc1`, `hvc1`) wrapping a decoder config box.
+pub(super) fn visual_sample_entry(
+    kind: &[u8; 4],
+    width: u32,
+    height: u32,
+    config: &[u8],
+) -> Vec<u8> {
+    let mut body = vec![0; 6];
+    body.u16(1); // data_reference_index
+    body.extend_from_slice(&[0; 16]);
+    body.u16(width as u16);
+    body.u16(height as u16);
+    body.u32(0x0048_0000); // 72 dpi
+    body.u32(0x0048_0000);
+    body.u32(0);
+    body.u16(1); // frame_count
+    body.extend_from_slice(&[0; 32]); // compressorname
+    body.u16(0x18);
+    body.u16(0xffff);
+    body.extend_from_slice(config);
+    mp4_box(kind, &body)
+}
+
+/// Build the `moov` box for the finished tracks.
+pub(super) fn moov(tracks: &[Track]) -> Vec<u8> {
+    let first_start = tracks
+        .iter()
+        .filter_map(|t| t.start)
+        .fold(f64::INFINITY, f64::min);
+
+    let mut movie_duration = 0;
+    let mut traks = Vec::with_capacity(tracks.len());
+    for (i, track) in tracks.iter().enumerate() {
+        let delay = match track.start {
+            Some(start) if first_start.is_finite() => ((start - first_start)
+                * MOVIE_TIMESCALE as f64)
+                .round()
+                .max(0.0) as u64,
+            _ => 0,
+        };
+        let (trak, duration) = trak(track, i as u32 + 1, delay);
+        movie_duration = movie_duration.max(duration);
+        traks.push(trak);
+    }
+
+    let mut children = vec![mvhd(movie_duration, tracks.len() as u32 + 1)];
+    children.extend(traks);
+    container(b"moov", &children)
+}
+
+fn mvhd(duration: u64, next_track_id: u32) -> Vec<u8> {
+    let version = u8::from(duration > u32::MAX as u64);
+    let mut body = Vec::new();
+    if version == 1 {
+        body.u64(0);
+        body.u64(0);
+        body.u32(MOVIE_TIMESCALE);
+        body.u64(duration);
+    } else {
+        body.u32(0);
+        body.u32(0);
+        body.u32(MOVIE_TIMESCALE);
+        body.u32(duration as u32);
+    }
+    body.u32(0x0001_0000); // rate 1.0
+    body.u16(0x0100); // volume 1.0
+    body.extend_from_slice(&[0; 10]);
+    UNITY_MATRIX.iter().for_each(|&v| body.u32(v));
+    body.extend_from_slice(&[0; 24]);
+    body.u32(next_track_id);
+    full_box(b"mvhd", version, 0, &body)
+}
+
+/// Build a `trak`; returns it with its duration in the movie timescale.
+fn trak(track: &Track, track_id: u32, delay: u64) -> (Vec<u8>, u64) {
+    let media_duration = track.media_duration();
+    // Skip the decoder delay of reordered video so presentation starts at 0
+    let media_time = track
+        .samples
+        .first()
+        .map_or(0, |s| s.cts_offset.max(0) as u64);
+    let presented =
+        media_duration.saturating_sub(media_time) * MOVIE_TIMESCALE as u64 / track.timescale as u64;
+    let duration = delay + presented;
+
+    let mut children = vec![tkhd(track, track_id, duration)];
+    if delay > 0 || media_time > 0 {
+        children.push(container(b"edts", &[elst(delay, presented, media_time)]));
+    }
+    children.push(mdia(track, media_duration));
+    (container(b"trak", &children), duration)
+}
+
+fn tkhd(track: &Track, track_id: u32, duration: u64) -> Vec<u8> {
+    let version = u8::from(duration > u32::MAX as u64);
+    let mut body = Vec::new();
+    if version == 1 {
+        body.u64(0);
+        body.u64(0);
+        body.u32(track_id);
+        body.u32(0);
+        body.u64(duration);
+    } else {
+        body.u32(0);
+        body.u32(0);
+        body.u32(track_id);
+        body.u32(0);
+        body.u32(duration as u32);
+    }
+    body.extend_from_slice(&[0; 8]);
+    body.u16(0); // layer
+    body.u16(0); // alternate_group
+    body.u16(if track.kind == TrackKind::Audio {
+        0x0100
+    } else {
+        0
+    });
+    body.u16(0);
+    UNITY_MATRIX.iter().for_each(|&v| body.u32(v));
+    body.u32(track.width << 16);
+    body.u32(track.height << 16);
+    full_box(b"tkhd", version, 0x3, &body) // enabled, in movie
+}
+
+fn elst(delay: u64, presented: u64, media_time: u64) -> Vec<u8> {
+    let mut entries = Vec::new();
+    if delay > 0 {
+        entries.push((delay, -1i64));
+    }
+    entries.push((presented, media_time as i64));
+
+    let version = u8::from(
+        entries
+            .iter()
+            .any(|&(d, t)| d > u32::MAX as u64 || t > i32::MAX as i64),
+    );
+    let mut body = Vec::new();
+    body.u32(entries.len() as u32);
+    for (segment_duration, media_time) in entries {
+        if version == 1 {
+            body.u64(segment_duration);
+            body.u64(media_time as u64);
+        } else {
+            body.u32(segment_duration as u32);
+            body.u32(media_time as i32 as u32);
+        }
+        body.u32(0x0001_0000); // media_rate 1.0
+    }
+    full_box(b"elst", version, 0, &body)
+}
+
+fn mdia(track: &Track, media_duration: u64) -> Vec<u8> {
+    let version = u8::from(media_duration > u32::MAX as u64);
+    let mut mdhd = Vec::new();
+    if version == 1 {
+        mdhd.u64(0);
+        mdhd.u64(0);
+        mdhd.u32(track.timescale);
+        mdhd.u64(media_duration);
+    } else {
+        mdhd.u32(0);
+        mdhd.u32(0);
+        mdhd.u32(track.timescale);
+        mdhd.u32(media_duration as u32);
+    }
+    mdhd.u16(0x55c4); // "und"
+    mdhd.u16(0);
+
+    let (handler, name, media_header) = match track.kind {
+        TrackKind::Video => (b"vide", "VideoHandler", full_box(b"vmhd", 0, 1, &[0; 8])),
+        TrackKind::Audio => (b"soun", "SoundHandler", full_box(b"smhd", 0, 0, &[0; 4])),
+    };
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(handler);
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(name.as_bytes());
+    hdlr.push(0);
+
+    let mut dref = Vec::new();
+    dref.u32(1);
+    dref.extend_from_slice(&full_box(b"url ", 0, 1, &[])); // data in this file
+    let dinf = container(b"dinf", &[full_box(b"dref", 0, 0, &dref)]);
+
+    container(
+        b"mdia",
+        &[
+            full_box(b"mdhd", version, 0, &mdhd),
+            full_box(b"hdlr", 0, 0, &hdlr),
+            container(b"minf", &[media_header, dinf, stbl(track)]),
+        ],
+    )
+}
+
+fn stbl(track: &Track) -> Vec<u8> {
+    let samples = &track.samples;
+
+    let mut stsd = Vec::new();
+    stsd.u32(track.sample_entries.len() as u32);
+    stsd.extend(track.sample_entries.concat());
+    let mut children = vec![full_box(b"stsd", 0, 0, &stsd)];
+
+    // Decode durations, run-length encoded
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for sample in samples {
+        let duration = track.duration(sample);
+        match runs.last_mut() {
+            Some((count, d)) if *d == duration => *count += 1,
+            _ => runs.push((1, duration)),
+        }
+    }
+    children.push(full_box(b"stts", 0, 0, &run_table(&runs)));
+
+    if samples.iter().any(|s| s.cts_offset != 0) {
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for sample in samples {
+            let offset = sample.cts_offset as u32;
+            match runs.last_mut() {
+                Some((count, o)) if *o == offset => *count += 1,
+                _ => runs.push((1, offset)),
+            }
+        }
+        let version = u8::from(samples.iter().any(|s| s.cts_offset < 0));
+        children.push(full_box(b"ctts", version, 0, &run_table(&runs)));
+    }
+
+    if samples.iter().any(|s| !s.sync) {
+        let sync: Vec<u32> = (1..=samples.len() as u32)
+            .filter(|&i| samples[i as usize - 1].sync)
+            .collect();
+        let mut stss = Vec::new();
+        stss.u32(sync.len() as u32);
+        sync.iter().for_each(|&i| stss.u32(i));
+        children.push(full_box(b"stss", 0, 0, &stss));
+    }
+
+    // Chunks: runs of samples that are contiguous in the file
+    let mut chunks: Vec<(u64, u32, u32)> = Vec::new();
+    let mut chunk_end = None;
+    for sample in samples {
+        match chunks.last_mut() {
+            Some((_, count, description))
+                if chunk_end == Some(sample.offset) && *description == sample.description =>
+            {
+                *count += 1
+            }
+            _ => chunks.push((sample.offset, 1, sample.description)),
+        }
+        chunk_end = Some(sample.offset + sample.size as u64);
+    }
+
+    let mut stsc_entries: Vec<(u32, u32, u32)> = Vec::new();
+    for (i, &(_, count, description)) in chunks.iter().enumerate() {
+        if stsc_entries
+            .last()
+            .is_none_or(|&(_, c, d)| c != count || d != description)
+        {
+            stsc_entries.push((i as u32 + 1, count, description));
+        }
+    }
+    let mut stsc = Vec::new();
+    stsc.u32(stsc_entries.len() as u32);
+    for (first_chunk, count, description) in stsc_entries {
+        stsc.u32(first_chunk);
+        stsc.u32(count);
+        stsc.u32(description);
+    }
+    children.push(full_box(b"stsc", 0, 0, &stsc));
+
+    let mut stsz = Vec::new();
+    stsz.u32(0);
+    stsz.u32(samples.len() as u32);
+    samples.iter().for_each(|s| stsz.u32(s.size));
+    children.push(full_box(b"stsz", 0, 0, &stsz));
+
+    let mut offsets = Vec::new();
+    offsets.u32(chunks.len() as u32);
+    if chunks
+        .iter()
+        .any(|&(offset, _, _)| offset > u32::MAX as u64)
+    {
+        chunks
+            .iter()
+            .for_each(|&(offset, _, _)| offsets.u64(offset));
+        children.push(full_box(b"co64", 0, 0, &offsets));
+    } else {
+        chunks
+            .iter()
+            .for_each(|&(offset, _, _)| offsets.u32(offset as u32));
+        children.push(full_box(b"stco", 0, 0, &offsets));
+    }
+
+    container(b"stbl", &children)
+}
+
+fn run_table(runs: &[(u32, u32)]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + runs.len() * 8);
+    body.u32(runs.len() as u32);
+    for &(count, value) in runs {
+        body.u32(count);
+        body.u32(value);
+    }
+    body
+}
@@ -0,0 +1,480 @@
+//! MPEG-TS demuxing: PAT/PMT, PES reassembly and H.264/H.265/AAC streams.
+
+use super::mp4::{mp4_box, visual_sample_entry};
+use super::{aac, h264, h265, SampleSink, Track, TrackKind};
+use crate::error::DlmanError;
+use std::collections::HashMap;
+use std::io::Read;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+/// PES timestamps run at 90 kHz and wrap around after 33 bits.
+const TS_CLOCK: i64 = 90_000;
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_H265: u8 = 0x24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Codec {
+    H264,
+    H265,
+    Aac,
+}
+
+/// Per-codec demuxing state. Streams are keyed by codec rather than PID so
+/// that segments whose PMT renumbers the PIDs still continue the same track.
+#[derive(Default)]
+struct StreamState {
+    track: Option<usize>,
+    /// Last unwrapped decode timestamp (90 kHz)
+    last_dts: Option<i64>,
+    /// Parameter sets of the current video sample entry
+    vps: Vec<Vec<u8>>,
+    sps: Vec<Vec<u8>>,
+    pps: Vec<Vec<u8>>,
+    /// ADTS bytes not yet forming a complete frame
+    pending_audio: Vec<u8>,
+    /// Decode time of the next audio frame, in the sample rate
+    next_audio_dts: Option<i64>,
+}
+
+struct Demuxer<'s, 'w> {
+    sink: &'s mut SampleSink<'w>,
+    tracks: Vec<Track>,
+    pmt_pid: Option<u16>,
+    pids: HashMap<u16, Codec>,
+    pes: HashMap<u16, Vec<u8>>,
+    streams: HashMap<Codec, StreamState>,
+}
+
+/// Demux a transport stream into tracks.
+pub(super) fn demux(mut input: impl Read, sink: &mut SampleSink) -> Result<Vec<Track>, DlmanError> {
+    let mut demuxer = Demuxer {
+        sink,
+        tracks: Vec::new(),
+        pmt_pid: None,
+        pids: HashMap::new(),
+        pes: HashMap::new(),
+        streams: HashMap::new(),
+    };
+
+    let mut packet = [0u8; PACKET_SIZE];
+    while read_packet(&mut input, &mut packet)? {
+        demuxer.packet(&packet)?;
+    }
+
+    let mut pids: Vec<u16> = demuxer.pes.keys().copied().collect();
+    pids.sort_unstable();
+    for pid in pids {
+        let pes = std::mem::take(demuxer.pes.get_mut(&pid).unwrap());
+        demuxer.flush_pes(pid, &pes)?;
+    }
+    Ok(demuxer.tracks)
+}
+
+/// Read the next packet, resynchronising on the sync byte if needed.
+/// Returns `false` at end of input.
+fn read_packet(input: &mut impl Read, packet: &mut [u8; PACKET_SIZE]) -> Result<bool, DlmanError> {
+    let mut filled = 0;
+    loop {
+        while filled < PACKET_SIZE {
+            let n = input.read(&mut packet[filled..])?;
+            if n == 0 {
+                return Ok(false);
+            }
+            filled += n;
+        }
+        if packet[0] == SYNC_BYTE {
+            return Ok(true);
+        }
+        let skip = packet[1..]
+            .iter()
+            .position(|&b| b == SYNC_BYTE)
+            .map_or(PACKET_SIZE, |p| p + 1);
+        packet.copy_within(skip.., 0);
+        filled = PACKET_SIZE - skip;
+    }
+}
+
+impl Demuxer<'_, '_> {
+    fn packet(&mut self, packet: &[u8; PACKET_SIZE]) -> Result<(), DlmanError> {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let adaptation = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        if !has_payload {
+            return Ok(());
+        }
+        let start = if adaptation {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        let Some(payload) = packet.get(start..) else {
+            return Ok(());
+        };
+
+        if pid == 0 {
+            if unit_start {
+                self.parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start {
+                self.parse_pmt(payload);
+            }
+        } else if self.pids.contains_key(&pid) {
+            if unit_start {
+                if let Some(previous) = self.pes.insert(pid, payload.to_vec()) {
+                    self.flush_pes(pid, &previous)?;
+                }
+            } else if let Some(pes) = self.pes.get_mut(&pid) {
+                pes.extend_from_slice(payload);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip a PSI pointer field and return the section with its length.
+    fn section(payload: &[u8]) -> Option<&[u8]> {
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+        let length = ((section.get(1)? & 0x0f) as usize) << 8 | *section.get(2)? as usize;
+        // Drop the trailing CRC
+        section.get(..(3 + length).checked_sub(4)?)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload) else {
+            return;
+        };
+        for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let program = u16::from_be_bytes([entry[0], entry[1]]);
+            if program != 0 {
+                self.pmt_pid = Some(u16::from_be_bytes([entry[2] & 0x1f, entry[3]]));
+                return;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload) else {
+            return;
+        };
+        if section.len() < 12 {
+            return;
+        }
+        let program_info_len = ((section[10] & 0x0f) as usize) << 8 | section[11] as usize;
+        let mut pos = 12 + program_info_len;
+        let mut seen = Vec::new();
+        while pos + 5 <= section.len() {
+            let stream_type = section[pos];
+            let pid = u16::from_be_bytes([section[pos + 1] & 0x1f, section[pos + 2]]);
+            let info_len = ((section[pos + 3] & 0x0f) as usize) << 8 | section[pos + 4] as usize;
+            pos += 5 + info_len;
+
+            let codec = match stream_type {
+                STREAM_TYPE_H264 => Codec::H264,
+                STREAM_TYPE_H265 => Codec::H265,
+                STREAM_TYPE_AAC => Codec::Aac,
+                other => {
+                    tracing::debug!("Skipping unsupported TS stream type 0x{:02x}", other);
+                    continue;
+                }
+            };
+            // Only the first stream of each kind (e.g. one audio language)
+            let kind = codec == Codec::Aac;
+            if seen.contains(&kind) {
+                continue;
+            }
+            seen.push(kind);
+            self.pids.insert(pid, codec);
+        }
+    }
+
+    fn flush_pes(&mut self, pid: u16, pes: &[u8]) -> Result<(), DlmanError> {
+        let Some(&codec) = self.pids.get(&pid) else {
+            return Ok(());
+        };
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return Ok(());
+        }
+        let flags = pes[7] >> 6;
+        let header_end = 9 + pes[8] as usize;
+        let Some(payload) = pes.get(header_end..) else {
+            return Ok(());
+        };
+        let pts = (flags & 0b10 != 0 && header_end >= 14).then(|| parse_timestamp(&pes[9..]));
+        let dts = if flags == 0b11 && header_end >= 19 {
+            Some(parse_timestamp(&pes[14..]))
+        } else {
+            pts
+        };
+
+        let state = self.streams.entry(codec).or_default();
+        let timing = dts.map(|raw_dts| {
+            let dts = unwrap_timestamp(raw_dts, state.last_dts);
+            state.last_dts = Some(dts);
+            let cts = (pts.unwrap_or(raw_dts) - raw_dts).rem_euclid(TIMESTAMP_WRAP);
+            (dts, cts)
+        });
+
+        match codec {
+            Codec::H264 | Codec::H265 => self.video_pes(codec, payload, timing),
+            Codec::Aac => self.audio_pes(payload, timing),
+        }
+    }
+
+    fn video_pes(
+        &mut self,
+        codec: Codec,
+        payload: &[u8],
+        timing: Option<(i64, i64)>,
+    ) -> Result<(), DlmanError> {
+        let mut vps = Vec::new();
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+        let mut sync = false;
+        let mut data = Vec::with_capacity(payload.len());
+        for nal in split_annex_b(payload) {
+            match codec {
+                Codec::H264 => match h264::nal_type(nal) {
+                    h264::NAL_AUD => continue,
+                    h264::NAL_SPS => sps.push(nal.to_vec()),
+                    h264::NAL_PPS => pps.push(nal.to_vec()),
+                    t => {
+                        sync |= t == h264::NAL_IDR;
+                        data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                        data.extend_from_slice(nal);
+                    }
+                },
+                _ => match h265::nal_type(nal) {
+                    h265::NAL_AUD => continue,
+                    h265::NAL_VPS => vps.push(nal.to_vec()),
+                    h265::NAL_SPS => sps.push(nal.to_vec()),
+                    h265::NAL_PPS => pps.push(nal.to_vec()),
+                    t => {
+                        sync |= h265::is_irap(t);
+                        data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                        data.extend_from_slice(nal);
+                    }
+                },
+            }
+        }
+
+        let state = self.streams.get_mut(&codec).unwrap();
+        let complete =
+            !sps.is_empty() && !pps.is_empty() && (codec == Codec::H264 || !vps.is_empty());
+        if complete && (sps != state.sps || pps != state.pps || vps != state.vps) {
+            let entry = match codec {
+                Codec::H264 => h264::parse_sps(&sps[0]).map(|parsed| {
+                    let config = mp4_box(b"avcC", &h264::avcc(&parsed, &sps, &pps));
+                    (
+                        parsed.width,
+                        parsed.height,
+                        visual_sample_entry(b"avc1", parsed.width, parsed.height, &config),
+                    )
+                }),
+                _ => h265::parse_sps(&sps[0]).map(|parsed| {
+                    let config = mp4_box(b"hvcC", &h265::hvcc(&parsed, &vps, &sps, &pps));
+                    (
+                        parsed.width,
+                        parsed.height,
+                        visual_sample_entry(b"hvc1", parsed.width, parsed.height, &config),
+                    )
+                }),
+            };
+            match entry {
+                Ok((width, height, entry)) => {
+                    let index = *state.track.get_or_insert_with(|| {
+                        self.tracks
+                            .push(Track::new(TrackKind::Video, TS_CLOCK as u32, 3000));
+                        self.tracks.len() - 1
+                    });
+                    let track = &mut self.tracks[index];
+                    if track.sample_entries.is_empty() {
+                        track.width = width;
+                        track.height = height;
+                    }
+                    track.use_sample_entry(entry);
+                    state.vps = vps;
+                    state.sps = sps;
+                    state.pps = pps;
+                }
+                Err(e) => tracing::warn!("Skipping unparseable video parameter sets: {}", e),
+            }
+        }
+
+        // Nothing is decodable before the first keyframe with parameter sets
+        let Some(index) = state.track else {
+            return Ok(());
+        };
+        let track = &mut self.tracks[index];
+        if data.is_empty() || (track.samples.is_empty() && !sync) {
+            return Ok(());
+        }
+        let (dts, cts) = match timing {
+            Some(timing) => timing,
+            None => match track.samples.last() {
+                Some(last) => (last.dts - track.shift + track.last_duration() as i64, 0),
+                None => return Ok(()),
+            },
+        };
+        track.push(self.sink, &data, dts, None, cts as i32, sync)
+    }
+
+    fn audio_pes(&mut self, payload: &[u8], timing: Option<(i64, i64)>) -> Result<(), DlmanError> {
+        let state = self.streams.get_mut(&Codec::Aac).unwrap();
+        let carried_over = !state.pending_audio.is_empty();
+        let mut buf = std::mem::take(&mut state.pending_audio);
+        buf.extend_from_slice(payload);
+
+        let mut pos = 0;
+        let mut frame_dts = None;
+        while let Some(rest) = buf.get(pos..).filter(|r| r.len() >= 7) {
+            let Some(header) = aac::parse_adts(rest) else {
+                pos += 1;
+                continue;
+            };
+            if header.frame_len > rest.len() {
+                break;
+            }
+            let rate = header.config.sample_rate() as i64;
+            let dts = match frame_dts {
+                Some(dts) => dts,
+                None => {
+                    // The PES timestamp belongs to the first frame that starts
+                    // in this PES; keep frames contiguous unless it disagrees
+                    // by more than half a frame.
+                    let pes_dts = timing.map(|(dts, cts)| (dts + cts) * rate / TS_CLOCK);
+                    match (state.next_audio_dts, pes_dts) {
+                        (Some(next), Some(pes)) if carried_over || (pes - next).abs() <= 512 => {
+                            next
+                        }
+                        (_, Some(pes)) => pes,
+                        (Some(next), None) => next,
+                        (None, None) => 0,
+                    }
+                }
+            };
+
+            let index = *state.track.get_or_insert_with(|| {
+                self.tracks.push(Track::new(
+                    TrackKind::Audio,
+                    rate as u32,
+                    aac::SAMPLES_PER_FRAME,
+                ));
+                self.tracks.len() - 1
+            });
+            let track = &mut self.tracks[index];
+            track.use_sample_entry(header.config.sample_entry());
+            track.push(
+                self.sink,
+                &rest[header.header_len..header.frame_len],
+                dts,
+                Some(aac::SAMPLES_PER_FRAME),
+                0,
+                true,
+            )?;
+
+            frame_dts = Some(dts + aac::SAMPLES_PER_FRAME as i64);
+            pos += header.frame_len;
+        }
+
+        if frame_dts.is_some() {
+            state.next_audio_dts = frame_dts;
+        }
+        buf.drain(..pos.min(buf.len()));
+        state.pending_audio = buf;
+        Ok(())
+    }
+}
+
+/// Decode a 33-bit PES timestamp (PTS/DTS field).
+fn parse_timestamp(b: &[u8]) -> i64 {
+    (((b[0] >> 1) & 0x07) as i64) << 30
+        | (b[1] as i64) << 22
+        | ((b[2] >> 1) as i64) << 15
+        | (b[3] as i64) << 7
+        | (b[4] >> 1) as i64
+}
+
+/// Place a raw 33-bit timestamp on a continuous timeline next to `previous`.
+fn unwrap_timestamp(raw: i64, previous: Option<i64>) -> i64 {
+    let Some(previous) = previous else {
+        return raw;
+    };
+    let base = previous - previous.rem_euclid(TIMESTAMP_WRAP);
+    [
+        base - TIMESTAMP_WRAP + raw,
+        base + raw,
+        base + TIMESTAMP_WRAP + raw,
+    ]
+    .into_iter()
+    .min_by_key(|candidate| (candidate - previous).abs())
+    .unwrap()
+}
+
+/// Split an Annex B byte stream into NAL units (start codes removed).
+fn split_annex_b(data: &[u8]) -> Vec<&[u8]> {
+    let mut nals = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(s) = start {
+                nals.push(trim_trailing_zeros(&data[s..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(s) = start {
+        nals.push(&data[s..]);
+    }
+    nals.retain(|n| !n.is_empty());
+    nals
+}
+
+/// Drop the leading zero of a 4-byte start code (and trailing_zero_8bits).
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let end = nal.iter().rposition(|&b| b != 0).map_or(0, |p| p + 1);
+    &nal[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        // PTS 126000 with the '0010' marker prefix
+        assert_eq!(parse_timestamp(&[0x21, 0x00, 0x07, 0xd8, 0x61]), 126000);
+
+        assert_eq!(unwrap_timestamp(1000, None), 1000);
+        // Wrapping past 2^33 keeps counting upwards
+        assert_eq!(
+            unwrap_timestamp(500, Some(TIMESTAMP_WRAP - 1000)),
+            TIMESTAMP_WRAP + 500
+        );
+        assert_eq!(
+            unwrap_timestamp(TIMESTAMP_WRAP - 1000, Some(TIMESTAMP_WRAP + 500)),
+            TIMESTAMP_WRAP - 1000
+        );
+    }
+
+    #[test]
+    fn test_split_annex_b() {
+        let data = [
+            0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65, 0x88, 0x80, 0, 0, 0, 1, 0x41, 0x9a,
+        ];
+        let nals = split_annex_b(&data);
+        assert_eq!(
+            nals,
+            vec![&[0x09, 0xf0][..], &[0x65, 0x88, 0x80], &[0x41, 0x9a]]
+        );
+    }
+}
@@ -0,0 +1,680 @@
+//! SAMPLE-AES decryption of MPEG-TS and packed ADTS audio segments.
+//!
+//! Unlike AES-128, SAMPLE-AES only encrypts parts of each media sample, as
+//! laid out in Apple's "MPEG-2 Stream Encryption Format for HTTP Live
+//! Streaming":
+//! - H.264 slices (NAL types 1 and 5) longer than 48 bytes: after a 32-byte
+//!   clear leader, every 16-byte block followed by 144 clear bytes is
+//!   encrypted, as one CBC chain per NAL unit. Emulation prevention bytes are
+//!   added after encryption, so they are removed before decrypting.
+//! - AAC (ADTS) frames: after the header and a 16-byte clear leader, every
+//!   whole 16-byte block is encrypted, as one CBC chain per frame.
+//!
+//! Decrypted transport streams get their PMT stream types switched back to
+//! the clear ones (0xdb → H.264, 0xcf → AAC), so the result is an ordinary
+//! TS that the remuxer and ffmpeg can read.
+
+use crate::error::DlmanError;
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, KeyIvInit};
+use std::collections::HashMap;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_SAMPLE_AES_H264: u8 = 0xdb;
+const STREAM_TYPE_SAMPLE_AES_AAC: u8 = 0xcf;
+const STREAM_TYPE_SAMPLE_AES_AC3: u8 = 0xc1;
+const STREAM_TYPE_SAMPLE_AES_EAC3: u8 = 0xc2;
+
+/// NAL units up to this size are left in the clear.
+const MIN_ENCRYPTED_NAL: usize = 48;
+/// Clear bytes at the start of an encrypted NAL unit (header included).
+const NAL_CLEAR_LEADER: usize = 32;
+/// One encrypted block is followed by this many clear bytes.
+const NAL_PATTERN_STRIDE: usize = 160;
+/// Clear bytes after the ADTS header of an encrypted frame.
+const AAC_CLEAR_LEADER: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    H264,
+    Aac,
+}
+
+/// Decrypt a SAMPLE-AES segment: a transport stream, or packed ADTS audio.
+pub fn decrypt_segment(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, DlmanError> {
+    if data.first() == Some(&SYNC_BYTE) {
+        decrypt_ts(data, key, iv)
+    } else {
+        let mut out = data.to_vec();
+        let start = id3_len(&out).unwrap_or(0);
+        decrypt_adts_frames(&mut out[start..], key, iv);
+        Ok(out)
+    }
+}
+
+/// Length of a leading ID3 tag (packed audio carries its timestamp in one).
+fn id3_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return None;
+    }
+    let size = data[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | (b & 0x7f) as usize);
+    Some((10 + size).min(data.len()))
+}
+
+/// A PES packet of an encrypted stream and the TS packets it came from.
+struct Pes {
+    pid: u16,
+    stream: Stream,
+    /// Indices of the TS packets carrying this PES, in order
+    packets: Vec<usize>,
+    data: Vec<u8>,
+}
+
+fn decrypt_ts(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, DlmanError> {
+    let packets: Vec<&[u8]> = data.chunks_exact(PACKET_SIZE).collect();
+    if packets.iter().any(|p| p[0] != SYNC_BYTE) {
+        return Err(DlmanError::InvalidOperation(
+            "SAMPLE-AES segment is not a well-formed transport stream".to_string(),
+        ));
+    }
+
+    // Pass 1: find the encrypted streams and reassemble their PES packets
+    let mut pmt_pid = None;
+    let mut streams: HashMap<u16, Stream> = HashMap::new();
+    let mut open: HashMap<u16, usize> = HashMap::new();
+    let mut pes_list: Vec<Pes> = Vec::new();
+    for (index, packet) in packets.iter().enumerate() {
+        let pid = packet_pid(packet);
+        let unit_start = packet[1] & 0x40 != 0;
+        let Some(payload) = packet_payload(packet) else {
+            continue;
+        };
+        if pid == 0 {
+            if unit_start {
+                pmt_pid = pmt_pid.or_else(|| parse_pat(payload));
+            }
+        } else if Some(pid) == pmt_pid {
+            if unit_start {
+                for (pid, stream_type) in pmt_streams(payload) {
+                    match stream_type {
+                        STREAM_TYPE_H264 | STREAM_TYPE_SAMPLE_AES_H264 => {
+                            streams.insert(pid, Stream::H264);
+                        }
+                        STREAM_TYPE_AAC | STREAM_TYPE_SAMPLE_AES_AAC => {
+                            streams.insert(pid, Stream::Aac);
+                        }
+                        STREAM_TYPE_SAMPLE_AES_AC3 | STREAM_TYPE_SAMPLE_AES_EAC3 => {
+                            return Err(DlmanError::InvalidOperation(
+                                "SAMPLE-AES encrypted AC-3 audio is not supported".to_string(),
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        } else if let Some(&stream) = streams.get(&pid) {
+            if unit_start {
+                open.insert(pid, pes_list.len());
+                pes_list.push(Pes { pid, stream, packets: vec![index], data: payload.to_vec() });
+            } else if let Some(&open_index) = open.get(&pid) {
+                let pes = &mut pes_list[open_index];
+                pes.packets.push(index);
+                pes.data.extend_from_slice(payload);
+            }
+        }
+    }
+
+    // Decrypt each PES and split it into packets again; decryption changes
+    // the size of video PES packets
+    let mut rebuilt: Vec<Option<Vec<Vec<u8>>>> = Vec::with_capacity(pes_list.len());
+    // Original packet index → (PES, position of the packet within it)
+    let mut slots: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (pes_index, pes) in pes_list.iter().enumerate() {
+        let decrypted = decrypt_pes(&pes.data, pes.stream, key, iv);
+        if decrypted.is_some() {
+            for (slot, &packet_index) in pes.packets.iter().enumerate() {
+                slots.insert(packet_index, (pes_index, slot));
+            }
+        }
+        rebuilt.push(decrypted.map(|d| packetize(pes.pid, packets[pes.packets[0]], &d)));
+    }
+
+    // Pass 2: write the stream back out. Rebuilt packets take the places of
+    // the originals (any extra ones follow the last), so the multiplex order
+    // is kept; continuity counters of the rewritten PIDs are renumbered.
+    let mut out = Vec::with_capacity(data.len() + PACKET_SIZE * 4);
+    let mut counters: HashMap<u16, u8> = HashMap::new();
+    for (index, packet) in packets.iter().enumerate() {
+        let pid = packet_pid(packet);
+        if let Some(&(pes_index, slot)) = slots.get(&index) {
+            let new_packets = rebuilt[pes_index].as_deref().unwrap_or_default();
+            let is_last = slot + 1 == pes_list[pes_index].packets.len();
+            let end = if is_last { new_packets.len() } else { (slot + 1).min(new_packets.len()) };
+            for new_packet in new_packets.get(slot..end).unwrap_or_default() {
+                out.extend_from_slice(new_packet);
+                restamp_continuity(&mut out, pid, &mut counters);
+            }
+            continue;
+        }
+        out.extend_from_slice(packet);
+        if Some(pid) == pmt_pid && packet[1] & 0x40 != 0 {
+            let start = out.len() - PACKET_SIZE;
+            clear_pmt_stream_types(&mut out[start..]);
+        }
+        if streams.contains_key(&pid) {
+            restamp_continuity(&mut out, pid, &mut counters);
+        }
+    }
+    // A truncated last packet is kept as it was
+    out.extend_from_slice(&data[packets.len() * PACKET_SIZE..]);
+    Ok(out)
+}
+
+fn packet_pid(packet: &[u8]) -> u16 {
+    u16::from_be_bytes([packet[1] & 0x1f, packet[2]])
+}
+
+fn packet_payload(packet: &[u8]) -> Option<&[u8]> {
+    if packet[3] & 0x10 == 0 {
+        return None;
+    }
+    let start = if packet[3] & 0x20 != 0 { 5 + packet[4] as usize } else { 4 };
+    packet.get(start..)
+}
+
+/// Continue the continuity counter of `pid` on the packet just written.
+/// Packets without payload repeat the previous value.
+fn restamp_continuity(out: &mut [u8], pid: u16, counters: &mut HashMap<u16, u8>) {
+    let start = out.len() - PACKET_SIZE;
+    let header = &mut out[start + 3];
+    let has_payload = *header & 0x10 != 0;
+    let counter = counters
+        .entry(pid)
+        .and_modify(|c| {
+            if has_payload {
+                *c = (*c + 1) & 0x0f;
+            }
+        })
+        .or_insert(*header & 0x0f);
+    *header = (*header & 0xf0) | *counter;
+}
+
+/// PSI section after the pointer field, up to (not including) its CRC.
+fn section(payload: &[u8]) -> Option<(usize, &[u8])> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let length = ((section.get(1)? & 0x0f) as usize) << 8 | *section.get(2)? as usize;
+    Some((1 + pointer, section.get(..(3 + length).checked_sub(4)?)?))
+}
+
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let (_, section) = section(payload)?;
+    section
+        .get(8..)?
+        .chunks_exact(4)
+        .find(|entry| u16::from_be_bytes([entry[0], entry[1]]) != 0)
+        .map(|entry| u16::from_be_bytes([entry[2] & 0x1f, entry[3]]))
+}
+
+/// Elementary streams of a PMT as (PID, stream type, offset of the stream
+/// type byte within the section).
+fn pmt_entries(section: &[u8]) -> Vec<(u16, u8, usize)> {
+    let mut entries = Vec::new();
+    if section.len() < 12 {
+        return entries;
+    }
+    let program_info_len = ((section[10] & 0x0f) as usize) << 8 | section[11] as usize;
+    let mut pos = 12 + program_info_len;
+    while pos + 5 <= section.len() {
+        let pid = u16::from_be_bytes([section[pos + 1] & 0x1f, section[pos + 2]]);
+        let info_len = ((section[pos + 3] & 0x0f) as usize) << 8 | section[pos + 4] as usize;
+        entries.push((pid, section[pos], pos));
+        pos += 5 + info_len;
+    }
+    entries
+}
+
+fn pmt_streams(payload: &[u8]) -> Vec<(u16, u8)> {
+    section(payload)
+        .map(|(_, s)| pmt_entries(s).into_iter().map(|(pid, t, _)| (pid, t)).collect())
+        .unwrap_or_default()
+}
+
+/// Switch SAMPLE-AES stream types in a PMT packet to their clear
+/// equivalents and recompute the section CRC.
+fn clear_pmt_stream_types(packet: &mut [u8]) {
+    let payload_start = if packet[3] & 0x20 != 0 { 5 + packet[4] as usize } else { 4 };
+    let Some(payload) = packet.get(payload_start..) else {
+        return;
+    };
+    let Some((offset, section_bytes)) = section(payload) else {
+        return;
+    };
+    let section_len = section_bytes.len();
+    let entries = pmt_entries(section_bytes);
+    let section_start = payload_start + offset;
+    // The CRC must fit in this packet for the section to be rewritten
+    if section_start + section_len + 4 > packet.len() {
+        return;
+    }
+
+    let mut changed = false;
+    for (_, stream_type, pos) in entries {
+        let clear = match stream_type {
+            STREAM_TYPE_SAMPLE_AES_H264 => STREAM_TYPE_H264,
+            STREAM_TYPE_SAMPLE_AES_AAC => STREAM_TYPE_AAC,
+            _ => continue,
+        };
+        packet[section_start + pos] = clear;
+        changed = true;
+    }
+    if changed {
+        let end = section_start + section_len;
+        let crc = crc32_mpeg2(&packet[section_start..end]);
+        packet[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// CRC-32/MPEG-2, as used by PSI sections.
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Decrypt the samples of one PES packet. Returns `None` when it has no
+/// PES header to work with, in which case it is passed through unchanged.
+fn decrypt_pes(pes: &[u8], stream: Stream, key: &[u8; 16], iv: &[u8; 16]) -> Option<Vec<u8>> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return None;
+    }
+    let header_end = 9 + pes[8] as usize;
+    let payload = pes.get(header_end..)?;
+    let payload = match stream {
+        Stream::H264 => decrypt_h264(payload, key, iv),
+        Stream::Aac => {
+            let mut payload = payload.to_vec();
+            decrypt_adts_frames(&mut payload, key, iv);
+            payload
+        }
+    };
+
+    let mut out = pes[..header_end].to_vec();
+    out.extend_from_slice(&payload);
+    // A bounded PES length has to follow the new payload size
+    if u16::from_be_bytes([out[4], out[5]]) != 0 {
+        let length = u16::try_from(out.len() - 6).unwrap_or(0);
+        out[4..6].copy_from_slice(&length.to_be_bytes());
+    }
+    Some(out)
+}
+
+/// Decrypt the slice NAL units of an Annex B payload. Start codes and
+/// other NAL units are copied through as they are.
+fn decrypt_h264(payload: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len());
+    let mut copied = 0;
+    for (start, end) in nal_ranges(payload) {
+        let nal = &payload[start..end];
+        let nal_type = nal[0] & 0x1f;
+        if (nal_type == 1 || nal_type == 5) && nal.len() > MIN_ENCRYPTED_NAL {
+            out.extend_from_slice(&payload[copied..start]);
+            out.extend_from_slice(&decrypt_nal(nal, key, iv));
+            copied = end;
+        }
+    }
+    out.extend_from_slice(&payload[copied..]);
+    out
+}
+
+/// Byte ranges of the NAL units in an Annex B stream, without start codes
+/// or the zero bytes leading into the next one.
+fn nal_ranges(data: &[u8]) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push((i, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+        .iter()
+        .enumerate()
+        .filter_map(|(n, &(_, start))| {
+            let next = starts.get(n + 1).map_or(data.len(), |&(code, _)| code);
+            // An encrypted NAL unit never ends in a zero byte: a 0x03 is
+            // appended when it would
+            let end = data[start..next]
+                .iter()
+                .rposition(|&b| b != 0)
+                .map_or(start, |p| start + p + 1);
+            (end > start).then_some((start, end))
+        })
+        .collect()
+}
+
+fn decrypt_nal(nal: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut data = remove_emulation_prevention(nal);
+    let len = data.len();
+    let blocks: Vec<usize> = (NAL_CLEAR_LEADER..)
+        .step_by(NAL_PATTERN_STRIDE)
+        .take_while(|&pos| pos + 16 < len)
+        .collect();
+    let mut encrypted: Vec<u8> = blocks.iter().flat_map(|&pos| data[pos..pos + 16].to_vec()).collect();
+    decrypt_cbc(&mut encrypted, key, iv);
+    for (block, &pos) in encrypted.chunks_exact(16).zip(&blocks) {
+        data[pos..pos + 16].copy_from_slice(block);
+    }
+    data
+}
+
+/// Strip the 0x03 of every `00 00 03` sequence.
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// Decrypt the ADTS frames in `data` in place. A frame cut off at the end
+/// is left as it is.
+fn decrypt_adts_frames(data: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    let mut pos = 0;
+    while pos + 7 <= data.len() {
+        let header = &data[pos..];
+        if header[0] != 0xff || header[1] & 0xf6 != 0xf0 {
+            pos += 1;
+            continue;
+        }
+        let header_len = if header[1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_len = ((header[3] & 0x03) as usize) << 11
+            | (header[4] as usize) << 3
+            | (header[5] >> 5) as usize;
+        if frame_len < header_len || pos + frame_len > data.len() {
+            break;
+        }
+        let body = &mut data[pos + header_len..pos + frame_len];
+        if body.len() > AAC_CLEAR_LEADER {
+            let whole = (body.len() - AAC_CLEAR_LEADER) / 16 * 16;
+            decrypt_cbc(&mut body[AAC_CLEAR_LEADER..AAC_CLEAR_LEADER + whole], key, iv);
+        }
+        pos += frame_len;
+    }
+}
+
+fn decrypt_cbc(data: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    if data.is_empty() {
+        return;
+    }
+    // Always whole blocks, so this cannot fail
+    let _ = Aes128CbcDec::new(key.into(), iv.into()).decrypt_padded_mut::<NoPadding>(data);
+}
+
+/// Split a PES packet into TS packets for `pid`, reusing the adaptation
+/// field (PCR, random access flag) of the packet it originally started in.
+fn packetize(pid: u16, first: &[u8], pes: &[u8]) -> Vec<Vec<u8>> {
+    let original_af: &[u8] = if first[3] & 0x20 != 0 {
+        &first[5..5 + first[4] as usize]
+    } else {
+        &[]
+    };
+
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos < pes.len() || packets.is_empty() {
+        let af_content: &[u8] = if packets.is_empty() { original_af } else { &[] };
+        let needs_af = !af_content.is_empty();
+        let room = PACKET_SIZE - 4 - if needs_af { 1 + af_content.len() } else { 0 };
+        let take = room.min(pes.len() - pos);
+
+        let mut packet = Vec::with_capacity(PACKET_SIZE);
+        packet.push(SYNC_BYTE);
+        let unit_start = if packets.is_empty() { 0x40 } else { 0 };
+        packet.push(unit_start | ((pid >> 8) as u8 & 0x1f));
+        packet.push(pid as u8);
+        let stuffing = room - take;
+        if needs_af || stuffing > 0 {
+            packet.push(0x30);
+            // Stuffing goes into the adaptation field
+            let af_len = if needs_af { af_content.len() + stuffing } else { stuffing - 1 };
+            packet.push(af_len as u8);
+            if needs_af {
+                packet.extend_from_slice(af_content);
+                packet.extend(std::iter::repeat_n(0xff, stuffing));
+            } else if af_len > 0 {
+                packet.push(0x00);
+                packet.extend(std::iter::repeat_n(0xff, af_len - 1));
+            }
+        } else {
+            packet.push(0x10);
+        }
+        packet.extend_from_slice(&pes[pos..pos + take]);
+        debug_assert_eq!(packet.len(), PACKET_SIZE);
+        packets.push(packet);
+        pos += take;
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+    const KEY: [u8; 16] = [0x11; 16];
+    const IV: [u8; 16] = [0x22; 16];
+
+    fn encrypt_cbc(data: &mut [u8]) {
+        let len = data.len();
+        Aes128CbcEnc::new(&KEY.into(), &IV.into())
+            .encrypt_padded_mut::<NoPadding>(data, len)
+            .unwrap();
+    }
+
+    fn add_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut zeros = 0;
+        for &byte in nal {
+            if zeros >= 2 && byte <= 3 {
+                out.push(3);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            out.push(byte);
+        }
+        out
+    }
+
+    /// Encrypt a NAL unit the way a SAMPLE-AES packager does.
+    fn encrypt_nal(nal: &[u8]) -> Vec<u8> {
+        let mut data = nal.to_vec();
+        let blocks: Vec<usize> = (NAL_CLEAR_LEADER..)
+            .step_by(NAL_PATTERN_STRIDE)
+            .take_while(|&pos| pos + 16 < data.len())
+            .collect();
+        let mut plain: Vec<u8> = blocks.iter().flat_map(|&p| data[p..p + 16].to_vec()).collect();
+        encrypt_cbc(&mut plain);
+        for (block, &pos) in plain.chunks_exact(16).zip(&blocks) {
+            data[pos..pos + 16].copy_from_slice(block);
+        }
+        add_emulation_prevention(&data)
+    }
+
+    fn adts_frame(body: &[u8]) -> Vec<u8> {
+        let len = 7 + body.len();
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            0x50,
+            0x80 | ((len >> 11) & 0x03) as u8,
+            ((len >> 3) & 0xff) as u8,
+            (((len & 0x07) << 5) | 0x1f) as u8,
+            0xfc,
+        ];
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn pes(stream_id: u8, payload: &[u8], bounded: bool) -> Vec<u8> {
+        let length = if bounded { (payload.len() + 8) as u16 } else { 0 };
+        let mut pes = vec![0, 0, 1, stream_id];
+        pes.extend_from_slice(&length.to_be_bytes());
+        pes.extend_from_slice(&[0x80, 0x80, 5, 0x21, 0x00, 0x01, 0x00, 0x01]);
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    fn psi_packet(pid: u16, table: &[u8]) -> Vec<u8> {
+        let mut packet = vec![SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0x00];
+        packet.extend_from_slice(table);
+        packet.extend_from_slice(&crc32_mpeg2(table).to_be_bytes());
+        packet.resize(PACKET_SIZE, 0xff);
+        packet
+    }
+
+    /// PAT → PMT on 0x1000 → SAMPLE-AES H.264 on 0x100 and AAC on 0x101.
+    fn encrypted_ts(video: &[u8], audio: &[u8]) -> Vec<u8> {
+        let pat = [0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00];
+        let pmt = [
+            0x02, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00,
+            STREAM_TYPE_SAMPLE_AES_H264, 0xe1, 0x00, 0xf0, 0x00,
+            STREAM_TYPE_SAMPLE_AES_AAC, 0xe1, 0x01, 0xf0, 0x00,
+        ];
+        let mut ts = psi_packet(0, &pat);
+        ts.extend(psi_packet(0x1000, &pmt));
+        for packet in packetize(0x100, &[SYNC_BYTE, 0x41, 0x00, 0x10], &pes(0xe0, video, false)) {
+            ts.extend(packet);
+        }
+        for packet in packetize(0x101, &[SYNC_BYTE, 0x41, 0x01, 0x10], &pes(0xc0, audio, true)) {
+            ts.extend(packet);
+        }
+        ts
+    }
+
+    /// The payloads of the PES packets on `pid`, and the PMT stream types.
+    fn demux(ts: &[u8], pid: u16) -> (Vec<u8>, Vec<u8>) {
+        let mut pes = Vec::new();
+        let mut types = Vec::new();
+        let mut counter = None;
+        for packet in ts.chunks_exact(PACKET_SIZE) {
+            let payload = packet_payload(packet).unwrap_or_default();
+            if packet_pid(packet) == 0x1000 {
+                let (_, section) = section(payload).unwrap();
+                let end = 1 + section.len();
+                assert_eq!(crc32_mpeg2(&payload[1..end]).to_be_bytes(), payload[end..end + 4]);
+                types = pmt_entries(section).into_iter().map(|(_, t, _)| t).collect();
+            } else if packet_pid(packet) == pid {
+                let cc = packet[3] & 0x0f;
+                if let Some(previous) = counter {
+                    assert_eq!(cc, (previous + 1) & 0x0f);
+                }
+                counter = Some(cc);
+                pes.extend_from_slice(payload);
+            }
+        }
+        let header_end = 9 + pes[8] as usize;
+        (pes[header_end..].to_vec(), types)
+    }
+
+    fn slice_nal(len: usize) -> Vec<u8> {
+        // Zero runs make the escaping add and remove bytes
+        let mut nal: Vec<u8> = (0..len).map(|i| if i % 37 < 3 { 0 } else { i as u8 | 1 }).collect();
+        nal[0] = 0x65;
+        nal[len - 1] = 0x80;
+        nal
+    }
+
+    #[test]
+    fn test_decrypts_transport_stream() {
+        let idr = slice_nal(700);
+        let sps = [0x67, 0x42, 0x00, 0x1e, 0x95];
+        let short = slice_nal(40);
+        let mut video = vec![0, 0, 0, 1];
+        video.extend_from_slice(&sps);
+        video.extend_from_slice(&[0, 0, 0, 1]);
+        video.extend(encrypt_nal(&idr));
+        video.extend_from_slice(&[0, 0, 1]);
+        video.extend_from_slice(&short);
+
+        let clear_body: Vec<u8> = (0..100u8).collect();
+        let mut encrypted_body = clear_body.clone();
+        encrypt_cbc(&mut encrypted_body[16..96]);
+        let audio = [adts_frame(&encrypted_body), adts_frame(&encrypted_body)].concat();
+
+        let ts = encrypted_ts(&video, &audio);
+        let decrypted = decrypt_segment(&ts, &KEY, &IV).unwrap();
+        assert_eq!(decrypted.len() % PACKET_SIZE, 0);
+
+        let (video_out, types) = demux(&decrypted, 0x100);
+        let mut expected = vec![0, 0, 0, 1];
+        expected.extend_from_slice(&sps);
+        expected.extend_from_slice(&[0, 0, 0, 1]);
+        expected.extend_from_slice(&idr);
+        expected.extend_from_slice(&[0, 0, 1]);
+        // Too short to be encrypted
+        expected.extend_from_slice(&short);
+        assert_eq!(video_out, expected);
+        assert_eq!(types, vec![STREAM_TYPE_H264, STREAM_TYPE_AAC]);
+
+        let (audio_out, _) = demux(&decrypted, 0x101);
+        assert_eq!(audio_out, [adts_frame(&clear_body), adts_frame(&clear_body)].concat());
+    }
+
+    #[test]
+    fn test_decrypts_packed_audio() {
+        let clear_body: Vec<u8> = (0..40u8).collect();
+        let mut encrypted_body = clear_body.clone();
+        encrypt_cbc(&mut encrypted_body[16..32]);
+        let mut id3 = b"ID3\x04\x00\x00\x00\x00\x00\x02".to_vec();
+        id3.extend_from_slice(&[0xaa, 0xbb]);
+
+        let segment = [id3.clone(), adts_frame(&encrypted_body)].concat();
+        let decrypted = decrypt_segment(&segment, &KEY, &IV).unwrap();
+        // Only the one whole block after the leader is encrypted
+        assert_eq!(decrypted, [id3, adts_frame(&clear_body)].concat());
+    }
+
+    #[test]
+    fn test_emulation_prevention_round_trip() {
+        let nal = [0x65, 0, 0, 0, 0, 0, 1, 0, 0, 3, 0, 0, 2];
+        let escaped = add_emulation_prevention(&nal);
+        assert_eq!(escaped, [0x65, 0, 0, 3, 0, 0, 3, 0, 1, 0, 0, 3, 3, 0, 0, 3, 2]);
+        assert_eq!(remove_emulation_prevention(&escaped), nal);
+    }
+
+    #[test]
+    fn test_packetize_keeps_adaptation_field() {
+        let first = [SYNC_BYTE, 0x41, 0x00, 0x30, 7, 0x50, 1, 2, 3, 4, 5, 6];
+        let pes = vec![0xab; 400];
+        let packets = packetize(0x100, &first, &pes);
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|p| p.len() == PACKET_SIZE));
+        assert_eq!(&packets[0][4..12], &first[4..]);
+        let payload: Vec<u8> = packets.iter().flat_map(|p| packet_payload(p).unwrap().to_vec()).collect();
+        assert_eq!(payload, pes);
+    }
+}
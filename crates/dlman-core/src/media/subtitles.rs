@@ -0,0 +1,564 @@
+//! Subtitle stitching: segmented WebVTT and TTML to a single sidecar file.
+//!
+//! HLS subtitle renditions are split into WebVTT segments, each mapped onto
+//! the video timeline with an `X-TIMESTAMP-MAP` header; DASH text tracks are
+//! a single WebVTT/TTML document or a few segments of one. The segments are
+//! parsed into cues, shifted onto a common timeline starting at the first
+//! segment, de-duplicated (cues spanning a segment boundary are repeated in
+//! both) and written out as one `.vtt` or `.srt` file.
+
+use crate::error::DlmanError;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// MPEG-TS timestamps tick at 90 kHz and wrap at 2^33.
+const MPEGTS_CLOCK: f64 = 90_000.0;
+const MPEGTS_WRAP: i64 = 1 << 33;
+
+/// The format of the source segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    WebVtt,
+    Ttml,
+}
+
+impl SubtitleFormat {
+    /// Extension of the sidecar file: WebVTT is kept as-is, TTML (which few
+    /// players read as a sidecar) is written as SubRip.
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::WebVtt => "vtt",
+            SubtitleFormat::Ttml => "srt",
+        }
+    }
+}
+
+/// One subtitle cue. `text` is WebVTT cue text: `&`, `<` and `>` are
+/// escaped and `<i>`/`<b>`/… tags may be present.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    /// Start time in seconds
+    pub start: f64,
+    /// End time in seconds
+    pub end: f64,
+    /// WebVTT cue settings (e.g. `align:start line:90%`), possibly empty
+    pub settings: String,
+    pub text: String,
+}
+
+/// Collects the segments of one subtitle track, in playlist order.
+#[derive(Debug, Default)]
+pub struct SubtitleStitcher {
+    format: Option<SubtitleFormat>,
+    /// WebVTT STYLE/REGION blocks, kept once
+    header_blocks: Vec<String>,
+    cues: Vec<Cue>,
+    seen: HashSet<(i64, i64, String)>,
+    /// `X-TIMESTAMP-MAP` MPEGTS value of the first segment that had one
+    base_mpegts: Option<i64>,
+}
+
+impl SubtitleStitcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Format of the segments pushed so far.
+    pub fn format(&self) -> Option<SubtitleFormat> {
+        self.format
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    /// Add the next segment of the track.
+    pub fn push_segment(&mut self, data: &[u8]) -> Result<(), DlmanError> {
+        let text = String::from_utf8_lossy(data);
+        let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+        let trimmed = text.trim_start();
+
+        let (format, cues) = if trimmed.starts_with("WEBVTT") {
+            (SubtitleFormat::WebVtt, self.parse_webvtt(trimmed))
+        } else if trimmed.starts_with('<') {
+            (SubtitleFormat::Ttml, parse_ttml(trimmed)?)
+        } else if trimmed.is_empty() {
+            return Ok(());
+        } else {
+            return Err(DlmanError::InvalidOperation(
+                "Subtitle segment is neither WebVTT nor TTML".to_string(),
+            ));
+        };
+        self.format.get_or_insert(format);
+
+        for cue in cues {
+            if cue.end <= 0.0 || cue.end < cue.start {
+                continue;
+            }
+            let key = (millis(cue.start), millis(cue.end), cue.text.clone());
+            if self.seen.insert(key) {
+                self.cues.push(cue);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse one WebVTT segment, shifting its cues onto the stitched
+    /// timeline and collecting its STYLE/REGION blocks.
+    fn parse_webvtt(&mut self, text: &str) -> Vec<Cue> {
+        let mut blocks = text.split("\n\n").map(|b| b.trim_matches('\n'));
+        let header = blocks.next().unwrap_or_default();
+
+        // X-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000 maps the local
+        // cue times onto the stream's presentation timestamps
+        let mut offset = 0.0;
+        if let Some((mpegts, local)) = header.lines().find_map(parse_timestamp_map) {
+            let base = *self.base_mpegts.get_or_insert(mpegts);
+            let mut delta = mpegts - base;
+            if delta < -MPEGTS_WRAP / 2 {
+                delta += MPEGTS_WRAP;
+            }
+            offset = delta as f64 / MPEGTS_CLOCK - local;
+        }
+
+        let mut cues = Vec::new();
+        for block in blocks.filter(|b| !b.is_empty()) {
+            if block.starts_with("NOTE") {
+                continue;
+            }
+            if block.starts_with("STYLE") || block.starts_with("REGION") {
+                if !self.header_blocks.iter().any(|b| b == block) {
+                    self.header_blocks.push(block.to_string());
+                }
+                continue;
+            }
+
+            let mut lines = block.lines().peekable();
+            // Cue identifiers are dropped: they needn't be unique across segments
+            if lines.peek().is_some_and(|l| !l.contains("-->")) {
+                lines.next();
+            }
+            let Some((start, end, settings)) = lines.next().and_then(parse_timing_line) else {
+                continue;
+            };
+            let text = lines.collect::<Vec<_>>().join("\n");
+            if text.trim().is_empty() {
+                continue;
+            }
+            cues.push(Cue {
+                start: (start + offset).max(0.0),
+                end: end + offset,
+                settings,
+                text,
+            });
+        }
+        cues
+    }
+
+    fn sorted_cues(&self) -> Vec<&Cue> {
+        let mut cues: Vec<&Cue> = self.cues.iter().collect();
+        cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+        cues
+    }
+
+    /// The stitched track as a WebVTT document.
+    pub fn to_webvtt(&self) -> String {
+        let mut out = String::from("WEBVTT\n\n");
+        for block in &self.header_blocks {
+            out.push_str(block);
+            out.push_str("\n\n");
+        }
+        for cue in self.sorted_cues() {
+            out.push_str(&format_timestamp(cue.start, '.'));
+            out.push_str(" --> ");
+            out.push_str(&format_timestamp(cue.end, '.'));
+            if !cue.settings.is_empty() {
+                out.push(' ');
+                out.push_str(&cue.settings);
+            }
+            out.push('\n');
+            out.push_str(&cue.text);
+            out.push_str("\n\n");
+        }
+        out
+    }
+
+    /// The stitched track as a SubRip document. Only the `<i>`, `<b>` and
+    /// `<u>` tags survive; cue settings are dropped.
+    pub fn to_srt(&self) -> String {
+        let mut out = String::new();
+        for (index, cue) in self.sorted_cues().into_iter().enumerate() {
+            out.push_str(&format!(
+                "{}\n{} --> {}\n{}\n\n",
+                index + 1,
+                format_timestamp(cue.start, ','),
+                format_timestamp(cue.end, ','),
+                srt_text(&cue.text)
+            ));
+        }
+        out
+    }
+
+    /// The stitched track in the format [`SubtitleFormat::extension`] names.
+    pub fn render(&self) -> String {
+        match self.format {
+            Some(SubtitleFormat::Ttml) => self.to_srt(),
+            _ => self.to_webvtt(),
+        }
+    }
+}
+
+/// Path of a subtitle sidecar next to `video`, tagged with the language:
+/// `Talk [720p].mp4` → `Talk [720p].en.vtt`. A number is appended when the
+/// name is already among `taken` (e.g. two English tracks).
+pub fn sidecar_path(
+    video: &Path,
+    language: Option<&str>,
+    extension: &str,
+    taken: &[PathBuf],
+) -> PathBuf {
+    let language: String = language
+        .unwrap_or("und")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    let language = if language.is_empty() { "und".to_string() } else { language };
+
+    let mut path = video.with_extension(format!("{language}.{extension}"));
+    let mut n = 2;
+    while taken.contains(&path) {
+        path = video.with_extension(format!("{language}.{n}.{extension}"));
+        n += 1;
+    }
+    path
+}
+
+fn millis(seconds: f64) -> i64 {
+    (seconds * 1000.0).round() as i64
+}
+
+/// Parse a WebVTT timestamp: `mm:ss.ttt` or `hh:mm:ss.ttt` (a `,` separator
+/// is tolerated).
+fn parse_vtt_timestamp(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    let seconds: f64 = parts.last()?.replace(',', ".").parse().ok()?;
+    let (hours, minutes): (f64, f64) = match parts.len() {
+        2 => (0.0, parts[0].parse().ok()?),
+        3 => (parts[0].parse().ok()?, parts[1].parse().ok()?),
+        _ => return None,
+    };
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// `start --> end [settings]`
+fn parse_timing_line(line: &str) -> Option<(f64, f64, String)> {
+    let (start, rest) = line.split_once("-->")?;
+    let rest = rest.trim();
+    let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((
+        parse_vtt_timestamp(start)?,
+        parse_vtt_timestamp(end)?,
+        settings.trim().to_string(),
+    ))
+}
+
+/// `X-TIMESTAMP-MAP=MPEGTS:<ticks>,LOCAL:<timestamp>` (in either order)
+fn parse_timestamp_map(line: &str) -> Option<(i64, f64)> {
+    let value = line.trim().strip_prefix("X-TIMESTAMP-MAP=")?;
+    let mut mpegts = None;
+    let mut local = None;
+    for field in value.split(',') {
+        match field.trim().split_once(':')? {
+            ("MPEGTS", v) => mpegts = v.trim().parse().ok(),
+            ("LOCAL", v) => local = parse_vtt_timestamp(v),
+            _ => {}
+        }
+    }
+    Some((mpegts?, local.unwrap_or(0.0)))
+}
+
+/// `hh:mm:ss<sep>mmm`
+fn format_timestamp(seconds: f64, separator: char) -> String {
+    let ms = millis(seconds.max(0.0));
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// WebVTT cue text to SubRip: drop tags other than i/b/u and unescape.
+fn srt_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        let tag = &rest[open + 1..open + close];
+        let name = tag.trim_start_matches('/').split(['.', ' ']).next().unwrap_or_default();
+        if matches!(name, "i" | "b" | "u") {
+            out.push('<');
+            if tag.starts_with('/') {
+                out.push('/');
+            }
+            out.push_str(name);
+            out.push('>');
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+// ── TTML ─────────────────────────────────────────────────────────────────
+
+/// Timing parameters from the `<tt>` element.
+struct TtmlTiming {
+    frame_rate: f64,
+    tick_rate: f64,
+}
+
+impl Default for TtmlTiming {
+    fn default() -> Self {
+        Self { frame_rate: 30.0, tick_rate: 1.0 }
+    }
+}
+
+impl TtmlTiming {
+    /// Parse a TTML time expression: clock time (`hh:mm:ss.fff`,
+    /// `hh:mm:ss:frames`) or offset time (`12.5s`, `500ms`, `90f`, `1000t`, …).
+    fn parse(&self, s: &str) -> Option<f64> {
+        let s = s.trim();
+        if s.contains(':') {
+            let parts: Vec<&str> = s.split(':').collect();
+            let (h, m, sec): (f64, f64, f64) =
+                (parts[0].parse().ok()?, parts.get(1)?.parse().ok()?, parts.get(2)?.parse().ok()?);
+            let frames: f64 = match parts.get(3) {
+                Some(f) => f.parse().ok()?,
+                None => 0.0,
+            };
+            return Some(h * 3600.0 + m * 60.0 + sec + frames / self.frame_rate);
+        }
+        let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let value: f64 = s[..split].parse().ok()?;
+        Some(match &s[split..] {
+            "h" => value * 3600.0,
+            "m" => value * 60.0,
+            "s" => value,
+            "ms" => value / 1000.0,
+            "f" => value / self.frame_rate,
+            "t" => value / self.tick_rate,
+            _ => return None,
+        })
+    }
+}
+
+/// A `<p>` being read.
+#[derive(Default)]
+struct TtmlParagraph {
+    begin: Option<f64>,
+    end: Option<f64>,
+    dur: Option<f64>,
+    text: String,
+}
+
+/// Attributes of an element as (local name, value) strings, so `ttp:tickRate`
+/// and `tickRate` read the same.
+fn local_attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .map(|attr| {
+            (
+                String::from_utf8_lossy(attr.key.local_name().as_ref()).to_string(),
+                attr.unescape_value().map(|v| v.to_string()).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+/// Parse a TTML document into cues. Only paragraph-level timing is used.
+fn parse_ttml(xml: &str) -> Result<Vec<Cue>, DlmanError> {
+    let mut reader = Reader::from_str(xml);
+    let mut timing = TtmlTiming::default();
+    let mut paragraph: Option<TtmlParagraph> = None;
+    let mut cues = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| {
+            DlmanError::InvalidOperation(format!("TTML parse error: {e}"))
+        })?;
+        match event {
+            Event::Eof => break,
+            Event::Start(ref e) | Event::Empty(ref e) => match e.local_name().as_ref() {
+                b"tt" => {
+                    for (key, val) in local_attributes(e) {
+                        match key.as_str() {
+                            "frameRate" => timing.frame_rate = val.parse().unwrap_or(timing.frame_rate),
+                            "tickRate" => timing.tick_rate = val.parse().unwrap_or(timing.tick_rate),
+                            _ => {}
+                        }
+                    }
+                }
+                b"p" => {
+                    let mut p = TtmlParagraph::default();
+                    for (key, val) in local_attributes(e) {
+                        match key.as_str() {
+                            "begin" => p.begin = timing.parse(&val),
+                            "end" => p.end = timing.parse(&val),
+                            "dur" => p.dur = timing.parse(&val),
+                            _ => {}
+                        }
+                    }
+                    paragraph = Some(p);
+                }
+                b"br" => {
+                    if let Some(ref mut p) = paragraph {
+                        p.text.push('\n');
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(ref t) => {
+                if let Some(ref mut p) = paragraph {
+                    let text = t.unescape().unwrap_or_default();
+                    // Default xml:space handling: whitespace runs are one space
+                    let mut last_space = p.text.ends_with([' ', '\n']);
+                    for c in text.chars() {
+                        if c.is_whitespace() {
+                            if !last_space {
+                                p.text.push(' ');
+                            }
+                            last_space = true;
+                        } else {
+                            match c {
+                                '&' => p.text.push_str("&amp;"),
+                                '<' => p.text.push_str("&lt;"),
+                                '>' => p.text.push_str("&gt;"),
+                                _ => p.text.push(c),
+                            }
+                            last_space = false;
+                        }
+                    }
+                }
+            }
+            Event::End(ref e) if e.local_name().as_ref() == b"p" => {
+                if let Some(p) = paragraph.take() {
+                    let end = p.end.or_else(|| Some(p.begin? + p.dur?));
+                    let text: Vec<&str> = p.text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+                    if let (Some(start), Some(end), false) = (p.begin, end, text.is_empty()) {
+                        cues.push(Cue { start, end, settings: String::new(), text: text.join("\n") });
+                    }
+                }
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stitch_webvtt_segments() {
+        let first = "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\n\n\
+            1\n00:00:01.000 --> 00:00:03.000 align:start\nHello <i>there</i>\n\n\
+            2\n00:00:05.500 --> 00:00:06.500\nSpans the boundary\n";
+        // This segment's map is 5 s later, with cue times restarting from
+        // zero: the boundary cue repeats, then a new one
+        let second = "\u{feff}WEBVTT\r\nX-TIMESTAMP-MAP=LOCAL:00:00.000,MPEGTS:1350000\r\n\r\n\
+            00:00:00.500 --> 00:00:01.500\r\nSpans the boundary\r\n\r\n\
+            NOTE skipped\r\n\r\n\
+            00:00:02.000 --> 00:00:03.000\r\nTom &amp; Jerry\r\n";
+
+        let mut stitcher = SubtitleStitcher::new();
+        stitcher.push_segment(first.as_bytes()).unwrap();
+        stitcher.push_segment(second.as_bytes()).unwrap();
+        assert_eq!(stitcher.format(), Some(SubtitleFormat::WebVtt));
+
+        assert_eq!(
+            stitcher.to_webvtt(),
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:03.000 align:start\nHello <i>there</i>\n\n\
+             00:00:05.500 --> 00:00:06.500\nSpans the boundary\n\n\
+             00:00:07.000 --> 00:00:08.000\nTom &amp; Jerry\n\n"
+        );
+        assert_eq!(
+            stitcher.to_srt(),
+            "1\n00:00:01,000 --> 00:00:03,000\nHello <i>there</i>\n\n\
+             2\n00:00:05,500 --> 00:00:06,500\nSpans the boundary\n\n\
+             3\n00:00:07,000 --> 00:00:08,000\nTom & Jerry\n\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_ttml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttp="http://www.w3.org/ns/ttml#parameter" ttp:tickRate="10000000">
+  <body><div>
+    <p begin="10000000t" end="30000000t">First line<br/>second   line</p>
+    <p begin="00:00:04.250" dur="1.5s"><span>Q &amp; A</span></p>
+    <p begin="5s">No end, skipped</p>
+  </div></body>
+</tt>"#;
+        let mut stitcher = SubtitleStitcher::new();
+        stitcher.push_segment(xml.as_bytes()).unwrap();
+        assert_eq!(stitcher.format(), Some(SubtitleFormat::Ttml));
+        assert_eq!(SubtitleFormat::Ttml.extension(), "srt");
+        assert_eq!(
+            stitcher.render(),
+            "1\n00:00:01,000 --> 00:00:03,000\nFirst line\nsecond line\n\n\
+             2\n00:00:04,250 --> 00:00:05,750\nQ & A\n\n"
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(parse_vtt_timestamp("01:02.5"), Some(62.5));
+        assert_eq!(parse_vtt_timestamp("100:00:00,250"), Some(360000.25));
+        assert_eq!(parse_vtt_timestamp("bogus"), None);
+        assert_eq!(format_timestamp(3723.0456, '.'), "01:02:03.046");
+
+        let timing = TtmlTiming { frame_rate: 25.0, tick_rate: 1.0 };
+        assert_eq!(timing.parse("00:00:01:05"), Some(1.2));
+        assert_eq!(timing.parse("500ms"), Some(0.5));
+        assert_eq!(timing.parse("2m"), Some(120.0));
+        assert_eq!(timing.parse("3x"), None);
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        let video = Path::new("/dl/Talk [720p].mp4");
+        let first = sidecar_path(video, Some("en"), "vtt", &[]);
+        assert_eq!(first, Path::new("/dl/Talk [720p].en.vtt"));
+        assert_eq!(
+            sidecar_path(video, Some("en"), "vtt", &[first]),
+            Path::new("/dl/Talk [720p].en.2.vtt")
+        );
+        assert_eq!(sidecar_path(video, Some("../x"), "srt", &[]), Path::new("/dl/Talk [720p].x.srt"));
+        assert_eq!(sidecar_path(video, None, "srt", &[]), Path::new("/dl/Talk [720p].und.srt"));
+    }
+
+    #[test]
+    fn test_rejects_binary_segments() {
+        let mut stitcher = SubtitleStitcher::new();
+        assert!(stitcher.push_segment(b"\x00\x00\x00\x18ftypiso6").is_err());
+        assert!(stitcher.is_empty());
+    }
+}
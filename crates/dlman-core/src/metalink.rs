@@ -0,0 +1,372 @@
+//! Metalink import
+//!
+//! Parses Metalink 4 (RFC 5854, `.meta4`) and Metalink 3 (`.metalink`)
+//! documents into the files they describe: the URLs each file can be fetched
+//! from (best first), its size, a whole-file hash and piece hashes. Element
+//! names are matched without their namespace, so both versions go through the
+//! same parser.
+
+use crate::error::DlmanError;
+use dlman_types::{Checksum, ChecksumAlgorithm, PieceHashes};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::path::{Component, Path};
+
+/// Priority of a URL that doesn't state one
+const DEFAULT_PRIORITY: u32 = 999_999;
+
+/// URL schemes the download engine can fetch
+const SUPPORTED_SCHEMES: &[&str] = &["http", "https", "ftp", "ftps", "sftp"];
+
+/// One file of a Metalink document
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetalinkFile {
+    /// Relative path, `/`-separated; never absolute and never contains `..`
+    pub name: String,
+    pub size: Option<u64>,
+    /// Sorted best first
+    pub urls: Vec<MetalinkUrl>,
+    /// Strongest whole-file hash listed
+    pub hash: Option<Checksum>,
+    /// Strongest complete set of piece hashes listed
+    pub pieces: Option<PieceHashes>,
+}
+
+/// A URL a [`MetalinkFile`] can be fetched from
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetalinkUrl {
+    pub url: String,
+    /// Lower is better (Metalink 4 semantics; v3 preferences are converted)
+    pub priority: u32,
+}
+
+#[derive(Default)]
+struct FileBuilder {
+    name: Option<String>,
+    size: Option<u64>,
+    urls: Vec<MetalinkUrl>,
+    hashes: Vec<Checksum>,
+    pieces: Vec<PieceHashes>,
+}
+
+struct PiecesBuilder {
+    algorithm: Option<ChecksumAlgorithm>,
+    length: u64,
+    hashes: Vec<String>,
+    valid: bool,
+}
+
+/// Parse a Metalink 3 or 4 document. Files without a usable name or URL are
+/// skipped; a document with no files left is an error.
+pub fn parse_metalink(xml: &str) -> Result<Vec<MetalinkFile>, DlmanError> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut files = Vec::new();
+    let mut saw_root = false;
+    let mut file: Option<FileBuilder> = None;
+    let mut pieces: Option<PiecesBuilder> = None;
+    // Attributes of the open leaf element whose text is being collected
+    let mut leaf: Option<(String, Vec<(String, String)>)> = None;
+    let mut text = String::new();
+    let mut buf = Vec::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| {
+            DlmanError::InvalidOperation(format!("Metalink parse error: {e}"))
+        })?;
+        match event {
+            Event::Eof => break,
+            Event::Start(ref e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                match name.as_str() {
+                    "metalink" => saw_root = true,
+                    "file" if saw_root => {
+                        file = Some(FileBuilder {
+                            name: attribute(e, "name"),
+                            ..Default::default()
+                        });
+                    }
+                    "pieces" if file.is_some() => {
+                        let attrs = attributes(e);
+                        let get = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+                        let algorithm = get("type").and_then(parse_algorithm);
+                        let length = get("length").and_then(|l| l.trim().parse().ok()).unwrap_or(0);
+                        pieces = Some(PiecesBuilder {
+                            algorithm,
+                            length,
+                            hashes: Vec::new(),
+                            valid: algorithm.is_some() && length > 0,
+                        });
+                    }
+                    "size" | "hash" | "url" => {
+                        leaf = Some((name, attributes(e)));
+                        text.clear();
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(ref t) if leaf.is_some() => {
+                text.push_str(&t.unescape().unwrap_or_default());
+            }
+            Event::CData(ref t) if leaf.is_some() => {
+                text.push_str(&String::from_utf8_lossy(t));
+            }
+            Event::End(ref e) => match e.local_name().as_ref() {
+                b"size" | b"hash" | b"url" => {
+                    if let (Some((name, attrs)), Some(f)) = (leaf.take(), file.as_mut()) {
+                        let value = text.trim();
+                        let get = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+                        match name.as_str() {
+                            "size" => f.size = value.parse().ok(),
+                            "hash" => match pieces.as_mut() {
+                                Some(p) => match p.algorithm.map(|a| format!("{}:{value}", a.name()).parse::<Checksum>()) {
+                                    Some(Ok(checksum)) => p.hashes.push(checksum.value),
+                                    _ => p.valid = false,
+                                },
+                                None => {
+                                    if let Some(checksum) = get("type")
+                                        .and_then(|t| format!("{t}:{value}").parse::<Checksum>().ok())
+                                    {
+                                        f.hashes.push(checksum);
+                                    }
+                                }
+                            },
+                            _ => {
+                                if is_supported_url(value, get("type")) {
+                                    f.urls.push(MetalinkUrl {
+                                        url: value.to_string(),
+                                        priority: url_priority(get("priority"), get("preference")),
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+                b"pieces" => {
+                    if let (Some(p), Some(f)) = (pieces.take(), file.as_mut()) {
+                        if let (true, Some(algorithm), false) = (p.valid, p.algorithm, p.hashes.is_empty()) {
+                            f.pieces.push(PieceHashes { algorithm, length: p.length, hashes: p.hashes });
+                        }
+                    }
+                }
+                b"file" => {
+                    if let Some(f) = file.take().and_then(FileBuilder::build) {
+                        files.push(f);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if !saw_root {
+        return Err(DlmanError::InvalidOperation("Not a Metalink document".to_string()));
+    }
+    if files.is_empty() {
+        return Err(DlmanError::InvalidOperation(
+            "The Metalink document lists no downloadable files".to_string(),
+        ));
+    }
+    Ok(files)
+}
+
+/// Whether `input` names a local Metalink file (`.meta4` or `.metalink`)
+/// rather than a URL
+pub fn is_metalink_path(input: &str) -> bool {
+    let path = Path::new(input.trim());
+    !input.contains("://")
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("meta4") || e.eq_ignore_ascii_case("metalink"))
+}
+
+impl FileBuilder {
+    fn build(mut self) -> Option<MetalinkFile> {
+        let name = safe_name(self.name.as_deref()?)?;
+        if self.urls.is_empty() {
+            return None;
+        }
+        // Stable, so URLs of equal priority keep document order
+        self.urls.sort_by_key(|u| u.priority);
+        let hash = self.hashes.into_iter().max_by_key(|c| strength(c.algorithm));
+        // Piece hashes that can't cover the whole file are useless
+        let size = self.size;
+        let pieces = self
+            .pieces
+            .into_iter()
+            .filter(|p| size.is_none_or(|s| (p.hashes.len() as u64) == s.div_ceil(p.length)))
+            .max_by_key(|p| strength(p.algorithm));
+        Some(MetalinkFile { name, size, urls: self.urls, hash, pieces })
+    }
+}
+
+fn attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .map(|attr| {
+            (
+                String::from_utf8_lossy(attr.key.local_name().as_ref()).to_string(),
+                attr.unescape_value().map(|v| v.to_string()).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+fn attribute(e: &BytesStart, key: &str) -> Option<String> {
+    attributes(e).into_iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn parse_algorithm(name: &str) -> Option<ChecksumAlgorithm> {
+    match name.trim().to_lowercase().replace('-', "").as_str() {
+        "md5" => Some(ChecksumAlgorithm::Md5),
+        "sha1" => Some(ChecksumAlgorithm::Sha1),
+        "sha256" => Some(ChecksumAlgorithm::Sha256),
+        "sha512" => Some(ChecksumAlgorithm::Sha512),
+        _ => None,
+    }
+}
+
+fn strength(algorithm: ChecksumAlgorithm) -> u8 {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => 0,
+        ChecksumAlgorithm::Sha1 => 1,
+        ChecksumAlgorithm::Sha256 => 2,
+        ChecksumAlgorithm::Sha512 => 3,
+    }
+}
+
+/// Whether the engine can fetch `value`. Metalink 3 marks torrents and the
+/// like with a `type`; Metalink 4 moves them to `metaurl`, which is ignored.
+fn is_supported_url(value: &str, kind: Option<&str>) -> bool {
+    kind.is_none_or(|kind| SUPPORTED_SCHEMES.contains(&kind.trim().to_lowercase().as_str()))
+        && url::Url::parse(value).is_ok_and(|url| SUPPORTED_SCHEMES.contains(&url.scheme()))
+}
+
+/// Metalink 4 `priority` (1 is best), or a Metalink 3 `preference`
+/// (100 is best) mapped onto the same scale
+fn url_priority(priority: Option<&str>, preference: Option<&str>) -> u32 {
+    match (priority, preference) {
+        (Some(p), _) => p.trim().parse().unwrap_or(DEFAULT_PRIORITY),
+        (None, Some(p)) => p.trim().parse::<u32>().map_or(DEFAULT_PRIORITY, |p| 101 - p.min(100)),
+        (None, None) => DEFAULT_PRIORITY,
+    }
+}
+
+/// A file name that stays inside the destination directory
+fn safe_name(name: &str) -> Option<String> {
+    let parts: Vec<&str> = Path::new(name.trim())
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| match c {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_A: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    const SHA256_A: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_parse_metalink4() {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="dir/example.iso">
+    <size>300</size>
+    <hash type="sha-1">{SHA1_A}</hash>
+    <hash type="sha-256">{SHA256_A}</hash>
+    <pieces length="256" type="sha-1">
+      <hash>{SHA1_A}</hash>
+      <hash>{SHA1_A}</hash>
+    </pieces>
+    <url location="de" priority="2">https://de.example.com/example.iso</url>
+    <url>https://fallback.example.com/example.iso</url>
+    <url location="US" priority="1">http://us.example.com/example.iso</url>
+    <url priority="1">magnet:?xt=urn:btih:abc</url>
+    <metaurl mediatype="torrent" priority="1">https://example.com/example.torrent</metaurl>
+  </file>
+  <file name="../escape.bin">
+    <url>https://example.com/escape.bin</url>
+  </file>
+  <file name="no-urls.bin"><size>1</size></file>
+</metalink>"#
+        );
+        let files = parse_metalink(&xml).unwrap();
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert_eq!(file.name, "dir/example.iso");
+        assert_eq!(file.size, Some(300));
+        let urls: Vec<_> = file.urls.iter().map(|u| u.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "http://us.example.com/example.iso",
+                "https://de.example.com/example.iso",
+                "https://fallback.example.com/example.iso",
+            ]
+        );
+        assert_eq!(file.hash.as_ref().unwrap().algorithm, ChecksumAlgorithm::Sha256);
+        let pieces = file.pieces.as_ref().unwrap();
+        assert_eq!((pieces.algorithm, pieces.length, pieces.hashes.len()), (ChecksumAlgorithm::Sha1, 256, 2));
+    }
+
+    #[test]
+    fn test_parse_metalink3() {
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files>
+    <file name="example.tar.gz">
+      <size>10</size>
+      <verification>
+        <hash type="md5">900150983cd24fb0d6963f7d28e17f72</hash>
+        <hash type="sha1">{SHA1_A}</hash>
+        <pieces length="4" type="sha1">
+          <hash piece="0">{SHA1_A}</hash>
+          <hash piece="1">{SHA1_A}</hash>
+        </pieces>
+      </verification>
+      <resources>
+        <url type="http" location="fr" preference="10">http://fr.example.com/example.tar.gz</url>
+        <url type="https" preference="90"><![CDATA[https://example.com/example.tar.gz]]></url>
+        <url type="bittorrent" preference="100">https://example.com/example.torrent.bin</url>
+      </resources>
+    </file>
+  </files>
+</metalink>"#
+        );
+        let files = parse_metalink(&xml).unwrap();
+        let file = &files[0];
+        assert_eq!(file.urls[0].url, "https://example.com/example.tar.gz");
+        assert_eq!(file.urls[0].priority, 11);
+        assert_eq!(file.urls.last().unwrap().url, "http://fr.example.com/example.tar.gz");
+        assert_eq!(file.hash.as_ref().unwrap().value, SHA1_A);
+        // Two 4-byte pieces can't cover 10 bytes
+        assert!(file.pieces.is_none());
+    }
+
+    #[test]
+    fn test_is_metalink_path() {
+        assert!(is_metalink_path("ubuntu.iso.meta4"));
+        assert!(is_metalink_path("/tmp/Fedora.METALINK"));
+        assert!(!is_metalink_path("https://example.com/ubuntu.iso.meta4"));
+        assert!(!is_metalink_path("ubuntu.iso"));
+    }
+
+    #[test]
+    fn test_invalid_documents() {
+        assert!(parse_metalink("<rss><channel/></rss>").is_err());
+        assert!(parse_metalink("<metalink xmlns=\"urn:ietf:params:xml:ns:metalink\"></metalink>").is_err());
+        assert!(parse_metalink("<metalink><file name=\"a\"><url>https://x/a</url></metalink>").is_err());
+    }
+}
@@ -0,0 +1,322 @@
+#!/usr/bin/env python3
+"""Generate the tiny sample streams used by the remux tests.
+
+The streams are synthetic: valid containers and codec headers (SPS/PPS,
+ADTS) around filler slice/frame payloads. They can't be decoded, but they
+exercise everything the remuxer looks at. Run from this directory:
+
+    python3 generate.py
+
+Outputs are deterministic, so re-running leaves the checked-in files as-is.
+"""
+
+import struct
+import zlib
+
+
+# ---------------------------------------------------------------------------
+# Codec headers
+# ---------------------------------------------------------------------------
+
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+
+    def u(self, n, value):
+        self.bits += [(value >> (n - 1 - i)) & 1 for i in range(n)]
+
+    def ue(self, value):
+        value += 1
+        n = value.bit_length()
+        self.u(n - 1, 0)
+        self.u(n, value)
+
+    def bytes(self):
+        self.bits.append(1)  # rbsp_stop_one_bit
+        while len(self.bits) % 8:
+            self.bits.append(0)
+        return bytes(
+            int("".join(map(str, self.bits[i:i + 8])), 2) for i in range(0, len(self.bits), 8)
+        )
+
+
+def h264_sps():
+    """Baseline profile, level 1.3, 320x240."""
+    w = BitWriter()
+    w.u(8, 66)      # profile_idc
+    w.u(8, 0xC0)    # constraint flags
+    w.u(8, 13)      # level_idc
+    w.ue(0)         # seq_parameter_set_id
+    w.ue(0)         # log2_max_frame_num_minus4
+    w.ue(2)         # pic_order_cnt_type
+    w.ue(1)         # max_num_ref_frames
+    w.u(1, 0)       # gaps_in_frame_num_value_allowed_flag
+    w.ue(320 // 16 - 1)
+    w.ue(240 // 16 - 1)
+    w.u(1, 1)       # frame_mbs_only_flag
+    w.u(1, 1)       # direct_8x8_inference_flag
+    w.u(1, 0)       # frame_cropping_flag
+    w.u(1, 0)       # vui_parameters_present_flag
+    return b"\x67" + w.bytes()
+
+
+SPS = h264_sps()
+PPS = b"\x68\xce\x38\x80"
+
+
+def filler(length, seed):
+    """Payload bytes that never form a start code."""
+    return bytes((seed * 31 + i * 7) % 250 + 1 for i in range(length))
+
+
+def slice_nal(index, keyframe):
+    return (b"\x65" if keyframe else b"\x41") + filler(100 + index, index)
+
+
+def adts_frame(index):
+    """AAC-LC, 48 kHz, stereo."""
+    payload = filler(20, 100 + index)
+    length = 7 + len(payload)
+    header = bytes([
+        0xFF, 0xF1,
+        (1 << 6) | (3 << 2) | (2 >> 2),
+        ((2 & 3) << 6) | (length >> 11),
+        (length >> 3) & 0xFF,
+        ((length & 7) << 5) | 0x1F,
+        0xFC,
+    ])
+    return header + payload
+
+
+# ---------------------------------------------------------------------------
+# MPEG-TS
+# ---------------------------------------------------------------------------
+
+VIDEO_PID = 0x100
+AUDIO_PID = 0x101
+PMT_PID = 0x1000
+
+
+def crc32_mpeg(data):
+    crc = 0xFFFFFFFF
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04C11DB7) if crc & 0x80000000 else crc << 1
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def psi_section(table_id, extension, body):
+    length = 5 + len(body) + 4
+    section = bytes([table_id, 0xB0 | (length >> 8), length & 0xFF])
+    section += struct.pack(">HBBB", extension, 0xC1, 0, 0) + body
+    return section + struct.pack(">I", crc32_mpeg(section))
+
+
+class TsWriter:
+    def __init__(self):
+        self.out = bytearray()
+        self.counters = {}
+
+    def packets(self, pid, payload, unit_start):
+        first = True
+        while payload:
+            counter = self.counters.get(pid, 0)
+            self.counters[pid] = (counter + 1) & 0xF
+            header = bytes([
+                0x47,
+                (0x40 if first and unit_start else 0) | (pid >> 8),
+                pid & 0xFF,
+            ])
+            chunk = payload[:184]
+            payload = payload[184:]
+            if len(chunk) < 184:
+                # Pad with an adaptation field of stuffing bytes
+                stuffing = 184 - len(chunk) - 1
+                adaptation = bytes([stuffing]) + (
+                    bytes([0x00]) + b"\xFF" * (stuffing - 1) if stuffing else b""
+                )
+                packet = header + bytes([0x30 | counter]) + adaptation + chunk
+            else:
+                packet = header + bytes([0x10 | counter]) + chunk
+            assert len(packet) == 188
+            self.out += packet
+            first = False
+
+    def psi(self, pid, section):
+        self.packets(pid, b"\x00" + section + b"\xFF" * (183 - len(section)), True)
+
+    def tables(self):
+        self.psi(0, psi_section(0x00, 1, struct.pack(">HH", 1, 0xE000 | PMT_PID)))
+        streams = struct.pack(">BHH", 0x1B, 0xE000 | VIDEO_PID, 0xF000)
+        streams += struct.pack(">BHH", 0x0F, 0xE000 | AUDIO_PID, 0xF000)
+        self.psi(PMT_PID, psi_section(0x02, 1, struct.pack(">HH", 0xE000 | VIDEO_PID, 0xF000) + streams))
+
+    def pes(self, pid, stream_id, data, pts, dts=None):
+        def timestamp(prefix, value):
+            return bytes([
+                (prefix << 4) | (((value >> 30) & 7) << 1) | 1,
+                (value >> 22) & 0xFF,
+                (((value >> 15) & 0x7F) << 1) | 1,
+                (value >> 7) & 0xFF,
+                ((value & 0x7F) << 1) | 1,
+            ])
+
+        if dts is None or dts == pts:
+            fields = timestamp(0b0010, pts)
+            flags = 0x80
+        else:
+            fields = timestamp(0b0011, pts) + timestamp(0b0001, dts)
+            flags = 0xC0
+        length = 3 + len(fields) + len(data)
+        header = b"\x00\x00\x01" + bytes([stream_id])
+        header += struct.pack(">H", length if length <= 0xFFFF and stream_id != 0xE0 else 0)
+        header += bytes([0x80, flags, len(fields)]) + fields
+        self.packets(pid, header + data, True)
+
+
+def transport_stream():
+    """10 H.264 frames at 25 fps (keyframes at 0 and 5), 12 AAC frames
+    starting 20 ms after the first video frame."""
+    ts = TsWriter()
+    ts.tables()
+
+    events = []
+    for i in range(10):
+        dts = 122400 + i * 3600
+        keyframe = i % 5 == 0
+        nals = [b"\x09\xF0"] + ([SPS, PPS] if keyframe else []) + [slice_nal(i, keyframe)]
+        data = b"".join(b"\x00\x00\x00\x01" + nal for nal in nals)
+        events.append((dts, VIDEO_PID, 0xE0, data, dts + 3600, dts))
+    for i in range(4):
+        pts = 126000 + 1800 + i * 3 * 1920
+        data = b"".join(adts_frame(i * 3 + j) for j in range(3))
+        events.append((pts, AUDIO_PID, 0xC0, data, pts, None))
+
+    for _, pid, stream_id, data, pts, dts in sorted(events, key=lambda e: (e[0], e[1])):
+        ts.pes(pid, stream_id, data, pts, dts)
+    return bytes(ts.out)
+
+
+# ---------------------------------------------------------------------------
+# Fragmented MP4
+# ---------------------------------------------------------------------------
+
+def box(kind, *children):
+    body = b"".join(children)
+    return struct.pack(">I", 8 + len(body)) + kind + body
+
+
+def full_box(kind, version, flags, *children):
+    return box(kind, struct.pack(">I", (version << 24) | flags), *children)
+
+
+MATRIX = struct.pack(">9I", 0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000)
+TIMESCALE = 12800
+FRAME_DURATION = 512
+
+
+def init_segment():
+    avcc = bytes([1, 66, 0xC0, 13, 0xFF, 0xE1]) + struct.pack(">H", len(SPS)) + SPS
+    avcc += bytes([1]) + struct.pack(">H", len(PPS)) + PPS
+    avc1 = box(
+        b"avc1",
+        b"\x00" * 6, struct.pack(">H", 1), b"\x00" * 16,
+        struct.pack(">HHIIIH", 320, 240, 0x480000, 0x480000, 0, 1),
+        b"\x00" * 32, struct.pack(">Hh", 0x18, -1),
+        box(b"avcC", avcc),
+    )
+    empty = struct.pack(">I", 0)
+    stbl = box(
+        b"stbl",
+        full_box(b"stsd", 0, 0, struct.pack(">I", 1), avc1),
+        full_box(b"stts", 0, 0, empty),
+        full_box(b"stsc", 0, 0, empty),
+        full_box(b"stsz", 0, 0, empty, empty),
+        full_box(b"stco", 0, 0, empty),
+    )
+    dinf = box(b"dinf", full_box(b"dref", 0, 0, struct.pack(">I", 1), full_box(b"url ", 0, 1)))
+    mdia = box(
+        b"mdia",
+        full_box(b"mdhd", 0, 0, struct.pack(">IIIIHH", 0, 0, TIMESCALE, 0, 0x55C4, 0)),
+        full_box(b"hdlr", 0, 0, b"\x00" * 4, b"vide", b"\x00" * 12, b"VideoHandler\x00"),
+        box(b"minf", full_box(b"vmhd", 0, 1, b"\x00" * 8), dinf, stbl),
+    )
+    tkhd = full_box(
+        b"tkhd", 0, 3,
+        struct.pack(">IIIII", 0, 0, 1, 0, 0), b"\x00" * 8,
+        struct.pack(">HHHH", 0, 0, 0, 0), MATRIX, struct.pack(">II", 320 << 16, 240 << 16),
+    )
+    mvhd = full_box(
+        b"mvhd", 0, 0,
+        struct.pack(">IIIIIH", 0, 0, 1000, 0, 0x10000, 0x100), b"\x00" * 10,
+        MATRIX, b"\x00" * 24, struct.pack(">I", 2),
+    )
+    mvex = box(b"mvex", full_box(b"trex", 0, 0, struct.pack(">IIIII", 1, 1, 0, 0, 0)))
+    ftyp = box(b"ftyp", b"iso6", struct.pack(">I", 0), b"iso6cmfcmp41")
+    return ftyp + box(b"moov", mvhd, box(b"trak", tkhd, mdia), mvex)
+
+
+def fragment(sequence, first_frame, count):
+    samples = []
+    for i in range(first_frame, first_frame + count):
+        keyframe = i % 5 == 0
+        nals = ([SPS, PPS] if keyframe else []) + [slice_nal(i, keyframe)]
+        samples.append((b"".join(struct.pack(">I", len(n)) + n for n in nals), keyframe))
+
+    def moof(data_offset):
+        entries = b"".join(
+            struct.pack(">III", FRAME_DURATION, len(data), 0x02000000 if key else 0x01010000)
+            for data, key in samples
+        )
+        trun = full_box(b"trun", 0, 0x701, struct.pack(">Ii", count, data_offset), entries)
+        traf = box(
+            b"traf",
+            full_box(b"tfhd", 0, 0x20000, struct.pack(">I", 1)),
+            full_box(b"tfdt", 1, 0, struct.pack(">Q", first_frame * FRAME_DURATION)),
+            trun,
+        )
+        return box(b"moof", full_box(b"mfhd", 0, 0, struct.pack(">I", sequence)), traf)
+
+    size = len(moof(0))
+    mdat = box(b"mdat", *(data for data, _ in samples))
+    return moof(size + 8) + mdat
+
+
+def fragmented_mp4():
+    """10 H.264 frames at 25 fps in two fragments of 5."""
+    return init_segment() + fragment(1, 0, 5) + fragment(2, 5, 5)
+
+
+# ---------------------------------------------------------------------------
+# Packed audio (HLS .aac segments)
+# ---------------------------------------------------------------------------
+
+def id3_timestamp(timestamp):
+    owner = b"com.apple.streaming.transportStreamTimestamp\x00"
+    frame_body = owner + struct.pack(">Q", timestamp)
+    frame = b"PRIV" + struct.pack(">I", len(frame_body)) + b"\x00\x00" + frame_body
+    size = len(frame)
+    syncsafe = bytes([(size >> 21) & 0x7F, (size >> 14) & 0x7F, (size >> 7) & 0x7F, size & 0x7F])
+    return b"ID3\x04\x00\x00" + syncsafe + frame
+
+
+def packed_audio():
+    """Two segments of 6 AAC frames, starting 100 ms into the timeline."""
+    out = b""
+    for segment in range(2):
+        out += id3_timestamp(9000 + segment * 6 * 1920)
+        out += b"".join(adts_frame(segment * 6 + i) for i in range(6))
+    return out
+
+
+if __name__ == "__main__":
+    for name, data in [
+        ("av.ts", transport_stream()),
+        ("video.mp4", fragmented_mp4()),
+        ("audio.aac", packed_audio()),
+    ]:
+        with open(name, "wb") as f:
+            f.write(data)
+        print(f"{name}: {len(data)} bytes, crc32 {zlib.crc32(data):08x}")
@@ -38,6 +38,38 @@ pub struct Download {
     /// Format: "name1=value1; name2=value2" (standard HTTP Cookie header format)
     #[serde(default)]
     pub cookies: Option<String>,
+    /// Expected hash of the completed file, verified after merging
+    #[serde(default)]
+    pub checksum: Option<Checksum>,
+    /// Hashes of fixed-size pieces, checked as each segment completes
+    #[serde(default)]
+    pub piece_hashes: Option<PieceHashes>,
+    /// `ETag` reported by the server when the download was probed
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` reported by the server when the download was probed
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Whether the server accepted byte-range requests when probed
+    #[serde(default)]
+    pub supports_range: bool,
+    /// Additional URLs serving the same file; segments are spread across
+    /// `url` and these
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>,
+    /// Segments write straight into `<filename>.part` beside the final file,
+    /// which is renamed when complete, instead of into scratch files that
+    /// are merged. Fixed when the download first starts.
+    #[serde(default)]
+    pub direct_write: bool,
+    /// Request headers for this download, overriding those of matching
+    /// [`HeaderRule`]s on the download's own host
+    #[serde(default)]
+    pub headers: Vec<RequestHeader>,
+    /// Variant and renditions of an HLS/DASH download, kept so a resume
+    /// fetches the same tracks as the segments already on disk
+    #[serde(default)]
+    pub stream_selection: Option<StreamSelection>,
 }
 
 impl Download {
@@ -67,6 +99,15 @@ impl Download {
             completed_at: None,
             retry_count: 0,
             cookies: None,
+            checksum: None,
+            piece_hashes: None,
+            etag: None,
+            last_modified: None,
+            supports_range: false,
+            mirrors: Vec::new(),
+            direct_write: false,
+            headers: Vec::new(),
+            stream_selection: None,
         }
     }
 
@@ -78,6 +119,133 @@ impl Download {
     }
 }
 
+/// An alternative source for a [`Download`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mirror {
+    pub url: String,
+    /// `ETag` seen the first time the mirror was used
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` seen the first time the mirror was used
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+impl Mirror {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            etag: None,
+            last_modified: None,
+        }
+    }
+}
+
+/// Hash algorithm of a [`Checksum`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// Length of the hex digest
+    pub fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Md5 => 32,
+            ChecksumAlgorithm::Sha1 => 40,
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Sha512 => 128,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// Expected hash of a download's content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex digest
+    pub value: String,
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.value)
+    }
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = String;
+
+    /// Parse `algorithm:hexdigest` (e.g. `sha256:9f86d0…`; `sha-256` and `=`
+    /// are accepted too), or a bare hex digest whose algorithm is inferred
+    /// from its length.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (algorithm, value) = match s.split_once([':', '=']) {
+            Some((name, value)) => {
+                let algorithm = match name.trim().to_lowercase().replace('-', "").as_str() {
+                    "md5" => ChecksumAlgorithm::Md5,
+                    "sha1" => ChecksumAlgorithm::Sha1,
+                    "sha256" => ChecksumAlgorithm::Sha256,
+                    "sha512" => ChecksumAlgorithm::Sha512,
+                    other => return Err(format!("Unsupported checksum algorithm: {other}")),
+                };
+                (Some(algorithm), value.trim())
+            }
+            None => (None, s),
+        };
+
+        let value = value.to_lowercase();
+        if value.is_empty() || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Checksum must be a hex digest".to_string());
+        }
+        let algorithm = match algorithm {
+            Some(a) if a.hex_len() == value.len() => a,
+            Some(a) => {
+                return Err(format!(
+                    "A {} checksum has {} hex digits, got {}",
+                    a.name(),
+                    a.hex_len(),
+                    value.len()
+                ))
+            }
+            None => [
+                ChecksumAlgorithm::Md5,
+                ChecksumAlgorithm::Sha1,
+                ChecksumAlgorithm::Sha256,
+                ChecksumAlgorithm::Sha512,
+            ]
+            .into_iter()
+            .find(|a| a.hex_len() == value.len())
+            .ok_or_else(|| format!("Can't tell the algorithm of a {}-digit checksum", value.len()))?,
+        };
+        Ok(Checksum { algorithm, value })
+    }
+}
+
+/// Hashes of consecutive fixed-size pieces of a file (e.g. from a Metalink)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceHashes {
+    pub algorithm: ChecksumAlgorithm,
+    /// Length of every piece but the last, in bytes
+    pub length: u64,
+    /// Lowercase hex digests, in file order
+    pub hashes: Vec<String>,
+}
+
 /// Status of a download
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -90,6 +258,9 @@ pub enum DownloadStatus {
     Queued,
     Cancelled,
     Deleted,
+    /// The server rejected the login; waits for the user to supply
+    /// credentials (see `CoreEvent::CredentialRequired`)
+    AwaitingAuth,
 }
 
 /// A segment of a multi-part download
@@ -137,6 +308,19 @@ impl Segment {
     }
 }
 
+/// Optional settings for a new download
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddDownloadOptions {
+    /// Cookie header from the browser the link came from
+    pub cookies: Option<String>,
+    /// Expected checksum of the merged file
+    pub checksum: Option<Checksum>,
+    /// Further URLs for the same file
+    pub mirrors: Vec<String>,
+    /// Sent with every request to the download's host
+    pub headers: Vec<RequestHeader>,
+}
+
 // ============================================================================
 // Queue Types
 // ============================================================================
@@ -317,6 +501,13 @@ pub struct Settings {
     /// font follows the active language's recommended default.
     #[serde(default)]
     pub font: Option<String>,
+    /// Fall back to an external `ffmpeg` when the built-in remuxer can't
+    /// handle a stream (e.g. a codec it doesn't know). Off by default.
+    #[serde(default)]
+    pub ffmpeg_remux_fallback: bool,
+    /// Connection and bandwidth caps per host, shared by all downloads
+    #[serde(default)]
+    pub host_limits: HostLimitSettings,
 }
 
 fn default_language() -> String {
@@ -357,10 +548,16 @@ fn default_proxy_mode() -> String {
 /// - the **app data** directory (always on the system disk) is fast, but a very
 ///   large download can fill the system disk (see issue #7).
 ///
+/// - **direct** writes skip scratch files altogether: segments write at their
+///   offsets into a preallocated `.part` file beside the final file, which is
+///   renamed on completion. No merge step and no second copy of the data on
+///   disk.
+///
 /// `mode` selects the policy; `custom_path` is only consulted for `"custom"`.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TempStorageSettings {
-    /// One of: `"auto"` (default), `"appdata"`, `"destination"`, `"custom"`.
+    /// One of: `"auto"` (default), `"appdata"`, `"destination"`, `"custom"`,
+    /// `"direct"`.
     #[serde(default = "default_temp_mode")]
     pub mode: String,
     /// Folder used when `mode == "custom"`.
@@ -381,6 +578,59 @@ impl Default for TempStorageSettings {
     }
 }
 
+/// Politeness limits for servers, applied across all downloads at once.
+///
+/// `max_concurrent_downloads`, queue limits and the segment count of each
+/// download don't know about each other, so ten downloads from one CDN could
+/// otherwise open forty connections to it. Connections beyond the cap wait
+/// for a slot.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HostLimitSettings {
+    /// Connections allowed to any one host without a matching rule (0 = no cap)
+    #[serde(default = "default_max_connections_per_host")]
+    pub max_connections_per_host: u32,
+    /// Limits for specific hosts; the first matching rule wins
+    #[serde(default)]
+    pub rules: Vec<HostLimitRule>,
+}
+
+fn default_max_connections_per_host() -> u32 {
+    8
+}
+
+impl Default for HostLimitSettings {
+    fn default() -> Self {
+        Self {
+            max_connections_per_host: default_max_connections_per_host(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Limit for the hosts matching `pattern`. All matching hosts share one pool
+/// of connections, so `*.example.com` caps the whole domain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HostLimitRule {
+    /// Host or domain pattern (e.g., "cdn.example.com", "*.example.com")
+    pub pattern: String,
+    /// Most connections open at once (0 = no cap)
+    pub max_connections: u32,
+    /// Bandwidth shared by those connections, in bytes per second
+    #[serde(default)]
+    pub speed_limit: Option<u64>,
+}
+
+impl HostLimitRule {
+    /// Check if `host` is covered by this rule. Like saved credentials, a
+    /// plain domain also covers its subdomains.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let pattern = self.pattern.trim().to_lowercase();
+        let domain = pattern.strip_prefix("*.").unwrap_or(&pattern);
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
@@ -402,6 +652,8 @@ impl Default for Settings {
             temp_storage: TempStorageSettings::default(),
             language: default_language(),
             font: None,
+            ffmpeg_remux_fallback: false,
+            host_limits: HostLimitSettings::default(),
         }
     }
 }
@@ -462,6 +714,28 @@ pub enum CoreEvent {
         url: String,
         status_code: u16,
     },
+    /// Result of checking a completed download against its expected checksum
+    ChecksumVerified {
+        id: Uuid,
+        algorithm: ChecksumAlgorithm,
+        expected: String,
+        actual: String,
+        matched: bool,
+    },
+    /// The file on the server changed while the download was paused or
+    /// interrupted; the partial data cannot be resumed and the user has to
+    /// restart or abort the download
+    ContentChanged {
+        id: Uuid,
+        url: String,
+    },
+    /// Progress of a live stream recording, whose final size is unknown
+    RecordingProgress {
+        id: Uuid,
+        elapsed_secs: u64,
+        recorded: u64,
+        segments: u64,
+    },
     Error {
         message: String,
         context: Option<String>,
@@ -498,13 +772,21 @@ pub struct SiteCredential {
     pub id: Uuid,
     /// The domain pattern (e.g., "example.com", "*.example.com")
     pub domain: String,
-    /// Protocol: "http", "https", "ftp", or "any"
+    /// Protocol: "http", "https", "ftp", "sftp", or "any". "ftp" also covers
+    /// `ftps://` (implicit TLS) and `ftpes://` URLs. The latter is not a
+    /// registered scheme; as in FileZilla, it means FTP upgraded with `AUTH TLS`.
     #[serde(default = "default_protocol")]
     pub protocol: String,
     /// Username for authentication
     pub username: String,
-    /// Password for authentication
+    /// Password for authentication, or the token for [`AuthScheme::Bearer`]
     pub password: String,
+    /// How HTTP requests authenticate with these credentials
+    #[serde(default)]
+    pub auth_scheme: AuthScheme,
+    /// The login form to submit for [`AuthScheme::Form`]
+    #[serde(default)]
+    pub form_login: Option<FormLogin>,
     /// Whether this credential is enabled
     #[serde(default = "default_enabled")]
     pub enabled: bool,
@@ -512,9 +794,122 @@ pub struct SiteCredential {
     pub created_at: DateTime<Utc>,
     /// When this credential was last used
     pub last_used_at: Option<DateTime<Utc>>,
+    /// Times a server refused this credential or said its quota was used up.
+    /// Kept by the engine; ignored when saving.
+    #[serde(default)]
+    pub failure_count: u32,
+    /// When a server last refused this credential. Kept by the engine;
+    /// ignored when saving.
+    #[serde(default)]
+    pub last_failed_at: Option<DateTime<Utc>>,
+    /// Bytes this account may download per day (UTC), if the site limits it.
+    /// Downloads go to the account that has used the least of its quota, and
+    /// to accounts without one after those.
+    #[serde(default)]
+    pub daily_quota: Option<u64>,
+    /// Bytes downloaded with this credential today (UTC). Kept by the engine;
+    /// ignored when saving.
+    #[serde(default)]
+    pub used_today: u64,
     /// Optional notes/description
     #[serde(default)]
     pub notes: Option<String>,
+    /// Private key used for SFTP logins instead of (or before) the password
+    #[serde(default)]
+    pub ssh_key: Option<SshKey>,
+}
+
+/// HTTP authentication scheme of a [`SiteCredential`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthScheme {
+    /// Username and password on every request
+    #[default]
+    Basic,
+    /// Challenge/response (RFC 7616); the password never goes over the wire
+    Digest,
+    /// A static token sent as `Authorization: Bearer <token>`
+    Bearer,
+    /// An HTML login form (see [`FormLogin`]); requests carry the session
+    /// cookies it sets
+    Form,
+}
+
+impl AuthScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthScheme::Basic => "basic",
+            AuthScheme::Digest => "digest",
+            AuthScheme::Bearer => "bearer",
+            AuthScheme::Form => "form",
+        }
+    }
+}
+
+impl std::str::FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "basic" => Ok(AuthScheme::Basic),
+            "digest" => Ok(AuthScheme::Digest),
+            "bearer" => Ok(AuthScheme::Bearer),
+            "form" => Ok(AuthScheme::Form),
+            other => Err(format!("Unknown auth scheme: {}", other)),
+        }
+    }
+}
+
+/// A login request that yields session cookies, as a site's login form sends it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormLogin {
+    /// Where the form is submitted (its `action`)
+    pub url: String,
+    /// "POST" (form-encoded body) or "GET" (query string)
+    #[serde(default = "default_login_method")]
+    pub method: String,
+    /// Form fields; `{username}` and `{password}` in values are replaced by
+    /// the credential's
+    pub fields: Vec<FormField>,
+    /// How to tell that the login worked
+    #[serde(default)]
+    pub success: LoginCheck,
+}
+
+/// One field of a [`FormLogin`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+}
+
+/// Success check of a [`FormLogin`], applied to the page the login ends on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LoginCheck {
+    /// The server set a cookie and didn't answer with an error
+    #[default]
+    AnyCookie,
+    /// The server set a cookie of this name
+    Cookie { name: String },
+    /// The page contains this text (e.g. "Log out")
+    BodyContains { text: String },
+    /// The page doesn't contain this text (e.g. "Invalid password")
+    BodyLacks { text: String },
+}
+
+fn default_login_method() -> String {
+    "POST".to_string()
+}
+
+/// An SSH private key on disk, optionally protected by a passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SshKey {
+    /// Path to the private key file (OpenSSH or PEM format)
+    pub path: PathBuf,
+    /// Passphrase for encrypted keys
+    #[serde(default)]
+    pub passphrase: Option<String>,
 }
 
 fn default_protocol() -> String {
@@ -533,10 +928,38 @@ impl SiteCredential {
             protocol: "any".to_string(),
             username,
             password,
+            auth_scheme: AuthScheme::Basic,
+            form_login: None,
             enabled: true,
             created_at: Utc::now(),
             last_used_at: None,
+            failure_count: 0,
+            last_failed_at: None,
+            daily_quota: None,
+            used_today: 0,
             notes: None,
+            ssh_key: None,
+        }
+    }
+    
+    /// Whether today's downloads have used up the daily quota
+    pub fn quota_exhausted(&self) -> bool {
+        self.daily_quota.is_some_and(|quota| self.used_today >= quota)
+    }
+    
+    /// Share of the daily quota used today, if there is one
+    pub fn quota_used(&self) -> Option<f64> {
+        self.daily_quota.map(|quota| match quota {
+            0 => 1.0,
+            quota => self.used_today as f64 / quota as f64,
+        })
+    }
+    
+    /// Whether the last time this credential was tried, it was refused
+    pub fn last_attempt_failed(&self) -> bool {
+        match (self.last_failed_at, self.last_used_at) {
+            (Some(failed), Some(used)) => failed > used,
+            (failed, _) => failed.is_some(),
         }
     }
     
@@ -559,24 +982,124 @@ impl SiteCredential {
         
         let url_scheme = parsed.scheme().to_lowercase();
         
-        // Check protocol match
-        if self.protocol != "any" {
-            if self.protocol.to_lowercase() != url_scheme {
-                return false;
-            }
+        // Check protocol match; "ftp" also covers FTP over TLS
+        let protocol = self.protocol.to_lowercase();
+        let scheme_matches = protocol == url_scheme
+            || (protocol == "ftp" && matches!(url_scheme.as_str(), "ftps" | "ftpes"));
+        if protocol != "any" && !scheme_matches {
+            return false;
         }
         
-        // Check domain match
-        let domain = self.domain.to_lowercase();
-        
-        // Wildcard domain matching (e.g., "*.example.com")
-        if domain.starts_with("*.") {
-            let suffix = &domain[2..]; // Remove "*."
-            return url_host == suffix || url_host.ends_with(&format!(".{}", suffix));
+        domain_matches(&self.domain, &url_host)
+    }
+}
+
+/// Whether `host` (lowercase) falls under a domain pattern such as
+/// "example.com" or "*.example.com". Both forms also match subdomains.
+fn domain_matches(pattern: &str, host: &str) -> bool {
+    let domain = pattern.trim().to_lowercase();
+    
+    // Wildcard domain matching (e.g., "*.example.com")
+    if let Some(suffix) = domain.strip_prefix("*.") {
+        return host == suffix || host.ends_with(&format!(".{}", suffix));
+    }
+    
+    // Exact domain match (also match subdomains)
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+// ============================================================================
+// Request Header Types
+// ============================================================================
+
+/// A header sent with download requests
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl RequestHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { name: name.into(), value: value.into() }
+    }
+}
+
+/// Headers added to every request to hosts matching a domain pattern, e.g. a
+/// browser `User-Agent` for a picky CDN or an `X-Api-Key` for an artifact
+/// server. Applies to probes, segments, mirrors, playlists and keys alike.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderRule {
+    pub id: Uuid,
+    /// The domain pattern (e.g., "example.com", "*.example.com"), matched as
+    /// for [`SiteCredential::domain`]
+    pub domain: String,
+    /// Headers to send; a later header with the same name replaces an earlier one
+    pub headers: Vec<RequestHeader>,
+    /// Whether this rule is enabled
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// When this rule was created
+    pub created_at: DateTime<Utc>,
+    /// Optional notes/description
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+impl HeaderRule {
+    pub fn new(domain: String, headers: Vec<RequestHeader>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            domain,
+            headers,
+            enabled: true,
+            created_at: Utc::now(),
+            notes: None,
+        }
+    }
+    
+    /// Check if this rule applies to requests to `url`, whatever the scheme
+    pub fn matches_url(&self, url: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_lowercase)) {
+            Some(host) => domain_matches(&self.domain, &host),
+            None => false,
+        }
+    }
+}
+
+/// Whether saved passwords are encrypted at rest, and if so whether their
+/// key is loaded for this session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VaultStatus {
+    /// No master key set up; passwords are stored as typed
+    Disabled,
+    /// Encrypted, and the key hasn't been supplied yet
+    Locked,
+    /// Encrypted, and saved logins can be read and written
+    Unlocked,
+}
+
+/// What the key that encrypts saved passwords is derived from
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum VaultKey {
+    /// A master passphrase, asked for once per session
+    Passphrase(String),
+    /// A file holding a secret, read when the app starts, for headless use.
+    /// One is generated when setting up with a path that doesn't exist yet.
+    Keyfile(PathBuf),
+}
+
+impl std::fmt::Debug for VaultKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VaultKey::Passphrase(_) => f.write_str("Passphrase(..)"),
+            VaultKey::Keyfile(path) => f.debug_tuple("Keyfile").field(path).finish(),
         }
-        
-        // Exact domain match (also match subdomains)
-        url_host == domain || url_host.ends_with(&format!(".{}", domain))
     }
 }
 
@@ -643,6 +1166,57 @@ pub struct MediaVariant {
     pub audio_only: bool,
     /// Estimated file size in bytes (if known)
     pub estimated_size: Option<u64>,
+    /// Alternate audio renditions this variant can be played with
+    #[serde(default)]
+    pub audio_tracks: Vec<MediaRendition>,
+    /// Subtitle renditions this variant can be played with
+    #[serde(default)]
+    pub subtitle_tracks: Vec<MediaRendition>,
+}
+
+/// An alternate audio or subtitle rendition (HLS `EXT-X-MEDIA`, or a DASH
+/// audio or text representation that pairs with a video-only variant)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaRendition {
+    /// Manifest URL of the rendition; `None` when it is muxed into the
+    /// variant's own stream
+    pub url: Option<String>,
+    /// Human-readable name (e.g. "English", "Commentary")
+    pub name: String,
+    /// Language tag (e.g. "en", "pt-BR")
+    pub language: Option<String>,
+    /// Whether the manifest marks this as the default rendition
+    #[serde(default)]
+    pub default: bool,
+    /// Bitrate in bits per second (DASH representations)
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+    /// Codec string (e.g. "mp4a.40.2")
+    #[serde(default)]
+    pub codecs: Option<String>,
+}
+
+/// Which alternate renditions to download alongside the chosen variant.
+///
+/// Indices refer to the variant's `audio_tracks` / `subtitle_tracks`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackSelection {
+    /// Audio rendition (None = the manifest's default)
+    #[serde(default)]
+    pub audio: Option<usize>,
+    /// Subtitle rendition (None = every subtitle track)
+    #[serde(default)]
+    pub subtitles: Option<usize>,
+}
+
+/// The variant and renditions an HLS/DASH download was started with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamSelection {
+    /// Index of the chosen variant (None = best quality)
+    #[serde(default)]
+    pub variant: Option<usize>,
+    #[serde(default)]
+    pub tracks: TrackSelection,
 }
 
 /// A media stream detected on a webpage
@@ -685,6 +1259,29 @@ pub struct MediaDownloadRequest {
     pub output_filename: Option<String>,
     /// Target queue ID
     pub queue_id: Option<Uuid>,
+    /// Limits for live streams (ignored for VOD)
+    #[serde(default)]
+    pub recording: Option<RecordingOptions>,
+    /// Index into the chosen variant's `audio_tracks` (None = default)
+    #[serde(default)]
+    pub audio_track: Option<usize>,
+    /// Index into the chosen variant's `subtitle_tracks` (None = all of them)
+    #[serde(default)]
+    pub subtitle_track: Option<usize>,
+}
+
+/// Optional limits for recording a live HLS/DASH stream.
+///
+/// A recording always ends when the stream ends or the user stops it; these
+/// add caps on top of that.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordingOptions {
+    /// Stop after this many seconds of recording
+    #[serde(default)]
+    pub max_duration_secs: Option<u64>,
+    /// Stop once this many bytes have been written
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
 }
 
 /// Response after initiating a media download